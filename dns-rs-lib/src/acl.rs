@@ -0,0 +1,328 @@
+use crate::cidr::Cidr;
+use crate::edns::{Edns, EdnsOption};
+use crate::extended_error::{ExtendedError, InfoCode};
+use crate::opcode::OpCode;
+use crate::packet::Packet;
+use crate::r#type::RRType;
+use crate::rcode::RCode;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Element {
+    Any,
+    None,
+    Address(Cidr),
+    // a TSIG key name, the signature has to have been verified already
+    Key(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub negated: bool,
+    pub element: Element,
+}
+
+// rules are tried in order and the first one that matches decides, allowing
+// unless it's negated; nothing matching denies, as in BIND's address match
+// lists
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Acl {
+    pub rules: Vec<Rule>,
+}
+
+// what a message is asking the server to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Query,
+    // a query with RD set
+    Recursion,
+    Transfer,
+    Notify,
+    Update,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Policy {
+    pub query: Acl,
+    pub recursion: Acl,
+    pub transfer: Acl,
+    pub notify: Acl,
+    pub update: Acl,
+}
+
+impl Element {
+    pub fn matches(&self, client: IpAddr, key: Option<&str>) -> bool {
+        match self {
+            Element::Any => true,
+            Element::None => false,
+            Element::Address(cidr) => cidr.contains(client),
+            Element::Key(name) => key.is_some_and(|key| {
+                name.trim_end_matches('.')
+                    .eq_ignore_ascii_case(key.trim_end_matches('.'))
+            }),
+        }
+    }
+}
+
+impl Acl {
+    pub fn any() -> Self {
+        Self {
+            rules: vec![Rule {
+                negated: false,
+                element: Element::Any,
+            }],
+        }
+    }
+
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    pub fn allows(&self, client: IpAddr, key: Option<&str>) -> bool {
+        self.rules
+            .iter()
+            .find(|rule| rule.element.matches(client, key))
+            .is_some_and(|rule| !rule.negated)
+    }
+}
+
+impl Operation {
+    pub fn of(query: &Packet) -> Self {
+        match OpCode::from_value(query.header.op_code) {
+            OpCode::NOTIFY => Operation::Notify,
+            OpCode::UPDATE => Operation::Update,
+            _ => match query.questions.first().map(|question| question.r#type) {
+                Some(RRType::AXFR | RRType::IXFR) => Operation::Transfer,
+                _ if query.header.should_recurse => Operation::Recursion,
+                _ => Operation::Query,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Operation::Query => "query",
+            Operation::Recursion => "recursion",
+            Operation::Transfer => "zone transfer",
+            Operation::Notify => "notify",
+            Operation::Update => "update",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Default for Policy {
+    // anyone can query, recursion is for this host only and everything else
+    // has to be opened up explicitly
+    fn default() -> Self {
+        Self {
+            query: Acl::any(),
+            recursion: "127.0.0.0/8, ::1".parse().unwrap(),
+            transfer: Acl::none(),
+            notify: Acl::none(),
+            update: Acl::none(),
+        }
+    }
+}
+
+impl Policy {
+    // a recursive query has to be allowed to query at all as well
+    pub fn allows(&self, operation: Operation, client: IpAddr, key: Option<&str>) -> bool {
+        match operation {
+            Operation::Query => self.query.allows(client, key),
+            Operation::Recursion => {
+                self.query.allows(client, key) && self.recursion.allows(client, key)
+            }
+            Operation::Transfer => self.transfer.allows(client, key),
+            Operation::Notify => self.notify.allows(client, key),
+            Operation::Update => self.update.allows(client, key),
+        }
+    }
+
+    // the response to send if the query isn't allowed; verified_key is the
+    // TSIG key the caller checked the query's signature with, a key name in
+    // the message itself proves nothing
+    pub fn check(
+        &self,
+        client: IpAddr,
+        verified_key: Option<&str>,
+        query: &Packet,
+    ) -> Option<Packet> {
+        let operation = Operation::of(query);
+        match self.allows(operation, client, verified_key) {
+            true => None,
+            false => Some(refuse(query, operation)),
+        }
+    }
+}
+
+// REFUSED, with an extended error saying why when the client sent EDNS
+pub fn refuse(query: &Packet, operation: Operation) -> Packet {
+    let mut header = query.header.clone();
+    header.response = true;
+    header.is_authoritative = false;
+    header.can_recurse = false;
+    header.resp_code = RCode::REFUSED as u8;
+    let mut response = Packet::new(header);
+    response.questions = query.questions.clone();
+    if let Some(edns) = &query.edns {
+        let mut edns = Edns::new(edns.udp_payload_size);
+        edns.set_option(EdnsOption::ExtendedError(ExtendedError::new(
+            InfoCode::Prohibited,
+            &format!("{} not allowed", operation),
+        )));
+        response.edns = Some(edns);
+    }
+    response
+}
+
+impl FromStr for Acl {
+    type Err = String;
+
+    // comma separated: any, none, key NAME or an address or prefix, each
+    // optionally negated with !
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rules = Vec::new();
+        for item in s.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+            let (negated, item) = match item.strip_prefix('!') {
+                Some(item) => (true, item.trim()),
+                None => (false, item),
+            };
+            let element = match item.split_once(char::is_whitespace) {
+                Some(("key", name)) => Element::Key(name.trim().to_ascii_lowercase()),
+                _ if item.eq_ignore_ascii_case("any") => Element::Any,
+                _ if item.eq_ignore_ascii_case("none") => Element::None,
+                _ => Element::Address(item.parse()?),
+            };
+            rules.push(Rule { negated, element });
+        }
+        Ok(Self { rules })
+    }
+}
+
+impl fmt::Display for Acl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, rule) in self.rules.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            if rule.negated {
+                write!(f, "!")?;
+            }
+            match &rule.element {
+                Element::Any => write!(f, "any")?,
+                Element::None => write!(f, "none")?,
+                Element::Address(cidr) => write!(f, "{}", cidr)?,
+                Element::Key(name) => write!(f, "key {}", name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::answer::Answer;
+    use crate::class::RRClass;
+    use crate::client;
+
+    fn allows(acl: &str, client: &str, key: Option<&str>) -> bool {
+        let acl: Acl = acl.parse().unwrap();
+        acl.allows(client.parse().unwrap(), key)
+    }
+
+    #[test]
+    fn test_first_match_wins() {
+        let acl = "!10.0.0.1, 10.0.0.0/8, key xfr-key., !any, 192.0.2.1";
+        assert!(allows(acl, "10.2.3.4", None));
+        assert!(!allows(acl, "10.0.0.1", None));
+        // the negation comes first, so the key doesn't help
+        assert!(!allows(acl, "10.0.0.1", Some("xfr-key")));
+        assert!(allows(acl, "203.0.113.1", Some("XFR-KEY")));
+        // !any denies everything not matched so far, and 192.0.2.1 is never
+        // reached
+        assert!(!allows(acl, "192.0.2.1", None));
+        // nothing matching denies
+        assert!(!allows("10.0.0.0/8", "2001:db8::1", None));
+        assert!(!allows("", "10.0.0.1", None));
+        assert!(allows("none, any", "10.0.0.1", None));
+    }
+
+    #[test]
+    fn test_parse() {
+        let acl: Acl = "! 10.0.0.0/8 , key Foo, any".parse().unwrap();
+        assert_eq!(acl.to_string(), "!10.0.0.0/8, key foo, any");
+        assert_eq!(acl.rules.len(), 3);
+        assert!("10.0.0.0/33".parse::<Acl>().is_err());
+        assert!("everyone".parse::<Acl>().is_err());
+    }
+
+    fn query(r#type: RRType, recurse: bool) -> Packet {
+        let mut query = client::build_query("example.com", r#type, RRClass::IN);
+        query.header.should_recurse = recurse;
+        query
+    }
+
+    #[test]
+    fn test_operations() {
+        assert_eq!(Operation::of(&query(RRType::A, false)), Operation::Query);
+        assert_eq!(Operation::of(&query(RRType::A, true)), Operation::Recursion);
+        assert_eq!(
+            Operation::of(&query(RRType::AXFR, true)),
+            Operation::Transfer
+        );
+        assert_eq!(
+            Operation::of(&query(RRType::IXFR, false)),
+            Operation::Transfer
+        );
+        let mut notify = query(RRType::SOA, false);
+        notify.header.op_code = OpCode::NOTIFY as u8;
+        assert_eq!(Operation::of(&notify), Operation::Notify);
+        let mut update = query(RRType::SOA, false);
+        update.header.op_code = OpCode::UPDATE as u8;
+        assert_eq!(Operation::of(&update), Operation::Update);
+    }
+
+    #[test]
+    fn test_policy() {
+        let policy = Policy {
+            transfer: "key xfr-key".parse().unwrap(),
+            ..Policy::default()
+        };
+        let local: IpAddr = "127.0.0.1".parse().unwrap();
+        let remote: IpAddr = "203.0.113.1".parse().unwrap();
+
+        assert!(policy
+            .check(remote, None, &query(RRType::A, false))
+            .is_none());
+        assert!(policy.check(local, None, &query(RRType::A, true)).is_none());
+
+        let mut recursive = query(RRType::A, true);
+        recursive.edns = Some(Edns::new(1232));
+        let refused = policy.check(remote, None, &recursive).unwrap();
+        assert_eq!(refused.rcode(), RCode::REFUSED as u16);
+        assert!(refused.header.response);
+        let errors = refused.edns.as_ref().unwrap().extended_errors();
+        assert_eq!(errors[0].info_code(), InfoCode::Prohibited);
+        assert_eq!(errors[0].extra_text, "recursion not allowed");
+        // no EDNS in the query, so none in the response
+        let refused = policy
+            .check(local, None, &query(RRType::AXFR, false))
+            .unwrap();
+        assert!(refused.edns.is_none());
+
+        // an unsigned TSIG record naming the key doesn't get a transfer, only
+        // a key the caller verified does
+        let mut transfer = query(RRType::AXFR, false);
+        transfer
+            .additionals
+            .push(Answer::new("xfr-key", RRType::TSIG, RRClass::IN, 0, vec![]));
+        assert!(policy.check(remote, None, &transfer).is_some());
+        assert!(policy.check(remote, Some("xfr-key"), &transfer).is_none());
+    }
+}
@@ -1,11 +1,18 @@
 use crate::buf_reader::BufReader;
 use crate::class::RRClass;
+use crate::name;
 use crate::parser::bit_accessor;
 use crate::r#type::RRType;
+use crate::rdata;
 use std::convert::TryInto;
-use std::str;
+use std::fmt;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "json",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "crate::json::RecordJson", try_from = "crate::json::RecordJson")
+)]
 pub struct Answer {
     pub name: String,
     pub r#type: RRType,
@@ -13,52 +20,71 @@ pub struct Answer {
     pub ttl: u32,
     pub len: u16,
     pub ip: String,
+    pub data: Vec<u8>,
 }
 
 impl Answer {
+    pub fn new(name: &str, r#type: RRType, class: RRClass, ttl: u32, data: Vec<u8>) -> Self {
+        Self {
+            name: name.to_string(),
+            r#type,
+            class,
+            ttl,
+            len: data.len() as u16,
+            ip: "ip".to_string(),
+            data,
+        }
+    }
+
     pub fn from_buf(buf: &mut BufReader) -> Self {
         let name = Self::read_labels(buf);
+        let r#type = Self::read_type(buf);
+        let class = Self::read_class(buf);
+        let ttl = Self::read_ttl(buf);
+        let len = Self::read_len(buf);
+        let data = Self::read_data(buf, r#type, len);
         Self {
             name,
-            r#type: Self::read_type(buf),
-            class: Self::read_class(buf),
-            ttl: Self::read_ttl(buf),
-            len: Self::read_len(buf),
+            r#type,
+            class,
+            ttl,
+            len,
             ip: "ip".to_string(),
+            data,
         }
     }
 
     pub fn read_labels(buf: &mut BufReader) -> String {
         let mut fragments = Vec::new();
-        let started_at = buf.pos;
+        let mut resume_at = None;
         let mut curr_byte = buf.read(1)[0];
-        let mut jumped = false;
 
         while curr_byte != 0b00000000 {
             // jump if the first two bytes are set
             if bit_accessor(&curr_byte, 0) == 1 && bit_accessor(&curr_byte, 1) == 1 {
                 let bytes = [curr_byte, buf.read(1)[0]];
-                let value = u16::from_be_bytes(bytes.try_into().unwrap());
+                let value = u16::from_be_bytes(bytes);
                 let goto = (value ^ 0b1100000000000000) as usize;
+                if resume_at.is_none() {
+                    resume_at = Some(buf.pos);
+                }
                 buf.goto(goto);
 
                 curr_byte = buf.read(1)[0];
-                jumped = true;
+                continue;
             }
 
-            let bytes = [curr_byte];
-            let label_len = u8::from_be_bytes(bytes.try_into().unwrap()) as usize;
+            let label_len = curr_byte as usize;
             let label_bytes = buf.read(label_len);
-            let label = str::from_utf8(label_bytes).expect("Unable to convert bytes to str");
-            fragments.push(label.to_owned());
+            fragments.push(String::from_utf8_lossy(label_bytes).into_owned());
 
             // advance to the next label len
             curr_byte = buf.read(1)[0];
         }
 
-        if jumped {
-            // reset our position to be where we started + 2
-            buf.goto(started_at + 2);
+        if let Some(pos) = resume_at {
+            // reset our position to just past the first pointer we followed
+            buf.goto(pos);
         }
         fragments.join(".")
     }
@@ -84,4 +110,100 @@ impl Answer {
         let bytes: &[u8] = buf.read(2);
         u16::from_be_bytes(bytes.try_into().unwrap())
     }
+
+    // names are written out uncompressed
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = name::to_wire(&self.name);
+        bytes.extend_from_slice(&self.r#type.to_value().to_be_bytes());
+        bytes.extend_from_slice(&self.class.to_value().to_be_bytes());
+        bytes.extend_from_slice(&self.ttl.to_be_bytes());
+        bytes.extend_from_slice(&(self.data.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+
+    // names embedded in the record data may be compressed, they are stored
+    // expanded so the data can be understood without the rest of the packet
+    pub fn read_data(buf: &mut BufReader, r#type: RRType, len: u16) -> Vec<u8> {
+        let end = buf.pos + len as usize;
+        let mut data = Vec::new();
+        match r#type {
+            RRType::NS | RRType::CNAME | RRType::MB | RRType::MG | RRType::MR | RRType::PTR => {
+                data.extend(name::to_wire(&Self::read_labels(buf)));
+            }
+            RRType::MINFO => {
+                data.extend(name::to_wire(&Self::read_labels(buf)));
+                data.extend(name::to_wire(&Self::read_labels(buf)));
+            }
+            RRType::SOA => {
+                data.extend(name::to_wire(&Self::read_labels(buf)));
+                data.extend(name::to_wire(&Self::read_labels(buf)));
+                data.extend_from_slice(buf.read(20));
+            }
+            RRType::MX => {
+                data.extend_from_slice(buf.read(2));
+                data.extend(name::to_wire(&Self::read_labels(buf)));
+            }
+            _ => data.extend_from_slice(buf.read(len as usize)),
+        }
+        buf.goto(end);
+        data
+    }
+}
+
+// one record per line in zone file syntax
+impl fmt::Display for Answer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{}",
+            name::to_presentation(&self.name),
+            self.ttl,
+            self.class,
+            self.r#type,
+            rdata::to_presentation(self.r#type, &self.data)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parsing_compressed_data() {
+        let header = vec![
+            0b00000000, 0b00000000, 0b10000001, 0b10000000, 0b00000000, 0b00000001, 0b00000000,
+            0b00000001, 0b00000000, 0b00000000, 0b00000000, 0b00000000,
+        ];
+        // google.com MX IN
+        let question = vec![
+            0b00000110, 0b01100111, 0b01101111, 0b01101111, 0b01100111, 0b01101100, 0b01100101,
+            0b00000011, 0b01100011, 0b01101111, 0b01101101, 0b00000000, 0b00000000, 0b00001111,
+            0b00000000, 0b00000001,
+        ];
+        // pointer to google.com, MX IN, ttl 300, preference 10, smtp + pointer
+        let answer = vec![
+            0b11000000, 0b00001100, 0b00000000, 0b00001111, 0b00000000, 0b00000001, 0b00000000,
+            0b00000000, 0b00000001, 0b00101100, 0b00000000, 0b00001001, 0b00000000, 0b00001010,
+            0b00000100, 0b01110011, 0b01101101, 0b01110100, 0b01110000, 0b11000000, 0b00001100,
+        ];
+        let packet = [header, question, answer].concat();
+        let mut buf = BufReader::new(&packet);
+        buf.goto(28);
+        let answer = Answer::from_buf(&mut buf);
+        assert_eq!(answer.name, "google.com");
+        assert_eq!(answer.r#type, RRType::MX);
+        assert_eq!(answer.ttl, 300);
+        assert_eq!(answer.len, 9);
+        assert_eq!(
+            answer.data,
+            [vec![0, 10], name::to_wire("smtp.google.com")].concat()
+        );
+        assert_eq!(buf.pos, packet.len());
+        assert_eq!(
+            answer.to_string(),
+            "google.com.\t300\tIN\tMX\t10 smtp.google.com."
+        );
+    }
 }
@@ -0,0 +1,94 @@
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const URL_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+// the standard alphabet with padding, as used for keys and signatures in
+// presentation format
+pub fn encode(data: &[u8]) -> String {
+    let mut encoded = encode_with(ALPHABET, data);
+    while !encoded.len().is_multiple_of(4) {
+        encoded.push('=');
+    }
+    encoded
+}
+
+// the URL safe alphabet without padding, as used by RFC 8484 section 4.1
+pub fn encode_url(data: &[u8]) -> String {
+    encode_with(URL_ALPHABET, data)
+}
+
+fn encode_with(alphabet: &[u8; 64], data: &[u8]) -> String {
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let mut bytes = [0u8; 3];
+        bytes[..chunk.len()].copy_from_slice(chunk);
+        let group = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for i in 0..=chunk.len() {
+            let index = (group >> (18 - 6 * i)) & 0b111111;
+            encoded.push(alphabet[index as usize] as char);
+        }
+    }
+    encoded
+}
+
+// accepts both the standard and URL safe alphabets, with or without padding
+pub fn decode(encoded: &str) -> Option<Vec<u8>> {
+    let encoded = encoded.trim_end_matches('=');
+    let mut data = Vec::new();
+    let mut group = 0u32;
+    let mut bits = 0;
+    for c in encoded.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return None,
+        };
+        group = group << 6 | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            data.push((group >> bits) as u8);
+        }
+    }
+    // a single leftover character can't encode a whole byte
+    if bits >= 6 {
+        return None;
+    }
+    Some(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode(b""), "");
+        assert_eq!(encode(b"f"), "Zg==");
+        assert_eq!(encode(b"fo"), "Zm8=");
+        assert_eq!(encode(b"foo"), "Zm9v");
+        assert_eq!(encode(&[0xfb, 0xff]), "+/8=");
+    }
+
+    #[test]
+    fn test_encode_url() {
+        assert_eq!(encode_url(b""), "");
+        assert_eq!(encode_url(b"f"), "Zg");
+        assert_eq!(encode_url(b"fo"), "Zm8");
+        assert_eq!(encode_url(b"foo"), "Zm9v");
+        assert_eq!(encode_url(b"foob"), "Zm9vYg");
+        assert_eq!(encode_url(&[0xfb, 0xff]), "-_8");
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode("Zm9vYg").unwrap(), b"foob");
+        assert_eq!(decode("Zm9vYg==").unwrap(), b"foob");
+        assert_eq!(decode("-_8").unwrap(), vec![0xfb, 0xff]);
+        assert_eq!(decode("+/8=").unwrap(), vec![0xfb, 0xff]);
+        assert_eq!(decode("Zm9v!"), None);
+        assert_eq!(decode("Zm9vY"), None);
+    }
+}
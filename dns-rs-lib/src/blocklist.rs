@@ -0,0 +1,469 @@
+use crate::answer::Answer;
+use crate::edns::EdnsOption;
+use crate::extended_error::{ExtendedError, InfoCode};
+use crate::name;
+use crate::packet::Packet;
+use crate::r#type::RRType;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+pub const DEFAULT_TTL: u32 = 60;
+// the made-up zone a negative answer's SOA claims to come from
+const SOA_PRIMARY: &str = "localhost";
+const SOA_MAILBOX: &str = "nobody.invalid";
+
+// how blocked names are answered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Nxdomain,
+    // 0.0.0.0 and ::, what most ad blockers send
+    NullAddress,
+    // addresses of a page explaining the block, either can be left out to
+    // answer that type with no data
+    Sinkhole(Option<Ipv4Addr>, Option<Ipv6Addr>),
+}
+
+// labels from the root down, so a name and everything under it share a path
+#[derive(Debug, Clone, Default)]
+struct Node {
+    children: HashMap<String, Node>,
+    blocked: bool,
+    // from a *. entry, which blocks what's under the name but not the name
+    blocked_below: bool,
+    allowed: bool,
+}
+
+// what a line in a list asks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Rule {
+    Block,
+    BlockBelow,
+    Allow,
+}
+
+// names to block along with their subdomains, and exceptions to that
+#[derive(Debug, Clone, Default)]
+pub struct Blocklist {
+    root: Node,
+    blocked: usize,
+    allowed: usize,
+}
+
+// a list file, an allowlist makes every entry in it an exception
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Source {
+    pub path: PathBuf,
+    pub allow: bool,
+}
+
+// lists loaded from files that can be reloaded while queries are being
+// answered
+#[derive(Debug)]
+pub struct Filter {
+    pub mode: Mode,
+    pub ttl: u32,
+    pub sources: Vec<Source>,
+    blocklist: RwLock<Arc<Blocklist>>,
+}
+
+impl Blocklist {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn block(&mut self, name: &str) {
+        let node = self.node(name);
+        if !node.blocked {
+            node.blocked = true;
+            self.blocked += 1;
+        }
+    }
+
+    // only the names under this one, for *.name entries
+    pub fn block_below(&mut self, name: &str) {
+        let node = self.node(name);
+        if !node.blocked_below {
+            node.blocked_below = true;
+            self.blocked += 1;
+        }
+    }
+
+    pub fn allow(&mut self, name: &str) {
+        let node = self.node(name);
+        if !node.allowed {
+            node.allowed = true;
+            self.allowed += 1;
+        }
+    }
+
+    fn node(&mut self, name: &str) -> &mut Node {
+        let name = name.to_ascii_lowercase();
+        let mut node = &mut self.root;
+        for label in name::labels(&name).into_iter().rev() {
+            node = node.children.entry(label.to_string()).or_default();
+        }
+        node
+    }
+
+    // the entry closest to the name decides, and an allow beats a block for
+    // the same name
+    pub fn is_blocked(&self, name: &str) -> bool {
+        let name = name.to_ascii_lowercase();
+        let labels = name::labels(&name);
+        let mut node = &self.root;
+        let mut blocked = false;
+        for (depth, label) in labels.iter().rev().enumerate() {
+            match node.children.get(*label) {
+                Some(child) => node = child,
+                None => break,
+            }
+            let below = depth + 1 < labels.len();
+            if node.allowed {
+                blocked = false;
+            } else if node.blocked || (below && node.blocked_below) {
+                blocked = true;
+            }
+        }
+        blocked
+    }
+
+    // hosts files, plain lists of domains and adblock filter lists, in any
+    // mix; returns how many lines were skipped as unusable
+    pub fn load(&mut self, text: &str, allow: bool) -> usize {
+        let mut skipped = 0;
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(['#', '!', '[']) {
+                continue;
+            }
+            match parse_line(line) {
+                Some(entries) => {
+                    for (name, rule) in entries {
+                        // a wildcard in an allowlist allows the name as well
+                        match (allow, rule) {
+                            (true, _) | (_, Rule::Allow) => self.allow(&name),
+                            (false, Rule::Block) => self.block(&name),
+                            (false, Rule::BlockBelow) => self.block_below(&name),
+                        }
+                    }
+                }
+                None => skipped += 1,
+            }
+        }
+        skipped
+    }
+
+    pub fn blocked_len(&self) -> usize {
+        self.blocked
+    }
+
+    pub fn allowed_len(&self) -> usize {
+        self.allowed
+    }
+}
+
+// the names on a line, and what to do with each
+fn parse_line(line: &str) -> Option<Vec<(String, Rule)>> {
+    // ||example.com^ blocks a domain in adblock syntax, and @@ makes it an
+    // exception; rules with $options only make sense to a browser
+    if let Some(pattern) = line.strip_prefix("@@||").or(line.strip_prefix("||")) {
+        let rule = match line.starts_with("@@") {
+            true => Rule::Allow,
+            false => Rule::Block,
+        };
+        let name = pattern.strip_suffix('^').unwrap_or(pattern);
+        return match is_domain(name) {
+            true => Some(vec![(name.to_string(), rule)]),
+            false => None,
+        };
+    }
+
+    let line = line.split('#').next().unwrap_or("").trim();
+    let mut fields = line.split_whitespace();
+    let first = fields.next()?;
+    // hosts files give an address and then names for it
+    if first.parse::<IpAddr>().is_ok() {
+        let names: Vec<(String, Rule)> = fields
+            .filter(|name| is_domain(name) && !is_local(name))
+            .map(|name| (name.to_string(), Rule::Block))
+            .collect();
+        return Some(names);
+    }
+    let (name, rule) = match first.strip_prefix("*.") {
+        Some(name) => (name, Rule::BlockBelow),
+        None => (first, Rule::Block),
+    };
+    match fields.next().is_none() && is_domain(name) {
+        true => Some(vec![(name.to_string(), rule)]),
+        false => None,
+    }
+}
+
+fn is_domain(name: &str) -> bool {
+    let name = name.trim_end_matches('.');
+    !name.is_empty()
+        && name.len() <= 253
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        })
+}
+
+// hosts files map these to the loopback address, blocking them would break
+// the machine rather than an ad
+fn is_local(name: &str) -> bool {
+    matches!(
+        name.to_ascii_lowercase().trim_end_matches('.'),
+        "localhost"
+            | "localhost.localdomain"
+            | "local"
+            | "broadcasthost"
+            | "ip6-localhost"
+            | "ip6-loopback"
+            | "ip6-localnet"
+            | "ip6-mcastprefix"
+            | "ip6-allnodes"
+            | "ip6-allrouters"
+            | "ip6-allhosts"
+            | "0.0.0.0"
+    )
+}
+
+impl Filter {
+    pub fn new(mode: Mode, sources: Vec<Source>) -> Self {
+        Self {
+            mode,
+            ttl: DEFAULT_TTL,
+            sources,
+            blocklist: RwLock::new(Arc::new(Blocklist::new())),
+        }
+    }
+
+    // reads every source again and swaps the new lists in, leaving the old
+    // ones in place if any can't be read
+    pub fn reload(&self) -> io::Result<Arc<Blocklist>> {
+        let mut blocklist = Blocklist::new();
+        for source in &self.sources {
+            let text = fs::read_to_string(&source.path).map_err(|e| {
+                io::Error::new(e.kind(), format!("{}: {}", source.path.display(), e))
+            })?;
+            blocklist.load(&text, source.allow);
+        }
+        let blocklist = Arc::new(blocklist);
+        *self.blocklist.write().unwrap() = blocklist.clone();
+        Ok(blocklist)
+    }
+
+    pub fn blocklist(&self) -> Arc<Blocklist> {
+        self.blocklist.read().unwrap().clone()
+    }
+
+    // the response to send if the query is for a blocked name
+    pub fn check(&self, query: &Packet) -> Option<Packet> {
+        let question = query.questions.first()?;
+        match self.blocklist().is_blocked(&question.name) {
+            true => Some(self.respond(query)),
+            false => None,
+        }
+    }
+
+    pub fn respond(&self, query: &Packet) -> Packet {
+        let mut header = query.header.clone();
+        header.response = true;
+        header.can_recurse = true;
+        header.is_authoritative = false;
+        let mut response = Packet::new(header);
+        response.questions = query.questions.clone();
+        response.edns = query.edns.clone();
+        if let Some(edns) = &mut response.edns {
+            edns.set_option(EdnsOption::ExtendedError(ExtendedError::new(
+                InfoCode::Blocked,
+                "",
+            )));
+        }
+
+        let (v4, v6) = match self.mode {
+            Mode::Nxdomain => {
+                response.header.resp_code = 3;
+                self.add_soa(&mut response);
+                return response;
+            }
+            Mode::NullAddress => (Some(Ipv4Addr::UNSPECIFIED), Some(Ipv6Addr::UNSPECIFIED)),
+            Mode::Sinkhole(v4, v6) => (v4, v6),
+        };
+        let Some(question) = query.questions.first() else {
+            return response;
+        };
+        // anything other than an address query gets no data
+        let data = match question.r#type {
+            RRType::A => v4.map(|address| address.octets().to_vec()),
+            RRType::AAAA => v6.map(|address| address.octets().to_vec()),
+            _ => None,
+        };
+        match data {
+            Some(data) => response.answers.push(Answer::new(
+                &question.name,
+                question.r#type,
+                question.class,
+                self.ttl,
+                data,
+            )),
+            None => self.add_soa(&mut response),
+        }
+        response
+    }
+
+    // negative answers need an SOA in the authority section to be cached,
+    // RFC 2308 section 5, so one is made up with the name as its zone and
+    // the filter's TTL as both its own and the negative TTL
+    fn add_soa(&self, response: &mut Packet) {
+        let Some(question) = response.questions.first() else {
+            return;
+        };
+        let mut data = name::to_wire(SOA_PRIMARY);
+        data.extend(name::to_wire(SOA_MAILBOX));
+        for value in [1, 3600, 600, 86400, self.ttl] {
+            data.extend(u32::to_be_bytes(value));
+        }
+        let soa = Answer::new(&question.name, RRType::SOA, question.class, self.ttl, data);
+        response.authorities.push(soa);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::class::RRClass;
+    use crate::client;
+    use crate::edns::Edns;
+
+    const HOSTS: &str = "# a hosts file
+127.0.0.1 localhost
+0.0.0.0 ads.example.com tracker.example.net # trailing comment
+::1 ip6-localhost
+";
+
+    const ADBLOCK: &str = "[Adblock Plus 2.0]
+! comment
+||doubleclick.example^
+@@||good.doubleclick.example^
+||popups.example^$third-party
+";
+
+    const DOMAINS: &str = "malware.example
+*.phishing.example
+not a domain
+";
+
+    #[test]
+    fn test_load_formats() {
+        let mut blocklist = Blocklist::new();
+        assert_eq!(blocklist.load(HOSTS, false), 0);
+        assert_eq!(blocklist.load(ADBLOCK, false), 1);
+        assert_eq!(blocklist.load(DOMAINS, false), 1);
+        assert_eq!(blocklist.blocked_len(), 5);
+        assert_eq!(blocklist.allowed_len(), 1);
+
+        assert!(blocklist.is_blocked("ads.example.com"));
+        assert!(blocklist.is_blocked("x.y.ADS.example.com."));
+        assert!(!blocklist.is_blocked("example.com"));
+        assert!(!blocklist.is_blocked("localhost"));
+        assert!(blocklist.is_blocked("doubleclick.example"));
+        assert!(!blocklist.is_blocked("good.doubleclick.example"));
+        assert!(!blocklist.is_blocked("www.good.doubleclick.example"));
+        assert!(!blocklist.is_blocked("popups.example"));
+        assert!(blocklist.is_blocked("www.phishing.example"));
+        // a wildcard doesn't cover the name itself
+        assert!(!blocklist.is_blocked("phishing.example"));
+        assert!(blocklist.is_blocked("malware.example"));
+    }
+
+    #[test]
+    fn test_closest_entry_wins() {
+        let mut blocklist = Blocklist::new();
+        blocklist.allow("example.com");
+        blocklist.block("ads.example.com");
+        blocklist.block("both.example.com");
+        blocklist.allow("both.example.com");
+        assert!(!blocklist.is_blocked("www.example.com"));
+        assert!(blocklist.is_blocked("ads.example.com"));
+        assert!(!blocklist.is_blocked("both.example.com"));
+
+        blocklist.block_below("example.net");
+        blocklist.allow("ok.example.net");
+        assert!(!blocklist.is_blocked("example.net"));
+        assert!(blocklist.is_blocked("a.b.example.net"));
+        assert!(!blocklist.is_blocked("ok.example.net"));
+        // an exact entry still blocks the name under a wildcard of its own
+        blocklist.block("example.net");
+        assert!(blocklist.is_blocked("example.net"));
+    }
+
+    fn answer(filter: &Filter, name: &str, r#type: RRType) -> Option<Packet> {
+        filter.check(&client::build_query(name, r#type, RRClass::IN))
+    }
+
+    #[test]
+    fn test_modes() {
+        let dir = std::env::temp_dir().join(format!("dns-rs-blocklist-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let block = dir.join("block.txt");
+        fs::write(&block, "ads.example.com\n").unwrap();
+
+        let mut filter = Filter::new(
+            Mode::Nxdomain,
+            vec![Source {
+                path: block.clone(),
+                allow: false,
+            }],
+        );
+        filter.reload().unwrap();
+        assert!(answer(&filter, "www.example.com", RRType::A).is_none());
+        let response = answer(&filter, "ads.example.com", RRType::A).unwrap();
+        assert_eq!(response.rcode(), 3);
+        // no EDNS in the query, so nowhere to say why
+        assert!(response.edns.is_none());
+        let soa = &response.authorities[0];
+        assert_eq!(soa.r#type, RRType::SOA);
+        assert_eq!(soa.name, "ads.example.com");
+        assert_eq!(
+            crate::rdata::to_presentation(RRType::SOA, &soa.data),
+            "localhost. nobody.invalid. 1 3600 600 86400 60"
+        );
+
+        filter.mode = Mode::NullAddress;
+        let response = answer(&filter, "ads.example.com", RRType::AAAA).unwrap();
+        assert_eq!(response.answers[0].data, vec![0; 16]);
+        assert_eq!(response.answers[0].ttl, DEFAULT_TTL);
+        let mut query = client::build_query("ads.example.com", RRType::A, RRClass::IN);
+        query.edns = Some(Edns::new(1232));
+        let response = filter.check(&query).unwrap();
+        let edns = response.edns.unwrap();
+        assert_eq!(edns.extended_errors()[0].info_code(), InfoCode::Blocked);
+
+        filter.mode = Mode::Sinkhole(Some(Ipv4Addr::new(192, 0, 2, 80)), None);
+        let response = answer(&filter, "ads.example.com", RRType::A).unwrap();
+        assert_eq!(response.answers[0].data, vec![192, 0, 2, 80]);
+        let response = answer(&filter, "ads.example.com", RRType::AAAA).unwrap();
+        assert!(response.answers.is_empty());
+        assert_eq!(response.rcode(), 0);
+        assert_eq!(response.authorities[0].r#type, RRType::SOA);
+
+        // reloading picks up the changed file, and a missing one leaves the
+        // lists as they were
+        fs::write(&block, "tracker.example.net\n").unwrap();
+        filter.reload().unwrap();
+        assert!(answer(&filter, "ads.example.com", RRType::A).is_none());
+        assert!(answer(&filter, "tracker.example.net", RRType::A).is_some());
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(filter.reload().is_err());
+        assert!(answer(&filter, "tracker.example.net", RRType::A).is_some());
+    }
+}
@@ -16,7 +16,7 @@ impl<'a> BufReader<'a> {
         buf
     }
 
-    pub fn goto(&mut self, pos: usize) -> () {
+    pub fn goto(&mut self, pos: usize) {
         self.pos = pos;
     }
 }
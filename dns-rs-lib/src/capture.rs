@@ -0,0 +1,466 @@
+use crate::pcap::{
+    Frame, LINKTYPE_ETHERNET, LINKTYPE_IPV4, LINKTYPE_IPV6, LINKTYPE_LINUX_SLL, LINKTYPE_NULL,
+    LINKTYPE_RAW,
+};
+use crate::tcp;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88a8;
+const PROTOCOL_TCP: u8 = 6;
+const PROTOCOL_UDP: u8 = 17;
+
+const TCP_FIN: u8 = 0b00000001;
+const TCP_SYN: u8 = 0b00000010;
+const TCP_RST: u8 = 0b00000100;
+// segments that arrive ahead of a gap are held until it is filled, up to a
+// point
+const MAX_PENDING_SEGMENTS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Transport {
+    Udp,
+    Tcp,
+}
+
+// a DNS message found in a capture
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub timestamp: Duration,
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    pub transport: Transport,
+    pub data: Vec<u8>,
+}
+
+// pulls DNS messages out of captured frames, reassembling TCP streams so
+// messages split across segments (or sharing one) come out whole
+pub struct Dissector {
+    pub ports: Vec<u16>,
+    streams: HashMap<(SocketAddr, SocketAddr), Stream>,
+}
+
+#[derive(Default)]
+struct Stream {
+    next_seq: Option<u32>,
+    buffer: Vec<u8>,
+    pending: BTreeMap<u32, Vec<u8>>,
+}
+
+struct Ip<'a> {
+    source: IpAddr,
+    destination: IpAddr,
+    protocol: u8,
+    payload: &'a [u8],
+}
+
+impl Default for Dissector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Dissector {
+    pub fn new() -> Self {
+        Self {
+            ports: vec![tcp::PORT],
+            streams: HashMap::new(),
+        }
+    }
+
+    // anything that isn't DNS over UDP or TCP on one of our ports is skipped
+    pub fn dissect(&mut self, frame: &Frame) -> Vec<Message> {
+        let Some(ip) = link_payload(frame.link_type, &frame.data).and_then(parse_ip) else {
+            return Vec::new();
+        };
+        match ip.protocol {
+            PROTOCOL_UDP => self.udp(frame.timestamp, &ip).into_iter().collect(),
+            PROTOCOL_TCP => self.tcp(frame.timestamp, &ip),
+            _ => Vec::new(),
+        }
+    }
+
+    fn is_dns(&self, source: u16, destination: u16) -> bool {
+        self.ports.contains(&source) || self.ports.contains(&destination)
+    }
+
+    fn udp(&self, timestamp: Duration, ip: &Ip) -> Option<Message> {
+        let header = ip.payload.get(..8)?;
+        let source_port = u16::from_be_bytes([header[0], header[1]]);
+        let destination_port = u16::from_be_bytes([header[2], header[3]]);
+        let len = u16::from_be_bytes([header[4], header[5]]) as usize;
+        if !self.is_dns(source_port, destination_port) {
+            return None;
+        }
+        let data = ip.payload.get(8..len.max(8))?;
+        Some(Message {
+            timestamp,
+            source: SocketAddr::new(ip.source, source_port),
+            destination: SocketAddr::new(ip.destination, destination_port),
+            transport: Transport::Udp,
+            data: data.to_vec(),
+        })
+    }
+
+    fn tcp(&mut self, timestamp: Duration, ip: &Ip) -> Vec<Message> {
+        let Some(header) = ip.payload.get(..20) else {
+            return Vec::new();
+        };
+        let source_port = u16::from_be_bytes([header[0], header[1]]);
+        let destination_port = u16::from_be_bytes([header[2], header[3]]);
+        if !self.is_dns(source_port, destination_port) {
+            return Vec::new();
+        }
+        let seq = u32::from_be_bytes(header[4..8].try_into().unwrap());
+        let data_offset = (header[12] >> 4) as usize * 4;
+        let flags = header[13];
+        let payload = ip.payload.get(data_offset..).unwrap_or(&[]);
+
+        let source = SocketAddr::new(ip.source, source_port);
+        let destination = SocketAddr::new(ip.destination, destination_port);
+        let key = (source, destination);
+        let stream = self.streams.entry(key).or_default();
+        if flags & TCP_SYN != 0 {
+            *stream = Stream {
+                next_seq: Some(seq.wrapping_add(1)),
+                ..Default::default()
+            };
+        }
+        stream.receive(seq, payload);
+
+        let messages = stream
+            .messages()
+            .into_iter()
+            .map(|data| Message {
+                timestamp,
+                source,
+                destination,
+                transport: Transport::Tcp,
+                data,
+            })
+            .collect();
+        if flags & (TCP_FIN | TCP_RST) != 0 {
+            self.streams.remove(&key);
+        }
+        messages
+    }
+}
+
+impl Stream {
+    fn receive(&mut self, seq: u32, payload: &[u8]) {
+        if payload.is_empty() {
+            return;
+        }
+        // a capture that starts part way through a connection is picked up
+        // from the first segment seen
+        let next = *self.next_seq.get_or_insert(seq);
+        // sequence numbers wrap, so compare them by their distance
+        let ahead = seq.wrapping_sub(next) as i32;
+        if ahead > 0 {
+            if self.pending.len() < MAX_PENDING_SEGMENTS {
+                self.pending.insert(seq, payload.to_vec());
+            }
+            return;
+        }
+        // skip anything already received, which covers retransmissions
+        let already = ahead.unsigned_abs() as usize;
+        if already >= payload.len() {
+            return;
+        }
+        self.append(&payload[already..]);
+
+        while let Some(next) = self.next_seq {
+            let Some((&seq, _)) = self
+                .pending
+                .iter()
+                .find(|(&seq, _)| seq.wrapping_sub(next) as i32 <= 0)
+            else {
+                break;
+            };
+            let segment = self.pending.remove(&seq).unwrap();
+            let already = next.wrapping_sub(seq) as usize;
+            if already < segment.len() {
+                self.append(&segment[already..]);
+            }
+        }
+    }
+
+    fn append(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+        self.next_seq = self
+            .next_seq
+            .map(|next| next.wrapping_add(data.len() as u32));
+    }
+
+    // whole length-prefixed messages, RFC 1035 section 4.2.2
+    fn messages(&mut self) -> Vec<Vec<u8>> {
+        let mut messages = Vec::new();
+        while self.buffer.len() >= 2 {
+            let len = u16::from_be_bytes([self.buffer[0], self.buffer[1]]) as usize;
+            if self.buffer.len() < 2 + len {
+                break;
+            }
+            messages.push(self.buffer[2..2 + len].to_vec());
+            self.buffer.drain(..2 + len);
+        }
+        messages
+    }
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Transport::Udp => write!(f, "UDP"),
+            Transport::Tcp => write!(f, "TCP"),
+        }
+    }
+}
+
+// the IP packet inside a frame
+fn link_payload(link_type: u32, data: &[u8]) -> Option<&[u8]> {
+    match link_type {
+        LINKTYPE_ETHERNET => {
+            let mut ethertype = u16::from_be_bytes(data.get(12..14)?.try_into().ok()?);
+            let mut pos = 14;
+            // 802.1Q tags, possibly stacked
+            while ethertype == ETHERTYPE_VLAN || ethertype == ETHERTYPE_QINQ {
+                ethertype = u16::from_be_bytes(data.get(pos + 2..pos + 4)?.try_into().ok()?);
+                pos += 4;
+            }
+            match ethertype {
+                ETHERTYPE_IPV4 | ETHERTYPE_IPV6 => data.get(pos..),
+                _ => None,
+            }
+        }
+        // the cooked header puts the protocol at the end
+        LINKTYPE_LINUX_SLL => match u16::from_be_bytes(data.get(14..16)?.try_into().ok()?) {
+            ETHERTYPE_IPV4 | ETHERTYPE_IPV6 => data.get(16..),
+            _ => None,
+        },
+        // an address family in the byte order of the capturing host
+        LINKTYPE_NULL => {
+            let family: [u8; 4] = data.get(..4)?.try_into().ok()?;
+            let family = u32::from_le_bytes(family).min(u32::from_be_bytes(family));
+            match family {
+                2 | 24 | 28 | 30 => data.get(4..),
+                _ => None,
+            }
+        }
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => Some(data),
+        _ => None,
+    }
+}
+
+// fragments are skipped rather than reassembled, DNS over UDP is rarely
+// fragmented and TCP never is
+fn parse_ip(data: &[u8]) -> Option<Ip<'_>> {
+    match data.first()? >> 4 {
+        4 => {
+            let header_len = (data[0] & 0x0f) as usize * 4;
+            let total_len = u16::from_be_bytes(data.get(2..4)?.try_into().ok()?) as usize;
+            let fragment = u16::from_be_bytes(data.get(6..8)?.try_into().ok()?);
+            let more_fragments = fragment & 0x2000 != 0;
+            if more_fragments || fragment & 0x1fff != 0 {
+                return None;
+            }
+            let source: [u8; 4] = data.get(12..16)?.try_into().ok()?;
+            let destination: [u8; 4] = data.get(16..20)?.try_into().ok()?;
+            Some(Ip {
+                source: Ipv4Addr::from(source).into(),
+                destination: Ipv4Addr::from(destination).into(),
+                protocol: data[9],
+                // trailing Ethernet padding isn't part of the packet
+                payload: data.get(header_len..total_len.min(data.len()))?,
+            })
+        }
+        6 => {
+            let payload_len = u16::from_be_bytes(data.get(4..6)?.try_into().ok()?) as usize;
+            let source: [u8; 16] = data.get(8..24)?.try_into().ok()?;
+            let destination: [u8; 16] = data.get(24..40)?.try_into().ok()?;
+            let mut protocol = data[6];
+            let mut payload = data.get(40..(40 + payload_len).min(data.len()))?;
+            loop {
+                let header_len = match protocol {
+                    // hop-by-hop, routing and destination options
+                    0 | 43 | 60 => (*payload.get(1)? as usize + 1) * 8,
+                    // authentication header
+                    51 => (*payload.get(1)? as usize + 2) * 4,
+                    // fragment
+                    44 => return None,
+                    _ => break,
+                };
+                protocol = *payload.first()?;
+                payload = payload.get(header_len..)?;
+            }
+            Some(Ip {
+                source: Ipv6Addr::from(source).into(),
+                destination: Ipv6Addr::from(destination).into(),
+                protocol,
+                payload,
+            })
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT: &str = "192.0.2.1:40000";
+    const SERVER: &str = "192.0.2.53:53";
+
+    fn ipv4(
+        protocol: u8,
+        source: SocketAddr,
+        destination: SocketAddr,
+        transport: &[u8],
+    ) -> Vec<u8> {
+        let (IpAddr::V4(source), IpAddr::V4(destination)) = (source.ip(), destination.ip()) else {
+            panic!("not IPv4");
+        };
+        let mut packet = vec![0x45, 0];
+        packet.extend(((20 + transport.len()) as u16).to_be_bytes());
+        packet.extend([0, 0, 0x40, 0, 64, protocol, 0, 0]);
+        packet.extend(source.octets());
+        packet.extend(destination.octets());
+        packet.extend_from_slice(transport);
+        packet
+    }
+
+    fn ethernet(ethertype: u16, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0; 12];
+        frame.extend(ethertype.to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    fn udp(source: SocketAddr, destination: SocketAddr, data: &[u8]) -> Vec<u8> {
+        let mut datagram = source.port().to_be_bytes().to_vec();
+        datagram.extend(destination.port().to_be_bytes());
+        datagram.extend(((8 + data.len()) as u16).to_be_bytes());
+        datagram.extend([0, 0]);
+        datagram.extend_from_slice(data);
+        datagram
+    }
+
+    fn tcp_segment(
+        source: SocketAddr,
+        destination: SocketAddr,
+        seq: u32,
+        flags: u8,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let mut segment = source.port().to_be_bytes().to_vec();
+        segment.extend(destination.port().to_be_bytes());
+        segment.extend(seq.to_be_bytes());
+        segment.extend([0, 0, 0, 0, 5 << 4, flags, 0xff, 0xff, 0, 0, 0, 0]);
+        segment.extend_from_slice(data);
+        segment
+    }
+
+    fn frame(link_type: u32, data: Vec<u8>) -> Frame {
+        Frame {
+            timestamp: Duration::from_secs(1),
+            link_type,
+            data,
+        }
+    }
+
+    #[test]
+    fn test_udp_over_ethernet() {
+        let (client, server) = (CLIENT.parse().unwrap(), SERVER.parse().unwrap());
+        let mut packet = ipv4(PROTOCOL_UDP, client, server, &udp(client, server, b"query"));
+        // padding up to the Ethernet minimum
+        packet.extend([0; 6]);
+        let mut dissector = Dissector::new();
+
+        let messages =
+            dissector.dissect(&frame(LINKTYPE_ETHERNET, ethernet(ETHERTYPE_IPV4, &packet)));
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].source, client);
+        assert_eq!(messages[0].destination, server);
+        assert_eq!(messages[0].transport, Transport::Udp);
+        assert_eq!(messages[0].data, b"query");
+
+        // the same packet behind a VLAN tag
+        let mut tagged = vec![0; 12];
+        tagged.extend(ETHERTYPE_VLAN.to_be_bytes());
+        tagged.extend([0, 10]);
+        tagged.extend(ETHERTYPE_IPV4.to_be_bytes());
+        tagged.extend(&packet);
+        assert_eq!(
+            dissector.dissect(&frame(LINKTYPE_ETHERNET, tagged)),
+            messages
+        );
+
+        let other: SocketAddr = "192.0.2.53:80".parse().unwrap();
+        let packet = ipv4(PROTOCOL_UDP, client, other, &udp(client, other, b"http"));
+        assert!(dissector.dissect(&frame(LINKTYPE_RAW, packet)).is_empty());
+    }
+
+    #[test]
+    fn test_udp_over_ipv6() {
+        let client: SocketAddr = "[2001:db8::1]:40000".parse().unwrap();
+        let server: SocketAddr = "[2001:db8::53]:53".parse().unwrap();
+        let datagram = udp(client, server, b"query");
+        // with a destination options header before the UDP header
+        let mut packet = vec![0x60, 0, 0, 0];
+        packet.extend(((8 + datagram.len()) as u16).to_be_bytes());
+        packet.extend([60, 64]);
+        let (IpAddr::V6(source), IpAddr::V6(destination)) = (client.ip(), server.ip()) else {
+            unreachable!();
+        };
+        packet.extend(source.octets());
+        packet.extend(destination.octets());
+        packet.extend([PROTOCOL_UDP, 0, 1, 4, 0, 0, 0, 0]);
+        packet.extend(datagram);
+
+        let messages = Dissector::new().dissect(&frame(LINKTYPE_IPV6, packet));
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].source, client);
+        assert_eq!(messages[0].data, b"query");
+    }
+
+    #[test]
+    fn test_tcp_reassembly() {
+        let (client, server) = (CLIENT.parse().unwrap(), SERVER.parse().unwrap());
+        let mut dissector = Dissector::new();
+        let mut send = |seq: u32, flags: u8, data: &[u8]| {
+            let segment = tcp_segment(client, server, seq, flags, data);
+            dissector.dissect(&frame(
+                LINKTYPE_RAW,
+                ipv4(PROTOCOL_TCP, client, server, &segment),
+            ))
+        };
+
+        assert!(send(1000, TCP_SYN, &[]).is_empty());
+        // the first message split in two with the second half arriving
+        // first, then a retransmission, then two messages in one segment
+        assert!(send(1005, 0, b"llo").is_empty());
+        let messages = send(1001, 0, &[0, 5, b'h', b'e']);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].data, b"hello");
+        assert_eq!(messages[0].transport, Transport::Tcp);
+        assert!(send(1001, 0, &[0, 5, b'h', b'e']).is_empty());
+
+        let messages = send(1008, TCP_FIN, &[0, 1, b'a', 0, 2, b'b', b'c']);
+        let data: Vec<&[u8]> = messages.iter().map(|message| &message.data[..]).collect();
+        assert_eq!(data, vec![&b"a"[..], &b"bc"[..]]);
+    }
+
+    #[test]
+    fn test_fragments_are_skipped() {
+        let (client, server) = (CLIENT.parse().unwrap(), SERVER.parse().unwrap());
+        let mut packet = ipv4(PROTOCOL_UDP, client, server, &udp(client, server, b"query"));
+        packet[6] = 0x20;
+        assert!(Dissector::new()
+            .dissect(&frame(LINKTYPE_RAW, packet))
+            .is_empty());
+    }
+}
@@ -0,0 +1,114 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Cidr {
+    pub address: IpAddr,
+    pub prefix: u8,
+}
+
+impl Cidr {
+    // host bits past the prefix are cleared, prefixes longer than the address
+    // are clamped
+    pub fn new(address: IpAddr, prefix: u8) -> Self {
+        let prefix = prefix.min(max_prefix(address));
+        Self {
+            address: truncate(address, prefix),
+            prefix,
+        }
+    }
+
+    pub fn contains(&self, address: IpAddr) -> bool {
+        self.address.is_ipv4() == address.is_ipv4()
+            && truncate(address, self.prefix) == self.address
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = match s.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (s, None),
+        };
+        let address: IpAddr = address
+            .parse()
+            .map_err(|_| format!("invalid address {:?}", address))?;
+        let prefix = match prefix {
+            Some(prefix) => match prefix.parse::<u8>() {
+                Ok(prefix) if prefix <= max_prefix(address) => prefix,
+                _ => return Err(format!("invalid prefix length {:?}", prefix)),
+            },
+            None => max_prefix(address),
+        };
+        Ok(Self::new(address, prefix))
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix)
+    }
+}
+
+pub fn max_prefix(address: IpAddr) -> u8 {
+    match address {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+pub fn truncate(address: IpAddr, prefix: u8) -> IpAddr {
+    match address {
+        IpAddr::V4(address) => {
+            let mask = u32::MAX
+                .checked_shl(32 - prefix.min(32) as u32)
+                .unwrap_or(0);
+            IpAddr::V4(Ipv4Addr::from(u32::from(address) & mask))
+        }
+        IpAddr::V6(address) => {
+            let mask = u128::MAX
+                .checked_shl(128 - prefix.min(128) as u32)
+                .unwrap_or(0);
+            IpAddr::V6(Ipv6Addr::from(u128::from(address) & mask))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate() {
+        let ip: IpAddr = "192.0.2.130".parse().unwrap();
+        assert_eq!(truncate(ip, 24), "192.0.2.0".parse::<IpAddr>().unwrap());
+        assert_eq!(truncate(ip, 25), "192.0.2.128".parse::<IpAddr>().unwrap());
+        assert_eq!(truncate(ip, 0), "0.0.0.0".parse::<IpAddr>().unwrap());
+        assert_eq!(truncate(ip, 32), ip);
+
+        let ip: IpAddr = "2001:db8:1234:5678::1".parse().unwrap();
+        assert_eq!(
+            truncate(ip, 48),
+            "2001:db8:1234::".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_contains() {
+        let cidr: Cidr = "10.1.2.3/8".parse().unwrap();
+        assert_eq!(cidr.to_string(), "10.0.0.0/8");
+        assert!(cidr.contains("10.200.0.1".parse().unwrap()));
+        assert!(!cidr.contains("11.0.0.1".parse().unwrap()));
+        assert!(!cidr.contains("::a00:1".parse().unwrap()));
+
+        let host: Cidr = "2001:db8::1".parse().unwrap();
+        assert_eq!(host.prefix, 128);
+        assert!(host.contains("2001:db8::1".parse().unwrap()));
+
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("example.com/8".parse::<Cidr>().is_err());
+    }
+}
@@ -1,9 +1,13 @@
-#[derive(Debug, PartialEq)]
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u16)]
 pub enum RRClass {
     IN = 1,
     CH = 3,
     HS = 4,
-    UNKNOWN,
+    UNKNOWN(u16),
 }
 
 impl RRClass {
@@ -12,7 +16,44 @@ impl RRClass {
             1 => RRClass::IN,
             3 => RRClass::CH,
             4 => RRClass::HS,
-            _ => RRClass::UNKNOWN,
+            value => RRClass::UNKNOWN(value),
+        }
+    }
+
+    pub fn to_value(&self) -> u16 {
+        match self {
+            RRClass::IN => 1,
+            RRClass::CH => 3,
+            RRClass::HS => 4,
+            RRClass::UNKNOWN(value) => *value,
+        }
+    }
+}
+
+impl fmt::Display for RRClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RRClass::UNKNOWN(value) => write!(f, "CLASS{}", value),
+            class => write!(f, "{:?}", class),
         }
     }
 }
+
+impl FromStr for RRClass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.to_ascii_uppercase();
+        let class = match name.as_str() {
+            "IN" => RRClass::IN,
+            "CH" => RRClass::CH,
+            "HS" => RRClass::HS,
+            _ => name
+                .strip_prefix("CLASS")
+                .and_then(|value| value.parse().ok())
+                .map(RRClass::from_value)
+                .ok_or_else(|| format!("unknown class {:?}", s))?,
+        };
+        Ok(class)
+    }
+}
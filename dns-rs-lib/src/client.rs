@@ -0,0 +1,977 @@
+use crate::answer::Answer;
+use crate::class::RRClass;
+use crate::cookie::{self, ClientCookies};
+use crate::dnstap::{Protocol, Tap};
+#[cfg(feature = "https")]
+use crate::doh::Https;
+#[cfg(feature = "quic")]
+use crate::doq::Quic;
+#[cfg(feature = "tls")]
+use crate::edns::QUERY_PADDING_BLOCK;
+use crate::edns::{Edns, EdnsOption};
+use crate::header::Header;
+use crate::metrics::Metrics;
+use crate::opcode::OpCode;
+use crate::packet::Packet;
+use crate::question::Question;
+use crate::r#type::RRType;
+use crate::rcode::RCode;
+use crate::tcp::{self, Deadline};
+#[cfg(feature = "tls")]
+use crate::tls::{ClientStream, ClientTls};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+pub const DEFAULT_RETRIES: u32 = 2;
+// a transfer can take much longer than a query, but not forever, and a
+// primary that never sends the closing SOA can't fill up memory
+pub const DEFAULT_TRANSFER_TIMEOUT: Duration = Duration::from_secs(300);
+pub const DEFAULT_MAX_TRANSFER_RECORDS: usize = 1_000_000;
+// how long a TLS connection is kept for the next query, the same as the
+// server keeps one open
+#[cfg(feature = "tls")]
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// a blocking stub client talking to a single server
+#[derive(Debug, Clone)]
+pub struct Client {
+    pub server: SocketAddr,
+    pub tcp: bool,
+    pub timeout: Duration,
+    pub retries: u32,
+    // for the whole of a zone transfer, on top of timeout for each read
+    pub transfer_timeout: Duration,
+    pub max_transfer_records: usize,
+    // logs every query sent and response received
+    pub dnstap: Option<Tap>,
+    // upstream latency, in-flight queries and failures
+    pub metrics: Option<Arc<Metrics>>,
+    // RFC 7873 cookies for queries with EDNS over UDP and TCP, shared by
+    // clones so the server cookies one learns go with the others' queries
+    pub cookies: Option<Arc<Mutex<ClientCookies>>>,
+    // DNS over TLS, RFC 7858, instead of UDP and TCP
+    #[cfg(feature = "tls")]
+    pub tls: Option<ClientTls>,
+    // DNS over HTTPS, RFC 8484, instead of any of them
+    #[cfg(feature = "https")]
+    pub https: Option<Https>,
+    // DNS over QUIC, RFC 9250, likewise
+    #[cfg(feature = "quic")]
+    pub quic: Option<Quic>,
+    #[cfg(feature = "tls")]
+    pub idle_timeout: Duration,
+    // shared by clones, so they all reuse the one connection
+    #[cfg(feature = "tls")]
+    connection: Arc<Mutex<Option<Connection>>>,
+}
+
+#[cfg(feature = "tls")]
+#[derive(Debug)]
+struct Connection {
+    stream: ClientStream,
+    last_used: Instant,
+}
+
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub packet: Packet,
+    pub size: usize,
+    pub over_tcp: bool,
+    pub elapsed: Duration,
+}
+
+impl Client {
+    pub fn new(server: SocketAddr) -> Self {
+        Self {
+            server,
+            tcp: false,
+            timeout: DEFAULT_TIMEOUT,
+            retries: DEFAULT_RETRIES,
+            transfer_timeout: DEFAULT_TRANSFER_TIMEOUT,
+            max_transfer_records: DEFAULT_MAX_TRANSFER_RECORDS,
+            dnstap: None,
+            metrics: None,
+            cookies: Some(Arc::new(Mutex::new(ClientCookies::new(
+                cookie::random_secret(),
+            )))),
+            #[cfg(feature = "tls")]
+            tls: None,
+            #[cfg(feature = "https")]
+            https: None,
+            #[cfg(feature = "quic")]
+            quic: None,
+            #[cfg(feature = "tls")]
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            #[cfg(feature = "tls")]
+            connection: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    // DNS over TLS to the server, which is usually on tcp::TLS_PORT
+    #[cfg(feature = "tls")]
+    pub fn with_tls(server: SocketAddr, tls: ClientTls) -> Self {
+        Self {
+            tls: Some(tls),
+            ..Self::new(server)
+        }
+    }
+
+    // DNS over HTTPS to the server, which is usually on doh::PORT
+    #[cfg(feature = "https")]
+    pub fn with_https(server: SocketAddr, https: Https) -> Self {
+        Self {
+            https: Some(https),
+            ..Self::new(server)
+        }
+    }
+
+    // DNS over QUIC to the server, which is usually on doq::PORT
+    #[cfg(feature = "quic")]
+    pub fn with_quic(server: SocketAddr, quic: Quic) -> Self {
+        Self {
+            quic: Some(quic),
+            ..Self::new(server)
+        }
+    }
+
+    pub fn query(&self, query: &Packet) -> io::Result<Response> {
+        let started = Instant::now();
+        let timer = self
+            .metrics
+            .as_ref()
+            .map(|metrics| metrics.upstream(self.server));
+        let (response, over_tcp) = self.exchange(query)?;
+        let packet = parse(&response)?;
+        if let Some(timer) = timer {
+            timer.observe();
+        }
+        Ok(Response {
+            packet,
+            size: response.len(),
+            over_tcp,
+            elapsed: started.elapsed(),
+        })
+    }
+
+    // over QUIC, HTTPS or TLS if the client has one of them, in that order
+    fn exchange(&self, query: &Packet) -> io::Result<(Vec<u8>, bool)> {
+        #[cfg(feature = "quic")]
+        if let Some(quic) = &self.quic {
+            return Ok((self.exchange_quic(quic, &padded(query))?, false));
+        }
+        #[cfg(feature = "https")]
+        if let Some(https) = &self.https {
+            return Ok((self.exchange_https(https, &padded(query))?, true));
+        }
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &self.tls {
+            let query = padded(query);
+            return Ok((self.exchange_tls(tls, &query, &query.to_bytes())?, true));
+        }
+        self.exchange_plain(query)
+    }
+
+    // a zone transfer, RFC 5936, which starts and ends with the zone's SOA
+    pub fn transfer(&self, zone: &str) -> io::Result<Vec<Answer>> {
+        let mut query = build_query(zone, RRType::AXFR, RRClass::IN);
+        query.header.should_recurse = false;
+        let mut stream = TcpStream::connect_timeout(&self.server, self.timeout)?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        tcp::write_message(&mut stream, &query.to_bytes())?;
+
+        let mut reader = Deadline {
+            stream: &stream,
+            timeout: self.timeout,
+            deadline: Instant::now() + self.transfer_timeout,
+        };
+        let mut records: Vec<Answer> = Vec::new();
+        loop {
+            let response = parse(&tcp::read_message(&mut reader)?)?;
+            if response.header.identifier != query.header.identifier {
+                return Err(invalid("transfer response has the wrong ID"));
+            }
+            if response.rcode() != 0 {
+                return Err(invalid(&format!(
+                    "transfer refused with {}",
+                    RCode::from_value(response.rcode())
+                )));
+            }
+            for record in response.answers {
+                let is_soa = record.r#type == RRType::SOA;
+                if records.is_empty() && !is_soa {
+                    return Err(invalid("transfer doesn't start with an SOA"));
+                }
+                if is_soa && !records.is_empty() {
+                    return Ok(records);
+                }
+                if records.len() >= self.max_transfer_records {
+                    return Err(invalid("transfer has too many records"));
+                }
+                records.push(record);
+            }
+        }
+    }
+
+    // tells a secondary the zone has changed, RFC 1996 section 3.7: an
+    // authoritative NOTIFY for the zone's SOA, which it acknowledges before
+    // checking the zone's serial for itself
+    pub fn notify(&self, zone: &str) -> io::Result<()> {
+        let mut query = build_query(zone, RRType::SOA, RRClass::IN);
+        query.header.should_recurse = false;
+        query.header.is_authoritative = true;
+        query.header.op_code = OpCode::NOTIFY as u8;
+        match self.query(&query)?.packet.rcode() {
+            0 => Ok(()),
+            rcode => Err(invalid(&format!(
+                "notify refused with {}",
+                RCode::from_value(rcode)
+            ))),
+        }
+    }
+
+    // UDP, or TCP when asked to or the answer is truncated, RFC 7766
+    // section 5. A BADCOOKIE response brings a server cookie to try again
+    // with, once, RFC 7873 section 5.3
+    fn exchange_plain(&self, query: &Packet) -> io::Result<(Vec<u8>, bool)> {
+        let mut retried = false;
+        loop {
+            let (query, client_ip) = self.with_cookie(query)?;
+            let message = query.to_bytes();
+            let (response, over_tcp) = match self.tcp {
+                true => (self.exchange_tcp(&query, &message)?, true),
+                false => {
+                    let response = self.exchange_udp(&query, &message)?;
+                    match parse(&response)?.header.truncated {
+                        true => (self.exchange_tcp(&query, &message)?, true),
+                        false => (response, false),
+                    }
+                }
+            };
+            let packet = parse(&response)?;
+            let received = packet.edns.as_ref().and_then(Edns::cookie);
+            if let (Some(cookies), Some(client_ip), Some(received)) =
+                (&self.cookies, client_ip, received)
+            {
+                cookies
+                    .lock()
+                    .unwrap()
+                    .remember(client_ip, self.server.ip(), received);
+                if packet.rcode() == RCode::BADCOOKIE as u16 && !retried {
+                    retried = true;
+                    continue;
+                }
+            }
+            return Ok((response, over_tcp));
+        }
+    }
+
+    // RFC 7873 section 5.1: the client cookie for this server and the
+    // address queries go to it from, with the server cookie it last sent.
+    // Queries without EDNS have nowhere to put one, and one the caller set
+    // is left alone
+    fn with_cookie(&self, query: &Packet) -> io::Result<(Packet, Option<IpAddr>)> {
+        let mut query = query.clone();
+        let (Some(cookies), Some(edns)) = (&self.cookies, query.edns.as_mut()) else {
+            return Ok((query, None));
+        };
+        if edns.cookie().is_some() {
+            return Ok((query, None));
+        }
+        let socket = UdpSocket::bind(unspecified(self.server))?;
+        socket.connect(self.server)?;
+        let client_ip = socket.local_addr()?.ip();
+        let cookie = cookies
+            .lock()
+            .unwrap()
+            .cookie_for(client_ip, self.server.ip());
+        edns.set_option(EdnsOption::Cookie(cookie));
+        Ok((query, Some(client_ip)))
+    }
+
+    fn exchange_udp(&self, query: &Packet, message: &[u8]) -> io::Result<Vec<u8>> {
+        let socket = UdpSocket::bind(unspecified(self.server))?;
+        socket.connect(self.server)?;
+        let local = socket.local_addr()?;
+
+        let mut buf = vec![0u8; u16::MAX as usize];
+        for _ in 0..=self.retries {
+            let sent = SystemTime::now();
+            socket.send(message)?;
+            if let Some(tap) = &self.dnstap {
+                tap.query(Protocol::Udp, local, self.server, sent, message);
+            }
+            let deadline = Instant::now() + self.timeout;
+            // keep listening until the deadline, ignoring anything that isn't
+            // an answer to this query
+            while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+                if remaining.is_zero() {
+                    break;
+                }
+                socket.set_read_timeout(Some(remaining))?;
+                let len = match socket.recv(&mut buf) {
+                    Ok(len) => len,
+                    Err(e) if is_timeout(&e) => break,
+                    Err(e) => return Err(e),
+                };
+                if is_response_to(query, &buf[..len]) {
+                    if let Some(tap) = &self.dnstap {
+                        tap.response(Protocol::Udp, local, self.server, sent, &buf[..len]);
+                    }
+                    return Ok(buf[..len].to_vec());
+                }
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "no response from server",
+        ))
+    }
+
+    fn exchange_tcp(&self, query: &Packet, message: &[u8]) -> io::Result<Vec<u8>> {
+        let mut attempt = 0;
+        loop {
+            match self.exchange_tcp_once(query, message) {
+                Err(e) if is_timeout(&e) && attempt < self.retries => attempt += 1,
+                result => return result,
+            }
+        }
+    }
+
+    fn exchange_tcp_once(&self, query: &Packet, message: &[u8]) -> io::Result<Vec<u8>> {
+        let mut stream = TcpStream::connect_timeout(&self.server, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        let local = stream.local_addr()?;
+        let sent = SystemTime::now();
+        tcp::write_message(&mut stream, message)?;
+        if let Some(tap) = &self.dnstap {
+            tap.query(Protocol::Tcp, local, self.server, sent, message);
+        }
+        let response = tcp::read_message(&mut stream)?;
+        if let Some(tap) = &self.dnstap {
+            tap.response(Protocol::Tcp, local, self.server, sent, &response);
+        }
+        // there's only one query on the connection, so anything else is an
+        // error rather than something to skip
+        if !is_response_to(query, &response) {
+            return Err(invalid("response doesn't match the query"));
+        }
+        Ok(response)
+    }
+
+    // RFC 8484 section 4.1: the ID is zero, so that the same query always
+    // makes the same GET request, and put back in the response
+    #[cfg(feature = "https")]
+    fn exchange_https(&self, https: &Https, query: &Packet) -> io::Result<Vec<u8>> {
+        let identifier = query.header.identifier;
+        let mut query = query.clone();
+        query.header.identifier = 0;
+        let message = query.to_bytes();
+        let mut attempt = 0;
+        loop {
+            let sent = SystemTime::now();
+            let (response, local) = match https.exchange(self.server, &message, self.timeout) {
+                Err(e) if is_timeout(&e) && attempt < self.retries => {
+                    attempt += 1;
+                    continue;
+                }
+                result => result?,
+            };
+            if let Some(tap) = &self.dnstap {
+                tap.query(Protocol::Doh, local, self.server, sent, &message);
+                tap.response(Protocol::Doh, local, self.server, sent, &response);
+            }
+            if !is_response_to(&query, &response) {
+                return Err(invalid("response doesn't match the query"));
+            }
+            let mut response = response;
+            response[..2].copy_from_slice(&identifier.to_be_bytes());
+            return Ok(response);
+        }
+    }
+
+    // RFC 9250 section 4.2.1: the ID is zero on the wire, and put back in
+    // the response
+    #[cfg(feature = "quic")]
+    fn exchange_quic(&self, quic: &Quic, query: &Packet) -> io::Result<Vec<u8>> {
+        let identifier = query.header.identifier;
+        let mut query = query.clone();
+        query.header.identifier = 0;
+        let mut attempt = 0;
+        loop {
+            let sent = SystemTime::now();
+            let (response, local) = match quic.exchange(self.server, &query, self.timeout) {
+                Err(e) if is_timeout(&e) && attempt < self.retries => {
+                    attempt += 1;
+                    continue;
+                }
+                result => result?,
+            };
+            if let Some(tap) = &self.dnstap {
+                let message = query.to_bytes();
+                tap.query(Protocol::Doq, local, self.server, sent, &message);
+                tap.response(Protocol::Doq, local, self.server, sent, &response);
+            }
+            if !is_response_to(&query, &response) {
+                return Err(invalid("response doesn't match the query"));
+            }
+            let mut response = response;
+            response[..2].copy_from_slice(&identifier.to_be_bytes());
+            return Ok(response);
+        }
+    }
+
+    #[cfg(feature = "tls")]
+    fn exchange_tls(&self, tls: &ClientTls, query: &Packet, message: &[u8]) -> io::Result<Vec<u8>> {
+        let mut attempt = 0;
+        loop {
+            match self.exchange_tls_once(tls, query, message) {
+                Err(e) if is_timeout(&e) && attempt < self.retries => attempt += 1,
+                result => return result,
+            }
+        }
+    }
+
+    // RFC 7858 section 3.4: the connection is kept for the queries that
+    // follow, and one the server has closed in the meantime is replaced
+    #[cfg(feature = "tls")]
+    fn exchange_tls_once(
+        &self,
+        tls: &ClientTls,
+        query: &Packet,
+        message: &[u8],
+    ) -> io::Result<Vec<u8>> {
+        // taken out rather than locked for the exchange, so that queries
+        // made at the same time open connections of their own
+        let idle = self.connection.lock().unwrap().take();
+        let reused = idle.filter(|connection| connection.last_used.elapsed() < self.idle_timeout);
+        if let Some(mut connection) = reused {
+            if let Ok(response) = self.exchange_stream(&mut connection.stream, query, message) {
+                self.keep(connection.stream);
+                return Ok(response);
+            }
+        }
+        let stream = TcpStream::connect_timeout(&self.server, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        let mut stream = tls.connect(stream)?;
+        let response = self.exchange_stream(&mut stream, query, message)?;
+        self.keep(stream);
+        Ok(response)
+    }
+
+    #[cfg(feature = "tls")]
+    fn exchange_stream(
+        &self,
+        stream: &mut ClientStream,
+        query: &Packet,
+        message: &[u8],
+    ) -> io::Result<Vec<u8>> {
+        let local = stream.sock.local_addr()?;
+        let sent = SystemTime::now();
+        tcp::write_message(stream, message)?;
+        if let Some(tap) = &self.dnstap {
+            tap.query(Protocol::Dot, local, self.server, sent, message);
+        }
+        // a reused connection can still have the answer to an earlier query
+        // that timed out, which is skipped
+        loop {
+            let response = tcp::read_message(stream)?;
+            if is_response_to(query, &response) {
+                if let Some(tap) = &self.dnstap {
+                    tap.response(Protocol::Dot, local, self.server, sent, &response);
+                }
+                return Ok(response);
+            }
+        }
+    }
+
+    #[cfg(feature = "tls")]
+    fn keep(&self, stream: ClientStream) {
+        *self.connection.lock().unwrap() = Some(Connection {
+            stream,
+            last_used: Instant::now(),
+        });
+    }
+}
+
+// a recursive query with a random ID, which is what a stub resolver sends
+pub fn build_query(name: &str, r#type: RRType, class: RRClass) -> Packet {
+    let mut header = Header::new(random_id());
+    header.should_recurse = true;
+    let mut packet = Packet::new(header);
+    packet.questions.push(Question::new(name, r#type, class));
+    packet
+}
+
+// RandomState is seeded from the OS, which is enough to make IDs unguessable
+// without pulling in a random number generator
+pub fn random_id() -> u16 {
+    let mut hasher = RandomState::new().build_hasher();
+    if let Ok(now) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        hasher.write_u128(now.as_nanos());
+    }
+    hasher.finish() as u16
+}
+
+// what goes over an encrypted transport, padded if it has EDNS so its size
+// says less about what was asked, RFC 8467 section 4.1
+#[cfg(feature = "tls")]
+fn padded(query: &Packet) -> Packet {
+    let mut query = query.clone();
+    query.pad(QUERY_PADDING_BLOCK, u16::MAX as usize);
+    query
+}
+
+// any local address of the server's family
+fn unspecified(server: SocketAddr) -> SocketAddr {
+    match server {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    }
+}
+
+// whatever the server sent back can't be trusted to be well formed
+fn parse(message: &[u8]) -> io::Result<Packet> {
+    Packet::try_from_buf(message)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// the ID and the response bit are checked before parsing anything else, then
+// the question has to be the one that was asked, and a cookie in the
+// response has to be the client's own, RFC 7873 section 5.3
+fn is_response_to(query: &Packet, message: &[u8]) -> bool {
+    if message.len() < 12
+        || u16::from_be_bytes([message[0], message[1]]) != query.header.identifier
+        || message[2] & 0b10000000 == 0
+    {
+        return false;
+    }
+    let Ok(response) = Packet::try_from_buf(message) else {
+        return false;
+    };
+    let cookie = |packet: &Packet| packet.edns.as_ref().and_then(Edns::cookie).cloned();
+    if let (Some(sent), Some(received)) = (cookie(query), cookie(&response)) {
+        if sent.client != received.client {
+            return false;
+        }
+    }
+    // a server that couldn't parse the query may not echo it back
+    if response.questions.is_empty() && response.rcode() == RCode::FORMERR as u16 {
+        return true;
+    }
+    response.questions.len() == query.questions.len()
+        && response
+            .questions
+            .iter()
+            .zip(&query.questions)
+            .all(|(answer, asked)| {
+                answer
+                    .name
+                    .trim_end_matches('.')
+                    .eq_ignore_ascii_case(asked.name.trim_end_matches('.'))
+                    && answer.r#type == asked.r#type
+                    && answer.class == asked.class
+            })
+}
+
+// sockets report a read timeout as either, depending on the platform
+pub fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::answer::Answer;
+    use std::net::TcpListener;
+    use std::thread;
+    #[cfg(feature = "tls")]
+    use {
+        crate::tls,
+        std::fs,
+        std::path::PathBuf,
+        std::sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    fn answer(query: &[u8], truncated: bool) -> Vec<u8> {
+        let query = Packet::from_buf(query);
+        let mut header = query.header.clone();
+        header.query = false;
+        header.response = true;
+        header.truncated = truncated;
+        let mut response = Packet::new(header);
+        response.questions = query.questions.clone();
+        if !truncated {
+            response.answers.push(Answer::new(
+                "example.com",
+                RRType::A,
+                RRClass::IN,
+                300,
+                vec![192, 0, 2, 1],
+            ));
+        }
+        response.to_bytes()
+    }
+
+    #[test]
+    fn test_udp_ignores_other_ids() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = Client::new(server.local_addr().unwrap());
+        thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            let (len, from) = server.recv_from(&mut buf).unwrap();
+            let mut stray = answer(&buf[..len], false);
+            stray[1] = stray[1].wrapping_add(1);
+            server.send_to(&stray, from).unwrap();
+            server.send_to(&answer(&buf[..len], false), from).unwrap();
+        });
+
+        let query = build_query("example.com", RRType::A, RRClass::IN);
+        let response = client.query(&query).unwrap();
+        assert_eq!(response.packet.header.identifier, query.header.identifier);
+        assert_eq!(response.packet.answers[0].data, vec![192, 0, 2, 1]);
+        assert!(!response.over_tcp);
+    }
+
+    #[test]
+    fn test_udp_checks_the_cookie() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = Client::new(server.local_addr().unwrap());
+        thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            let (len, from) = server.recv_from(&mut buf).unwrap();
+            let query = Packet::from_buf(&buf[..len]);
+            let sent = query.edns.as_ref().unwrap().cookie().unwrap().clone();
+            let mut response = Packet::from_buf(&answer(&buf[..len], false));
+            let mut cookie = crate::cookie::Cookie::new([0; 8]);
+            cookie.server = vec![1; 16];
+            let mut edns = Edns::new(1232);
+            edns.set_option(EdnsOption::Cookie(cookie.clone()));
+            response.edns = Some(edns.clone());
+            server.send_to(&response.to_bytes(), from).unwrap();
+            cookie.client = sent.client;
+            edns.set_option(EdnsOption::Cookie(cookie));
+            response.edns = Some(edns);
+            server.send_to(&response.to_bytes(), from).unwrap();
+        });
+
+        let mut query = build_query("example.com", RRType::A, RRClass::IN);
+        query.edns = Some(Edns::new(1232));
+        let response = client.query(&query).unwrap();
+        let cookie = response.packet.edns.unwrap().cookie().unwrap().clone();
+        assert_ne!(cookie.client, [0; 8]);
+        // and the server cookie is sent with the next query
+        let localhost: IpAddr = "127.0.0.1".parse().unwrap();
+        let cookies = client.cookies.as_ref().unwrap().lock().unwrap();
+        assert_eq!(cookies.cookie_for(localhost, localhost), cookie);
+    }
+
+    // the right ID but a different question, as an off-path attacker
+    // guessing IDs might send
+    fn other_question(query: &[u8]) -> Vec<u8> {
+        let mut response = Packet::from_buf(&answer(query, false));
+        response.questions[0].name = "attacker.example".to_string();
+        response.to_bytes()
+    }
+
+    #[test]
+    fn test_udp_ignores_other_questions() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = Client::new(server.local_addr().unwrap());
+        thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            let (len, from) = server.recv_from(&mut buf).unwrap();
+            server.send_to(&other_question(&buf[..len]), from).unwrap();
+            let mut other_type = Packet::from_buf(&answer(&buf[..len], false));
+            other_type.questions[0].r#type = RRType::AAAA;
+            server.send_to(&other_type.to_bytes(), from).unwrap();
+            server.send_to(&answer(&buf[..len], false), from).unwrap();
+        });
+
+        let query = build_query("Example.COM", RRType::A, RRClass::IN);
+        let response = client.query(&query).unwrap();
+        assert_eq!(response.packet.questions[0].name, "Example.COM");
+        assert_eq!(response.packet.answers[0].data, vec![192, 0, 2, 1]);
+        assert!(!response.over_tcp);
+    }
+
+    #[test]
+    fn test_truncated_falls_back_to_tcp() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let listener = TcpListener::bind(server.local_addr().unwrap()).unwrap();
+        let client = Client::new(server.local_addr().unwrap());
+        thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            let (len, from) = server.recv_from(&mut buf).unwrap();
+            server.send_to(&answer(&buf[..len], true), from).unwrap();
+
+            let (mut stream, _) = listener.accept().unwrap();
+            let query = tcp::read_message(&mut stream).unwrap();
+            tcp::write_message(&mut stream, &answer(&query, false)).unwrap();
+        });
+
+        let query = build_query("example.com", RRType::A, RRClass::IN);
+        let response = client.query(&query).unwrap();
+        assert!(response.over_tcp);
+        assert!(!response.packet.header.truncated);
+        assert_eq!(response.packet.answers.len(), 1);
+    }
+
+    #[test]
+    fn test_tcp_checks_the_response() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = Client::new(listener.local_addr().unwrap());
+        client.tcp = true;
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let query = tcp::read_message(&mut stream).unwrap();
+            let mut stray = answer(&query, false);
+            stray[1] = stray[1].wrapping_add(1);
+            tcp::write_message(&mut stream, &stray).unwrap();
+
+            let (mut stream, _) = listener.accept().unwrap();
+            let query = tcp::read_message(&mut stream).unwrap();
+            tcp::write_message(&mut stream, &other_question(&query)).unwrap();
+        });
+
+        let query = build_query("example.com", RRType::A, RRClass::IN);
+        for _ in 0..2 {
+            let err = client.query(&query).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn test_transfer() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = Client::new(listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let query = Packet::from_buf(&tcp::read_message(&mut stream).unwrap());
+            let soa = Answer::new("example.com", RRType::SOA, RRClass::IN, 300, vec![0; 22]);
+            let a = Answer::new(
+                "www.example.com",
+                RRType::A,
+                RRClass::IN,
+                300,
+                vec![192, 0, 2, 1],
+            );
+            // split over two messages
+            for answers in [vec![soa.clone(), a], vec![soa]] {
+                let mut header = query.header.clone();
+                header.response = true;
+                let mut response = Packet::new(header);
+                response.answers = answers;
+                tcp::write_message(&mut stream, &response.to_bytes()).unwrap();
+            }
+        });
+
+        let records = client.transfer("example.com").unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].r#type, RRType::SOA);
+        assert_eq!(records[1].name, "www.example.com");
+    }
+
+    #[test]
+    fn test_notify() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = Client::new(socket.local_addr().unwrap());
+        let secondary = thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            let mut queries = Vec::new();
+            for rcode in [RCode::NOERROR, RCode::REFUSED] {
+                let (len, from) = socket.recv_from(&mut buf).unwrap();
+                let query = Packet::from_buf(&buf[..len]);
+                let mut header = query.header.clone();
+                header.response = true;
+                header.resp_code = rcode as u8;
+                let mut response = Packet::new(header);
+                response.questions = query.questions.clone();
+                socket.send_to(&response.to_bytes(), from).unwrap();
+                queries.push(query);
+            }
+            queries
+        });
+
+        client.notify("example.com").unwrap();
+        let err = client.notify("example.com").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let queries = secondary.join().unwrap();
+        assert_eq!(
+            OpCode::from_value(queries[0].header.op_code),
+            OpCode::NOTIFY
+        );
+        assert!(queries[0].header.is_authoritative);
+        assert!(!queries[0].header.should_recurse);
+        assert_eq!(queries[0].questions[0].r#type, RRType::SOA);
+    }
+
+    // a primary that sends records forever without the closing SOA
+    fn endless_transfer(listener: TcpListener, pause: Duration) {
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let query = Packet::from_buf(&tcp::read_message(&mut stream).unwrap());
+            let mut header = query.header.clone();
+            header.response = true;
+            let mut response = Packet::new(header);
+            response.answers.push(Answer::new(
+                "example.com",
+                RRType::SOA,
+                RRClass::IN,
+                300,
+                vec![0; 22],
+            ));
+            loop {
+                if tcp::write_message(&mut stream, &response.to_bytes()).is_err() {
+                    return;
+                }
+                response.answers = vec![Answer::new(
+                    "www.example.com",
+                    RRType::A,
+                    RRClass::IN,
+                    300,
+                    vec![192, 0, 2, 1],
+                )];
+                thread::sleep(pause);
+            }
+        });
+    }
+
+    #[test]
+    fn test_transfer_limits() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = Client::new(listener.local_addr().unwrap());
+        client.max_transfer_records = 10;
+        endless_transfer(listener, Duration::ZERO);
+        let err = client.transfer("example.com").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // every message arrives well within the timeout, but the whole
+        // transfer doesn't
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = Client::new(listener.local_addr().unwrap());
+        client.transfer_timeout = Duration::from_millis(200);
+        endless_transfer(listener, Duration::from_millis(20));
+        let started = Instant::now();
+        let err = client.transfer("example.com").unwrap_err();
+        assert!(is_timeout(&err), "{}", err);
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_udp_timeout() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut client = Client::new(server.local_addr().unwrap());
+        client.timeout = Duration::from_millis(50);
+        client.retries = 1;
+
+        let query = build_query("example.com", RRType::A, RRClass::IN);
+        let err = client.query(&query).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+
+    // a DoT server with a self-signed certificate for localhost, answering
+    // every query on every connection, and the certificate it presents
+    #[cfg(feature = "tls")]
+    fn tls_server(connections: Arc<AtomicUsize>) -> (SocketAddr, Vec<u8>, PathBuf) {
+        let dir = std::env::temp_dir().join(format!(
+            "dns-rs-client-tls-{}-{}",
+            std::process::id(),
+            random_id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let (certificate, private_key) = (dir.join("cert.pem"), dir.join("key.pem"));
+        fs::write(&certificate, certified.cert.pem()).unwrap();
+        fs::write(&private_key, certified.signing_key.serialize_pem()).unwrap();
+        let config = tls::server_config(&certificate, &private_key, &[tls::ALPN]).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                connections.fetch_add(1, Ordering::Relaxed);
+                let connection = rustls::ServerConnection::new(config.clone()).unwrap();
+                let mut stream = rustls::StreamOwned::new(connection, stream.unwrap());
+                thread::spawn(move || {
+                    while let Ok(query) = tcp::read_message(&mut stream) {
+                        tcp::write_message(&mut stream, &answer(&query, false)).unwrap();
+                    }
+                });
+            }
+        });
+        (address, certified.cert.der().to_vec(), certificate)
+    }
+
+    #[cfg(feature = "tls")]
+    fn local_port(client: &Client) -> u16 {
+        let connection = client.connection.lock().unwrap();
+        let stream = &connection.as_ref().unwrap().stream;
+        stream.sock.local_addr().unwrap().port()
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn test_tls_reuses_connections() {
+        let connections = Arc::new(AtomicUsize::new(0));
+        let (server, certificate, path) = tls_server(connections.clone());
+        let pin = tls::spki_pin(&certificate).unwrap();
+        let tls = ClientTls::pinned("localhost", vec![pin], tls::ALPN).unwrap();
+        let mut client = Client::with_tls(server, tls);
+
+        let query = build_query("example.com", RRType::A, RRClass::IN);
+        let response = client.query(&query).unwrap();
+        assert!(response.over_tcp);
+        assert_eq!(response.packet.answers[0].data, vec![192, 0, 2, 1]);
+        let port = local_port(&client);
+        // clones share the connection
+        let query = build_query("example.com", RRType::A, RRClass::IN);
+        client.clone().query(&query).unwrap();
+        assert_eq!(local_port(&client), port);
+        assert_eq!(connections.load(Ordering::Relaxed), 1);
+
+        // an idle connection is replaced rather than reused
+        client.idle_timeout = Duration::ZERO;
+        client.query(&query).unwrap();
+        assert_ne!(local_port(&client), port);
+        assert_eq!(connections.load(Ordering::Relaxed), 2);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn test_tls_checks_the_certificate() {
+        let (server, _, certificate) = tls_server(Arc::new(AtomicUsize::new(0)));
+        let query = build_query("example.com", RRType::A, RRClass::IN);
+
+        let roots = tls::load_roots(&certificate).unwrap();
+        let tls = ClientTls::new("localhost", Some(roots.clone()), tls::ALPN).unwrap();
+        assert!(Client::with_tls(server, tls).query(&query).is_ok());
+
+        // the wrong name, an unknown issuer and the wrong pin
+        let tls = ClientTls::new("dns.example", Some(roots), tls::ALPN).unwrap();
+        assert!(Client::with_tls(server, tls).query(&query).is_err());
+        let tls = ClientTls::new("localhost", None, tls::ALPN).unwrap();
+        assert!(Client::with_tls(server, tls).query(&query).is_err());
+        let tls = ClientTls::pinned("localhost", vec![vec![0; 32]], tls::ALPN).unwrap();
+        let mut client = Client::with_tls(server, tls);
+        client.retries = 0;
+        let err = client.query(&query).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(client.connection.lock().unwrap().is_none());
+        fs::remove_dir_all(certificate.parent().unwrap()).unwrap();
+    }
+}
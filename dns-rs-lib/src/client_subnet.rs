@@ -0,0 +1,288 @@
+use crate::answer::Answer;
+use crate::cidr::{self, Cidr};
+use crate::class::RRClass;
+use crate::r#type::RRType;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+// RFC 7871 section 11.1 recommends not revealing more than this upstream
+pub const DEFAULT_V4_PREFIX: u8 = 24;
+pub const DEFAULT_V6_PREFIX: u8 = 56;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientSubnet {
+    pub source_prefix: u8,
+    pub scope_prefix: u8,
+    pub address: IpAddr,
+}
+
+impl ClientSubnet {
+    pub fn new(address: IpAddr, source_prefix: u8) -> Self {
+        let subnet = Cidr::new(address, source_prefix);
+        Self {
+            source_prefix: subnet.prefix,
+            scope_prefix: 0,
+            address: subnet.address,
+        }
+    }
+
+    // what a resolver sends upstream on behalf of a client, never revealing
+    // more of the address than the configured prefixes
+    pub fn from_client(client: IpAddr, max_v4_prefix: u8, max_v6_prefix: u8) -> Self {
+        match client {
+            IpAddr::V4(_) => Self::new(client, max_v4_prefix),
+            IpAddr::V6(_) => Self::new(client, max_v6_prefix),
+        }
+    }
+
+    pub fn truncated(&self, max_v4_prefix: u8, max_v6_prefix: u8) -> Self {
+        let max_prefix = match self.address {
+            IpAddr::V4(_) => max_v4_prefix,
+            IpAddr::V6(_) => max_v6_prefix,
+        };
+        Self::new(self.address, self.source_prefix.min(max_prefix))
+    }
+
+    // the option an authoritative server echoes back, stating how much of
+    // the address its answer depends on
+    pub fn with_scope(&self, scope_prefix: u8) -> Self {
+        Self {
+            scope_prefix: scope_prefix.min(cidr::max_prefix(self.address)),
+            ..self.clone()
+        }
+    }
+
+    pub fn family(&self) -> u16 {
+        match self.address {
+            IpAddr::V4(_) => 1,
+            IpAddr::V6(_) => 2,
+        }
+    }
+
+    pub fn scope(&self) -> Cidr {
+        Cidr::new(self.address, self.scope_prefix)
+    }
+
+    // the address is truncated to the fewest bytes holding the source prefix
+    pub fn from_data(data: &[u8]) -> Option<Self> {
+        if data.len() < 4 {
+            return None;
+        }
+        let family = u16::from_be_bytes([data[0], data[1]]);
+        let source_prefix = data[2];
+        let scope_prefix = data[3];
+        let address = &data[4..];
+        if address.len() != (source_prefix as usize).div_ceil(8) {
+            return None;
+        }
+
+        let address = match family {
+            1 if source_prefix <= 32 && scope_prefix <= 32 => {
+                let mut octets = [0u8; 4];
+                octets[..address.len()].copy_from_slice(address);
+                IpAddr::V4(Ipv4Addr::from(octets))
+            }
+            2 if source_prefix <= 128 && scope_prefix <= 128 => {
+                let mut octets = [0u8; 16];
+                octets[..address.len()].copy_from_slice(address);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            _ => return None,
+        };
+        Some(Self {
+            source_prefix,
+            scope_prefix,
+            address,
+        })
+    }
+
+    pub fn to_data(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&self.family().to_be_bytes());
+        data.push(self.source_prefix);
+        data.push(self.scope_prefix);
+        let octets = match cidr::truncate(self.address, self.source_prefix) {
+            IpAddr::V4(address) => address.octets().to_vec(),
+            IpAddr::V6(address) => address.octets().to_vec(),
+        };
+        data.extend_from_slice(&octets[..(self.source_prefix as usize).div_ceil(8)]);
+        data
+    }
+}
+
+// lets a zone answer differently depending on where the client is, RFC 7871
+// section 7.2.1
+pub trait SubnetHook: Send + Sync {
+    // the records for the name and type as seen from the subnet and the
+    // scope prefix they hold for, or None to answer from the zone as usual
+    fn answer(
+        &self,
+        name: &str,
+        r#type: RRType,
+        subnet: &ClientSubnet,
+    ) -> Option<(Vec<Answer>, u8)>;
+}
+
+#[derive(Debug)]
+struct SubnetEntry<T> {
+    scope: Cidr,
+    expires_at: u64,
+    value: T,
+}
+
+// a cache for answers that vary by client subnet, an answer is only handed
+// to clients inside the scope the authoritative server returned it for
+#[derive(Debug)]
+pub struct SubnetCache<T> {
+    entries: HashMap<(String, RRType, RRClass), Vec<SubnetEntry<T>>>,
+}
+
+impl<T> Default for SubnetCache<T> {
+    fn default() -> Self {
+        Self {
+            entries: HashMap::new(),
+        }
+    }
+}
+
+impl<T> SubnetCache<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn insert(
+        &mut self,
+        name: &str,
+        r#type: RRType,
+        class: RRClass,
+        scope: Cidr,
+        ttl: u32,
+        now: u64,
+        value: T,
+    ) {
+        let entries = self
+            .entries
+            .entry((name.to_ascii_lowercase(), r#type, class))
+            .or_default();
+        entries.retain(|entry| entry.scope != scope && entry.expires_at > now);
+        entries.push(SubnetEntry {
+            scope,
+            expires_at: now + ttl as u64,
+            value,
+        });
+    }
+
+    // answers held, including any that have expired but not been purged
+    pub fn len(&self) -> usize {
+        self.entries.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn purge(&mut self, now: u64) {
+        for entries in self.entries.values_mut() {
+            entries.retain(|entry| entry.expires_at > now);
+        }
+        self.entries.retain(|_, entries| !entries.is_empty());
+    }
+
+    // the most specific unexpired answer covering the client
+    pub fn get(
+        &self,
+        name: &str,
+        r#type: RRType,
+        class: RRClass,
+        client: IpAddr,
+        now: u64,
+    ) -> Option<&T> {
+        self.entries
+            .get(&(name.to_ascii_lowercase(), r#type, class))?
+            .iter()
+            .filter(|entry| entry.expires_at > now && entry.scope.contains(client))
+            .max_by_key(|entry| entry.scope.prefix)
+            .map(|entry| &entry.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_option_data() {
+        let subnet = ClientSubnet::new("192.0.2.130".parse().unwrap(), 24);
+        assert_eq!(subnet.to_data(), vec![0, 1, 24, 0, 192, 0, 2]);
+        assert_eq!(
+            ClientSubnet::from_data(&subnet.to_data()),
+            Some(subnet.clone())
+        );
+
+        let subnet = subnet.with_scope(16);
+        assert_eq!(subnet.to_data(), vec![0, 1, 24, 16, 192, 0, 2]);
+
+        let subnet = ClientSubnet::new("2001:db8:1234:5678::1".parse().unwrap(), 56);
+        assert_eq!(
+            subnet.to_data(),
+            vec![0, 2, 56, 0, 0x20, 0x01, 0x0d, 0xb8, 0x12, 0x34, 0x56]
+        );
+
+        // the address must be exactly as long as the source prefix needs
+        assert_eq!(ClientSubnet::from_data(&[0, 1, 24, 0, 192, 0, 2, 1]), None);
+        assert_eq!(ClientSubnet::from_data(&[0, 3, 0, 0]), None);
+        assert_eq!(ClientSubnet::from_data(&[0, 1, 33, 0, 1, 2, 3, 4, 5]), None);
+    }
+
+    #[test]
+    fn test_from_client() {
+        let subnet = ClientSubnet::from_client(
+            "198.51.100.77".parse().unwrap(),
+            DEFAULT_V4_PREFIX,
+            DEFAULT_V6_PREFIX,
+        );
+        assert_eq!(subnet.address, "198.51.100.0".parse::<IpAddr>().unwrap());
+        assert_eq!(subnet.source_prefix, 24);
+
+        let from_client = ClientSubnet::new("198.51.100.77".parse().unwrap(), 32);
+        let subnet = from_client.truncated(20, DEFAULT_V6_PREFIX);
+        assert_eq!(subnet.address, "198.51.96.0".parse::<IpAddr>().unwrap());
+        assert_eq!(subnet.source_prefix, 20);
+
+        let from_client = ClientSubnet::new("198.51.100.77".parse().unwrap(), 16);
+        assert_eq!(
+            from_client.truncated(DEFAULT_V4_PREFIX, DEFAULT_V6_PREFIX),
+            from_client
+        );
+    }
+
+    #[test]
+    fn test_cache_keys_on_scope() {
+        let mut cache = SubnetCache::new();
+        let wide: Cidr = "198.51.0.0/16".parse().unwrap();
+        let narrow: Cidr = "198.51.100.0/24".parse().unwrap();
+        cache.insert("Example.com", RRType::A, RRClass::IN, wide, 60, 0, "wide");
+        cache.insert(
+            "example.com",
+            RRType::A,
+            RRClass::IN,
+            narrow,
+            30,
+            0,
+            "narrow",
+        );
+
+        let lookup = |cache: &SubnetCache<&'static str>, client: &str, now| {
+            let client = client.parse().unwrap();
+            cache
+                .get("example.com", RRType::A, RRClass::IN, client, now)
+                .copied()
+        };
+        assert_eq!(lookup(&cache, "198.51.100.1", 0), Some("narrow"));
+        assert_eq!(lookup(&cache, "198.51.7.1", 0), Some("wide"));
+        assert_eq!(lookup(&cache, "203.0.113.1", 0), None);
+        assert_eq!(lookup(&cache, "198.51.100.1", 45), Some("wide"));
+        assert_eq!(lookup(&cache, "198.51.100.1", 60), None);
+    }
+}
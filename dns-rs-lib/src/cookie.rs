@@ -0,0 +1,420 @@
+use crate::edns::{Edns, EdnsOption, COOKIE};
+use crate::siphash::siphash24;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::hash::{BuildHasher, Hasher};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+pub const BADCOOKIE: u16 = 23;
+pub const FORMERR: u16 = 1;
+
+// server cookies older than this, or further than the skew into the future,
+// are no longer accepted (RFC 9018 section 4.3)
+const MAX_AGE: u32 = 3600;
+const MAX_SKEW: u32 = 300;
+// how often a server changes its secret, so a cookie made with the one
+// before is still good for as long as it would have been anyway
+pub const ROTATION_INTERVAL: u32 = MAX_AGE;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cookie {
+    pub client: [u8; 8],
+    pub server: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CookieStatus {
+    Missing,
+    Malformed,
+    ClientOnly,
+    Invalid,
+    Valid,
+}
+
+impl Cookie {
+    pub fn new(client: [u8; 8]) -> Self {
+        Self {
+            client,
+            server: Vec::new(),
+        }
+    }
+
+    // a client cookie is always 8 bytes, a server cookie is between 8 and 32
+    pub fn from_data(data: &[u8]) -> Option<Self> {
+        let server_len = data.len().checked_sub(8)?;
+        if server_len != 0 && !(8..=32).contains(&server_len) {
+            return None;
+        }
+        Some(Self {
+            client: data[..8].try_into().unwrap(),
+            server: data[8..].to_vec(),
+        })
+    }
+
+    pub fn to_data(&self) -> Vec<u8> {
+        [self.client.to_vec(), self.server.clone()].concat()
+    }
+}
+
+impl CookieStatus {
+    // the rcode a server should answer with, if the request shouldn't be
+    // processed normally. RFC 7873 section 5.2.4 leaves a server cookie that
+    // doesn't check out to the server, which sends BADCOOKIE and a new one
+    // so the client can try again with it
+    pub fn rcode(&self, require_server_cookie: bool) -> Option<u16> {
+        match self {
+            CookieStatus::Malformed => Some(FORMERR),
+            CookieStatus::Invalid => Some(BADCOOKIE),
+            CookieStatus::ClientOnly if require_server_cookie => Some(BADCOOKIE),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ClientCookies {
+    secret: [u8; 16],
+    server_cookies: HashMap<IpAddr, Vec<u8>>,
+}
+
+impl ClientCookies {
+    pub fn new(secret: [u8; 16]) -> Self {
+        Self {
+            secret,
+            server_cookies: HashMap::new(),
+        }
+    }
+
+    // RFC 7873 section 4.1: the client cookie is a hash of both addresses so
+    // it differs per server without having to be stored
+    pub fn client_cookie(&self, client_ip: IpAddr, server_ip: IpAddr) -> [u8; 8] {
+        let data = [ip_bytes(client_ip), ip_bytes(server_ip)].concat();
+        siphash24(&self.secret, &data).to_le_bytes()
+    }
+
+    pub fn cookie_for(&self, client_ip: IpAddr, server_ip: IpAddr) -> Cookie {
+        Cookie {
+            client: self.client_cookie(client_ip, server_ip),
+            server: self
+                .server_cookies
+                .get(&server_ip)
+                .cloned()
+                .unwrap_or_default(),
+        }
+    }
+
+    // remembers the server cookie from a response, returning false when the
+    // response doesn't echo our client cookie and should be discarded
+    pub fn remember(&mut self, client_ip: IpAddr, server_ip: IpAddr, cookie: &Cookie) -> bool {
+        if cookie.client != self.client_cookie(client_ip, server_ip) {
+            return false;
+        }
+        if !cookie.server.is_empty() {
+            self.server_cookies.insert(server_ip, cookie.server.clone());
+        }
+        true
+    }
+}
+
+#[derive(Debug)]
+pub struct ServerCookies {
+    secret: [u8; 16],
+    previous_secret: Option<[u8; 16]>,
+}
+
+impl ServerCookies {
+    pub fn new(secret: [u8; 16]) -> Self {
+        Self {
+            secret,
+            previous_secret: None,
+        }
+    }
+
+    // cookies made with the previous secret stay valid until the next
+    // rotation so clients have time to pick up a new one
+    pub fn rotate(&mut self, secret: [u8; 16]) {
+        self.previous_secret = Some(self.secret);
+        self.secret = secret;
+    }
+
+    // RFC 9018 section 4: version, reserved, timestamp and a SipHash-2-4
+    // over the client cookie, those fields and the client address
+    pub fn make(&self, client: &[u8; 8], client_ip: IpAddr, now: u32) -> Cookie {
+        let server = Self::make_with_secret(&self.secret, client, client_ip, now);
+        Cookie {
+            client: *client,
+            server,
+        }
+    }
+
+    fn make_with_secret(
+        secret: &[u8; 16],
+        client: &[u8; 8],
+        client_ip: IpAddr,
+        timestamp: u32,
+    ) -> Vec<u8> {
+        let mut server = vec![1, 0, 0, 0];
+        server.extend_from_slice(&timestamp.to_be_bytes());
+        let data = [client.to_vec(), server.clone(), ip_bytes(client_ip)].concat();
+        server.extend_from_slice(&siphash24(secret, &data).to_le_bytes());
+        server
+    }
+
+    pub fn check(&self, edns: Option<&Edns>, client_ip: IpAddr, now: u32) -> CookieStatus {
+        let edns = match edns {
+            Some(edns) => edns,
+            None => return CookieStatus::Missing,
+        };
+        match edns.option(COOKIE) {
+            None => CookieStatus::Missing,
+            Some(EdnsOption::Cookie(cookie)) => self.check_cookie(cookie, client_ip, now),
+            Some(_) => CookieStatus::Malformed,
+        }
+    }
+
+    pub fn check_cookie(&self, cookie: &Cookie, client_ip: IpAddr, now: u32) -> CookieStatus {
+        if cookie.server.is_empty() {
+            return CookieStatus::ClientOnly;
+        }
+        if cookie.server.len() != 16 || cookie.server[0] != 1 {
+            return CookieStatus::Invalid;
+        }
+
+        let timestamp = u32::from_be_bytes(cookie.server[4..8].try_into().unwrap());
+        // serial number arithmetic, the timestamp wraps around
+        let age = now.wrapping_sub(timestamp);
+        if age > MAX_AGE && timestamp.wrapping_sub(now) > MAX_SKEW {
+            return CookieStatus::Invalid;
+        }
+
+        let secrets = std::iter::once(&self.secret).chain(self.previous_secret.iter());
+        for secret in secrets {
+            if Self::make_with_secret(secret, &cookie.client, client_ip, timestamp) == cookie.server
+            {
+                return CookieStatus::Valid;
+            }
+        }
+        CookieStatus::Invalid
+    }
+}
+
+// server cookies with a random secret that is replaced every
+// ROTATION_INTERVAL, RFC 9018 section 4.3
+#[derive(Debug)]
+pub struct RotatingCookies {
+    state: Mutex<(ServerCookies, u32)>,
+}
+
+impl Default for RotatingCookies {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RotatingCookies {
+    pub fn new() -> Self {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs() as u32);
+        Self {
+            state: Mutex::new((ServerCookies::new(random_secret()), now)),
+        }
+    }
+
+    // what to make of a request's cookie, and the cookie to answer with if
+    // it had a client cookie to build one on
+    pub fn check(
+        &self,
+        edns: Option<&Edns>,
+        client_ip: IpAddr,
+        now: u32,
+    ) -> (CookieStatus, Option<Cookie>) {
+        let mut state = self.state.lock().unwrap();
+        let (cookies, rotated) = &mut *state;
+        if now.wrapping_sub(*rotated) >= ROTATION_INTERVAL {
+            cookies.rotate(random_secret());
+            *rotated = now;
+        }
+        let status = cookies.check(edns, client_ip, now);
+        let answer = match edns.and_then(Edns::cookie) {
+            Some(cookie) if status != CookieStatus::Malformed => {
+                Some(cookies.make(&cookie.client, client_ip, now))
+            }
+            _ => None,
+        };
+        (status, answer)
+    }
+}
+
+// RandomState is seeded from the OS, as query IDs are, and each one made
+// is keyed differently
+pub fn random_secret() -> [u8; 16] {
+    let mut secret = [0u8; 16];
+    for half in secret.chunks_mut(8) {
+        let mut hasher = RandomState::new().build_hasher();
+        if let Ok(now) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+            hasher.write_u128(now.as_nanos());
+        }
+        half.copy_from_slice(&hasher.finish().to_le_bytes());
+    }
+    secret
+}
+
+fn ip_bytes(ip: IpAddr) -> Vec<u8> {
+    match ip {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    const SECRET: [u8; 16] = [
+        0xe5, 0xe9, 0x73, 0xe5, 0xa6, 0xb2, 0xa4, 0x3f, 0x48, 0xe7, 0xdc, 0x84, 0x9e, 0x37, 0xbf,
+        0xcf,
+    ];
+    const CLIENT: [u8; 8] = [0x24, 0x64, 0xc4, 0xab, 0xcf, 0x10, 0xc9, 0x57];
+
+    #[test]
+    fn test_server_cookie_vector() {
+        // RFC 9018 appendix A.1
+        let server = ServerCookies::new(SECRET);
+        let client_ip: IpAddr = "198.51.100.100".parse().unwrap();
+        let cookie = server.make(&CLIENT, client_ip, 1559731985);
+        assert_eq!(cookie.server, hex("010000005cf79f111f8130c3eee29480"));
+        assert_eq!(
+            server.check_cookie(&cookie, client_ip, 1559731985 + 60),
+            CookieStatus::Valid
+        );
+    }
+
+    #[test]
+    fn test_server_cookie_checks() {
+        let mut server = ServerCookies::new(SECRET);
+        let client_ip: IpAddr = "198.51.100.100".parse().unwrap();
+        let now = 1559731985;
+        let cookie = server.make(&CLIENT, client_ip, now);
+
+        let other_ip: IpAddr = "198.51.100.101".parse().unwrap();
+        assert_eq!(
+            server.check_cookie(&cookie, other_ip, now),
+            CookieStatus::Invalid
+        );
+        assert_eq!(
+            server.check_cookie(&cookie, client_ip, now + MAX_AGE + 1),
+            CookieStatus::Invalid
+        );
+        assert_eq!(
+            server.check_cookie(&Cookie::new(CLIENT), client_ip, now),
+            CookieStatus::ClientOnly
+        );
+
+        server.rotate([7; 16]);
+        assert_eq!(
+            server.check_cookie(&cookie, client_ip, now),
+            CookieStatus::Valid
+        );
+        server.rotate([8; 16]);
+        assert_eq!(
+            server.check_cookie(&cookie, client_ip, now),
+            CookieStatus::Invalid
+        );
+    }
+
+    #[test]
+    fn test_check_edns() {
+        let server = ServerCookies::new(SECRET);
+        let client_ip: IpAddr = "198.51.100.100".parse().unwrap();
+        assert_eq!(server.check(None, client_ip, 0), CookieStatus::Missing);
+
+        let mut edns = Edns::new(1232);
+        assert_eq!(
+            server.check(Some(&edns), client_ip, 0),
+            CookieStatus::Missing
+        );
+
+        edns.set_option(EdnsOption::Unknown(COOKIE, vec![1, 2, 3]));
+        let status = server.check(Some(&edns), client_ip, 0);
+        assert_eq!(status, CookieStatus::Malformed);
+        assert_eq!(status.rcode(false), Some(FORMERR));
+
+        edns.set_option(EdnsOption::Cookie(Cookie::new(CLIENT)));
+        let status = server.check(Some(&edns), client_ip, 0);
+        assert_eq!(status, CookieStatus::ClientOnly);
+        assert_eq!(status.rcode(false), None);
+        assert_eq!(status.rcode(true), Some(BADCOOKIE));
+    }
+
+    #[test]
+    fn test_rotating_cookies() {
+        let cookies = RotatingCookies::new();
+        let client_ip: IpAddr = "198.51.100.100".parse().unwrap();
+        // the secret is replaced on the first check, so the clock starts at 1000
+        let mut edns = Edns::new(1232);
+        assert_eq!(
+            cookies.check(Some(&edns), client_ip, 1000),
+            (CookieStatus::Missing, None)
+        );
+
+        edns.set_option(EdnsOption::Cookie(Cookie::new(CLIENT)));
+        let (status, fresh) = cookies.check(Some(&edns), client_ip, 1000);
+        assert_eq!(status, CookieStatus::ClientOnly);
+        let fresh = fresh.unwrap();
+        assert_eq!(fresh.client, CLIENT);
+
+        edns.set_option(EdnsOption::Cookie(fresh.clone()));
+        let (status, _) = cookies.check(Some(&edns), client_ip, 1000 + 60);
+        assert_eq!(status, CookieStatus::Valid);
+        // the previous secret is still accepted after it changes, but not
+        // once it has changed twice
+        let (status, _) = cookies.check(Some(&edns), client_ip, 1000 + ROTATION_INTERVAL);
+        assert_eq!(status, CookieStatus::Valid);
+        let now = 1000 + 2 * ROTATION_INTERVAL;
+        let (status, fresh) = cookies.check(Some(&edns), client_ip, now);
+        assert_eq!(status, CookieStatus::Invalid);
+
+        let mut bad = fresh.unwrap();
+        bad.server[10] ^= 1;
+        edns.set_option(EdnsOption::Cookie(bad));
+        let (status, fresh) = cookies.check(Some(&edns), client_ip, now);
+        assert_eq!(status.rcode(false), Some(BADCOOKIE));
+        assert!(fresh.is_some());
+        assert_ne!(random_secret(), random_secret());
+    }
+
+    #[test]
+    fn test_client_cookies() {
+        let mut client = ClientCookies::new([1; 16]);
+        let client_ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let server_a: IpAddr = "198.51.100.1".parse().unwrap();
+        let server_b: IpAddr = "198.51.100.2".parse().unwrap();
+
+        let cookie = client.cookie_for(client_ip, server_a);
+        assert!(cookie.server.is_empty());
+        assert_ne!(cookie.client, client.client_cookie(client_ip, server_b));
+
+        let server = ServerCookies::new(SECRET);
+        let response = server.make(&cookie.client, client_ip, 0);
+        assert!(client.remember(client_ip, server_a, &response));
+        assert_eq!(client.cookie_for(client_ip, server_a), response);
+        assert!(client.cookie_for(client_ip, server_b).server.is_empty());
+
+        let spoofed = Cookie {
+            client: [0; 8],
+            server: response.server.clone(),
+        };
+        assert!(!client.remember(client_ip, server_b, &spoofed));
+    }
+}
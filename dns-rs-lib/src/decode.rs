@@ -0,0 +1,489 @@
+use crate::buf_reader::BufReader;
+use crate::class::RRClass;
+use crate::edns::EdnsOption;
+use crate::header::Header;
+use crate::name;
+use crate::opcode::OpCode;
+use crate::r#type::RRType;
+use crate::rcode::RCode;
+use crate::rdata;
+use std::fmt;
+
+const HEADER_LEN: usize = 12;
+// type, class, TTL and data length
+const RECORD_FIXED_LEN: usize = 10;
+const MAX_NAME_LEN: usize = 255;
+const MAX_LABEL_LEN: usize = 63;
+
+// a piece of the message and what it means
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub offset: usize,
+    pub len: usize,
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodeError {
+    pub offset: usize,
+    pub message: String,
+}
+
+// everything that could be understood, up to the first error if there is one
+#[derive(Debug, Clone, PartialEq)]
+pub struct Annotation {
+    pub fields: Vec<Field>,
+    pub error: Option<DecodeError>,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "offset {}: {}", self.offset, self.message)
+    }
+}
+
+// walks a message checking every length and pointer, so that it is safe to
+// hand to Packet::from_buf afterwards
+pub fn annotate(buf: &[u8]) -> Annotation {
+    let mut walker = Walker {
+        buf,
+        pos: 0,
+        fields: Vec::new(),
+    };
+    let error = walker.message().err();
+    Annotation {
+        fields: walker.fields,
+        error,
+    }
+}
+
+struct Walker<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    fields: Vec<Field>,
+}
+
+impl Walker<'_> {
+    fn message(&mut self) -> Result<(), DecodeError> {
+        if self.buf.len() < HEADER_LEN {
+            return Err(self.error(
+                0,
+                format!(
+                    "the header needs {} bytes but the message is only {}",
+                    HEADER_LEN,
+                    self.buf.len()
+                ),
+            ));
+        }
+        let header = Header::from_buf(&mut BufReader::new(self.buf));
+        self.header(&header);
+
+        for i in 1..=header.question_count {
+            let context = format!("question {} of {}", i, header.question_count);
+            self.question(&context)?;
+        }
+        let sections = [
+            ("answer", header.answer_count),
+            ("authority", header.authority_count),
+            ("additional", header.additional_count),
+        ];
+        for (section, count) in sections {
+            for i in 1..=count {
+                let context = format!("{} {} of {}", section, i, count);
+                self.record(&context)?;
+            }
+        }
+
+        if self.pos < self.buf.len() {
+            let len = self.buf.len() - self.pos;
+            self.field(len, "trailing data", format!("{} bytes ignored", len));
+        }
+        Ok(())
+    }
+
+    fn header(&mut self, header: &Header) {
+        self.field(2, "ID", header.identifier.to_string());
+        self.field(
+            2,
+            "flags",
+            format!(
+                "opcode {}, rcode {}, {}",
+                OpCode::from_value(header.op_code),
+                RCode::from_value(header.resp_code as u16),
+                match header.flags().join(" ") {
+                    flags if flags.is_empty() => "no flags".to_string(),
+                    flags => flags,
+                }
+            ),
+        );
+        self.field(2, "QDCOUNT", header.question_count.to_string());
+        self.field(2, "ANCOUNT", header.answer_count.to_string());
+        self.field(2, "NSCOUNT", header.authority_count.to_string());
+        self.field(2, "ARCOUNT", header.additional_count.to_string());
+    }
+
+    fn question(&mut self, context: &str) -> Result<(), DecodeError> {
+        let (name, len) = self.name(self.pos, context)?;
+        self.field(
+            len,
+            &format!("{} name", context),
+            name::to_presentation(&name),
+        );
+        self.need(4, context, "type and class")?;
+        let r#type = self.u16(self.pos);
+        self.field(2, &format!("{} type", context), rdata::type_name(r#type));
+        let class = self.u16(self.pos);
+        self.field(2, &format!("{} class", context), class_name(class));
+        Ok(())
+    }
+
+    fn record(&mut self, context: &str) -> Result<(), DecodeError> {
+        let start = self.pos;
+        let (name, len) = self.name(self.pos, context)?;
+        self.field(
+            len,
+            &format!("{} name", context),
+            name::to_presentation(&name),
+        );
+        self.need(RECORD_FIXED_LEN, context, "type, class, TTL and length")?;
+
+        let r#type = self.u16(self.pos);
+        self.field(2, &format!("{} type", context), rdata::type_name(r#type));
+        // an OPT record is only recognised with an uncompressed root owner
+        if RRType::from_value(r#type) == RRType::OPT && self.buf[start] == 0 {
+            return self.opt(context);
+        }
+
+        let class = self.u16(self.pos);
+        self.field(2, &format!("{} class", context), class_name(class));
+        let ttl = u32::from_be_bytes(self.buf[self.pos..self.pos + 4].try_into().unwrap());
+        self.field(4, &format!("{} TTL", context), ttl.to_string());
+        let len = self.u16(self.pos) as usize;
+        self.field(2, &format!("{} RDLENGTH", context), len.to_string());
+        self.need(len, context, "record data")?;
+
+        let data = self.rdata(RRType::from_value(r#type), len, context)?;
+        self.field(
+            len,
+            &format!("{} RDATA", context),
+            rdata::to_presentation(RRType::from_value(r#type), &data),
+        );
+        Ok(())
+    }
+
+    // the fixed fields of OPT are reused for EDNS, RFC 6891 section 6.1.3
+    fn opt(&mut self, context: &str) -> Result<(), DecodeError> {
+        let udp_payload_size = self.u16(self.pos);
+        self.field(
+            2,
+            &format!("{} UDP payload size", context),
+            udp_payload_size.to_string(),
+        );
+        let extended_rcode = self.buf[self.pos];
+        self.field(
+            1,
+            &format!("{} extended rcode", context),
+            extended_rcode.to_string(),
+        );
+        let version = self.buf[self.pos];
+        self.field(1, &format!("{} version", context), version.to_string());
+        let flags = self.u16(self.pos);
+        let flags = match flags & 0b1000000000000000 != 0 {
+            true => "do".to_string(),
+            false => "no flags".to_string(),
+        };
+        self.field(2, &format!("{} EDNS flags", context), flags);
+        let len = self.u16(self.pos) as usize;
+        self.field(2, &format!("{} RDLENGTH", context), len.to_string());
+        self.need(len, context, "options")?;
+
+        let end = self.pos + len;
+        while self.pos + 4 <= end {
+            let option_len = self.u16(self.pos + 2) as usize;
+            if self.pos + 4 + option_len > end {
+                return Err(self.error(
+                    self.pos,
+                    format!(
+                        "{}: option length {} runs past the end of the record data",
+                        context, option_len
+                    ),
+                ));
+            }
+            let option = EdnsOption::from_buf(&mut BufReader::new(&self.buf[self.pos..end]));
+            let description = option.to_string();
+            self.field(
+                4 + option_len,
+                &format!("{} option {}", context, option.code()),
+                description.trim_start_matches("; ").to_string(),
+            );
+        }
+        if self.pos < end {
+            self.field(
+                end - self.pos,
+                &format!("{} padding", context),
+                "too short for an option, ignored".to_string(),
+            );
+        }
+        Ok(())
+    }
+
+    // names in record data are expanded the same way Answer::read_data does,
+    // and must not run past the data length
+    fn rdata(&mut self, r#type: RRType, len: usize, context: &str) -> Result<Vec<u8>, DecodeError> {
+        let start = self.pos;
+        let end = start + len;
+        let layout: &[Part] = match r#type {
+            RRType::NS | RRType::CNAME | RRType::MB | RRType::MG | RRType::MR | RRType::PTR => {
+                &[Part::Name]
+            }
+            RRType::MINFO => &[Part::Name, Part::Name],
+            RRType::SOA => &[Part::Name, Part::Name, Part::Bytes(20)],
+            RRType::MX => &[Part::Bytes(2), Part::Name],
+            _ => &[Part::Bytes(len)],
+        };
+
+        let mut data = Vec::new();
+        let mut pos = start;
+        for part in layout {
+            match part {
+                Part::Name => {
+                    let (name, name_len) = self.name(pos, context)?;
+                    data.extend(name::to_wire(&name));
+                    pos += name_len;
+                }
+                Part::Bytes(n) => {
+                    if pos + n <= end {
+                        data.extend_from_slice(&self.buf[pos..pos + n]);
+                    }
+                    pos += n;
+                }
+            }
+            if pos > end {
+                return Err(self.error(
+                    start,
+                    format!(
+                        "{}: {} record data is longer than its length of {}",
+                        context, r#type, len
+                    ),
+                ));
+            }
+        }
+        Ok(data)
+    }
+
+    // reads a possibly compressed name at pos, returning it and how many bytes
+    // it takes up there
+    fn name(&self, pos: usize, context: &str) -> Result<(String, usize), DecodeError> {
+        let mut labels = Vec::new();
+        let mut wire_len = 1;
+        let mut at = pos;
+        let mut len_here = None;
+        // each pointer has to go further back than the last, so loops are
+        // impossible
+        let mut lowest_target = pos;
+
+        loop {
+            let Some(&byte) = self.buf.get(at) else {
+                return Err(self.error(at, format!("{}: name runs past the end", context)));
+            };
+            match byte >> 6 {
+                0b11 => {
+                    let Some(&low) = self.buf.get(at + 1) else {
+                        return Err(self.error(
+                            at,
+                            format!("{}: compression pointer runs past the end", context),
+                        ));
+                    };
+                    let target = ((byte & 0b00111111) as usize) << 8 | low as usize;
+                    if target >= lowest_target {
+                        return Err(self.error(
+                            at,
+                            format!(
+                                "{}: compression pointer to offset {} doesn't point backwards",
+                                context, target
+                            ),
+                        ));
+                    }
+                    len_here.get_or_insert_with(|| at + 2 - pos);
+                    lowest_target = target;
+                    at = target;
+                }
+                0b00 => {
+                    let label_len = byte as usize;
+                    if label_len == 0 {
+                        let len_here = *len_here.get_or_insert_with(|| at + 1 - pos);
+                        return Ok((labels.join("."), len_here));
+                    }
+                    let Some(label) = self.buf.get(at + 1..at + 1 + label_len) else {
+                        return Err(self.error(at, format!("{}: label runs past the end", context)));
+                    };
+                    wire_len += 1 + label_len;
+                    if wire_len > MAX_NAME_LEN {
+                        return Err(self.error(
+                            pos,
+                            format!("{}: name is longer than {} bytes", context, MAX_NAME_LEN),
+                        ));
+                    }
+                    labels.push(String::from_utf8_lossy(label).into_owned());
+                    at += 1 + label_len;
+                }
+                _ => {
+                    return Err(self.error(
+                        at,
+                        format!(
+                            "{}: label length {:#04x} is over {} and not a pointer",
+                            context, byte, MAX_LABEL_LEN
+                        ),
+                    ));
+                }
+            }
+        }
+    }
+
+    fn need(&self, len: usize, context: &str, what: &str) -> Result<(), DecodeError> {
+        let left = self.buf.len() - self.pos;
+        if left < len {
+            return Err(self.error(
+                self.pos,
+                format!(
+                    "{}: {} needs {} bytes but only {} are left",
+                    context, what, len, left
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    fn u16(&self, pos: usize) -> u16 {
+        u16::from_be_bytes([self.buf[pos], self.buf[pos + 1]])
+    }
+
+    // records a field at the current position and moves past it
+    fn field(&mut self, len: usize, name: &str, value: String) {
+        self.fields.push(Field {
+            offset: self.pos,
+            len,
+            name: name.to_string(),
+            value,
+        });
+        self.pos += len;
+    }
+
+    fn error(&self, offset: usize, message: String) -> DecodeError {
+        DecodeError { offset, message }
+    }
+}
+
+enum Part {
+    Name,
+    Bytes(usize),
+}
+
+fn class_name(value: u16) -> String {
+    RRClass::from_value(value).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::answer::Answer;
+    use crate::edns::Edns;
+    use crate::packet::Packet;
+    use crate::question::Question;
+
+    fn response() -> Vec<u8> {
+        let mut header = Header::new(0x1234);
+        header.response = true;
+        let mut packet = Packet::new(header);
+        packet
+            .questions
+            .push(Question::new("example.com", RRType::MX, RRClass::IN));
+        let data = [vec![0, 10], name::to_wire("mail.example.com")].concat();
+        packet.answers.push(Answer::new(
+            "example.com",
+            RRType::MX,
+            RRClass::IN,
+            300,
+            data,
+        ));
+        packet.edns = Some(Edns::new(1232));
+        packet.to_bytes()
+    }
+
+    #[test]
+    fn test_annotate() {
+        let annotation = annotate(&response());
+        assert_eq!(annotation.error, None);
+        let fields: Vec<(usize, &str, &str)> = annotation
+            .fields
+            .iter()
+            .map(|field| (field.offset, field.name.as_str(), field.value.as_str()))
+            .collect();
+        assert_eq!(fields[0], (0, "ID", "4660"));
+        assert_eq!(fields[1], (2, "flags", "opcode QUERY, rcode NOERROR, qr"));
+        assert_eq!(fields[6], (12, "question 1 of 1 name", "example.com."));
+        assert_eq!(fields[7], (25, "question 1 of 1 type", "MX"));
+        assert!(fields.contains(&(52, "answer 1 of 1 RDATA", "10 mail.example.com.")));
+        assert!(fields.contains(&(75, "additional 1 of 1 UDP payload size", "1232")));
+    }
+
+    #[test]
+    fn test_compressed_names() {
+        let mut message = response();
+        // point the answer's owner at the question name
+        let answer = 12 + 13 + 4;
+        message.splice(answer..answer + 13, [0xc0, 12]);
+        let annotation = annotate(&message);
+        assert_eq!(annotation.error, None);
+        let owner = &annotation.fields[9];
+        assert_eq!((owner.offset, owner.len), (answer, 2));
+        assert_eq!(owner.value, "example.com.");
+        assert_eq!(
+            Packet::try_from_buf(&message).unwrap().answers[0].name,
+            "example.com"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = annotate(&[0, 1, 2]).error.unwrap();
+        assert_eq!(error.offset, 0);
+
+        let message = response();
+        let error = annotate(&message[..40]).error.unwrap();
+        assert_eq!(error.offset, 37);
+        assert_eq!(
+            error.to_string(),
+            "offset 37: answer 1 of 1: label runs past the end"
+        );
+
+        let mut looped = message.clone();
+        looped[12] = 0xc0;
+        looped[13] = 12;
+        let error = annotate(&looped).error.unwrap();
+        assert_eq!(error.offset, 12);
+        assert!(error.message.contains("doesn't point backwards"));
+
+        let mut long_rdlength = message.clone();
+        // the answer's RDLENGTH
+        long_rdlength[51] = 2;
+        let error = Packet::try_from_buf(&long_rdlength).unwrap_err();
+        assert_eq!(error.offset, 52);
+    }
+
+    #[test]
+    fn test_malformed_input_never_panics() {
+        let message = response();
+        for len in 0..message.len() {
+            assert!(Packet::try_from_buf(&message[..len]).is_err());
+        }
+        for i in 0..message.len() {
+            for byte in [0x00, 0x01, 0x3f, 0x40, 0x80, 0xc0, 0xff] {
+                let mut mutated = message.clone();
+                mutated[i] = byte;
+                let _ = Packet::try_from_buf(&mutated);
+            }
+        }
+    }
+}
@@ -0,0 +1,925 @@
+use crate::name;
+use crate::r#type::RRType;
+use crate::rrset::RRset;
+#[cfg(feature = "dnssec")]
+use ring::{
+    digest,
+    rand::SystemRandom,
+    signature::{
+        self, EcdsaKeyPair, Ed25519KeyPair, KeyPair, RsaKeyPair, RsaPublicKeyComponents,
+        UnparsedPublicKey,
+    },
+};
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// DNSKEY flags, RFC 4034 section 2.1.1 and RFC 5011 section 7
+pub const ZONE_KEY: u16 = 0x0100;
+pub const REVOKE: u16 = 0x0080;
+pub const SECURE_ENTRY_POINT: u16 = 0x0001;
+// the only protocol value a DNSKEY may have
+pub const PROTOCOL: u8 = 3;
+// the one NSEC3 hash algorithm, SHA-1, and the opt-out flag, RFC 5155
+pub const NSEC3_SHA1: u8 = 1;
+pub const OPT_OUT: u8 = 0x01;
+// RFC 9276 section 3.2: a zone asking for more iterations than this is
+// treated as insecure rather than costing every validator the hashing
+pub const MAX_NSEC3_ITERATIONS: u16 = 150;
+
+// the algorithms from RFC 8624 section 3.1 that are worth supporting
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Algorithm {
+    RsaSha256 = 8,
+    EcdsaP256Sha256 = 13,
+    EcdsaP384Sha384 = 14,
+    Ed25519 = 15,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DigestType {
+    Sha1 = 1,
+    Sha256 = 2,
+    Sha384 = 4,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dnskey {
+    pub flags: u16,
+    pub protocol: u8,
+    pub algorithm: u8,
+    pub public_key: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ds {
+    pub key_tag: u16,
+    pub algorithm: u8,
+    pub digest_type: u8,
+    pub digest: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rrsig {
+    pub type_covered: RRType,
+    pub algorithm: u8,
+    pub labels: u8,
+    pub original_ttl: u32,
+    pub expiration: u32,
+    pub inception: u32,
+    pub key_tag: u16,
+    pub signer: String,
+    pub signature: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Nsec {
+    pub next: String,
+    pub types: Vec<RRType>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Nsec3 {
+    pub hash_algorithm: u8,
+    pub flags: u8,
+    pub iterations: u16,
+    pub salt: Vec<u8>,
+    pub next_hashed: Vec<u8>,
+    pub types: Vec<RRType>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Nsec3Param {
+    pub hash_algorithm: u8,
+    pub flags: u8,
+    pub iterations: u16,
+    pub salt: Vec<u8>,
+}
+
+// a private key for signing, kept as PKCS#8 as well so it can be saved
+#[cfg(feature = "dnssec")]
+pub struct SigningKey {
+    pub algorithm: Algorithm,
+    pub flags: u16,
+    pkcs8: Vec<u8>,
+    pair: Pair,
+}
+
+#[cfg(feature = "dnssec")]
+enum Pair {
+    Rsa(RsaKeyPair),
+    Ecdsa(EcdsaKeyPair),
+    Ed25519(Ed25519KeyPair),
+}
+
+impl Algorithm {
+    pub fn from_value(value: u8) -> Option<Self> {
+        match value {
+            8 => Some(Algorithm::RsaSha256),
+            13 => Some(Algorithm::EcdsaP256Sha256),
+            14 => Some(Algorithm::EcdsaP384Sha384),
+            15 => Some(Algorithm::Ed25519),
+            _ => None,
+        }
+    }
+
+    pub fn to_value(self) -> u8 {
+        self as u8
+    }
+}
+
+// the mnemonics from the IANA registry, or the number
+impl FromStr for Algorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let algorithm = match s.to_ascii_uppercase().as_str() {
+            "RSASHA256" => Some(Algorithm::RsaSha256),
+            "ECDSAP256SHA256" => Some(Algorithm::EcdsaP256Sha256),
+            "ECDSAP384SHA384" => Some(Algorithm::EcdsaP384Sha384),
+            "ED25519" => Some(Algorithm::Ed25519),
+            number => number.parse().ok().and_then(Self::from_value),
+        };
+        algorithm.ok_or_else(|| format!("unsupported algorithm {:?}", s))
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Algorithm::RsaSha256 => "RSASHA256",
+            Algorithm::EcdsaP256Sha256 => "ECDSAP256SHA256",
+            Algorithm::EcdsaP384Sha384 => "ECDSAP384SHA384",
+            Algorithm::Ed25519 => "ED25519",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl DigestType {
+    pub fn from_value(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(DigestType::Sha1),
+            2 => Some(DigestType::Sha256),
+            4 => Some(DigestType::Sha384),
+            _ => None,
+        }
+    }
+
+    #[cfg(feature = "dnssec")]
+    fn digest(self, data: &[u8]) -> Vec<u8> {
+        let algorithm = match self {
+            DigestType::Sha1 => &digest::SHA1_FOR_LEGACY_USE_ONLY,
+            DigestType::Sha256 => &digest::SHA256,
+            DigestType::Sha384 => &digest::SHA384,
+        };
+        digest::digest(algorithm, data).as_ref().to_vec()
+    }
+}
+
+impl Dnskey {
+    pub fn new(flags: u16, algorithm: Algorithm, public_key: Vec<u8>) -> Self {
+        Self {
+            flags,
+            protocol: PROTOCOL,
+            algorithm: algorithm.to_value(),
+            public_key,
+        }
+    }
+
+    pub fn from_data(data: &[u8]) -> Option<Self> {
+        if data.len() < 4 {
+            return None;
+        }
+        Some(Self {
+            flags: u16::from_be_bytes([data[0], data[1]]),
+            protocol: data[2],
+            algorithm: data[3],
+            public_key: data[4..].to_vec(),
+        })
+    }
+
+    pub fn to_data(&self) -> Vec<u8> {
+        let mut data = self.flags.to_be_bytes().to_vec();
+        data.push(self.protocol);
+        data.push(self.algorithm);
+        data.extend_from_slice(&self.public_key);
+        data
+    }
+
+    // RFC 4034 appendix B, a checksum over the record data
+    pub fn key_tag(&self) -> u16 {
+        let mut sum: u32 = 0;
+        for (i, byte) in self.to_data().iter().enumerate() {
+            sum += match i % 2 {
+                0 => (*byte as u32) << 8,
+                _ => *byte as u32,
+            };
+        }
+        sum += sum >> 16 & 0xffff;
+        (sum & 0xffff) as u16
+    }
+
+    // only zone keys can sign, and a revoked key signs nothing but its own
+    // DNSKEY set, which isn't worth trusting either
+    pub fn can_sign(&self) -> bool {
+        self.flags & ZONE_KEY != 0 && self.flags & REVOKE == 0 && self.protocol == PROTOCOL
+    }
+
+    pub fn is_sep(&self) -> bool {
+        self.flags & SECURE_ENTRY_POINT != 0
+    }
+
+    #[cfg(feature = "dnssec")]
+    pub fn verify(&self, data: &[u8], signature: &[u8]) -> bool {
+        let Some(algorithm) = Algorithm::from_value(self.algorithm) else {
+            return false;
+        };
+        match algorithm {
+            Algorithm::RsaSha256 => {
+                let Some((exponent, modulus)) = rsa_components(&self.public_key) else {
+                    return false;
+                };
+                RsaPublicKeyComponents {
+                    n: modulus,
+                    e: exponent,
+                }
+                .verify(
+                    &signature::RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY,
+                    data,
+                    signature,
+                )
+                .is_ok()
+            }
+            // RFC 6605 section 4: the key is the point without the leading
+            // 4 that marks it uncompressed
+            Algorithm::EcdsaP256Sha256 | Algorithm::EcdsaP384Sha384 => {
+                let verification = match algorithm {
+                    Algorithm::EcdsaP256Sha256 => &signature::ECDSA_P256_SHA256_FIXED,
+                    _ => &signature::ECDSA_P384_SHA384_FIXED,
+                };
+                let point = [&[4], self.public_key.as_slice()].concat();
+                UnparsedPublicKey::new(verification, point)
+                    .verify(data, signature)
+                    .is_ok()
+            }
+            Algorithm::Ed25519 => UnparsedPublicKey::new(&signature::ED25519, &self.public_key)
+                .verify(data, signature)
+                .is_ok(),
+        }
+    }
+}
+
+// RFC 3110 section 2: the exponent's length in one byte, or in two after a
+// zero, then the exponent and the modulus, without leading zeros
+#[cfg(feature = "dnssec")]
+fn rsa_components(key: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (len, rest) = match *key.first()? {
+        0 => (
+            u16::from_be_bytes([*key.get(1)?, *key.get(2)?]) as usize,
+            &key[3..],
+        ),
+        len => (len as usize, &key[1..]),
+    };
+    if len == 0 || rest.len() <= len {
+        return None;
+    }
+    let (exponent, modulus) = rest.split_at(len);
+    let exponent = &exponent[exponent.iter().take_while(|&&byte| byte == 0).count()..];
+    let modulus = &modulus[modulus.iter().take_while(|&&byte| byte == 0).count()..];
+    Some((exponent, modulus))
+}
+
+impl Ds {
+    pub fn from_data(data: &[u8]) -> Option<Self> {
+        if data.len() < 4 {
+            return None;
+        }
+        Some(Self {
+            key_tag: u16::from_be_bytes([data[0], data[1]]),
+            algorithm: data[2],
+            digest_type: data[3],
+            digest: data[4..].to_vec(),
+        })
+    }
+
+    pub fn to_data(&self) -> Vec<u8> {
+        let mut data = self.key_tag.to_be_bytes().to_vec();
+        data.push(self.algorithm);
+        data.push(self.digest_type);
+        data.extend_from_slice(&self.digest);
+        data
+    }
+
+    // RFC 4034 section 5.1.4: a digest of the owner name and the key
+    #[cfg(feature = "dnssec")]
+    pub fn from_key(owner: &str, key: &Dnskey, digest_type: DigestType) -> Self {
+        let mut data = name::to_canonical_wire(owner);
+        data.extend(key.to_data());
+        Self {
+            key_tag: key.key_tag(),
+            algorithm: key.algorithm,
+            digest_type: digest_type as u8,
+            digest: digest_type.digest(&data),
+        }
+    }
+
+    // whether a validator could use this DS at all, RFC 4035 section 5.2
+    pub fn is_supported(&self) -> bool {
+        Algorithm::from_value(self.algorithm).is_some()
+            && DigestType::from_value(self.digest_type).is_some()
+    }
+
+    #[cfg(feature = "dnssec")]
+    pub fn matches(&self, owner: &str, key: &Dnskey) -> bool {
+        let Some(digest_type) = DigestType::from_value(self.digest_type) else {
+            return false;
+        };
+        self.key_tag == key.key_tag()
+            && self.algorithm == key.algorithm
+            && Self::from_key(owner, key, digest_type).digest == self.digest
+    }
+}
+
+impl Rrsig {
+    pub fn from_data(data: &[u8]) -> Option<Self> {
+        if data.len() < 18 {
+            return None;
+        }
+        let u32_at = |at: usize| u32::from_be_bytes(data[at..at + 4].try_into().unwrap());
+        let (signer, len) = name::from_wire(&data[18..])?;
+        Some(Self {
+            type_covered: RRType::from_value(u16::from_be_bytes([data[0], data[1]])),
+            algorithm: data[2],
+            labels: data[3],
+            original_ttl: u32_at(4),
+            expiration: u32_at(8),
+            inception: u32_at(12),
+            key_tag: u16::from_be_bytes([data[16], data[17]]),
+            signer,
+            signature: data[18 + len..].to_vec(),
+        })
+    }
+
+    pub fn to_data(&self) -> Vec<u8> {
+        let mut data = self.unsigned_data(&name::to_wire(&self.signer));
+        data.extend_from_slice(&self.signature);
+        data
+    }
+
+    fn unsigned_data(&self, signer: &[u8]) -> Vec<u8> {
+        let mut data = self.type_covered.to_value().to_be_bytes().to_vec();
+        data.push(self.algorithm);
+        data.push(self.labels);
+        data.extend(self.original_ttl.to_be_bytes());
+        data.extend(self.expiration.to_be_bytes());
+        data.extend(self.inception.to_be_bytes());
+        data.extend(self.key_tag.to_be_bytes());
+        data.extend_from_slice(signer);
+        data
+    }
+
+    // RFC 4034 section 3.1.8.1: the record data without the signature
+    // followed by the records in canonical form, with the original TTL and,
+    // for an answer from a wildcard, the wildcard as the owner
+    pub fn signed_data(&self, rrset: &RRset) -> Vec<u8> {
+        let mut data = self.unsigned_data(&name::to_canonical_wire(&self.signer));
+        let mut rrset = rrset.clone();
+        rrset.ttl = self.original_ttl;
+        let labels = name::labels(&rrset.name);
+        if (self.labels as usize) < labels.len() {
+            let closest = &labels[labels.len() - self.labels as usize..];
+            rrset.name = ["*"]
+                .iter()
+                .chain(closest)
+                .copied()
+                .collect::<Vec<_>>()
+                .join(".");
+        }
+        data.extend(rrset.to_canonical_wire());
+        data
+    }
+
+    // RFC 4034 section 3.1.5: the times are compared in serial number
+    // arithmetic, so they keep working after 2106
+    pub fn is_current(&self, now: u32) -> bool {
+        now.wrapping_sub(self.inception) as i32 >= 0
+            && self.expiration.wrapping_sub(now) as i32 >= 0
+    }
+
+    // whether the key made this signature over the set, leaving the times
+    // to is_current
+    #[cfg(feature = "dnssec")]
+    pub fn verify(&self, rrset: &RRset, key: &Dnskey) -> bool {
+        self.type_covered == rrset.r#type
+            && self.algorithm == key.algorithm
+            && self.key_tag == key.key_tag()
+            && key.can_sign()
+            && (self.labels as usize) <= name::labels(&rrset.name).len()
+            && key.verify(&self.signed_data(rrset), &self.signature)
+    }
+}
+
+impl Nsec {
+    pub fn from_data(data: &[u8]) -> Option<Self> {
+        let (next, len) = name::from_wire(data)?;
+        Some(Self {
+            next,
+            types: types_from_bitmap(&data[len..])?,
+        })
+    }
+
+    pub fn to_data(&self) -> Vec<u8> {
+        let mut data = name::to_wire(&self.next);
+        data.extend(type_bitmap(&self.types));
+        data
+    }
+
+    pub fn has(&self, r#type: RRType) -> bool {
+        self.types.contains(&r#type)
+    }
+
+    // whether the name falls strictly between this record's owner and the
+    // next name, the last record of a zone wrapping round to the apex
+    pub fn covers(&self, owner: &str, name: &str) -> bool {
+        let after_owner = name::canonical_cmp(owner, name) == Ordering::Less;
+        let before_next = name::canonical_cmp(name, &self.next) == Ordering::Less;
+        match name::canonical_cmp(owner, &self.next) {
+            Ordering::Less => after_owner && before_next,
+            _ => after_owner || before_next,
+        }
+    }
+}
+
+impl Nsec3 {
+    pub fn from_data(data: &[u8]) -> Option<Self> {
+        let salt_len = *data.get(4)? as usize;
+        let hash_at = 5 + salt_len;
+        let hash_len = *data.get(hash_at)? as usize;
+        let types_at = hash_at + 1 + hash_len;
+        Some(Self {
+            hash_algorithm: data[0],
+            flags: data[1],
+            iterations: u16::from_be_bytes([data[2], data[3]]),
+            salt: data.get(5..hash_at)?.to_vec(),
+            next_hashed: data.get(hash_at + 1..types_at)?.to_vec(),
+            types: types_from_bitmap(data.get(types_at..)?)?,
+        })
+    }
+
+    pub fn to_data(&self) -> Vec<u8> {
+        let mut data = vec![self.hash_algorithm, self.flags];
+        data.extend(self.iterations.to_be_bytes());
+        data.push(self.salt.len() as u8);
+        data.extend_from_slice(&self.salt);
+        data.push(self.next_hashed.len() as u8);
+        data.extend_from_slice(&self.next_hashed);
+        data.extend(type_bitmap(&self.types));
+        data
+    }
+
+    pub fn has(&self, r#type: RRType) -> bool {
+        self.types.contains(&r#type)
+    }
+
+    pub fn opt_out(&self) -> bool {
+        self.flags & OPT_OUT != 0
+    }
+
+    // the same as Nsec::covers, comparing hashes instead of names
+    pub fn covers(&self, owner_hash: &[u8], hash: &[u8]) -> bool {
+        let after_owner = owner_hash < hash;
+        let before_next = hash < self.next_hashed.as_slice();
+        match owner_hash < self.next_hashed.as_slice() {
+            true => after_owner && before_next,
+            false => after_owner || before_next,
+        }
+    }
+}
+
+impl Nsec3Param {
+    pub fn from_data(data: &[u8]) -> Option<Self> {
+        let salt_len = *data.get(4)? as usize;
+        Some(Self {
+            hash_algorithm: data[0],
+            flags: data[1],
+            iterations: u16::from_be_bytes([data[2], data[3]]),
+            salt: data.get(5..5 + salt_len)?.to_vec(),
+        })
+    }
+
+    pub fn to_data(&self) -> Vec<u8> {
+        let mut data = vec![self.hash_algorithm, self.flags];
+        data.extend(self.iterations.to_be_bytes());
+        data.push(self.salt.len() as u8);
+        data.extend_from_slice(&self.salt);
+        data
+    }
+}
+
+// RFC 5155 section 5: SHA-1 over the canonical name and the salt, then over
+// each digest and the salt again for every extra iteration
+#[cfg(feature = "dnssec")]
+pub fn nsec3_hash(name: &str, salt: &[u8], iterations: u16) -> Vec<u8> {
+    let mut hash =
+        DigestType::Sha1.digest(&[name::to_canonical_wire(name), salt.to_vec()].concat());
+    for _ in 0..iterations {
+        hash = DigestType::Sha1.digest(&[hash, salt.to_vec()].concat());
+    }
+    hash
+}
+
+// RFC 4034 section 4.1.2: windows of up to 256 types, each with a bitmap of
+// up to 32 bytes that leaves out trailing zero bytes
+pub fn types_from_bitmap(data: &[u8]) -> Option<Vec<RRType>> {
+    let mut types = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let window = *data.get(pos)? as u16;
+        let len = *data.get(pos + 1)? as usize;
+        if len == 0 || len > 32 {
+            return None;
+        }
+        let bitmap = data.get(pos + 2..pos + 2 + len)?;
+        for (i, byte) in bitmap.iter().enumerate() {
+            for bit in 0..8 {
+                if byte & (0b10000000 >> bit) != 0 {
+                    types.push(RRType::from_value(window << 8 | (i * 8 + bit) as u16));
+                }
+            }
+        }
+        pos += 2 + len;
+    }
+    Some(types)
+}
+
+pub fn type_bitmap(types: &[RRType]) -> Vec<u8> {
+    let mut values: Vec<u16> = types.iter().map(RRType::to_value).collect();
+    values.sort_unstable();
+    values.dedup();
+    let mut data = Vec::new();
+    let mut i = 0;
+    while i < values.len() {
+        let window = values[i] >> 8;
+        let mut bitmap = [0u8; 32];
+        let mut len = 0;
+        while i < values.len() && values[i] >> 8 == window {
+            let low = (values[i] & 0xff) as usize;
+            bitmap[low / 8] |= 0b10000000 >> (low % 8);
+            len = low / 8 + 1;
+            i += 1;
+        }
+        data.push(window as u8);
+        data.push(len as u8);
+        data.extend_from_slice(&bitmap[..len]);
+    }
+    data
+}
+
+#[cfg(feature = "dnssec")]
+impl SigningKey {
+    // RSA keys can be loaded but not generated, the crypto library can't
+    pub fn generate(algorithm: Algorithm, flags: u16) -> Result<Self, String> {
+        let rng = SystemRandom::new();
+        let pkcs8 = match algorithm {
+            Algorithm::RsaSha256 => {
+                return Err("RSA keys can't be generated, only loaded".to_string())
+            }
+            Algorithm::EcdsaP256Sha256 | Algorithm::EcdsaP384Sha384 => {
+                EcdsaKeyPair::generate_pkcs8(ecdsa_signing(algorithm), &rng)
+                    .map_err(|_| "couldn't generate a key".to_string())?
+            }
+            Algorithm::Ed25519 => Ed25519KeyPair::generate_pkcs8(&rng)
+                .map_err(|_| "couldn't generate a key".to_string())?,
+        };
+        Self::from_pkcs8(algorithm, flags, pkcs8.as_ref())
+    }
+
+    pub fn from_pkcs8(algorithm: Algorithm, flags: u16, pkcs8: &[u8]) -> Result<Self, String> {
+        let rejected = |e: ring::error::KeyRejected| format!("invalid {} key: {}", algorithm, e);
+        let pair = match algorithm {
+            Algorithm::RsaSha256 => Pair::Rsa(RsaKeyPair::from_pkcs8(pkcs8).map_err(rejected)?),
+            Algorithm::EcdsaP256Sha256 | Algorithm::EcdsaP384Sha384 => Pair::Ecdsa(
+                EcdsaKeyPair::from_pkcs8(ecdsa_signing(algorithm), pkcs8, &SystemRandom::new())
+                    .map_err(rejected)?,
+            ),
+            Algorithm::Ed25519 => {
+                Pair::Ed25519(Ed25519KeyPair::from_pkcs8_maybe_unchecked(pkcs8).map_err(rejected)?)
+            }
+        };
+        Ok(Self {
+            algorithm,
+            flags,
+            pkcs8: pkcs8.to_vec(),
+            pair,
+        })
+    }
+
+    pub fn pkcs8(&self) -> &[u8] {
+        &self.pkcs8
+    }
+
+    pub fn dnskey(&self) -> Dnskey {
+        let public_key = match &self.pair {
+            Pair::Rsa(pair) => {
+                let components: RsaPublicKeyComponents<Vec<u8>> = pair.public().into();
+                let mut key = match components.e.len() {
+                    len @ 1..=255 => vec![len as u8],
+                    len => [vec![0], (len as u16).to_be_bytes().to_vec()].concat(),
+                };
+                key.extend(components.e);
+                key.extend(components.n);
+                key
+            }
+            Pair::Ecdsa(pair) => pair.public_key().as_ref()[1..].to_vec(),
+            Pair::Ed25519(pair) => pair.public_key().as_ref().to_vec(),
+        };
+        Dnskey::new(self.flags, self.algorithm, public_key)
+    }
+
+    pub fn sign(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        let rng = SystemRandom::new();
+        let failed = |_| format!("couldn't sign with the {} key", self.algorithm);
+        match &self.pair {
+            Pair::Rsa(pair) => {
+                let mut signature = vec![0; pair.public().modulus_len()];
+                pair.sign(&signature::RSA_PKCS1_SHA256, &rng, data, &mut signature)
+                    .map_err(failed)?;
+                Ok(signature)
+            }
+            Pair::Ecdsa(pair) => Ok(pair.sign(&rng, data).map_err(failed)?.as_ref().to_vec()),
+            Pair::Ed25519(pair) => Ok(pair.sign(data).as_ref().to_vec()),
+        }
+    }
+
+    // a signature over the set by the zone's key, valid between the times
+    pub fn sign_rrset(
+        &self,
+        rrset: &RRset,
+        signer: &str,
+        inception: u32,
+        expiration: u32,
+    ) -> Result<Rrsig, String> {
+        // the labels don't count the root or a leading wildcard, RFC 4034
+        // section 3.1.3
+        let labels = name::labels(&rrset.name);
+        let labels = labels.len() - labels.first().is_some_and(|label| *label == "*") as usize;
+        let mut rrsig = Rrsig {
+            type_covered: rrset.r#type,
+            algorithm: self.algorithm.to_value(),
+            labels: labels as u8,
+            original_ttl: rrset.ttl,
+            expiration,
+            inception,
+            key_tag: self.dnskey().key_tag(),
+            signer: signer.trim_end_matches('.').to_ascii_lowercase(),
+            signature: Vec::new(),
+        };
+        rrsig.signature = self.sign(&rrsig.signed_data(rrset))?;
+        Ok(rrsig)
+    }
+}
+
+#[cfg(feature = "dnssec")]
+impl fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SigningKey")
+            .field("algorithm", &self.algorithm)
+            .field("flags", &self.flags)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "dnssec")]
+fn ecdsa_signing(algorithm: Algorithm) -> &'static signature::EcdsaSigningAlgorithm {
+    match algorithm {
+        Algorithm::EcdsaP384Sha384 => &signature::ECDSA_P384_SHA384_FIXED_SIGNING,
+        _ => &signature::ECDSA_P256_SHA256_FIXED_SIGNING,
+    }
+}
+
+// seconds since the epoch, which signature times count modulo 2^32
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "dnssec")]
+    use {crate::base64, crate::class::RRClass, crate::rdata};
+
+    // a 2048 bit RSA key in PKCS#8, since RSA keys can't be generated
+    #[cfg(feature = "dnssec")]
+    const RSA_KEY: &str = concat!(
+        "MIIEvQIBADANBgkqhkiG9w0BAQEFAASCBKcwggSjAgEAAoIBAQClLOGEOJ9KcjLJZP1SmDreoE23WVDJ",
+        "S7qAfOnAcizc+b59ThgzCiTUFRDZaRYnC1UkV14U6nhocoDbJNM3yxsH+oBPvBiPBzfLXNiLVSpBP9bJ",
+        "CqAjPTR6eVtxGPyat1iRu1JOahkybFE8nTZ/7xryOfPH4A6uhNyTpKUay2QWkW9eWK7iQqRiY9T47YxS",
+        "UuhAbLMYbMfeiPjAYlUmrgpnsxOO9IuUaF/7CtCsQr0q3mgtfdVda/haxSJoY3kNpeJGPNYVpX2VXbxx",
+        "2jvG7iKY+Tooa7ycrLNE5dzm+FFjE1ViUm5vwCbHmd+TIcLS82GX/sTgmTKvywI5mUPm+dqzAgMBAAEC",
+        "ggEAD7uXphgdQWJhPgJAMswh3K9Y17Gf2pyQ9h4acbnZAMSfr/XD+PtACMdnjx3NqLMRTWCDq0BYxFzB",
+        "CJ6YU8R6cvxu7lP/OYMO0xr+hpRPwwlJSbruXTeTi7rgH9n+bYowhdOu153kKoOwtTJQTPKXFs1i+qh3",
+        "yDMK2Nv3h0wqNg/wJpEVt41vfXyae73yJNyCrS8ZafxilQY/QDF6mZduF1aY0Qt3aQb8wVN9kIph2DCw",
+        "/2B8NnaJ9alnGKq25gLEaD++VCVaoCPUBwj2IzuwJkeuVw5yRNLEIA1kscIbANooozBWsMqY+KKb8iEa",
+        "lhIxQLKIKU1oxwBqM0GPMGTh6QKBgQDmTUlHTSCmdnGehcmJe5GmMdM6vJfmYShvWm+QZv1//kxjKVhp",
+        "88pvD5Xx/AJUeZG5xNo0xmAOz63Tw4iRMG6ShdQ9YDANaauWXglkKT8KLDte9GH0aGCa9W+qtc1OCYQ8",
+        "CrLaLoAZDrz912+qu/qVaZqkhVu/TeCUyI8SNXB2DwKBgQC3mzbYSzLYagILPPLNq4tSM0WbXUZxQU3T",
+        "IWBiR7g1fSdO4BwXmcP+cLddoLX7vugaRtcuL+Nx8vSN1oa+OPvGVs0FTwOr2Qcg/2uiqeYFFTgKorhZ",
+        "fiRGrFQq+1DnGHEPMs+NbZyOJKe1ZenruRu9HwxlwK0XVqhIhLiQut7VHQKBgQDVBfAeL81frHd0NjQx",
+        "I0eZ/sRvtDqdMomAVpqTa3BYkBSANKI/fu1kxXnhF0XgdYLB3sAnyMbZbGhRVLBh+/aLOZcUHoDUJ0Y8",
+        "FEeDilNxSXCxaEOjG1kgfJ4JvZhl8o+KKis9fX2nr+ZItpnnx3EF+2S7gU9YKOTvOoR8R0n7KQKBgHLN",
+        "eGOR0X8D+bcxKthy1LBXIsTcc7lJDfJfwjZxhbrcQIHPG4GeQbCMpfrDDEcWvWYAuXdFtj9/nG47nxC9",
+        "7J+9koApQbBoCU8WpiSVex/efqYXzJEYkHIoOpCNh66X18mbCa/yw5sVTE/eI3+ixLdh/Ix7Bz+6jYqa",
+        "ghTs43v5AoGAGnLUD4kPziz6ITjOFgwkJPnxPmwu5UVWcuZxc4s3EVSABbmZ0BbyKdwEN8h0dTFoAS/V",
+        "zvgMVKEUWws+5A8ZD9IAmZ+PcpIeTAlzn4HarqWVWi7cGp0PLaqd9KqUV04MbfIeouvtiOsUby4OonhM",
+        "5Mb4NGkTR3sHaiMDcoalZ2Q=",
+    );
+
+    #[cfg(feature = "dnssec")]
+    fn rrset(name: &str) -> RRset {
+        let mut rrset = RRset::new(name, RRType::A, RRClass::IN, 300);
+        rrset.data.push(vec![192, 0, 2, 1]);
+        rrset.data.push(vec![192, 0, 2, 2]);
+        rrset
+    }
+
+    #[cfg(feature = "dnssec")]
+    fn keys() -> Vec<SigningKey> {
+        let rsa = base64::decode(RSA_KEY).unwrap();
+        vec![
+            SigningKey::from_pkcs8(Algorithm::RsaSha256, ZONE_KEY, &rsa).unwrap(),
+            SigningKey::generate(Algorithm::EcdsaP256Sha256, ZONE_KEY).unwrap(),
+            SigningKey::generate(Algorithm::EcdsaP384Sha384, ZONE_KEY).unwrap(),
+            SigningKey::generate(Algorithm::Ed25519, ZONE_KEY).unwrap(),
+        ]
+    }
+
+    #[cfg(feature = "dnssec")]
+    #[test]
+    fn test_sign_and_verify() {
+        for key in keys() {
+            let dnskey = key.dnskey();
+            assert_eq!(Dnskey::from_data(&dnskey.to_data()), Some(dnskey.clone()));
+            let set = rrset("www.example.com");
+            let rrsig = key.sign_rrset(&set, "Example.COM.", 1000, 2000).unwrap();
+            assert_eq!(rrsig.signer, "example.com");
+            assert_eq!(rrsig.labels, 3);
+            assert_eq!(rrsig.key_tag, dnskey.key_tag());
+            assert_eq!(Rrsig::from_data(&rrsig.to_data()), Some(rrsig.clone()));
+            assert!(rrsig.verify(&set, &dnskey), "{}", key.algorithm);
+
+            // the TTL and case the set arrives with don't matter
+            let mut received = set.clone();
+            received.name = "WWW.example.com".to_string();
+            received.ttl = 10;
+            received.data.reverse();
+            assert!(rrsig.verify(&received, &dnskey), "{}", key.algorithm);
+
+            let mut changed = set.clone();
+            changed.data[0] = vec![192, 0, 2, 3];
+            assert!(!rrsig.verify(&changed, &dnskey), "{}", key.algorithm);
+            let mut revoked = dnskey.clone();
+            revoked.flags |= REVOKE;
+            assert!(!rrsig.verify(&set, &revoked), "{}", key.algorithm);
+        }
+    }
+
+    #[cfg(feature = "dnssec")]
+    #[test]
+    fn test_wildcard_signature() {
+        let key = SigningKey::generate(Algorithm::Ed25519, ZONE_KEY).unwrap();
+        let rrsig = key
+            .sign_rrset(&rrset("*.example.com"), "example.com", 1000, 2000)
+            .unwrap();
+        assert_eq!(rrsig.labels, 2);
+        assert!(rrsig.verify(&rrset("a.b.example.com"), &key.dnskey()));
+        assert!(!rrsig.verify(&rrset("example.com"), &key.dnskey()));
+    }
+
+    #[test]
+    fn test_is_current() {
+        let rrsig = |inception, expiration| Rrsig {
+            type_covered: RRType::A,
+            algorithm: 15,
+            labels: 2,
+            original_ttl: 300,
+            expiration,
+            inception,
+            key_tag: 0,
+            signer: "example.com".to_string(),
+            signature: Vec::new(),
+        };
+        assert!(rrsig(1000, 2000).is_current(1500));
+        assert!(!rrsig(1000, 2000).is_current(999));
+        assert!(!rrsig(1000, 2000).is_current(2001));
+        // a validity period across the 2106 wrap
+        assert!(rrsig(u32::MAX - 10, 10).is_current(5));
+        assert!(!rrsig(u32::MAX - 10, 10).is_current(u32::MAX - 20));
+    }
+
+    #[cfg(feature = "dnssec")]
+    #[test]
+    fn test_root_key() {
+        // the root zone's KSK-2017 and its DS from the IANA trust anchor
+        let key = base64::decode(concat!(
+            "AwEAAaz/tAm8yTn4Mfeh5eyI96WSVexTBAvkMgJzkKTOiW1vkIbzxeF3+/4RgWOq7HrxRixHlFlExOLAJr5e",
+            "mLvN7SWXgnLh4+B5xQlNVz8Og8kvArMtNROxVQuCaSnIDdD5LKyWbRd2n9WGe2R8PzgCmr3EgVLrjyBxWezF",
+            "0jLHwVN8efS3rCj/EWgvIWgb9tarpVUDK/b58Da+sqqls3eNbuv7pr+eoZG+SrDK6nWeL3c6H5Apxz7LjVc1",
+            "uTIdsIXxuOLYA4/ilBmSVIzuDWfdRUfhHdY6+cn8HFRm+2hM8AnXGXws9555KrUB5qihylGa8subX2Nn6UwN",
+            "R1AkUTV74bU=",
+        ))
+        .unwrap();
+        let key = Dnskey::new(ZONE_KEY | SECURE_ENTRY_POINT, Algorithm::RsaSha256, key);
+        assert_eq!(key.key_tag(), 20326);
+        let ds = Ds::from_key("", &key, DigestType::Sha256);
+        assert_eq!(
+            rdata::to_hex(&ds.digest),
+            "E06D44B80B8F1D39A95C0B0D7C65D08458E880409BBC683457104237C7F8EC8D"
+        );
+        assert!(ds.matches(".", &key));
+        assert!(!ds.matches("com", &key));
+        assert_eq!(Ds::from_data(&ds.to_data()), Some(ds));
+    }
+
+    #[cfg(feature = "dnssec")]
+    #[test]
+    fn test_nsec3_hash() {
+        // RFC 5155 appendix A
+        let salt = [0xaa, 0xbb, 0xcc, 0xdd];
+        let hash = |name| rdata::base32hex(&nsec3_hash(name, &salt, 12)).to_ascii_lowercase();
+        assert_eq!(hash("example"), "0p9mhaveqvm6t7vbl5lop2u3t2rp3tom");
+        assert_eq!(hash("a.example"), "35mthgpgcu1qg68fab165klnsnk3dpvl");
+        assert_eq!(hash("A.EXAMPLE."), "35mthgpgcu1qg68fab165klnsnk3dpvl");
+    }
+
+    #[test]
+    fn test_type_bitmap() {
+        // RFC 4034 section 4.3
+        let types = [
+            RRType::A,
+            RRType::MX,
+            RRType::RRSIG,
+            RRType::NSEC,
+            RRType::from_value(1234),
+        ];
+        let bitmap = type_bitmap(&types);
+        let mut expected = vec![0x00, 0x06, 0x40, 0x01, 0x00, 0x00, 0x00, 0x03];
+        expected.extend([0x04, 0x1b]);
+        expected.extend([0; 26]);
+        expected.push(0x20);
+        assert_eq!(bitmap, expected);
+        assert_eq!(types_from_bitmap(&bitmap), Some(types.to_vec()));
+        assert_eq!(types_from_bitmap(&[0x00, 0x00]), None);
+        assert_eq!(types_from_bitmap(&[0x00, 0x02, 0x40]), None);
+    }
+
+    #[test]
+    fn test_covers() {
+        let nsec = Nsec {
+            next: "d.example.com".to_string(),
+            types: vec![RRType::A, RRType::RRSIG, RRType::NSEC],
+        };
+        assert!(nsec.covers("b.example.com", "c.example.com"));
+        assert!(nsec.covers("b.example.com", "x.b.example.com"));
+        assert!(!nsec.covers("b.example.com", "b.example.com"));
+        assert!(!nsec.covers("b.example.com", "d.example.com"));
+        assert!(!nsec.covers("b.example.com", "e.example.com"));
+        assert_eq!(Nsec::from_data(&nsec.to_data()), Some(nsec));
+
+        // the last record wraps round to the apex
+        let last = Nsec {
+            next: "example.com".to_string(),
+            types: vec![RRType::A],
+        };
+        assert!(last.covers("z.example.com", "zz.example.com"));
+        assert!(!last.covers("z.example.com", "a.example.com"));
+
+        let nsec3 = Nsec3 {
+            hash_algorithm: NSEC3_SHA1,
+            flags: OPT_OUT,
+            iterations: 0,
+            salt: vec![0xab],
+            next_hashed: vec![0x80; 20],
+            types: vec![RRType::NS],
+        };
+        assert!(nsec3.opt_out());
+        assert!(nsec3.covers(&[0x40; 20], &[0x50; 20]));
+        assert!(!nsec3.covers(&[0x40; 20], &[0x90; 20]));
+        // the last hash wraps round to the first
+        assert!(nsec3.covers(&[0xf0; 20], &[0x10; 20]));
+        assert!(!nsec3.covers(&[0xf0; 20], &[0x80; 20]));
+        assert_eq!(Nsec3::from_data(&nsec3.to_data()), Some(nsec3));
+
+        let param = Nsec3Param {
+            hash_algorithm: NSEC3_SHA1,
+            flags: 0,
+            iterations: 5,
+            salt: vec![1, 2],
+        };
+        assert_eq!(Nsec3Param::from_data(&param.to_data()), Some(param));
+    }
+}
@@ -0,0 +1,886 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+// the Frame Streams content type for dnstap
+pub const CONTENT_TYPE: &str = "protobuf:dnstap.Dnstap";
+pub const DEFAULT_QUEUE_SIZE: usize = 1024;
+
+// Frame Streams control frames, from the fstrm protocol
+const CONTROL_ACCEPT: u32 = 1;
+const CONTROL_START: u32 = 2;
+const CONTROL_STOP: u32 = 3;
+const CONTROL_READY: u32 = 4;
+const CONTROL_FINISH: u32 = 5;
+const CONTROL_FIELD_CONTENT_TYPE: u32 = 1;
+// control frames are small, anything bigger is corrupt
+const MAX_CONTROL_LEN: usize = 512;
+// a data frame holds one or two messages of at most 64KiB and a little
+// metadata, so the length is checked before anything is allocated for it
+const MAX_FRAME_LEN: usize = 256 * 1024;
+
+// Dnstap.Type, the only one defined is MESSAGE
+const DNSTAP_MESSAGE: u64 = 1;
+
+// Message.Type from dnstap.proto, queries are odd and responses even
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MessageType {
+    AuthQuery = 1,
+    AuthResponse = 2,
+    ResolverQuery = 3,
+    ResolverResponse = 4,
+    ClientQuery = 5,
+    ClientResponse = 6,
+    ForwarderQuery = 7,
+    ForwarderResponse = 8,
+    StubQuery = 9,
+    StubResponse = 10,
+    ToolQuery = 11,
+    ToolResponse = 12,
+    UpdateQuery = 13,
+    UpdateResponse = 14,
+}
+
+// SocketProtocol from dnstap.proto
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Protocol {
+    Udp = 1,
+    Tcp = 2,
+    Dot = 3,
+    Doh = 4,
+    DnscryptUdp = 5,
+    DnscryptTcp = 6,
+    Doq = 7,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub r#type: MessageType,
+    pub protocol: Option<Protocol>,
+    pub query_address: Option<SocketAddr>,
+    pub response_address: Option<SocketAddr>,
+    // since the Unix epoch
+    pub query_time: Option<Duration>,
+    pub query_message: Option<Vec<u8>>,
+    // the zone a resolver sent the query to, as a wire format name
+    pub query_zone: Option<Vec<u8>>,
+    pub response_time: Option<Duration>,
+    pub response_message: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dnstap {
+    pub identity: Option<Vec<u8>>,
+    pub version: Option<Vec<u8>>,
+    pub message: Message,
+}
+
+// hands messages to a writer thread through a bounded queue, dropping them
+// when it is full rather than holding up the caller
+#[derive(Debug, Clone)]
+pub struct Logger {
+    sender: SyncSender<Vec<u8>>,
+    dropped: Arc<AtomicU64>,
+    pub identity: Option<Vec<u8>>,
+    pub version: Option<Vec<u8>>,
+}
+
+// a logger along with the message types to log queries and responses as
+#[derive(Debug, Clone)]
+pub struct Tap {
+    pub logger: Logger,
+    pub query_type: MessageType,
+}
+
+impl MessageType {
+    pub fn from_value(value: u64) -> Option<Self> {
+        let r#type = match value {
+            1 => MessageType::AuthQuery,
+            2 => MessageType::AuthResponse,
+            3 => MessageType::ResolverQuery,
+            4 => MessageType::ResolverResponse,
+            5 => MessageType::ClientQuery,
+            6 => MessageType::ClientResponse,
+            7 => MessageType::ForwarderQuery,
+            8 => MessageType::ForwarderResponse,
+            9 => MessageType::StubQuery,
+            10 => MessageType::StubResponse,
+            11 => MessageType::ToolQuery,
+            12 => MessageType::ToolResponse,
+            13 => MessageType::UpdateQuery,
+            14 => MessageType::UpdateResponse,
+            _ => return None,
+        };
+        Some(r#type)
+    }
+
+    pub fn is_query(&self) -> bool {
+        *self as u64 % 2 == 1
+    }
+
+    // the response type that goes with a query type
+    pub fn response(&self) -> Self {
+        match self.is_query() {
+            true => Self::from_value(*self as u64 + 1).unwrap(),
+            false => *self,
+        }
+    }
+
+    // the two letter mnemonics dnstap-ldns uses
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            MessageType::AuthQuery => "AQ",
+            MessageType::AuthResponse => "AR",
+            MessageType::ResolverQuery => "RQ",
+            MessageType::ResolverResponse => "RR",
+            MessageType::ClientQuery => "CQ",
+            MessageType::ClientResponse => "CR",
+            MessageType::ForwarderQuery => "FQ",
+            MessageType::ForwarderResponse => "FR",
+            MessageType::StubQuery => "SQ",
+            MessageType::StubResponse => "SR",
+            MessageType::ToolQuery => "TQ",
+            MessageType::ToolResponse => "TR",
+            MessageType::UpdateQuery => "UQ",
+            MessageType::UpdateResponse => "UR",
+        }
+    }
+}
+
+impl Protocol {
+    pub fn from_value(value: u64) -> Option<Self> {
+        let protocol = match value {
+            1 => Protocol::Udp,
+            2 => Protocol::Tcp,
+            3 => Protocol::Dot,
+            4 => Protocol::Doh,
+            5 => Protocol::DnscryptUdp,
+            6 => Protocol::DnscryptTcp,
+            7 => Protocol::Doq,
+            _ => return None,
+        };
+        Some(protocol)
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Protocol::Udp => "UDP",
+            Protocol::Tcp => "TCP",
+            Protocol::Dot => "DOT",
+            Protocol::Doh => "DOH",
+            Protocol::DnscryptUdp => "DNSCRYPT-UDP",
+            Protocol::DnscryptTcp => "DNSCRYPT-TCP",
+            Protocol::Doq => "DOQ",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Message {
+    pub fn new(r#type: MessageType) -> Self {
+        Self {
+            r#type,
+            protocol: None,
+            query_address: None,
+            response_address: None,
+            query_time: None,
+            query_message: None,
+            query_zone: None,
+            response_time: None,
+            response_message: None,
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        put_varint_field(&mut bytes, 1, self.r#type as u64);
+        let address = self.query_address.or(self.response_address);
+        if let Some(address) = address {
+            let family = if address.is_ipv4() { 1 } else { 2 };
+            put_varint_field(&mut bytes, 2, family);
+        }
+        if let Some(protocol) = self.protocol {
+            put_varint_field(&mut bytes, 3, protocol as u64);
+        }
+        if let Some(address) = self.query_address {
+            put_bytes_field(&mut bytes, 4, &ip_bytes(address.ip()));
+        }
+        if let Some(address) = self.response_address {
+            put_bytes_field(&mut bytes, 5, &ip_bytes(address.ip()));
+        }
+        if let Some(address) = self.query_address {
+            put_varint_field(&mut bytes, 6, address.port() as u64);
+        }
+        if let Some(address) = self.response_address {
+            put_varint_field(&mut bytes, 7, address.port() as u64);
+        }
+        if let Some(time) = self.query_time {
+            put_varint_field(&mut bytes, 8, time.as_secs());
+            put_fixed32_field(&mut bytes, 9, time.subsec_nanos());
+        }
+        if let Some(message) = &self.query_message {
+            put_bytes_field(&mut bytes, 10, message);
+        }
+        if let Some(zone) = &self.query_zone {
+            put_bytes_field(&mut bytes, 11, zone);
+        }
+        if let Some(time) = self.response_time {
+            put_varint_field(&mut bytes, 12, time.as_secs());
+            put_fixed32_field(&mut bytes, 13, time.subsec_nanos());
+        }
+        if let Some(message) = &self.response_message {
+            put_bytes_field(&mut bytes, 14, message);
+        }
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut r#type = None;
+        let mut message = Message::new(MessageType::ToolQuery);
+        let mut query_ip = None;
+        let mut response_ip = None;
+        let mut query_port = 0;
+        let mut response_port = 0;
+        let mut query_secs = None;
+        let mut query_nanos = 0;
+        let mut response_secs = None;
+        let mut response_nanos = 0;
+
+        for field in Fields::new(bytes) {
+            match field? {
+                (1, Value::Varint(value)) => r#type = MessageType::from_value(value),
+                (3, Value::Varint(value)) => message.protocol = Protocol::from_value(value),
+                (4, Value::Bytes(value)) => query_ip = to_ip(value),
+                (5, Value::Bytes(value)) => response_ip = to_ip(value),
+                (6, Value::Varint(value)) => query_port = value as u16,
+                (7, Value::Varint(value)) => response_port = value as u16,
+                (8, Value::Varint(value)) => query_secs = Some(value),
+                (9, Value::Fixed32(value)) => query_nanos = value,
+                (10, Value::Bytes(value)) => message.query_message = Some(value.to_vec()),
+                (11, Value::Bytes(value)) => message.query_zone = Some(value.to_vec()),
+                (12, Value::Varint(value)) => response_secs = Some(value),
+                (13, Value::Fixed32(value)) => response_nanos = value,
+                (14, Value::Bytes(value)) => message.response_message = Some(value.to_vec()),
+                // the socket family is implied by the addresses, and policy
+                // and HTTP details aren't kept
+                _ => {}
+            }
+        }
+
+        message.r#type = r#type.ok_or("message has no known type")?;
+        message.query_address = query_ip.map(|ip| SocketAddr::new(ip, query_port));
+        message.response_address = response_ip.map(|ip| SocketAddr::new(ip, response_port));
+        message.query_time =
+            query_secs.map(|secs| Duration::new(secs, query_nanos.min(999_999_999)));
+        message.response_time =
+            response_secs.map(|secs| Duration::new(secs, response_nanos.min(999_999_999)));
+        Ok(message)
+    }
+}
+
+impl Dnstap {
+    pub fn new(message: Message) -> Self {
+        Self {
+            identity: None,
+            version: None,
+            message,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        if let Some(identity) = &self.identity {
+            put_bytes_field(&mut bytes, 1, identity);
+        }
+        if let Some(version) = &self.version {
+            put_bytes_field(&mut bytes, 2, version);
+        }
+        put_bytes_field(&mut bytes, 14, &self.message.to_bytes());
+        put_varint_field(&mut bytes, 15, DNSTAP_MESSAGE);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut identity = None;
+        let mut version = None;
+        let mut message = None;
+        for field in Fields::new(bytes) {
+            match field? {
+                (1, Value::Bytes(value)) => identity = Some(value.to_vec()),
+                (2, Value::Bytes(value)) => version = Some(value.to_vec()),
+                (14, Value::Bytes(value)) => message = Some(Message::from_bytes(value)?),
+                _ => {}
+            }
+        }
+        Ok(Self {
+            identity,
+            version,
+            message: message.ok_or("frame has no message")?,
+        })
+    }
+}
+
+impl Logger {
+    // a one-way stream, as written to files
+    pub fn new<W: Write + Send + 'static>(
+        writer: W,
+        queue_size: usize,
+    ) -> io::Result<(Self, JoinHandle<io::Result<()>>)> {
+        let mut writer = BufWriter::new(writer);
+        write_control(&mut writer, CONTROL_START, Some(CONTENT_TYPE))?;
+        Ok(Self::spawn(queue_size, move |receiver| {
+            write_frames(&mut writer, receiver)?;
+            write_control(&mut writer, CONTROL_STOP, None)?;
+            writer.flush()
+        }))
+    }
+
+    pub fn to_file(
+        path: &str,
+        queue_size: usize,
+    ) -> io::Result<(Self, JoinHandle<io::Result<()>>)> {
+        Self::new(File::create(path)?, queue_size)
+    }
+
+    // collectors listening on a socket expect the bidirectional handshake
+    #[cfg(unix)]
+    pub fn to_unix_socket(
+        path: &str,
+        queue_size: usize,
+    ) -> io::Result<(Self, JoinHandle<io::Result<()>>)> {
+        let mut stream = std::os::unix::net::UnixStream::connect(path)?;
+        write_control(&mut stream, CONTROL_READY, Some(CONTENT_TYPE))?;
+        match read_control_frame(&mut stream)? {
+            (CONTROL_ACCEPT, content_types) if content_types.iter().any(|t| t == CONTENT_TYPE) => {}
+            _ => return Err(invalid("collector didn't accept dnstap")),
+        }
+        write_control(&mut stream, CONTROL_START, Some(CONTENT_TYPE))?;
+
+        Ok(Self::spawn(queue_size, move |receiver| {
+            let mut writer = BufWriter::new(stream.try_clone()?);
+            write_frames(&mut writer, receiver)?;
+            write_control(&mut writer, CONTROL_STOP, None)?;
+            writer.flush()?;
+            // the collector confirms it has everything
+            match read_control_frame(&mut stream)? {
+                (CONTROL_FINISH, _) => Ok(()),
+                _ => Err(invalid("collector didn't finish the stream")),
+            }
+        }))
+    }
+
+    fn spawn<F>(queue_size: usize, run: F) -> (Self, JoinHandle<io::Result<()>>)
+    where
+        F: FnOnce(Receiver<Vec<u8>>) -> io::Result<()> + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel(queue_size);
+        let handle = thread::spawn(move || run(receiver));
+        let logger = Self {
+            sender,
+            dropped: Arc::new(AtomicU64::new(0)),
+            identity: None,
+            version: None,
+        };
+        (logger, handle)
+    }
+
+    // false if the message had to be dropped
+    pub fn log(&self, message: Message) -> bool {
+        let dnstap = Dnstap {
+            identity: self.identity.clone(),
+            version: self.version.clone(),
+            message,
+        };
+        match self.sender.try_send(dnstap.to_bytes()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+        }
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Tap {
+    pub fn new(logger: Logger, query_type: MessageType) -> Self {
+        Self { logger, query_type }
+    }
+
+    pub fn query(
+        &self,
+        protocol: Protocol,
+        local: SocketAddr,
+        remote: SocketAddr,
+        sent: SystemTime,
+        query: &[u8],
+    ) {
+        let mut message = self.message(self.query_type, protocol, local, remote, sent);
+        message.query_message = Some(query.to_vec());
+        self.logger.log(message);
+    }
+
+    pub fn response(
+        &self,
+        protocol: Protocol,
+        local: SocketAddr,
+        remote: SocketAddr,
+        sent: SystemTime,
+        response: &[u8],
+    ) {
+        let mut message = self.message(self.query_type.response(), protocol, local, remote, sent);
+        message.response_time = since_epoch(SystemTime::now());
+        message.response_message = Some(response.to_vec());
+        self.logger.log(message);
+    }
+
+    // from our side the query address is local and the response address is
+    // the server's
+    fn message(
+        &self,
+        r#type: MessageType,
+        protocol: Protocol,
+        local: SocketAddr,
+        remote: SocketAddr,
+        sent: SystemTime,
+    ) -> Message {
+        let mut message = Message::new(r#type);
+        message.protocol = Some(protocol);
+        message.query_address = Some(local);
+        message.response_address = Some(remote);
+        message.query_time = since_epoch(sent);
+        message
+    }
+}
+
+// reads a one-way stream as written to a file
+pub struct Reader<R> {
+    inner: R,
+    done: bool,
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let mut escape = [0u8; 4];
+        inner.read_exact(&mut escape)?;
+        if escape != [0; 4] {
+            return Err(invalid("not a Frame Streams file"));
+        }
+        match read_control(&mut inner)? {
+            (CONTROL_START, content_types)
+                if content_types.is_empty() || content_types.iter().any(|t| t == CONTENT_TYPE) => {}
+            (CONTROL_START, _) => return Err(invalid("stream isn't dnstap")),
+            _ => return Err(invalid("stream doesn't begin with a start frame")),
+        }
+        Ok(Self { inner, done: false })
+    }
+
+    // None once the stop frame has been read
+    pub fn next_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if self.done {
+            return Ok(None);
+        }
+        let mut len = [0u8; 4];
+        self.inner.read_exact(&mut len)?;
+        let len = u32::from_be_bytes(len) as usize;
+        if len == 0 {
+            let mut escape_len = [0u8; 4];
+            self.inner.read_exact(&mut escape_len)?;
+            // put the length back for read_control
+            let mut control = escape_len.as_slice().chain(&mut self.inner);
+            return match read_control(&mut control)? {
+                (CONTROL_STOP, _) => {
+                    self.done = true;
+                    Ok(None)
+                }
+                _ => Err(invalid("unexpected control frame")),
+            };
+        }
+        if len > MAX_FRAME_LEN {
+            return Err(invalid(&format!("frame of {} bytes is too big", len)));
+        }
+        let mut frame = vec![0u8; len];
+        self.inner.read_exact(&mut frame)?;
+        Ok(Some(frame))
+    }
+}
+
+impl<R: Read> Iterator for Reader<R> {
+    type Item = io::Result<Dnstap>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_frame() {
+            Ok(Some(frame)) => Some(Dnstap::from_bytes(&frame).map_err(|e| invalid(&e))),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+pub fn since_epoch(time: SystemTime) -> Option<Duration> {
+    time.duration_since(SystemTime::UNIX_EPOCH).ok()
+}
+
+// writes queued frames until every logger is gone, flushing whenever the
+// queue runs dry
+fn write_frames<W: Write>(writer: &mut W, receiver: Receiver<Vec<u8>>) -> io::Result<()> {
+    loop {
+        let frame = match receiver.try_recv() {
+            Ok(frame) => frame,
+            Err(_) => {
+                writer.flush()?;
+                match receiver.recv() {
+                    Ok(frame) => frame,
+                    Err(_) => return Ok(()),
+                }
+            }
+        };
+        writer.write_all(&(frame.len() as u32).to_be_bytes())?;
+        writer.write_all(&frame)?;
+    }
+}
+
+fn write_control<W: Write>(
+    writer: &mut W,
+    control: u32,
+    content_type: Option<&str>,
+) -> io::Result<()> {
+    let mut frame = control.to_be_bytes().to_vec();
+    if let Some(content_type) = content_type {
+        frame.extend(CONTROL_FIELD_CONTENT_TYPE.to_be_bytes());
+        frame.extend((content_type.len() as u32).to_be_bytes());
+        frame.extend(content_type.as_bytes());
+    }
+    let mut bytes = vec![0, 0, 0, 0];
+    bytes.extend((frame.len() as u32).to_be_bytes());
+    bytes.extend(frame);
+    writer.write_all(&bytes)?;
+    writer.flush()
+}
+
+// reads a control frame after its escape, returning its type and any
+// content types it carries
+fn read_control<R: Read>(reader: &mut R) -> io::Result<(u32, Vec<String>)> {
+    let mut len = [0u8; 4];
+    reader.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if !(4..=MAX_CONTROL_LEN).contains(&len) {
+        return Err(invalid("control frame has an invalid length"));
+    }
+    let mut frame = vec![0u8; len];
+    reader.read_exact(&mut frame)?;
+    let control = u32::from_be_bytes(frame[0..4].try_into().unwrap());
+
+    let mut content_types = Vec::new();
+    let mut fields = &frame[4..];
+    while fields.len() >= 8 {
+        let field = u32::from_be_bytes(fields[0..4].try_into().unwrap());
+        let field_len = u32::from_be_bytes(fields[4..8].try_into().unwrap()) as usize;
+        let value = fields
+            .get(8..8 + field_len)
+            .ok_or_else(|| invalid("control field runs past the end of its frame"))?;
+        if field == CONTROL_FIELD_CONTENT_TYPE {
+            content_types.push(String::from_utf8_lossy(value).into_owned());
+        }
+        fields = &fields[8 + field_len..];
+    }
+    Ok((control, content_types))
+}
+
+// a whole control frame, escape included
+#[cfg(unix)]
+fn read_control_frame<R: Read>(reader: &mut R) -> io::Result<(u32, Vec<String>)> {
+    let mut escape = [0u8; 4];
+    reader.read_exact(&mut escape)?;
+    if escape != [0; 4] {
+        return Err(invalid("expected a control frame"));
+    }
+    read_control(reader)
+}
+
+fn ip_bytes(ip: IpAddr) -> Vec<u8> {
+    match ip {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    }
+}
+
+fn to_ip(bytes: &[u8]) -> Option<IpAddr> {
+    match bytes.len() {
+        4 => Some(Ipv4Addr::from(<[u8; 4]>::try_from(bytes).ok()?).into()),
+        16 => Some(Ipv6Addr::from(<[u8; 16]>::try_from(bytes).ok()?).into()),
+        _ => None,
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// just enough protobuf for dnstap: varints, fixed32 and length-delimited
+// fields
+fn put_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn put_varint_field(bytes: &mut Vec<u8>, field: u64, value: u64) {
+    put_varint(bytes, field << 3);
+    put_varint(bytes, value);
+}
+
+fn put_fixed32_field(bytes: &mut Vec<u8>, field: u64, value: u32) {
+    put_varint(bytes, field << 3 | 5);
+    bytes.extend(value.to_le_bytes());
+}
+
+fn put_bytes_field(bytes: &mut Vec<u8>, field: u64, value: &[u8]) {
+    put_varint(bytes, field << 3 | 2);
+    put_varint(bytes, value.len() as u64);
+    bytes.extend_from_slice(value);
+}
+
+enum Value<'a> {
+    Varint(u64),
+    Fixed32(u32),
+    Fixed64,
+    Bytes(&'a [u8]),
+}
+
+struct Fields<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    fn varint(&mut self) -> Result<u64, String> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = *self.bytes.get(self.pos).ok_or("varint runs past the end")?;
+            self.pos += 1;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("varint is too long".to_string())
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .bytes
+            .get(self.pos..self.pos.saturating_add(len))
+            .ok_or("field runs past the end")?;
+        self.pos += len;
+        Ok(bytes)
+    }
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = Result<(u64, Value<'a>), String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.bytes.len() {
+            return None;
+        }
+        let field = (|| {
+            let key = self.varint()?;
+            let value = match key & 0b111 {
+                0 => Value::Varint(self.varint()?),
+                1 => {
+                    self.take(8)?;
+                    Value::Fixed64
+                }
+                2 => {
+                    let len = self.varint()? as usize;
+                    Value::Bytes(self.take(len)?)
+                }
+                5 => Value::Fixed32(u32::from_le_bytes(self.take(4)?.try_into().unwrap())),
+                wire_type => return Err(format!("unsupported wire type {}", wire_type)),
+            };
+            Ok((key >> 3, value))
+        })();
+        if field.is_err() {
+            // nothing after a malformed field can be trusted
+            self.pos = self.bytes.len();
+        }
+        Some(field)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::sync::Mutex;
+
+    fn message() -> Message {
+        let mut message = Message::new(MessageType::ToolResponse);
+        message.protocol = Some(Protocol::Udp);
+        message.query_address = Some("192.0.2.1:40000".parse().unwrap());
+        message.response_address = Some("192.0.2.53:53".parse().unwrap());
+        message.query_time = Some(Duration::new(1700000000, 5));
+        message.response_time = Some(Duration::new(1700000000, 1_000_005));
+        message.query_message = Some(vec![1, 2, 3]);
+        message.response_message = Some(vec![4, 5]);
+        message
+    }
+
+    #[test]
+    fn test_encoding() {
+        let mut dnstap = Dnstap::new(message());
+        dnstap.identity = Some(b"ns1".to_vec());
+        let bytes = dnstap.to_bytes();
+        // identity, then the message starting with its type
+        assert_eq!(&bytes[..5], &[0x0a, 3, b'n', b's', b'1']);
+        assert_eq!(bytes[5], 0x72);
+        assert_eq!(&bytes[7..9], &[0x08, 12]);
+        // and the dnstap type last
+        assert_eq!(&bytes[bytes.len() - 2..], &[0x78, 1]);
+
+        assert_eq!(Dnstap::from_bytes(&bytes).unwrap(), dnstap);
+        assert!(Dnstap::from_bytes(&bytes[..bytes.len() - 10]).is_err());
+    }
+
+    #[test]
+    fn test_message_types() {
+        assert!(MessageType::ClientQuery.is_query());
+        assert_eq!(
+            MessageType::ClientQuery.response(),
+            MessageType::ClientResponse
+        );
+        assert_eq!(
+            MessageType::ResolverResponse.response(),
+            MessageType::ResolverResponse
+        );
+        assert_eq!(MessageType::ForwarderQuery.mnemonic(), "FQ");
+    }
+
+    #[test]
+    fn test_oversized_frame() {
+        let mut bytes = Vec::new();
+        write_control(&mut bytes, CONTROL_START, Some(CONTENT_TYPE)).unwrap();
+        bytes.extend(u32::MAX.to_be_bytes());
+        let mut reader = Reader::new(Cursor::new(bytes)).unwrap();
+        let err = reader.next_frame().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    // a writer that can be shared with the test, and held up until released
+    #[derive(Clone)]
+    struct Shared {
+        bytes: Arc<Mutex<Vec<u8>>>,
+        gate: Arc<Mutex<()>>,
+    }
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let _gate = self.gate.lock().unwrap();
+            self.bytes.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            let _gate = self.gate.lock().unwrap();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_logger_round_trip() {
+        let shared = Shared {
+            bytes: Arc::default(),
+            gate: Arc::default(),
+        };
+        let (mut logger, handle) = Logger::new(shared.clone(), 16).unwrap();
+        logger.version = Some(b"dns-rs".to_vec());
+        assert!(logger.log(message()));
+        assert!(logger.log(Message::new(MessageType::ClientQuery)));
+        drop(logger);
+        handle.join().unwrap().unwrap();
+
+        let bytes = shared.bytes.lock().unwrap().clone();
+        let frames: Vec<Dnstap> = Reader::new(Cursor::new(bytes))
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].message, message());
+        assert_eq!(frames[0].version, Some(b"dns-rs".to_vec()));
+        assert_eq!(frames[1].message.r#type, MessageType::ClientQuery);
+    }
+
+    #[test]
+    fn test_logger_drops_when_full() {
+        let shared = Shared {
+            bytes: Arc::default(),
+            gate: Arc::default(),
+        };
+        let (logger, handle) = Logger::new(shared.clone(), 2).unwrap();
+        // the writer thread can take one message off the queue and then gets
+        // stuck writing it, so at most three fit
+        let gate = shared.gate.lock().unwrap();
+        let logged = (0..10).filter(|_| logger.log(message())).count();
+        assert!((2..=3).contains(&logged));
+        assert_eq!(logger.dropped(), 10 - logged as u64);
+        drop(gate);
+        drop(logger);
+        handle.join().unwrap().unwrap();
+
+        let bytes = shared.bytes.lock().unwrap().clone();
+        assert_eq!(Reader::new(Cursor::new(bytes)).unwrap().count(), logged);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_socket_handshake() {
+        use std::os::unix::net::UnixListener;
+
+        let path = std::env::temp_dir().join(format!("dns-rs-dnstap-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let collector = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let (control, content_types) = read_control_frame(&mut stream).unwrap();
+            assert_eq!(control, CONTROL_READY);
+            assert_eq!(content_types, vec![CONTENT_TYPE]);
+            write_control(&mut stream, CONTROL_ACCEPT, Some(CONTENT_TYPE)).unwrap();
+
+            // what follows is the same as a file
+            let mut reader = Reader::new(stream.try_clone().unwrap()).unwrap();
+            let mut frames = Vec::new();
+            while let Some(frame) = reader.next_frame().unwrap() {
+                frames.push(Dnstap::from_bytes(&frame).unwrap());
+            }
+            write_control(&mut stream, CONTROL_FINISH, None).unwrap();
+            frames
+        });
+
+        let (logger, handle) = Logger::to_unix_socket(path.to_str().unwrap(), 16).unwrap();
+        assert!(logger.log(message()));
+        drop(logger);
+        handle.join().unwrap().unwrap();
+        let frames = collector.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].message, message());
+    }
+}
@@ -0,0 +1,437 @@
+use crate::base64;
+use crate::packet::Packet;
+#[cfg(feature = "https")]
+use {
+    crate::tls::ClientTls,
+    bytes::Bytes,
+    http_body_util::{BodyExt, Full, Limited},
+    hyper::body::Body,
+    hyper::client::conn::http2::{self, SendRequest},
+    hyper::header::{self, HeaderValue},
+    hyper::{Method, Request, Response, StatusCode},
+    hyper_util::rt::{TokioExecutor, TokioIo},
+    std::io,
+    std::net::SocketAddr,
+    std::sync::{Arc, Mutex},
+    std::time::Duration,
+    tokio::net::TcpStream,
+    tokio::runtime::{self, Runtime},
+    tokio_rustls::TlsConnector,
+};
+
+pub const PATH: &str = "/dns-query";
+pub const CONTENT_TYPE: &str = "application/dns-message";
+pub const PORT: u16 = 443;
+// RFC 8484 section 5.2: HTTP/2 is the minimum, HTTP/1.1 is offered to
+// clients without it
+pub const ALPN: &[u8] = b"h2";
+pub const HTTP1_ALPN: &[u8] = b"http/1.1";
+// the most a DNS message can be
+#[cfg(feature = "https")]
+const MAX_MESSAGE: usize = u16::MAX as usize;
+
+// a DoH client for one endpoint, keeping its HTTP/2 connection for the
+// queries that follow, shared by clones
+#[cfg(feature = "https")]
+#[derive(Debug, Clone)]
+pub struct Https {
+    // with ALPN for HTTP/2
+    pub tls: ClientTls,
+    pub path: String,
+    // GET, which HTTP caches can answer, rather than POST
+    pub get: bool,
+    runtime: Arc<Runtime>,
+    connection: Arc<Mutex<Option<Connection>>>,
+}
+
+#[cfg(feature = "https")]
+#[derive(Debug, Clone)]
+struct Connection {
+    sender: SendRequest<Full<Bytes>>,
+    local: SocketAddr,
+}
+
+#[cfg(feature = "https")]
+impl Https {
+    pub fn new(tls: ClientTls) -> io::Result<Self> {
+        let runtime = runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()?;
+        Ok(Self {
+            tls,
+            path: PATH.to_string(),
+            get: false,
+            runtime: Arc::new(runtime),
+            connection: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    // sends the message and returns the response's, along with the local
+    // address it went from; a connection the server has closed in the
+    // meantime is replaced
+    pub fn exchange(
+        &self,
+        server: SocketAddr,
+        message: &[u8],
+        timeout: Duration,
+    ) -> io::Result<(Vec<u8>, SocketAddr)> {
+        let exchange = async {
+            let idle = self.connection.lock().unwrap().clone();
+            if let Some(connection) = idle.filter(|connection| !connection.sender.is_closed()) {
+                if let Ok(response) = self.send(connection.sender, server, message).await {
+                    return Ok((response, connection.local));
+                }
+            }
+            let connection = self.connect(server).await?;
+            *self.connection.lock().unwrap() = Some(connection.clone());
+            let response = self.send(connection.sender, server, message).await?;
+            Ok((response, connection.local))
+        };
+        self.runtime
+            .block_on(async { tokio::time::timeout(timeout, exchange).await })
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no response from server"))?
+    }
+
+    async fn connect(&self, server: SocketAddr) -> io::Result<Connection> {
+        let stream = TcpStream::connect(server).await?;
+        let local = stream.local_addr()?;
+        let name = self.tls.server_name.clone().try_into().map_err(invalid)?;
+        let stream = TlsConnector::from(self.tls.config())
+            .connect(name, stream)
+            .await?;
+        let (sender, connection) = http2::handshake(TokioExecutor::new(), TokioIo::new(stream))
+            .await
+            .map_err(invalid)?;
+        self.runtime.spawn(connection);
+        Ok(Connection { sender, local })
+    }
+
+    async fn send(
+        &self,
+        mut sender: SendRequest<Full<Bytes>>,
+        server: SocketAddr,
+        message: &[u8],
+    ) -> io::Result<Vec<u8>> {
+        let authority = match (server.port(), self.tls.server_name.contains(':')) {
+            (PORT, false) => self.tls.server_name.clone(),
+            (PORT, true) => format!("[{}]", self.tls.server_name),
+            (port, false) => format!("{}:{}", self.tls.server_name, port),
+            (port, true) => format!("[{}]:{}", self.tls.server_name, port),
+        };
+        let endpoint = format!("https://{}{}", authority, self.path);
+        let request = match self.get {
+            true => {
+                let packet = Packet::try_from_buf(message).map_err(invalid)?;
+                Request::get(get_url(&endpoint, &packet)).body(Full::default())
+            }
+            false => Request::post(endpoint)
+                .header(header::CONTENT_TYPE, CONTENT_TYPE)
+                .body(Full::new(Bytes::copy_from_slice(message))),
+        };
+        let request = request
+            .map(|mut request| {
+                let accept = HeaderValue::from_static(CONTENT_TYPE);
+                request.headers_mut().insert(header::ACCEPT, accept);
+                request
+            })
+            .map_err(invalid)?;
+        sender.ready().await.map_err(invalid)?;
+        let response = sender.send_request(request).await.map_err(invalid)?;
+        if response.status() != StatusCode::OK {
+            return Err(invalid(format!("server returned {}", response.status())));
+        }
+        let content_type = response.headers().get(header::CONTENT_TYPE);
+        if content_type.and_then(|value| value.to_str().ok()) != Some(CONTENT_TYPE) {
+            return Err(invalid("response isn't a DNS message"));
+        }
+        let body = Limited::new(response.into_body(), MAX_MESSAGE)
+            .collect()
+            .await
+            .map_err(invalid)?;
+        Ok(body.to_bytes().to_vec())
+    }
+}
+
+// the DNS message a request to the endpoint carries, or the status to turn
+// it away with, RFC 8484 section 4.1
+#[cfg(feature = "https")]
+pub async fn request_message<B>(request: Request<B>) -> Result<Vec<u8>, StatusCode>
+where
+    B: Body,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let (parts, body) = request.into_parts();
+    if parts.uri.path() != PATH {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    match parts.method {
+        Method::GET => parts
+            .uri
+            .query()
+            .and_then(decode_get_query)
+            .filter(|message| !message.is_empty() && message.len() <= MAX_MESSAGE)
+            .ok_or(StatusCode::BAD_REQUEST),
+        Method::POST => {
+            let content_type = parts.headers.get(header::CONTENT_TYPE);
+            if content_type.and_then(|value| value.to_str().ok()) != Some(CONTENT_TYPE) {
+                return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+            }
+            let body = Limited::new(body, MAX_MESSAGE)
+                .collect()
+                .await
+                .map_err(|_| StatusCode::PAYLOAD_TOO_LARGE)?
+                .to_bytes();
+            match body.is_empty() {
+                true => Err(StatusCode::BAD_REQUEST),
+                false => Ok(body.to_vec()),
+            }
+        }
+        _ => Err(StatusCode::METHOD_NOT_ALLOWED),
+    }
+}
+
+// the response to an answered query, which caches may keep for as long as
+// its records, RFC 8484 section 5.1
+#[cfg(feature = "https")]
+pub fn response(message: Vec<u8>) -> Response<Full<Bytes>> {
+    let cache_control = Packet::try_from_buf(&message)
+        .ok()
+        .and_then(|packet| cache_control(&packet));
+    let mut response = Response::builder()
+        .header(header::CONTENT_TYPE, CONTENT_TYPE)
+        .header(header::CONTENT_LENGTH, message.len());
+    if let Some(cache_control) = cache_control {
+        response = response.header(header::CACHE_CONTROL, cache_control);
+    }
+    response.body(Full::new(Bytes::from(message))).unwrap()
+}
+
+#[cfg(feature = "https")]
+pub fn status_response(status: StatusCode) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::default());
+    *response.status_mut() = status;
+    response
+}
+
+// RFC 8484 section 4.1: the message ID should be zero so that identical
+// GET requests can be cached by HTTP caches
+pub fn get_url(endpoint: &str, query: &Packet) -> String {
+    let mut query = query.clone();
+    query.header.identifier = 0;
+    format!("{}?dns={}", endpoint, base64::encode_url(&query.to_bytes()))
+}
+
+// pulls the message out of the query string of a GET request
+pub fn decode_get_query(query_string: &str) -> Option<Vec<u8>> {
+    let query_string = query_string.trim_start_matches('?');
+    let encoded = query_string
+        .split('&')
+        .find_map(|param| param.strip_prefix("dns="))?;
+    base64::decode(encoded)
+}
+
+// RFC 8484 section 5.1: a response mustn't be cached longer than its
+// smallest TTL, negative answers fall back to the authority section
+pub fn cache_max_age(response: &Packet) -> Option<u32> {
+    let records = if response.answers.is_empty() {
+        &response.authorities
+    } else {
+        &response.answers
+    };
+    records.iter().map(|record| record.ttl).min()
+}
+
+pub fn cache_control(response: &Packet) -> Option<String> {
+    cache_max_age(response).map(|max_age| format!("max-age={}", max_age))
+}
+
+#[cfg(feature = "https")]
+fn invalid(e: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::answer::Answer;
+    use crate::class::RRClass;
+    use crate::header::Header;
+    use crate::question::Question;
+    use crate::r#type::RRType;
+    #[cfg(feature = "https")]
+    use {
+        crate::client::{self, Client},
+        crate::server::{self, Server},
+        crate::tls,
+        crate::zone::Zone,
+        std::fs,
+        std::io::{Read, Write},
+        std::net::TcpListener,
+    };
+
+    #[test]
+    fn test_get_url() {
+        // the example query from RFC 8484 section 4.1.1
+        let mut header = Header::new(0xabcd);
+        header.should_recurse = true;
+        let mut query = Packet::new(header);
+        query
+            .questions
+            .push(Question::new("www.example.com", RRType::A, RRClass::IN));
+
+        let url = get_url("https://dnsserver.example.net/dns-query", &query);
+        assert_eq!(
+            url,
+            "https://dnsserver.example.net/dns-query?dns=AAABAAABAAAAAAAAA3d3dwdleGFtcGxlA2NvbQAAAQAB"
+        );
+
+        let (_, query_string) = url.split_once('?').unwrap();
+        let message = decode_get_query(query_string).unwrap();
+        let decoded = Packet::from_buf(&message);
+        assert_eq!(decoded.header.identifier, 0);
+        assert_eq!(decoded.questions, query.questions);
+
+        assert_eq!(decode_get_query("ct&other=1"), None);
+    }
+
+    #[test]
+    fn test_cache_control() {
+        let mut header = Header::new(0);
+        header.response = true;
+        let mut response = Packet::new(header);
+        assert_eq!(cache_control(&response), None);
+
+        let soa = Answer::new("example.com", RRType::SOA, RRClass::IN, 900, vec![]);
+        response.authorities.push(soa);
+        assert_eq!(cache_control(&response).unwrap(), "max-age=900");
+
+        for ttl in [300, 60, 3600] {
+            let a = Answer::new(
+                "example.com",
+                RRType::A,
+                RRClass::IN,
+                ttl,
+                vec![192, 0, 2, 1],
+            );
+            response.answers.push(a);
+        }
+        assert_eq!(cache_max_age(&response), Some(60));
+    }
+
+    // a DoH server with a self-signed certificate for localhost, answering
+    // from one zone, and the pin for its key
+    #[cfg(feature = "https")]
+    fn https_server() -> (SocketAddr, Vec<u8>) {
+        let dir = std::env::temp_dir().join(format!(
+            "dns-rs-doh-{}-{}",
+            std::process::id(),
+            client::random_id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let (certificate, private_key) = (dir.join("cert.pem"), dir.join("key.pem"));
+        fs::write(&certificate, certified.cert.pem()).unwrap();
+        fs::write(&private_key, certified.signing_key.serialize_pem()).unwrap();
+        let config = tls::server_config(&certificate, &private_key, &[ALPN, HTTP1_ALPN]).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let mut server = Server::new();
+        let zone = "$TTL 300\n@ SOA ns1 hostmaster 1 7200 3600 1209600 60\n@ NS ns1\nns1 A 192.0.2.53\nwww 120 A 192.0.2.1\n";
+        server.add_zone(Zone::from_text("example.com", zone).unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        server::serve_https(listener, config, Arc::new(server)).unwrap();
+        (address, tls::spki_pin(certified.cert.der()).unwrap())
+    }
+
+    #[cfg(feature = "https")]
+    fn local_port(https: &Https) -> u16 {
+        let connection = https.connection.lock().unwrap();
+        connection.as_ref().unwrap().local.port()
+    }
+
+    #[cfg(feature = "https")]
+    #[test]
+    fn test_client() {
+        let (address, pin) = https_server();
+        let tls = ClientTls::pinned("localhost", vec![pin], ALPN).unwrap();
+        let mut https = Https::new(tls).unwrap();
+        let client = Client::with_https(address, https.clone());
+
+        let query = client::build_query("www.example.com", RRType::A, RRClass::IN);
+        let response = client.query(&query).unwrap();
+        assert_eq!(response.packet.header.identifier, query.header.identifier);
+        assert!(response.packet.header.is_authoritative);
+        assert_eq!(response.packet.answers[0].data, vec![192, 0, 2, 1]);
+        let port = local_port(&https);
+
+        // GET over the same connection
+        https.get = true;
+        let client = Client::with_https(address, https.clone());
+        let query = client::build_query("missing.example.com", RRType::A, RRClass::IN);
+        let response = client.query(&query).unwrap();
+        assert_eq!(response.packet.rcode(), 3);
+        assert_eq!(local_port(&https), port);
+
+        https.path = "/other".to_string();
+        let err = Client::with_https(address, https)
+            .query(&query)
+            .unwrap_err();
+        assert!(err.to_string().contains("404"), "{}", err);
+    }
+
+    // HTTP/1.1 by hand, to see the headers
+    #[cfg(feature = "https")]
+    fn http1(address: SocketAddr, pin: &[u8], request: &str) -> String {
+        let tls = ClientTls::pinned("localhost", vec![pin.to_vec()], HTTP1_ALPN).unwrap();
+        let stream = std::net::TcpStream::connect(address).unwrap();
+        let mut stream = tls.connect(stream).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response);
+        String::from_utf8_lossy(&response).to_ascii_lowercase()
+    }
+
+    #[cfg(feature = "https")]
+    #[test]
+    fn test_endpoint() {
+        let (address, pin) = https_server();
+        let query = client::build_query("www.example.com", RRType::A, RRClass::IN);
+        let url = get_url(PATH, &query);
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            url
+        );
+        let response = http1(address, &pin, &request);
+        assert!(response.starts_with("http/1.1 200 ok\r\n"), "{}", response);
+        assert!(response.contains("\r\ncontent-type: application/dns-message\r\n"));
+        // the smallest TTL in the answer
+        assert!(
+            response.contains("\r\ncache-control: max-age=120\r\n"),
+            "{}",
+            response
+        );
+
+        for (request, status) in [
+            ("GET /dns-query HTTP/1.1", "400"),
+            ("GET /dns-query?dns=!! HTTP/1.1", "400"),
+            ("GET /other?dns=AAAB HTTP/1.1", "404"),
+            ("PUT /dns-query HTTP/1.1\r\nContent-Length: 0", "405"),
+            ("POST /dns-query HTTP/1.1\r\nContent-Type: text/plain\r\nContent-Length: 1\r\n\r\nx", "415"),
+        ] {
+            let (line, rest) = request.split_once("\r\n\r\n").unwrap_or((request, ""));
+            let request = format!(
+                "{}\r\nHost: localhost\r\nConnection: close\r\n\r\n{}",
+                line, rest
+            );
+            let response = http1(address, &pin, &request);
+            assert!(
+                response.starts_with(&format!("http/1.1 {} ", status)),
+                "{} {}",
+                request,
+                response
+            );
+        }
+    }
+}
@@ -0,0 +1,472 @@
+use crate::packet::Packet;
+use crate::r#type::RRType;
+use crate::tcp;
+use crate::tls::ClientTls;
+use quinn::crypto::rustls::QuicClientConfig;
+use quinn::{ReadError, ReadToEndError, VarInt};
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::runtime::{self, Runtime};
+use tokio::sync::watch;
+use tokio::time::{self, Instant};
+
+pub const ALPN: &[u8] = b"doq";
+pub const PORT: u16 = 853;
+// a message and its length
+pub const MAX_STREAM: usize = u16::MAX as usize + 2;
+
+// the option is meaningless over QUIC and must not be sent, RFC 9250
+// section 4.2.2
+const TCP_KEEPALIVE: u16 = 11;
+
+// application error codes used to close streams and connections, RFC 9250
+// section 4.3
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorCode {
+    NoError,
+    InternalError,
+    ProtocolError,
+    RequestCancelled,
+    ExcessiveLoad,
+    UnspecifiedError,
+    ErrorReserved,
+    UNKNOWN,
+}
+
+impl ErrorCode {
+    pub fn from_value(value: u64) -> Self {
+        match value {
+            0x0 => ErrorCode::NoError,
+            0x1 => ErrorCode::InternalError,
+            0x2 => ErrorCode::ProtocolError,
+            0x3 => ErrorCode::RequestCancelled,
+            0x4 => ErrorCode::ExcessiveLoad,
+            0x5 => ErrorCode::UnspecifiedError,
+            0xd098ea5e => ErrorCode::ErrorReserved,
+            _ => ErrorCode::UNKNOWN,
+        }
+    }
+
+    // unknown codes are treated as unspecified errors
+    pub fn to_value(&self) -> u64 {
+        match self {
+            ErrorCode::NoError => 0x0,
+            ErrorCode::InternalError => 0x1,
+            ErrorCode::ProtocolError => 0x2,
+            ErrorCode::RequestCancelled => 0x3,
+            ErrorCode::ExcessiveLoad => 0x4,
+            ErrorCode::UnspecifiedError | ErrorCode::UNKNOWN => 0x5,
+            ErrorCode::ErrorReserved => 0xd098ea5e,
+        }
+    }
+
+    // every code fits in 32 bits
+    pub fn var_int(&self) -> VarInt {
+        VarInt::from_u32(self.to_value() as u32)
+    }
+}
+
+// a DoQ client for one server, keeping its connection for the queries that
+// follow, shared by clones
+#[derive(Debug, Clone)]
+pub struct Quic {
+    // with ALPN for DoQ
+    pub tls: ClientTls,
+    runtime: Arc<Runtime>,
+    connection: Arc<Mutex<Option<Connection>>>,
+}
+
+#[derive(Debug, Clone)]
+struct Connection {
+    connection: quinn::Connection,
+    endpoint: quinn::Endpoint,
+    // None until the handshake is done, then whether the server took what
+    // was sent in 0-RTT; connections made without it start out done
+    zero_rtt: watch::Receiver<Option<bool>>,
+}
+
+impl Quic {
+    pub fn new(tls: ClientTls) -> io::Result<Self> {
+        let runtime = runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()?;
+        Ok(Self {
+            tls,
+            runtime: Arc::new(runtime),
+            connection: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    // sends the query on a stream of its own and returns the response's
+    // message, along with the local address it went from
+    pub fn exchange(
+        &self,
+        server: SocketAddr,
+        query: &Packet,
+        timeout: Duration,
+    ) -> io::Result<(Vec<u8>, SocketAddr)> {
+        let message = encode_message(query);
+        let replayable = allowed_in_early_data(query);
+        self.runtime.block_on(async {
+            let deadline = Instant::now() + timeout;
+            let idle = self.connection.lock().unwrap().clone();
+            let idle = idle.filter(|connection| connection.connection.close_reason().is_none());
+            if let Some(connection) = idle {
+                match self.send(&connection, &message, replayable, deadline).await {
+                    Err(e) if e.kind() != io::ErrorKind::TimedOut => {}
+                    result => return result.map(|response| (response, connection.local())),
+                }
+            }
+            let connect = self.connect(server, replayable);
+            let connection = time::timeout_at(deadline, connect)
+                .await
+                .map_err(|_| timed_out())??;
+            *self.connection.lock().unwrap() = Some(connection.clone());
+            let response = match self.send(&connection, &message, replayable, deadline).await {
+                // what went in 0-RTT is lost if the server turned it down,
+                // and has to be sent again now the handshake is done
+                Err(_) if connection.rejected_0rtt().await => {
+                    self.send(&connection, &message, replayable, deadline).await
+                }
+                result => result,
+            };
+            response.map(|response| (response, connection.local()))
+        })
+    }
+
+    // RFC 9250 section 4.5: only queries that are safe to replay go in
+    // 0-RTT, when there's a session to resume
+    async fn connect(&self, server: SocketAddr, replayable: bool) -> io::Result<Connection> {
+        let local: SocketAddr = match server {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let mut endpoint = quinn::Endpoint::client(local)?;
+        let mut tls = (*self.tls.config()).clone();
+        tls.enable_early_data = true;
+        let crypto = QuicClientConfig::try_from(tls).map_err(invalid)?;
+        endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(crypto)));
+
+        let connecting = endpoint
+            .connect(server, &self.tls.server_name)
+            .map_err(invalid)?;
+        let attempt = match replayable {
+            true => connecting.into_0rtt(),
+            false => Err(connecting),
+        };
+        let (done, zero_rtt) = watch::channel(None);
+        let connection = match attempt {
+            Ok((connection, accepted)) => {
+                tokio::spawn(async move {
+                    let _ = done.send(Some(accepted.await));
+                });
+                connection
+            }
+            Err(connecting) => {
+                let connection = connecting.await.map_err(io::Error::other)?;
+                let _ = done.send(Some(true));
+                connection
+            }
+        };
+        Ok(Connection {
+            connection,
+            endpoint,
+            zero_rtt,
+        })
+    }
+
+    async fn send(
+        &self,
+        connection: &Connection,
+        message: &[u8],
+        replayable: bool,
+        deadline: Instant,
+    ) -> io::Result<Vec<u8>> {
+        // a query that isn't safe to replay waits for the handshake
+        if !replayable {
+            let mut zero_rtt = connection.zero_rtt.clone();
+            time::timeout_at(deadline, zero_rtt.wait_for(Option::is_some))
+                .await
+                .map_err(|_| timed_out())?
+                .map_err(io::Error::other)?;
+        }
+        let (mut send, mut recv) = connection
+            .connection
+            .open_bi()
+            .await
+            .map_err(io::Error::other)?;
+        send.write_all(message).await?;
+        send.finish().map_err(io::Error::other)?;
+        let framed = match time::timeout_at(deadline, recv.read_to_end(MAX_STREAM)).await {
+            Ok(Ok(framed)) => framed,
+            Ok(Err(ReadToEndError::Read(ReadError::Reset(code)))) => {
+                let code = ErrorCode::from_value(code.into_inner());
+                return Err(invalid(format!("server reset the stream with {:?}", code)));
+            }
+            Ok(Err(e)) => return Err(io::Error::other(e)),
+            // RFC 9250 section 4.3: the server is told the answer isn't wanted
+            Err(_) => {
+                let _ = send.reset(ErrorCode::RequestCancelled.var_int());
+                let _ = recv.stop(ErrorCode::RequestCancelled.var_int());
+                return Err(timed_out());
+            }
+        };
+        match decode_message(&framed) {
+            Ok(response) => Ok(response.to_vec()),
+            Err(code) => {
+                connection.connection.close(code.var_int(), b"");
+                Err(invalid("response breaks the DoQ rules"))
+            }
+        }
+    }
+}
+
+impl Connection {
+    fn local(&self) -> SocketAddr {
+        self.endpoint
+            .local_addr()
+            .unwrap_or_else(|_| (Ipv4Addr::UNSPECIFIED, 0).into())
+    }
+
+    async fn rejected_0rtt(&self) -> bool {
+        let mut zero_rtt = self.zero_rtt.clone();
+        let accepted = zero_rtt
+            .wait_for(Option::is_some)
+            .await
+            .ok()
+            .map(|accepted| *accepted);
+        accepted == Some(Some(false))
+    }
+}
+
+// each stream carries exactly one message, framed as over TCP, with the
+// message ID set to zero
+pub fn encode_message(packet: &Packet) -> Vec<u8> {
+    let mut packet = packet.clone();
+    packet.header.identifier = 0;
+    let mut framed = Vec::new();
+    tcp::write_message(&mut framed, &packet.to_bytes()).unwrap();
+    framed
+}
+
+// the error a peer's stream should be reset with, if its message breaks
+// the rules of the transport
+pub fn check_message(packet: &Packet) -> Result<(), ErrorCode> {
+    if packet.header.identifier != 0 {
+        return Err(ErrorCode::ProtocolError);
+    }
+    let keepalive = packet
+        .edns
+        .as_ref()
+        .is_some_and(|edns| edns.option(TCP_KEEPALIVE).is_some());
+    if keepalive {
+        return Err(ErrorCode::ProtocolError);
+    }
+    Ok(())
+}
+
+// the message on a stream, which has to be exactly the length it's
+// prefixed with, and keep to the rules above if it can be parsed at all;
+// one that can't is left for the server to answer with FORMERR
+pub fn decode_message(framed: &[u8]) -> Result<&[u8], ErrorCode> {
+    let [high, low, message @ ..] = framed else {
+        return Err(ErrorCode::ProtocolError);
+    };
+    if u16::from_be_bytes([*high, *low]) as usize != message.len() {
+        return Err(ErrorCode::ProtocolError);
+    }
+    if let Ok(packet) = Packet::try_from_buf(message) {
+        check_message(&packet)?;
+    }
+    Ok(message)
+}
+
+// RFC 9250 section 4.5: only plain queries may be sent in 0-RTT data, where
+// they could be replayed by an attacker
+pub fn allowed_in_early_data(query: &Packet) -> bool {
+    query.header.op_code == 0
+        && query
+            .questions
+            .iter()
+            .all(|question| !matches!(question.r#type, RRType::AXFR | RRType::IXFR))
+}
+
+fn timed_out() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "no response from server")
+}
+
+fn invalid(e: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::class::RRClass;
+    use crate::client::{self, Client};
+    use crate::edns::{Edns, EdnsOption};
+    use crate::header::Header;
+    use crate::question::Question;
+    use crate::server::{self, Server};
+    use crate::tls;
+    use crate::zone::Zone;
+    use std::fs;
+    use std::net::UdpSocket;
+
+    fn query(r#type: RRType) -> Packet {
+        let mut query = Packet::new(Header::new(0x1234));
+        query
+            .questions
+            .push(Question::new("example.com", r#type, RRClass::IN));
+        query
+    }
+
+    #[test]
+    fn test_error_codes() {
+        for value in [0x0, 0x1, 0x2, 0x3, 0x4, 0x5, 0xd098ea5e] {
+            assert_eq!(ErrorCode::from_value(value).to_value(), value);
+        }
+        assert_eq!(ErrorCode::from_value(0x6), ErrorCode::UNKNOWN);
+    }
+
+    #[test]
+    fn test_encode_message() {
+        let query = query(RRType::A);
+        let framed = encode_message(&query);
+        let len = u16::from_be_bytes([framed[0], framed[1]]) as usize;
+        assert_eq!(len, framed.len() - 2);
+
+        let decoded = Packet::from_buf(&framed[2..]);
+        assert_eq!(decoded.header.identifier, 0);
+        assert_eq!(check_message(&decoded), Ok(()));
+        assert_eq!(check_message(&query), Err(ErrorCode::ProtocolError));
+
+        let mut keepalive = decoded.clone();
+        let mut edns = Edns::new(1232);
+        edns.set_option(EdnsOption::Unknown(TCP_KEEPALIVE, vec![]));
+        keepalive.edns = Some(edns);
+        assert_eq!(check_message(&keepalive), Err(ErrorCode::ProtocolError));
+    }
+
+    #[test]
+    fn test_decode_message() {
+        let framed = encode_message(&query(RRType::A));
+        assert_eq!(decode_message(&framed), Ok(&framed[2..]));
+        assert_eq!(decode_message(&framed[..1]), Err(ErrorCode::ProtocolError));
+        let short = &framed[..framed.len() - 1];
+        assert_eq!(decode_message(short), Err(ErrorCode::ProtocolError));
+
+        let mut framed = Vec::new();
+        tcp::write_message(&mut framed, &query(RRType::A).to_bytes()).unwrap();
+        assert_eq!(decode_message(&framed), Err(ErrorCode::ProtocolError));
+        // left for the server to answer with FORMERR
+        assert_eq!(decode_message(&[0, 3, 0, 0, 1]), Ok(&[0, 0, 1][..]));
+    }
+
+    #[test]
+    fn test_early_data() {
+        assert!(allowed_in_early_data(&query(RRType::A)));
+        assert!(!allowed_in_early_data(&query(RRType::AXFR)));
+        assert!(!allowed_in_early_data(&query(RRType::IXFR)));
+
+        let mut update = query(RRType::SOA);
+        update.header.op_code = 5;
+        assert!(!allowed_in_early_data(&update));
+    }
+
+    // a DoQ server with a self-signed certificate for localhost, answering
+    // from one zone, and the pin for its key
+    fn quic_server() -> (SocketAddr, Vec<u8>) {
+        let dir = std::env::temp_dir().join(format!(
+            "dns-rs-doq-{}-{}",
+            std::process::id(),
+            client::random_id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let (certificate, private_key) = (dir.join("cert.pem"), dir.join("key.pem"));
+        fs::write(&certificate, certified.cert.pem()).unwrap();
+        fs::write(&private_key, certified.signing_key.serialize_pem()).unwrap();
+        let config = tls::server_config(&certificate, &private_key, &[ALPN]).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let mut server = Server::new();
+        let zone = "$TTL 300\n@ SOA ns1 hostmaster 1 7200 3600 1209600 60\n@ NS ns1\nns1 A 192.0.2.53\nwww A 192.0.2.1\n";
+        server.add_zone(Zone::from_text("example.com", zone).unwrap());
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        server::serve_quic(socket, config, Arc::new(server)).unwrap();
+        (address, tls::spki_pin(certified.cert.der()).unwrap())
+    }
+
+    fn local_port(quic: &Quic) -> u16 {
+        let connection = quic.connection.lock().unwrap();
+        connection.as_ref().unwrap().local().port()
+    }
+
+    #[test]
+    fn test_client() {
+        let (address, pin) = quic_server();
+        let tls = ClientTls::pinned("localhost", vec![pin], ALPN).unwrap();
+        let quic = Quic::new(tls.clone()).unwrap();
+        let client = Client::with_quic(address, quic.clone());
+
+        let query = client::build_query("www.example.com", RRType::A, RRClass::IN);
+        let response = client.query(&query).unwrap();
+        assert_eq!(response.packet.header.identifier, query.header.identifier);
+        assert!(response.packet.header.is_authoritative);
+        assert_eq!(response.packet.answers[0].data, vec![192, 0, 2, 1]);
+        let port = local_port(&quic);
+
+        // another stream on the same connection, for a query that has to
+        // wait for the handshake
+        let mut update = client::build_query("example.com", RRType::SOA, RRClass::IN);
+        update.header.op_code = 5;
+        let response = client.query(&update).unwrap();
+        assert_eq!(response.packet.rcode(), 5);
+        assert_eq!(local_port(&quic), port);
+
+        // a new connection resumes the session, in 0-RTT
+        let quic = Quic::new(tls).unwrap();
+        let response = Client::with_quic(address, quic.clone()).query(&query);
+        assert_eq!(response.unwrap().packet.answers.len(), 1);
+        let connection = quic.connection.lock().unwrap().clone().unwrap();
+        assert!(!quic.runtime.block_on(connection.rejected_0rtt()));
+    }
+
+    #[test]
+    fn test_errors() {
+        let (address, pin) = quic_server();
+        let tls = ClientTls::pinned("localhost", vec![pin], ALPN).unwrap();
+        let quic = Quic::new(tls).unwrap();
+        let deadline = || Instant::now() + Duration::from_secs(5);
+
+        // a response where the query should be isn't answered
+        let mut response = query(RRType::A);
+        response.header.response = true;
+        let err = quic.exchange(address, &response, Duration::from_secs(5));
+        let err = err.unwrap_err().to_string();
+        assert!(err.contains("InternalError"), "{}", err);
+
+        // a message ID other than zero closes the connection
+        quic.runtime.block_on(async {
+            let connection = quic.connect(address, true).await.unwrap();
+            let mut framed = Vec::new();
+            tcp::write_message(&mut framed, &query(RRType::A).to_bytes()).unwrap();
+            assert!(quic
+                .send(&connection, &framed, true, deadline())
+                .await
+                .is_err());
+            let reason = connection.connection.closed().await;
+            assert!(
+                matches!(reason, quinn::ConnectionError::ApplicationClosed(ref close)
+                    if close.error_code == ErrorCode::ProtocolError.var_int()),
+                "{:?}",
+                reason
+            );
+        });
+    }
+}
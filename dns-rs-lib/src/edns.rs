@@ -0,0 +1,324 @@
+use crate::buf_reader::BufReader;
+use crate::client_subnet::ClientSubnet;
+use crate::cookie::Cookie;
+use crate::extended_error::ExtendedError;
+use crate::r#type::RRType;
+use crate::rdata;
+use std::convert::TryInto;
+use std::fmt;
+
+pub const CLIENT_SUBNET: u16 = 8;
+pub const COOKIE: u16 = 10;
+pub const PADDING: u16 = 12;
+pub const EXTENDED_ERROR: u16 = 15;
+
+// the block-length padding policy recommended by RFC 8467 section 4.1
+pub const QUERY_PADDING_BLOCK: usize = 128;
+pub const RESPONSE_PADDING_BLOCK: usize = 468;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edns {
+    pub udp_payload_size: u16,
+    pub extended_rcode: u8,
+    pub version: u8,
+    pub dnssec_ok: bool,
+    pub options: Vec<EdnsOption>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EdnsOption {
+    ClientSubnet(ClientSubnet),
+    Cookie(Cookie),
+    ExtendedError(ExtendedError),
+    Padding(u16),
+    Unknown(u16, Vec<u8>),
+}
+
+impl Edns {
+    pub fn new(udp_payload_size: u16) -> Self {
+        Self {
+            udp_payload_size,
+            extended_rcode: 0,
+            version: 0,
+            dnssec_ok: false,
+            options: Vec::new(),
+        }
+    }
+
+    // peeks at the next record to see if it's an OPT pseudo-record, which
+    // always has the root as its owner
+    pub fn is_opt(buf: &mut BufReader) -> bool {
+        let started_at = buf.pos;
+        let name = buf.read(1)[0];
+        let r#type = Self::read_u16(buf);
+        buf.goto(started_at);
+        name == 0 && RRType::from_value(r#type) == RRType::OPT
+    }
+
+    pub fn from_buf(buf: &mut BufReader) -> Self {
+        // skip the root name and the type
+        buf.read(3);
+        let udp_payload_size = Self::read_u16(buf);
+        let extended_rcode = buf.read(1)[0];
+        let version = buf.read(1)[0];
+        let flags = Self::read_u16(buf);
+        let len = Self::read_u16(buf) as usize;
+
+        let end = buf.pos + len;
+        let mut options = Vec::new();
+        while buf.pos + 4 <= end {
+            options.push(EdnsOption::from_buf(buf));
+        }
+        buf.goto(end);
+
+        Self {
+            udp_payload_size,
+            extended_rcode,
+            version,
+            dnssec_ok: flags & 0b1000000000000000 != 0,
+            options,
+        }
+    }
+
+    fn read_u16(buf: &mut BufReader) -> u16 {
+        let bytes: &[u8] = buf.read(2);
+        u16::from_be_bytes(bytes.try_into().unwrap())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        for option in &self.options {
+            data.extend(option.to_bytes());
+        }
+
+        let mut bytes = vec![0];
+        bytes.extend_from_slice(&RRType::OPT.to_value().to_be_bytes());
+        bytes.extend_from_slice(&self.udp_payload_size.to_be_bytes());
+        bytes.push(self.extended_rcode);
+        bytes.push(self.version);
+        let flags: u16 = if self.dnssec_ok {
+            0b1000000000000000
+        } else {
+            0
+        };
+        bytes.extend_from_slice(&flags.to_be_bytes());
+        bytes.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        bytes.extend(data);
+        bytes
+    }
+
+    pub fn option(&self, code: u16) -> Option<&EdnsOption> {
+        self.options.iter().find(|option| option.code() == code)
+    }
+
+    pub fn cookie(&self) -> Option<&Cookie> {
+        self.options.iter().find_map(|option| match option {
+            EdnsOption::Cookie(cookie) => Some(cookie),
+            _ => None,
+        })
+    }
+
+    pub fn client_subnet(&self) -> Option<&ClientSubnet> {
+        self.options.iter().find_map(|option| match option {
+            EdnsOption::ClientSubnet(subnet) => Some(subnet),
+            _ => None,
+        })
+    }
+
+    // a response may carry several extended errors
+    pub fn extended_errors(&self) -> Vec<&ExtendedError> {
+        self.options
+            .iter()
+            .filter_map(|option| match option {
+                EdnsOption::ExtendedError(error) => Some(error),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // replaces any existing option with the same code
+    pub fn set_option(&mut self, option: EdnsOption) {
+        self.options
+            .retain(|existing| existing.code() != option.code());
+        self.options.push(option);
+    }
+}
+
+impl EdnsOption {
+    pub fn from_buf(buf: &mut BufReader) -> Self {
+        let code = Edns::read_u16(buf);
+        let len = Edns::read_u16(buf) as usize;
+        let data = buf.read(len);
+        match code {
+            CLIENT_SUBNET => match ClientSubnet::from_data(data) {
+                Some(subnet) => EdnsOption::ClientSubnet(subnet),
+                None => EdnsOption::Unknown(code, data.to_vec()),
+            },
+            COOKIE => match Cookie::from_data(data) {
+                Some(cookie) => EdnsOption::Cookie(cookie),
+                None => EdnsOption::Unknown(code, data.to_vec()),
+            },
+            // the contents of padding are meaningless, only its length matters
+            PADDING => EdnsOption::Padding(len as u16),
+            EXTENDED_ERROR => match ExtendedError::from_data(data) {
+                Some(error) => EdnsOption::ExtendedError(error),
+                None => EdnsOption::Unknown(code, data.to_vec()),
+            },
+            _ => EdnsOption::Unknown(code, data.to_vec()),
+        }
+    }
+
+    pub fn code(&self) -> u16 {
+        match self {
+            EdnsOption::ClientSubnet(_) => CLIENT_SUBNET,
+            EdnsOption::Cookie(_) => COOKIE,
+            EdnsOption::ExtendedError(_) => EXTENDED_ERROR,
+            EdnsOption::Padding(_) => PADDING,
+            EdnsOption::Unknown(code, _) => *code,
+        }
+    }
+
+    pub fn data(&self) -> Vec<u8> {
+        match self {
+            EdnsOption::ClientSubnet(subnet) => subnet.to_data(),
+            EdnsOption::Cookie(cookie) => cookie.to_data(),
+            EdnsOption::ExtendedError(error) => error.to_data(),
+            EdnsOption::Padding(len) => vec![0; *len as usize],
+            EdnsOption::Unknown(_, data) => data.clone(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let data = self.data();
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&self.code().to_be_bytes());
+        bytes.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        bytes.extend(data);
+        bytes
+    }
+}
+
+// the OPT pseudo-section as dig shows it
+impl fmt::Display for Edns {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, ";; OPT PSEUDOSECTION:")?;
+        let flags = if self.dnssec_ok { " do" } else { "" };
+        write!(
+            f,
+            "; EDNS: version: {}, flags:{}; udp: {}",
+            self.version, flags, self.udp_payload_size
+        )?;
+        for option in &self.options {
+            write!(f, "\n{}", option)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for EdnsOption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EdnsOption::ClientSubnet(subnet) => write!(
+                f,
+                "; CLIENT-SUBNET: {}/{}/{}",
+                subnet.address, subnet.source_prefix, subnet.scope_prefix
+            ),
+            EdnsOption::Cookie(cookie) => write!(
+                f,
+                "; COOKIE: {}{}",
+                rdata::to_hex(&cookie.client).to_ascii_lowercase(),
+                rdata::to_hex(&cookie.server).to_ascii_lowercase()
+            ),
+            EdnsOption::ExtendedError(error) => write!(f, "; EDE: {}", error),
+            EdnsOption::Padding(len) => write!(f, "; PAD: ({} bytes)", len),
+            EdnsOption::Unknown(code, data) => {
+                write!(f, "; OPT={}: {}", code, rdata::to_hex(data))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extended_error::InfoCode;
+
+    #[test]
+    fn test_parsing_opt() {
+        let opt = vec![
+            0b00000000, 0b00000000, 0b00101001, 0b00010000, 0b00000000, 0b00000000, 0b00000000,
+            0b10000000, 0b00000000, 0b00000000, 0b00001100, 0b00000000, 0b00001010, 0b00000000,
+            0b00001000, 0b00100100, 0b01100100, 0b11000100, 0b10101011, 0b11001111, 0b00010000,
+            0b11001001, 0b01010111,
+        ];
+        let mut buf = BufReader::new(&opt);
+        assert!(Edns::is_opt(&mut buf));
+        let edns = Edns::from_buf(&mut buf);
+        assert_eq!(edns.udp_payload_size, 4096);
+        assert_eq!(edns.version, 0);
+        assert!(edns.dnssec_ok);
+        assert_eq!(
+            edns.cookie().unwrap().client,
+            [0x24, 0x64, 0xc4, 0xab, 0xcf, 0x10, 0xc9, 0x57]
+        );
+        assert!(edns.cookie().unwrap().server.is_empty());
+        assert_eq!(buf.pos, opt.len());
+
+        assert_eq!(edns.to_bytes(), opt);
+        assert_eq!(
+            edns.to_string(),
+            ";; OPT PSEUDOSECTION:\n\
+             ; EDNS: version: 0, flags: do; udp: 4096\n\
+             ; COOKIE: 2464c4abcf10c957"
+        );
+    }
+
+    #[test]
+    fn test_client_subnet_option() {
+        let mut edns = Edns::new(1232);
+        let subnet = ClientSubnet::new("192.0.2.1".parse().unwrap(), 24);
+        edns.set_option(EdnsOption::ClientSubnet(subnet.clone()));
+        let bytes = edns.to_bytes();
+        let mut buf = BufReader::new(&bytes);
+        let edns = Edns::from_buf(&mut buf);
+        assert_eq!(edns.client_subnet(), Some(&subnet));
+    }
+
+    #[test]
+    fn test_extended_error_options() {
+        let mut edns = Edns::new(1232);
+        edns.options
+            .push(EdnsOption::ExtendedError(ExtendedError::new(
+                InfoCode::NetworkError,
+                "192.0.2.53 timed out",
+            )));
+        edns.options
+            .push(EdnsOption::ExtendedError(ExtendedError::new(
+                InfoCode::NoReachableAuthority,
+                "",
+            )));
+        let bytes = edns.to_bytes();
+        let mut buf = BufReader::new(&bytes);
+        let edns = Edns::from_buf(&mut buf);
+        let errors = edns.extended_errors();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].info_code(), InfoCode::NetworkError);
+        assert_eq!(errors[0].extra_text, "192.0.2.53 timed out");
+        assert_eq!(errors[1].info_code(), InfoCode::NoReachableAuthority);
+    }
+
+    #[test]
+    fn test_malformed_cookie_is_kept_raw() {
+        let mut edns = Edns::new(1232);
+        edns.options
+            .push(EdnsOption::Unknown(COOKIE, vec![1, 2, 3]));
+        let bytes = edns.to_bytes();
+        let mut buf = BufReader::new(&bytes);
+        let edns = Edns::from_buf(&mut buf);
+        assert!(edns.cookie().is_none());
+        assert_eq!(
+            edns.option(COOKIE),
+            Some(&EdnsOption::Unknown(COOKIE, vec![1, 2, 3]))
+        );
+    }
+}
@@ -0,0 +1,195 @@
+use std::fmt;
+
+// the registry of info codes from RFC 8914 section 5.2 and its later
+// additions at https://www.iana.org/assignments/dns-parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InfoCode {
+    Other = 0,
+    UnsupportedDnskeyAlgorithm = 1,
+    UnsupportedDsDigestType = 2,
+    StaleAnswer = 3,
+    ForgedAnswer = 4,
+    DnssecIndeterminate = 5,
+    DnssecBogus = 6,
+    SignatureExpired = 7,
+    SignatureNotYetValid = 8,
+    DnskeyMissing = 9,
+    RrsigsMissing = 10,
+    NoZoneKeyBitSet = 11,
+    NsecMissing = 12,
+    CachedError = 13,
+    NotReady = 14,
+    Blocked = 15,
+    Censored = 16,
+    Filtered = 17,
+    Prohibited = 18,
+    StaleNxdomainAnswer = 19,
+    NotAuthoritative = 20,
+    NotSupported = 21,
+    NoReachableAuthority = 22,
+    NetworkError = 23,
+    InvalidData = 24,
+    SignatureExpiredBeforeValid = 25,
+    TooEarly = 26,
+    UnsupportedNsec3IterationsValue = 27,
+    UnableToConformToPolicy = 28,
+    Synthesized = 29,
+    InvalidQueryType = 30,
+    UNKNOWN,
+}
+
+impl InfoCode {
+    pub fn from_value(value: u16) -> Self {
+        match value {
+            0 => InfoCode::Other,
+            1 => InfoCode::UnsupportedDnskeyAlgorithm,
+            2 => InfoCode::UnsupportedDsDigestType,
+            3 => InfoCode::StaleAnswer,
+            4 => InfoCode::ForgedAnswer,
+            5 => InfoCode::DnssecIndeterminate,
+            6 => InfoCode::DnssecBogus,
+            7 => InfoCode::SignatureExpired,
+            8 => InfoCode::SignatureNotYetValid,
+            9 => InfoCode::DnskeyMissing,
+            10 => InfoCode::RrsigsMissing,
+            11 => InfoCode::NoZoneKeyBitSet,
+            12 => InfoCode::NsecMissing,
+            13 => InfoCode::CachedError,
+            14 => InfoCode::NotReady,
+            15 => InfoCode::Blocked,
+            16 => InfoCode::Censored,
+            17 => InfoCode::Filtered,
+            18 => InfoCode::Prohibited,
+            19 => InfoCode::StaleNxdomainAnswer,
+            20 => InfoCode::NotAuthoritative,
+            21 => InfoCode::NotSupported,
+            22 => InfoCode::NoReachableAuthority,
+            23 => InfoCode::NetworkError,
+            24 => InfoCode::InvalidData,
+            25 => InfoCode::SignatureExpiredBeforeValid,
+            26 => InfoCode::TooEarly,
+            27 => InfoCode::UnsupportedNsec3IterationsValue,
+            28 => InfoCode::UnableToConformToPolicy,
+            29 => InfoCode::Synthesized,
+            30 => InfoCode::InvalidQueryType,
+            _ => InfoCode::UNKNOWN,
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            InfoCode::Other => "Other Error",
+            InfoCode::UnsupportedDnskeyAlgorithm => "Unsupported DNSKEY Algorithm",
+            InfoCode::UnsupportedDsDigestType => "Unsupported DS Digest Type",
+            InfoCode::StaleAnswer => "Stale Answer",
+            InfoCode::ForgedAnswer => "Forged Answer",
+            InfoCode::DnssecIndeterminate => "DNSSEC Indeterminate",
+            InfoCode::DnssecBogus => "DNSSEC Bogus",
+            InfoCode::SignatureExpired => "Signature Expired",
+            InfoCode::SignatureNotYetValid => "Signature Not Yet Valid",
+            InfoCode::DnskeyMissing => "DNSKEY Missing",
+            InfoCode::RrsigsMissing => "RRSIGs Missing",
+            InfoCode::NoZoneKeyBitSet => "No Zone Key Bit Set",
+            InfoCode::NsecMissing => "NSEC Missing",
+            InfoCode::CachedError => "Cached Error",
+            InfoCode::NotReady => "Not Ready",
+            InfoCode::Blocked => "Blocked",
+            InfoCode::Censored => "Censored",
+            InfoCode::Filtered => "Filtered",
+            InfoCode::Prohibited => "Prohibited",
+            InfoCode::StaleNxdomainAnswer => "Stale NXDOMAIN Answer",
+            InfoCode::NotAuthoritative => "Not Authoritative",
+            InfoCode::NotSupported => "Not Supported",
+            InfoCode::NoReachableAuthority => "No Reachable Authority",
+            InfoCode::NetworkError => "Network Error",
+            InfoCode::InvalidData => "Invalid Data",
+            InfoCode::SignatureExpiredBeforeValid => "Signature Expired before Valid",
+            InfoCode::TooEarly => "Too Early",
+            InfoCode::UnsupportedNsec3IterationsValue => "Unsupported NSEC3 Iterations Value",
+            InfoCode::UnableToConformToPolicy => "Unable to conform to policy",
+            InfoCode::Synthesized => "Synthesized",
+            InfoCode::InvalidQueryType => "Invalid Query Type",
+            InfoCode::UNKNOWN => "Unknown",
+        }
+    }
+}
+
+// the raw code is kept so codes registered after this was written survive
+// being parsed and written back out
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtendedError {
+    pub code: u16,
+    pub extra_text: String,
+}
+
+impl ExtendedError {
+    pub fn new(info_code: InfoCode, extra_text: &str) -> Self {
+        Self {
+            code: info_code as u16,
+            extra_text: extra_text.to_string(),
+        }
+    }
+
+    pub fn info_code(&self) -> InfoCode {
+        InfoCode::from_value(self.code)
+    }
+
+    // the extra text isn't required to be null terminated, but a trailing
+    // null is tolerated and dropped
+    pub fn from_data(data: &[u8]) -> Option<Self> {
+        if data.len() < 2 {
+            return None;
+        }
+        let text = &data[2..];
+        let text = text.strip_suffix(&[0]).unwrap_or(text);
+        Some(Self {
+            code: u16::from_be_bytes([data[0], data[1]]),
+            extra_text: String::from_utf8_lossy(text).into_owned(),
+        })
+    }
+
+    pub fn to_data(&self) -> Vec<u8> {
+        let mut data = self.code.to_be_bytes().to_vec();
+        data.extend_from_slice(self.extra_text.as_bytes());
+        data
+    }
+}
+
+impl fmt::Display for ExtendedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.code, self.info_code().description())?;
+        if !self.extra_text.is_empty() {
+            write!(f, ": {}", self.extra_text)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_info_codes() {
+        for value in 0..=30 {
+            assert_eq!(InfoCode::from_value(value) as u16, value);
+        }
+        assert_eq!(InfoCode::from_value(31), InfoCode::UNKNOWN);
+    }
+
+    #[test]
+    fn test_option_data() {
+        let error = ExtendedError::new(InfoCode::DnssecBogus, "no valid RRSIG");
+        let data = error.to_data();
+        assert_eq!(&data[..2], &[0, 6]);
+        assert_eq!(ExtendedError::from_data(&data), Some(error.clone()));
+        assert_eq!(error.to_string(), "6 (DNSSEC Bogus): no valid RRSIG");
+
+        let error = ExtendedError::from_data(&[0, 99, b'h', b'i', 0]).unwrap();
+        assert_eq!(error.info_code(), InfoCode::UNKNOWN);
+        assert_eq!(error.extra_text, "hi");
+        assert_eq!(error.to_data(), vec![0, 99, b'h', b'i']);
+
+        assert_eq!(ExtendedError::from_data(&[0]), None);
+    }
+}
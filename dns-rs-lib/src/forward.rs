@@ -0,0 +1,454 @@
+use crate::class::RRClass;
+use crate::client::{self, Client};
+use crate::packet::Packet;
+use crate::r#type::RRType;
+use crate::rcode::RCode;
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// shorter than a stub's, there are other upstreams to fail over to
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+pub const DEFAULT_RETRIES: u32 = 1;
+pub const DEFAULT_PROBE_INTERVAL: Duration = Duration::from_secs(30);
+// failures in a row before an upstream counts as down, so one lost packet or
+// a SERVFAIL for one broken zone doesn't take it out
+pub const DEFAULT_MAX_FAILURES: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Health {
+    // None until the first answer, so new upstreams get tried early
+    srtt: Option<Duration>,
+    up: bool,
+    failures: u64,
+    // since the last answer
+    consecutive_failures: u32,
+}
+
+#[derive(Debug)]
+pub struct Upstream {
+    pub client: Client,
+    pub max_failures: u32,
+    health: Mutex<Health>,
+}
+
+// upstreams that answer for the same names, tried fastest first
+#[derive(Debug, Default)]
+pub struct Pool {
+    pub upstreams: Vec<Upstream>,
+}
+
+// forwards everything to the default pool, except names under a zone with
+// forwarders of its own
+#[derive(Debug, Default)]
+pub struct Forwarder {
+    pub default: Pool,
+    zones: HashMap<String, Pool>,
+}
+
+impl Upstream {
+    pub fn new(server: SocketAddr) -> Self {
+        let mut client = Client::new(server);
+        client.timeout = DEFAULT_TIMEOUT;
+        client.retries = DEFAULT_RETRIES;
+        Self::from_client(client)
+    }
+
+    pub fn from_client(client: Client) -> Self {
+        Self {
+            client,
+            max_failures: DEFAULT_MAX_FAILURES,
+            health: Mutex::new(Health {
+                srtt: None,
+                up: true,
+                failures: 0,
+                consecutive_failures: 0,
+            }),
+        }
+    }
+
+    // smoothed the way TCP does it, RFC 6298: 7/8 of the old value and 1/8
+    // of the new sample
+    pub fn srtt(&self) -> Option<Duration> {
+        self.health.lock().unwrap().srtt
+    }
+
+    pub fn is_up(&self) -> bool {
+        self.health.lock().unwrap().up
+    }
+
+    pub fn failures(&self) -> u64 {
+        self.health.lock().unwrap().failures
+    }
+
+    pub fn record_success(&self, rtt: Duration) {
+        let mut health = self.health.lock().unwrap();
+        health.srtt = Some(match health.srtt {
+            Some(srtt) => (srtt * 7 + rtt) / 8,
+            None => rtt,
+        });
+        health.up = true;
+        health.consecutive_failures = 0;
+    }
+
+    // each failure doubles the RTT so the upstream falls behind the others,
+    // and after max_failures in a row it's down until it answers again
+    pub fn record_failure(&self) {
+        let mut health = self.health.lock().unwrap();
+        health.srtt = Some(match health.srtt {
+            Some(srtt) => (srtt * 2).min(self.client.timeout),
+            None => self.client.timeout,
+        });
+        health.failures += 1;
+        health.consecutive_failures = health.consecutive_failures.saturating_add(1);
+        if health.consecutive_failures >= self.max_failures {
+            health.up = false;
+        }
+    }
+
+    // any answer other than SERVFAIL shows the upstream is working
+    pub fn probe(&self) -> bool {
+        let mut query = client::build_query("", RRType::NS, RRClass::IN);
+        query.header.should_recurse = false;
+        match self.client.query(&query) {
+            Ok(response) if response.packet.rcode() != RCode::SERVFAIL as u16 => {
+                self.record_success(response.elapsed);
+                true
+            }
+            _ => {
+                self.record_failure();
+                false
+            }
+        }
+    }
+}
+
+impl Pool {
+    pub fn new(servers: &[SocketAddr]) -> Self {
+        Self {
+            upstreams: servers
+                .iter()
+                .map(|server| Upstream::new(*server))
+                .collect(),
+        }
+    }
+
+    // upstreams that are up by smoothed RTT, then the ones that are down as
+    // a last resort
+    pub fn order(&self) -> Vec<&Upstream> {
+        let mut upstreams: Vec<(&Upstream, Health)> = self
+            .upstreams
+            .iter()
+            .map(|upstream| (upstream, *upstream.health.lock().unwrap()))
+            .collect();
+        upstreams.sort_by_key(|(_, health)| (!health.up, health.srtt.unwrap_or_default()));
+        upstreams
+            .into_iter()
+            .map(|(upstream, _)| upstream)
+            .collect()
+    }
+
+    // fails over on timeouts, errors and SERVFAIL; if every upstream fails,
+    // the last SERVFAIL is passed on, or else the last error
+    pub fn forward(&self, query: &Packet) -> io::Result<Packet> {
+        let mut servfail = None;
+        let mut error = io::Error::new(io::ErrorKind::NotFound, "no upstreams to forward to");
+        for upstream in self.order() {
+            match upstream.client.query(query) {
+                Ok(response) if response.packet.rcode() == RCode::SERVFAIL as u16 => {
+                    upstream.record_failure();
+                    servfail = Some(response.packet);
+                }
+                Ok(response) => {
+                    upstream.record_success(response.elapsed);
+                    return Ok(response.packet);
+                }
+                Err(e) => {
+                    upstream.record_failure();
+                    error = e;
+                }
+            }
+        }
+        servfail.ok_or(error)
+    }
+
+    pub fn probe(&self) {
+        for upstream in &self.upstreams {
+            upstream.probe();
+        }
+    }
+}
+
+impl Forwarder {
+    pub fn new(default: Pool) -> Self {
+        Self {
+            default,
+            zones: HashMap::new(),
+        }
+    }
+
+    // a conditional forwarder for the zone and everything under it
+    pub fn add_zone(&mut self, zone: &str, pool: Pool) {
+        let zone = zone.trim_end_matches('.').to_ascii_lowercase();
+        self.zones.insert(zone, pool);
+    }
+
+    // the closest enclosing zone with forwarders, or the default pool
+    pub fn pool_for(&self, name: &str) -> &Pool {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        let mut suffix = name.as_str();
+        loop {
+            if let Some(pool) = self.zones.get(suffix) {
+                return pool;
+            }
+            match suffix.split_once('.') {
+                Some((_, parent)) => suffix = parent,
+                None if !suffix.is_empty() => suffix = "",
+                None => return &self.default,
+            }
+        }
+    }
+
+    pub fn forward(&self, query: &Packet) -> io::Result<Packet> {
+        let name = query
+            .questions
+            .first()
+            .map(|question| question.name.as_str())
+            .unwrap_or("");
+        self.pool_for(name).forward(query)
+    }
+
+    pub fn probe(&self) {
+        self.default.probe();
+        for pool in self.zones.values() {
+            pool.probe();
+        }
+    }
+
+    // probes every upstream each interval until the forwarder is dropped
+    pub fn spawn_probes(forwarder: &Arc<Forwarder>, interval: Duration) -> JoinHandle<()> {
+        let forwarder: Weak<Forwarder> = Arc::downgrade(forwarder);
+        thread::spawn(move || loop {
+            match forwarder.upgrade() {
+                Some(forwarder) => forwarder.probe(),
+                None => return,
+            }
+            thread::sleep(interval);
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::answer::Answer;
+    use std::net::UdpSocket;
+
+    // answers every query with the rcode, and 192.0.2.1 when that's NOERROR
+    fn server(rcode: RCode) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            while let Ok((len, from)) = socket.recv_from(&mut buf) {
+                let query = Packet::from_buf(&buf[..len]);
+                let mut header = query.header.clone();
+                header.response = true;
+                header.resp_code = rcode as u8;
+                let mut response = Packet::new(header);
+                response.questions = query.questions.clone();
+                if rcode == RCode::NOERROR {
+                    response.answers.push(Answer::new(
+                        &query.questions[0].name,
+                        RRType::A,
+                        RRClass::IN,
+                        300,
+                        vec![192, 0, 2, 1],
+                    ));
+                }
+                let _ = socket.send_to(&response.to_bytes(), from);
+            }
+        });
+        addr
+    }
+
+    // a socket that's bound but never answers, kept open by the caller
+    fn silent() -> (UdpSocket, SocketAddr) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        (socket, addr)
+    }
+
+    fn pool(servers: &[SocketAddr]) -> Pool {
+        let mut pool = Pool::new(servers);
+        for upstream in &mut pool.upstreams {
+            upstream.client.timeout = Duration::from_millis(100);
+            upstream.client.retries = 0;
+        }
+        pool
+    }
+
+    fn query(name: &str) -> Packet {
+        client::build_query(name, RRType::A, RRClass::IN)
+    }
+
+    #[test]
+    fn test_srtt() {
+        let upstream = Upstream::new("192.0.2.53:53".parse().unwrap());
+        assert_eq!(upstream.srtt(), None);
+        upstream.record_success(Duration::from_millis(80));
+        assert_eq!(upstream.srtt(), Some(Duration::from_millis(80)));
+        upstream.record_success(Duration::from_millis(160));
+        assert_eq!(upstream.srtt(), Some(Duration::from_millis(90)));
+        upstream.record_failure();
+        assert_eq!(upstream.srtt(), Some(Duration::from_millis(180)));
+        // one failure isn't enough to be down
+        assert!(upstream.is_up());
+        for _ in 0..10 {
+            upstream.record_failure();
+        }
+        assert_eq!(upstream.srtt(), Some(DEFAULT_TIMEOUT));
+        assert_eq!(upstream.failures(), 11);
+        assert!(!upstream.is_up());
+        upstream.record_success(Duration::from_millis(10));
+        assert!(upstream.is_up());
+        // and an answer starts the count again
+        for _ in 1..DEFAULT_MAX_FAILURES {
+            upstream.record_failure();
+        }
+        assert!(upstream.is_up());
+        upstream.record_failure();
+        assert!(!upstream.is_up());
+    }
+
+    #[test]
+    fn test_order() {
+        let pool = Pool::new(&[
+            "192.0.2.1:53".parse().unwrap(),
+            "192.0.2.2:53".parse().unwrap(),
+            "192.0.2.3:53".parse().unwrap(),
+        ]);
+        pool.upstreams[0].record_success(Duration::from_millis(50));
+        pool.upstreams[1].record_success(Duration::from_millis(10));
+        pool.upstreams[2].record_success(Duration::from_millis(1));
+        for _ in 0..DEFAULT_MAX_FAILURES {
+            pool.upstreams[2].record_failure();
+        }
+        let order: Vec<SocketAddr> = pool
+            .order()
+            .iter()
+            .map(|upstream| upstream.client.server)
+            .collect();
+        assert_eq!(
+            order,
+            vec![
+                pool.upstreams[1].client.server,
+                pool.upstreams[0].client.server,
+                pool.upstreams[2].client.server,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_failover() {
+        let (_socket, silent) = silent();
+        let pool = pool(&[silent, server(RCode::SERVFAIL), server(RCode::NOERROR)]);
+        let response = pool.forward(&query("www.example.com")).unwrap();
+        assert_eq!(response.answers[0].data, vec![192, 0, 2, 1]);
+        assert_eq!(pool.upstreams[0].failures(), 1);
+        assert_eq!(pool.upstreams[1].failures(), 1);
+        assert_eq!(pool.upstreams[2].failures(), 0);
+        // the working upstream is tried first from now on
+        assert_eq!(
+            pool.order()[0].client.server,
+            pool.upstreams[2].client.server
+        );
+
+        // with nothing working, a SERVFAIL beats a timeout
+        let pool = self::pool(&[server(RCode::SERVFAIL), silent]);
+        let response = pool.forward(&query("www.example.com")).unwrap();
+        assert_eq!(response.rcode(), RCode::SERVFAIL as u16);
+        let pool = self::pool(&[silent]);
+        assert!(pool.forward(&query("www.example.com")).is_err());
+    }
+
+    #[test]
+    fn test_down_after_repeated_failures() {
+        let pool = pool(&[server(RCode::SERVFAIL)]);
+        for _ in 1..DEFAULT_MAX_FAILURES {
+            pool.forward(&query("www.example.com")).unwrap();
+            assert!(pool.upstreams[0].is_up());
+        }
+        pool.forward(&query("www.example.com")).unwrap();
+        assert!(!pool.upstreams[0].is_up());
+    }
+
+    // answers with the query's ID but for another name, as a spoofer
+    // guessing IDs would
+    fn spoofer() -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            while let Ok((len, from)) = socket.recv_from(&mut buf) {
+                let query = Packet::from_buf(&buf[..len]);
+                let mut header = query.header.clone();
+                header.response = true;
+                let mut response = Packet::new(header);
+                response.questions = query.questions.clone();
+                response.questions[0].name = "attacker.example".to_string();
+                response.answers.push(Answer::new(
+                    &query.questions[0].name,
+                    RRType::A,
+                    RRClass::IN,
+                    300,
+                    vec![203, 0, 113, 66],
+                ));
+                let _ = socket.send_to(&response.to_bytes(), from);
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn test_ignores_mismatched_questions() {
+        let pool = pool(&[spoofer(), server(RCode::NOERROR)]);
+        let response = pool.forward(&query("www.example.com")).unwrap();
+        assert_eq!(response.answers[0].data, vec![192, 0, 2, 1]);
+        // the spoofed answer was never accepted, so that upstream timed out
+        assert_eq!(pool.upstreams[0].failures(), 1);
+    }
+
+    #[test]
+    fn test_conditional_forwarders() {
+        let mut forwarder = Forwarder::new(pool(&[server(RCode::NOERROR)]));
+        forwarder.add_zone("corp.example.", pool(&[server(RCode::NXDOMAIN)]));
+        forwarder.add_zone("lab.corp.example", pool(&[server(RCode::REFUSED)]));
+
+        let rcode = |name: &str| forwarder.forward(&query(name)).unwrap().rcode();
+        assert_eq!(rcode("www.example.com"), 0);
+        assert_eq!(rcode("corp.example"), 3);
+        assert_eq!(rcode("HOST.Corp.Example."), 3);
+        assert_eq!(rcode("host.lab.corp.example"), 5);
+        assert_eq!(rcode("notcorp.example"), 0);
+    }
+
+    #[test]
+    fn test_probes() {
+        let (_socket, silent) = silent();
+        let forwarder = Arc::new(Forwarder::new(pool(&[server(RCode::REFUSED), silent])));
+        let probes = Forwarder::spawn_probes(&forwarder, Duration::from_millis(10));
+        while forwarder.default.upstreams[1].is_up() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(forwarder.default.upstreams[0].is_up());
+        assert!(forwarder.default.upstreams[0].srtt().is_some());
+        assert!(!forwarder.default.upstreams[1].is_up());
+        // dropping the forwarder stops the probes
+        drop(forwarder);
+        probes.join().unwrap();
+    }
+}
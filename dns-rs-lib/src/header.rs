@@ -1,9 +1,17 @@
+use crate::opcode::OpCode;
 use crate::parser;
+use crate::rcode::RCode;
 use std::convert::TryInto;
+use std::fmt;
 
 use crate::buf_reader::BufReader;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "json",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "crate::json::HeaderJson", from = "crate::json::HeaderJson")
+)]
 pub struct Header {
     pub identifier: u16,
     pub query: bool,
@@ -14,6 +22,8 @@ pub struct Header {
     pub should_recurse: bool,
     pub can_recurse: bool,
     pub reserved: u8,
+    pub authentic_data: bool,
+    pub checking_disabled: bool,
     pub resp_code: u8,
     pub question_count: u16,
     pub answer_count: u16,
@@ -22,6 +32,27 @@ pub struct Header {
 }
 
 impl Header {
+    pub fn new(identifier: u16) -> Self {
+        Self {
+            identifier,
+            query: true,
+            response: false,
+            op_code: 0,
+            is_authoritative: false,
+            truncated: false,
+            should_recurse: false,
+            can_recurse: false,
+            reserved: 0,
+            authentic_data: false,
+            checking_disabled: false,
+            resp_code: 0,
+            question_count: 0,
+            answer_count: 0,
+            authority_count: 0,
+            additional_count: 0,
+        }
+    }
+
     pub fn from_buf(buf: &mut BufReader) -> Self {
         let header = buf.read(12);
         Self {
@@ -34,6 +65,8 @@ impl Header {
             should_recurse: Self::should_recurse(header),
             can_recurse: Self::can_recurse(header),
             reserved: Self::reserved(header),
+            authentic_data: Self::is_authentic_data(header),
+            checking_disabled: Self::is_checking_disabled(header),
             resp_code: Self::resp_code(header),
             question_count: Self::question_count(header),
             answer_count: Self::answer_count(header),
@@ -42,6 +75,68 @@ impl Header {
         }
     }
 
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&self.identifier.to_be_bytes());
+        bytes.push(
+            (self.response as u8) << 7
+                | (self.op_code & 0b1111) << 3
+                | (self.is_authoritative as u8) << 2
+                | (self.truncated as u8) << 1
+                | self.should_recurse as u8,
+        );
+        // the Z bit is the leftmost of the bits read into reserved
+        bytes.push(
+            (self.can_recurse as u8) << 7
+                | (self.reserved >> 3 & 1) << 6
+                | (self.authentic_data as u8) << 5
+                | (self.checking_disabled as u8) << 4
+                | self.resp_code & 0b1111,
+        );
+        bytes.extend_from_slice(&self.question_count.to_be_bytes());
+        bytes.extend_from_slice(&self.answer_count.to_be_bytes());
+        bytes.extend_from_slice(&self.authority_count.to_be_bytes());
+        bytes.extend_from_slice(&self.additional_count.to_be_bytes());
+        bytes
+    }
+
+    // the flags that are set, as dig shows them
+    pub fn flags(&self) -> Vec<&'static str> {
+        let flags = [
+            (self.response, "qr"),
+            (self.is_authoritative, "aa"),
+            (self.truncated, "tc"),
+            (self.should_recurse, "rd"),
+            (self.can_recurse, "ra"),
+            (self.authentic_data, "ad"),
+            (self.checking_disabled, "cd"),
+        ];
+        flags
+            .iter()
+            .filter(|(set, _)| *set)
+            .map(|(_, flag)| *flag)
+            .collect()
+    }
+
+    pub fn fmt_with_rcode(&self, f: &mut fmt::Formatter, rcode: u16) -> fmt::Result {
+        writeln!(
+            f,
+            ";; ->>HEADER<<- opcode: {}, status: {}, id: {}",
+            OpCode::from_value(self.op_code),
+            RCode::from_value(rcode),
+            self.identifier
+        )?;
+        write!(
+            f,
+            ";; flags: {}; QUERY: {}, ANSWER: {}, AUTHORITY: {}, ADDITIONAL: {}",
+            self.flags().join(" "),
+            self.question_count,
+            self.answer_count,
+            self.authority_count,
+            self.additional_count
+        )
+    }
+
     fn identifier(buf: &[u8]) -> u16 {
         let bytes = &buf[0..2];
         u16::from_be_bytes(bytes.try_into().unwrap())
@@ -103,6 +198,18 @@ impl Header {
         parser::bits_to_u8(my_bits)
     }
 
+    fn is_authentic_data(buf: &[u8]) -> bool {
+        let byte = &buf[3];
+        let bit: u8 = parser::bit_accessor(byte, 2);
+        bit == 1
+    }
+
+    fn is_checking_disabled(buf: &[u8]) -> bool {
+        let byte = &buf[3];
+        let bit: u8 = parser::bit_accessor(byte, 3);
+        bit == 1
+    }
+
     fn resp_code(buf: &[u8]) -> u8 {
         let mut my_bits = vec![];
         let byte = &buf[3];
@@ -134,6 +241,12 @@ impl Header {
     }
 }
 
+impl fmt::Display for Header {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_with_rcode(f, self.resp_code as u16)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -165,12 +278,12 @@ mod tests {
         ];
         let mut buf = BufReader::new(&packet);
         let header = Header::from_buf(&mut buf);
-        assert_eq!(header.query, true);
-        assert_eq!(header.response, false);
+        assert!(header.query);
+        assert!(!header.response);
         assert_eq!(header.op_code, 1);
-        assert_eq!(header.is_authoritative, true);
-        assert_eq!(header.truncated, true);
-        assert_eq!(header.should_recurse, true);
+        assert!(header.is_authoritative);
+        assert!(header.truncated);
+        assert!(header.should_recurse);
 
         let packet = vec![
             0b00000000, 0b00000000, 0b10000001, 0b00000000, 0b00000000, 0b00000000, 0b00000000,
@@ -178,12 +291,12 @@ mod tests {
         ];
         let mut buf = BufReader::new(&packet);
         let header = Header::from_buf(&mut buf);
-        assert_eq!(header.query, false);
-        assert_eq!(header.response, true);
+        assert!(!header.query);
+        assert!(header.response);
         assert_eq!(header.op_code, 0);
-        assert_eq!(header.is_authoritative, false);
-        assert_eq!(header.truncated, false);
-        assert_eq!(header.should_recurse, true);
+        assert!(!header.is_authoritative);
+        assert!(!header.truncated);
+        assert!(header.should_recurse);
     }
 
     #[test]
@@ -194,11 +307,58 @@ mod tests {
         ];
         let mut buf = BufReader::new(&packet);
         let header = Header::from_buf(&mut buf);
-        assert_eq!(header.can_recurse, true);
+        assert!(header.can_recurse);
         assert_eq!(header.reserved, 0);
         assert_eq!(header.resp_code, 0);
     }
 
+    #[test]
+    fn test_writing_header() {
+        let packet = vec![
+            0b11000111, 0b01010111, 0b10001111, 0b10110011, 0b00000000, 0b00000001, 0b00000000,
+            0b00000010, 0b00000000, 0b00000011, 0b00000000, 0b00000100,
+        ];
+        let mut buf = BufReader::new(&packet);
+        let header = Header::from_buf(&mut buf);
+        assert_eq!(header.to_bytes(), packet);
+    }
+
+    #[test]
+    fn test_display() {
+        let mut header = Header::new(19678);
+        header.response = true;
+        header.should_recurse = true;
+        header.can_recurse = true;
+        header.resp_code = 3;
+        header.question_count = 1;
+        assert_eq!(
+            header.to_string(),
+            ";; ->>HEADER<<- opcode: QUERY, status: NXDOMAIN, id: 19678\n\
+             ;; flags: qr rd ra; QUERY: 1, ANSWER: 0, AUTHORITY: 0, ADDITIONAL: 0"
+        );
+    }
+
+    #[test]
+    fn test_parsing_dnssec_bits() {
+        let packet = vec![
+            0b00000000, 0b00000000, 0b00000000, 0b00100000, 0b00000000, 0b00000000, 0b00000000,
+            0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b00000000,
+        ];
+        let mut buf = BufReader::new(&packet);
+        let header = Header::from_buf(&mut buf);
+        assert!(header.authentic_data);
+        assert!(!header.checking_disabled);
+
+        let packet = vec![
+            0b00000000, 0b00000000, 0b00000000, 0b00010000, 0b00000000, 0b00000000, 0b00000000,
+            0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b00000000,
+        ];
+        let mut buf = BufReader::new(&packet);
+        let header = Header::from_buf(&mut buf);
+        assert!(!header.authentic_data);
+        assert!(header.checking_disabled);
+    }
+
     #[test]
     fn test_parsing_question_counts() {
         let packet = vec![
@@ -0,0 +1,446 @@
+// The JSON representation of messages from RFC 8427. The types here mirror
+// the member names of the RFC and are converted to and from the packet
+// types, which serialize through them.
+use crate::answer::Answer;
+use crate::class::RRClass;
+use crate::edns::Edns;
+use crate::header::Header;
+use crate::packet::Packet;
+use crate::question::Question;
+use crate::r#type::RRType;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::convert::TryInto;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HeaderJson {
+    #[serde(rename = "ID")]
+    pub id: u16,
+    #[serde(rename = "QR", deserialize_with = "flag")]
+    pub qr: bool,
+    #[serde(rename = "Opcode")]
+    pub opcode: u8,
+    #[serde(rename = "AA", deserialize_with = "flag")]
+    pub aa: bool,
+    #[serde(rename = "TC", deserialize_with = "flag")]
+    pub tc: bool,
+    #[serde(rename = "RD", deserialize_with = "flag")]
+    pub rd: bool,
+    #[serde(rename = "RA", deserialize_with = "flag")]
+    pub ra: bool,
+    #[serde(rename = "AD", deserialize_with = "flag")]
+    pub ad: bool,
+    #[serde(rename = "CD", deserialize_with = "flag")]
+    pub cd: bool,
+    #[serde(rename = "RCODE")]
+    pub rcode: u8,
+    #[serde(rename = "QDCOUNT")]
+    pub qdcount: u16,
+    #[serde(rename = "ANCOUNT")]
+    pub ancount: u16,
+    #[serde(rename = "NSCOUNT")]
+    pub nscount: u16,
+    #[serde(rename = "ARCOUNT")]
+    pub arcount: u16,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QuestionJson {
+    #[serde(rename = "NAME")]
+    pub name: String,
+    #[serde(rename = "TYPE")]
+    pub r#type: u16,
+    #[serde(rename = "CLASS")]
+    pub class: u16,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecordJson {
+    #[serde(rename = "NAME")]
+    pub name: String,
+    #[serde(rename = "TYPE")]
+    pub r#type: u16,
+    #[serde(rename = "CLASS")]
+    pub class: u16,
+    #[serde(rename = "TTL")]
+    pub ttl: u32,
+    #[serde(rename = "RDLENGTH")]
+    pub rdlength: u16,
+    #[serde(rename = "RDATAHEX")]
+    pub rdatahex: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MessageJson {
+    #[serde(flatten)]
+    pub header: HeaderJson,
+    #[serde(rename = "QNAME", skip_serializing_if = "Option::is_none")]
+    pub qname: Option<String>,
+    #[serde(rename = "QTYPE", skip_serializing_if = "Option::is_none")]
+    pub qtype: Option<u16>,
+    #[serde(rename = "QCLASS", skip_serializing_if = "Option::is_none")]
+    pub qclass: Option<u16>,
+    #[serde(rename = "questionRRs", skip_serializing_if = "Vec::is_empty")]
+    pub question_rrs: Vec<QuestionJson>,
+    #[serde(rename = "answerRRs", skip_serializing_if = "Vec::is_empty")]
+    pub answer_rrs: Vec<RecordJson>,
+    #[serde(rename = "authorityRRs", skip_serializing_if = "Vec::is_empty")]
+    pub authority_rrs: Vec<RecordJson>,
+    #[serde(rename = "additionalRRs", skip_serializing_if = "Vec::is_empty")]
+    pub additional_rrs: Vec<RecordJson>,
+}
+
+// RFC 8427 describes the flags as booleans but its own examples use 0 and 1,
+// so either is accepted
+fn flag<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Flag {
+        Bool(bool),
+        Int(u8),
+    }
+    match Flag::deserialize(deserializer)? {
+        Flag::Bool(value) => Ok(value),
+        Flag::Int(value) => Ok(value != 0),
+    }
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+// only ASCII hex digits in pairs, so slicing can't split a character
+fn from_hex(hex: &str) -> Result<Vec<u8>, String> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Err(format!("invalid RDATAHEX {:?}", hex));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|e| e.to_string()))
+        .collect()
+}
+
+impl From<Header> for HeaderJson {
+    fn from(header: Header) -> Self {
+        Self {
+            id: header.identifier,
+            qr: header.response,
+            opcode: header.op_code,
+            aa: header.is_authoritative,
+            tc: header.truncated,
+            rd: header.should_recurse,
+            ra: header.can_recurse,
+            ad: header.authentic_data,
+            cd: header.checking_disabled,
+            rcode: header.resp_code,
+            qdcount: header.question_count,
+            ancount: header.answer_count,
+            nscount: header.authority_count,
+            arcount: header.additional_count,
+        }
+    }
+}
+
+impl From<HeaderJson> for Header {
+    fn from(json: HeaderJson) -> Self {
+        let mut header = Header::new(json.id);
+        header.query = !json.qr;
+        header.response = json.qr;
+        header.op_code = json.opcode;
+        header.is_authoritative = json.aa;
+        header.truncated = json.tc;
+        header.should_recurse = json.rd;
+        header.can_recurse = json.ra;
+        header.authentic_data = json.ad;
+        header.checking_disabled = json.cd;
+        header.resp_code = json.rcode;
+        header.question_count = json.qdcount;
+        header.answer_count = json.ancount;
+        header.authority_count = json.nscount;
+        header.additional_count = json.arcount;
+        header
+    }
+}
+
+impl From<Question> for QuestionJson {
+    fn from(question: Question) -> Self {
+        Self {
+            name: question.name,
+            r#type: question.r#type.to_value(),
+            class: question.class.to_value(),
+        }
+    }
+}
+
+impl From<QuestionJson> for Question {
+    fn from(json: QuestionJson) -> Self {
+        Question::new(
+            &json.name,
+            RRType::from_value(json.r#type),
+            RRClass::from_value(json.class),
+        )
+    }
+}
+
+impl From<Answer> for RecordJson {
+    fn from(answer: Answer) -> Self {
+        Self {
+            name: answer.name,
+            r#type: answer.r#type.to_value(),
+            class: answer.class.to_value(),
+            ttl: answer.ttl,
+            rdlength: answer.data.len() as u16,
+            rdatahex: to_hex(&answer.data),
+        }
+    }
+}
+
+impl TryFrom<RecordJson> for Answer {
+    type Error = String;
+
+    fn try_from(json: RecordJson) -> Result<Self, Self::Error> {
+        Ok(Answer::new(
+            &json.name,
+            RRType::from_value(json.r#type),
+            RRClass::from_value(json.class),
+            json.ttl,
+            from_hex(&json.rdatahex)?,
+        ))
+    }
+}
+
+// the OPT pseudo-record is written as a plain additional record, its class
+// and TTL fields carrying the EDNS parameters
+impl From<Edns> for RecordJson {
+    fn from(edns: Edns) -> Self {
+        let bytes = edns.to_bytes();
+        Self {
+            name: ".".to_string(),
+            r#type: RRType::OPT.to_value(),
+            class: edns.udp_payload_size,
+            ttl: u32::from_be_bytes(bytes[5..9].try_into().unwrap()),
+            rdlength: (bytes.len() - 11) as u16,
+            rdatahex: to_hex(&bytes[11..]),
+        }
+    }
+}
+
+// the options come from the JSON, so the record goes through the same
+// checks as one read off the wire, inside an otherwise empty message
+impl TryFrom<RecordJson> for Edns {
+    type Error = String;
+
+    fn try_from(json: RecordJson) -> Result<Self, Self::Error> {
+        let data = from_hex(&json.rdatahex)?;
+        let mut header = Header::new(0);
+        header.additional_count = 1;
+        let mut bytes = header.to_bytes();
+        bytes.push(0);
+        bytes.extend_from_slice(&json.r#type.to_be_bytes());
+        bytes.extend_from_slice(&json.class.to_be_bytes());
+        bytes.extend_from_slice(&json.ttl.to_be_bytes());
+        bytes.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        bytes.extend(data);
+        Packet::try_from_buf(&bytes)
+            .map_err(|e| format!("invalid OPT record: {}", e))?
+            .edns
+            .ok_or_else(|| "invalid OPT record".to_string())
+    }
+}
+
+impl From<Packet> for MessageJson {
+    fn from(packet: Packet) -> Self {
+        let mut header = HeaderJson::from(packet.header);
+        header.qdcount = packet.questions.len() as u16;
+        header.ancount = packet.answers.len() as u16;
+        header.nscount = packet.authorities.len() as u16;
+        header.arcount = (packet.additionals.len() + packet.edns.is_some() as usize) as u16;
+
+        // a lone question is flattened into the message, as in the RFC's
+        // examples
+        let (qname, qtype, qclass, question_rrs) = match packet.questions.len() {
+            1 => {
+                let question = QuestionJson::from(packet.questions[0].clone());
+                (
+                    Some(question.name),
+                    Some(question.r#type),
+                    Some(question.class),
+                    Vec::new(),
+                )
+            }
+            _ => (
+                None,
+                None,
+                None,
+                packet.questions.into_iter().map(Into::into).collect(),
+            ),
+        };
+
+        let mut additional_rrs: Vec<RecordJson> =
+            packet.additionals.into_iter().map(Into::into).collect();
+        if let Some(edns) = packet.edns {
+            additional_rrs.push(edns.into());
+        }
+
+        Self {
+            header,
+            qname,
+            qtype,
+            qclass,
+            question_rrs,
+            answer_rrs: packet.answers.into_iter().map(Into::into).collect(),
+            authority_rrs: packet.authorities.into_iter().map(Into::into).collect(),
+            additional_rrs,
+        }
+    }
+}
+
+impl TryFrom<MessageJson> for Packet {
+    type Error = String;
+
+    fn try_from(json: MessageJson) -> Result<Self, Self::Error> {
+        let mut packet = Packet::new(json.header.into());
+        packet.questions = json.question_rrs.into_iter().map(Into::into).collect();
+        if let (true, Some(qname)) = (packet.questions.is_empty(), json.qname) {
+            packet.questions.push(Question::new(
+                &qname,
+                RRType::from_value(json.qtype.unwrap_or(0)),
+                RRClass::from_value(json.qclass.unwrap_or(0)),
+            ));
+        }
+        packet.answers = json
+            .answer_rrs
+            .into_iter()
+            .map(TryInto::try_into)
+            .collect::<Result<_, _>>()?;
+        packet.authorities = json
+            .authority_rrs
+            .into_iter()
+            .map(TryInto::try_into)
+            .collect::<Result<_, _>>()?;
+        for record in json.additional_rrs {
+            if RRType::from_value(record.r#type) == RRType::OPT {
+                packet.edns = Some(record.try_into()?);
+            } else {
+                packet.additionals.push(record.try_into()?);
+            }
+        }
+        Ok(packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn response() -> Packet {
+        let mut header = Header::new(19678);
+        header.query = false;
+        header.response = true;
+        header.should_recurse = true;
+        header.can_recurse = true;
+        let mut packet = Packet::new(header);
+        packet
+            .questions
+            .push(Question::new("example.com", RRType::A, RRClass::IN));
+        packet.answers.push(Answer::new(
+            "example.com",
+            RRType::A,
+            RRClass::IN,
+            300,
+            vec![192, 0, 2, 1],
+        ));
+        packet.edns = Some(Edns::new(1232));
+        packet
+    }
+
+    #[test]
+    fn test_serialize_packet() {
+        let value = serde_json::to_value(response()).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "ID": 19678, "QR": true, "Opcode": 0, "AA": false, "TC": false,
+                "RD": true, "RA": true, "AD": false, "CD": false, "RCODE": 0,
+                "QDCOUNT": 1, "ANCOUNT": 1, "NSCOUNT": 0, "ARCOUNT": 1,
+                "QNAME": "example.com", "QTYPE": 1, "QCLASS": 1,
+                "answerRRs": [{
+                    "NAME": "example.com", "TYPE": 1, "CLASS": 1, "TTL": 300,
+                    "RDLENGTH": 4, "RDATAHEX": "C0000201"
+                }],
+                "additionalRRs": [{
+                    "NAME": ".", "TYPE": 41, "CLASS": 1232, "TTL": 0,
+                    "RDLENGTH": 0, "RDATAHEX": ""
+                }]
+            })
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let packet = response();
+        let json = serde_json::to_string(&packet).unwrap();
+        let parsed: Packet = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.to_bytes(), packet.to_bytes());
+    }
+
+    #[test]
+    fn test_deserialize_rfc_example() {
+        // flags as integers and no counts, as in RFC 8427 appendix A
+        let packet: Packet = serde_json::from_str(
+            r#"{ "ID": 32784, "QR": 0, "Opcode": 2, "AA": 0, "TC": 0, "RD": 0,
+                 "RA": 0, "AD": 0, "CD": 0, "RCODE": 0,
+                 "QNAME": "example.com", "QTYPE": 1, "QCLASS": 1 }"#,
+        )
+        .unwrap();
+        assert_eq!(packet.header.identifier, 32784);
+        assert!(packet.header.query);
+        assert_eq!(packet.header.op_code, 2);
+        assert_eq!(
+            packet.questions,
+            vec![Question::new("example.com", RRType::A, RRClass::IN)]
+        );
+    }
+
+    #[test]
+    fn test_individual_types() {
+        let question = Question::new("example.com", RRType::MX, RRClass::IN);
+        let value = serde_json::to_value(question.clone()).unwrap();
+        assert_eq!(
+            value,
+            json!({ "NAME": "example.com", "TYPE": 15, "CLASS": 1 })
+        );
+        assert_eq!(serde_json::from_value::<Question>(value).unwrap(), question);
+
+        let header: Header = serde_json::from_value(json!({ "ID": 7, "QR": true })).unwrap();
+        assert!(header.response);
+        assert!(!header.query);
+    }
+
+    #[test]
+    fn test_invalid_rdata() {
+        let record = |hex: &str| json!({ "NAME": "example.com", "TYPE": 1, "RDATAHEX": hex });
+        assert_eq!(
+            serde_json::from_value::<Answer>(record("c0000201"))
+                .unwrap()
+                .data,
+            vec![192, 0, 2, 1]
+        );
+        // a multi-byte character, a bad pair and an odd trailing digit
+        for hex in ["A\u{e9}00", "C0XX", "C00"] {
+            assert!(serde_json::from_value::<Answer>(record(hex)).is_err());
+        }
+
+        // an option claiming 16 bytes with none following
+        let message = json!({
+            "ID": 1,
+            "additionalRRs": [{ "NAME": ".", "TYPE": 41, "CLASS": 1232, "RDATAHEX": "000A0010" }]
+        });
+        let err = serde_json::from_value::<Packet>(message).unwrap_err();
+        assert!(err.to_string().contains("invalid OPT record"), "{}", err);
+    }
+}
@@ -1,9 +1,51 @@
+pub mod acl;
 pub mod answer;
+pub mod base64;
+pub mod blocklist;
 pub mod buf_reader;
+pub mod capture;
+pub mod cidr;
 pub mod class;
+pub mod client;
+pub mod client_subnet;
+pub mod cookie;
+pub mod decode;
+pub mod dnssec;
+pub mod dnstap;
+pub mod doh;
+#[cfg(feature = "quic")]
+pub mod doq;
+pub mod edns;
+pub mod extended_error;
+pub mod forward;
 pub mod header;
+#[cfg(feature = "json")]
+pub mod json;
+pub mod metrics;
+pub mod name;
+pub mod opcode;
 pub mod packet;
 pub mod parser;
+pub mod pcap;
+pub mod querylog;
 pub mod question;
+pub mod rcode;
+pub mod rdata;
 pub mod records;
+pub mod rpz;
+pub mod rrl;
+pub mod rrset;
+pub mod server;
+#[cfg(feature = "dnssec")]
+pub mod sign;
+pub mod siphash;
+pub mod tcp;
+#[cfg(feature = "tls")]
+pub mod tls;
+#[cfg(feature = "tsig")]
+pub mod tsig;
 pub mod r#type;
+#[cfg(feature = "dnssec")]
+pub mod validator;
+pub mod views;
+pub mod zone;
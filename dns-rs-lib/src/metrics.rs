@@ -0,0 +1,432 @@
+use crate::dnstap::Protocol;
+use crate::opcode::OpCode;
+use crate::packet::Packet;
+use crate::rcode::RCode;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// upper bounds in seconds, from a cache hit on the LAN to a slow authority
+// on the other side of the world
+pub const LATENCY_BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+// the Prometheus text exposition format
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+// scrapes are a single small GET, anything bigger isn't one
+const MAX_REQUEST_LEN: usize = 8192;
+// for the whole request, not each read, so a slow client can't hold on
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+// scrapers beyond this many at once are turned away
+const MAX_CONNECTIONS: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct QueryLabels {
+    transport: String,
+    r#type: String,
+    class: String,
+    opcode: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct ResponseLabels {
+    transport: String,
+    rcode: String,
+    truncated: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Upstream {
+    in_flight: u64,
+    errors: u64,
+    // not cumulative, that happens when rendering
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+// counters shared by everything answering and forwarding queries, rendered
+// for Prometheus to scrape
+#[derive(Debug, Default)]
+pub struct Metrics {
+    queries: Mutex<BTreeMap<QueryLabels, u64>>,
+    responses: Mutex<BTreeMap<ResponseLabels, u64>>,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    upstreams: Mutex<BTreeMap<SocketAddr, Upstream>>,
+}
+
+// counts a query to an upstream as in flight until it is observed, or as an
+// error if it is dropped first
+#[derive(Debug)]
+pub struct UpstreamTimer<'a> {
+    metrics: &'a Metrics,
+    upstream: SocketAddr,
+    started: Instant,
+    observed: bool,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_query(&self, transport: Protocol, query: &Packet) {
+        let (r#type, class) = match query.questions.first() {
+            Some(question) => (question.r#type.to_string(), question.class.to_string()),
+            None => ("none".to_string(), "none".to_string()),
+        };
+        let labels = QueryLabels {
+            transport: label(transport),
+            r#type,
+            class,
+            opcode: OpCode::from_value(query.header.op_code).to_string(),
+        };
+        *self.queries.lock().unwrap().entry(labels).or_default() += 1;
+    }
+
+    pub fn record_response(&self, transport: Protocol, response: &Packet) {
+        let labels = ResponseLabels {
+            transport: label(transport),
+            rcode: RCode::from_value(response.rcode()).to_string(),
+            truncated: response.header.truncated,
+        };
+        *self.responses.lock().unwrap().entry(labels).or_default() += 1;
+    }
+
+    pub fn cache_hit(&self) {
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn cache_miss(&self) {
+        self.cache_misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn upstream(&self, upstream: SocketAddr) -> UpstreamTimer<'_> {
+        self.upstreams
+            .lock()
+            .unwrap()
+            .entry(upstream)
+            .or_default()
+            .in_flight += 1;
+        UpstreamTimer {
+            metrics: self,
+            upstream,
+            started: Instant::now(),
+            observed: false,
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "dns_queries_total",
+            "counter",
+            "Queries received.",
+        );
+        for (labels, count) in self.queries.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "dns_queries_total{{transport=\"{}\",type=\"{}\",class=\"{}\",opcode=\"{}\"}} {}",
+                escape(&labels.transport),
+                escape(&labels.r#type),
+                escape(&labels.class),
+                escape(&labels.opcode),
+                count
+            );
+        }
+
+        header(
+            &mut out,
+            "dns_responses_total",
+            "counter",
+            "Responses sent.",
+        );
+        for (labels, count) in self.responses.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "dns_responses_total{{transport=\"{}\",rcode=\"{}\",truncated=\"{}\"}} {}",
+                escape(&labels.transport),
+                escape(&labels.rcode),
+                labels.truncated,
+                count
+            );
+        }
+
+        let hits = self.cache_hits.load(Ordering::Relaxed);
+        let misses = self.cache_misses.load(Ordering::Relaxed);
+        header(
+            &mut out,
+            "dns_cache_hits_total",
+            "counter",
+            "Answers found in the cache.",
+        );
+        let _ = writeln!(out, "dns_cache_hits_total {}", hits);
+        header(
+            &mut out,
+            "dns_cache_misses_total",
+            "counter",
+            "Answers not found in the cache.",
+        );
+        let _ = writeln!(out, "dns_cache_misses_total {}", misses);
+
+        let upstreams = self.upstreams.lock().unwrap();
+        header(
+            &mut out,
+            "dns_upstream_in_flight",
+            "gauge",
+            "Queries sent upstream and not yet answered.",
+        );
+        for (upstream, stats) in upstreams.iter() {
+            let _ = writeln!(
+                out,
+                "dns_upstream_in_flight{{upstream=\"{}\"}} {}",
+                upstream, stats.in_flight
+            );
+        }
+        header(
+            &mut out,
+            "dns_upstream_errors_total",
+            "counter",
+            "Queries sent upstream that failed or timed out.",
+        );
+        for (upstream, stats) in upstreams.iter() {
+            let _ = writeln!(
+                out,
+                "dns_upstream_errors_total{{upstream=\"{}\"}} {}",
+                upstream, stats.errors
+            );
+        }
+        header(
+            &mut out,
+            "dns_upstream_latency_seconds",
+            "histogram",
+            "Time taken for upstreams to answer.",
+        );
+        for (upstream, stats) in upstreams.iter() {
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(stats.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "dns_upstream_latency_seconds_bucket{{upstream=\"{}\",le=\"{}\"}} {}",
+                    upstream, bound, cumulative
+                );
+            }
+            let _ = writeln!(
+                out,
+                "dns_upstream_latency_seconds_bucket{{upstream=\"{}\",le=\"+Inf\"}} {}",
+                upstream, stats.count
+            );
+            let _ = writeln!(
+                out,
+                "dns_upstream_latency_seconds_sum{{upstream=\"{}\"}} {}",
+                upstream, stats.sum
+            );
+            let _ = writeln!(
+                out,
+                "dns_upstream_latency_seconds_count{{upstream=\"{}\"}} {}",
+                upstream, stats.count
+            );
+        }
+        out
+    }
+}
+
+impl UpstreamTimer<'_> {
+    // the upstream answered, record how long it took
+    pub fn observe(mut self) -> Duration {
+        self.observed = true;
+        let elapsed = self.started.elapsed();
+        let seconds = elapsed.as_secs_f64();
+        let mut upstreams = self.metrics.upstreams.lock().unwrap();
+        let stats = upstreams.entry(self.upstream).or_default();
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            stats.buckets[bucket] += 1;
+        }
+        stats.count += 1;
+        stats.sum += seconds;
+        elapsed
+    }
+}
+
+impl Drop for UpstreamTimer<'_> {
+    fn drop(&mut self) {
+        let mut upstreams = self.metrics.upstreams.lock().unwrap();
+        let stats = upstreams.entry(self.upstream).or_default();
+        stats.in_flight = stats.in_flight.saturating_sub(1);
+        if !self.observed {
+            stats.errors += 1;
+        }
+    }
+}
+
+// answers GET /metrics until the listener fails, each connection on its own
+// thread so a stalled scraper doesn't hold up the rest
+pub fn serve(listener: TcpListener, metrics: Arc<Metrics>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let connections = Arc::new(AtomicUsize::new(0));
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if connections.fetch_add(1, Ordering::Relaxed) >= MAX_CONNECTIONS {
+                        connections.fetch_sub(1, Ordering::Relaxed);
+                        continue;
+                    }
+                    let metrics = metrics.clone();
+                    let connections = connections.clone();
+                    thread::spawn(move || {
+                        // a misbehaving scraper only loses its own request
+                        let _ = handle(stream, &metrics);
+                        connections.fetch_sub(1, Ordering::Relaxed);
+                    });
+                }
+                Err(e) if e.kind() == io::ErrorKind::ConnectionAborted => continue,
+                Err(_) => return,
+            }
+        }
+    })
+}
+
+fn handle(mut stream: TcpStream, metrics: &Metrics) -> io::Result<()> {
+    let deadline = Instant::now() + REQUEST_TIMEOUT;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "request took too long",
+            ));
+        }
+        stream.set_read_timeout(Some(remaining))?;
+        let len = stream.read(&mut buf)?;
+        if len == 0 || request.len() + len > MAX_REQUEST_LEN {
+            return respond(
+                &mut stream,
+                "400 Bad Request",
+                "text/plain",
+                "bad request\n",
+            );
+        }
+        request.extend_from_slice(&buf[..len]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.lines().next().unwrap_or("").split_whitespace();
+    let method = request_line.next().unwrap_or("");
+    let path = request_line.next().unwrap_or("");
+    let path = path.split_once('?').map_or(path, |(path, _)| path);
+    match (method, path) {
+        ("GET", "/metrics") => respond(&mut stream, "200 OK", CONTENT_TYPE, &metrics.render()),
+        (_, "/metrics") => respond(
+            &mut stream,
+            "405 Method Not Allowed",
+            "text/plain",
+            "method not allowed\n",
+        ),
+        _ => respond(&mut stream, "404 Not Found", "text/plain", "not found\n"),
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+fn header(out: &mut String, name: &str, r#type: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, r#type);
+}
+
+fn label(transport: Protocol) -> String {
+    transport.to_string().to_ascii_lowercase()
+}
+
+// label values are quoted, so quotes, backslashes and newlines need escaping
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::class::RRClass;
+    use crate::client;
+    use crate::r#type::RRType;
+
+    #[test]
+    fn test_render_counters() {
+        let metrics = Metrics::new();
+        let query = client::build_query("example.com", RRType::AAAA, RRClass::IN);
+        metrics.record_query(Protocol::Udp, &query);
+        metrics.record_query(Protocol::Udp, &query);
+        let mut response = query.clone();
+        response.header.response = true;
+        response.header.truncated = true;
+        metrics.record_response(Protocol::Udp, &response);
+        metrics.cache_miss();
+
+        let out = metrics.render();
+        assert!(out.contains("# TYPE dns_queries_total counter\n"));
+        assert!(out.contains(
+            "dns_queries_total{transport=\"udp\",type=\"AAAA\",class=\"IN\",opcode=\"QUERY\"} 2\n"
+        ));
+        assert!(out.contains(
+            "dns_responses_total{transport=\"udp\",rcode=\"NOERROR\",truncated=\"true\"} 1\n"
+        ));
+        assert!(out.contains("dns_cache_hits_total 0\n"));
+        assert!(out.contains("dns_cache_misses_total 1\n"));
+    }
+
+    #[test]
+    fn test_upstream_timer() {
+        let metrics = Metrics::new();
+        let upstream: SocketAddr = "192.0.2.53:53".parse().unwrap();
+        let answered = metrics.upstream(upstream);
+        let failed = metrics.upstream(upstream);
+        assert!(metrics
+            .render()
+            .contains("dns_upstream_in_flight{upstream=\"192.0.2.53:53\"} 2\n"));
+
+        answered.observe();
+        drop(failed);
+        let out = metrics.render();
+        assert!(out.contains("dns_upstream_in_flight{upstream=\"192.0.2.53:53\"} 0\n"));
+        assert!(out.contains("dns_upstream_errors_total{upstream=\"192.0.2.53:53\"} 1\n"));
+        assert!(out.contains(
+            "dns_upstream_latency_seconds_bucket{upstream=\"192.0.2.53:53\",le=\"5\"} 1\n"
+        ));
+        assert!(out.contains(
+            "dns_upstream_latency_seconds_bucket{upstream=\"192.0.2.53:53\",le=\"+Inf\"} 1\n"
+        ));
+        assert!(out.contains("dns_upstream_latency_seconds_count{upstream=\"192.0.2.53:53\"} 1\n"));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
@@ -0,0 +1,158 @@
+use std::cmp::Ordering;
+
+pub fn to_wire(name: &str) -> Vec<u8> {
+    let mut wire = Vec::new();
+    for label in labels(name) {
+        wire.push(label.len() as u8);
+        wire.extend_from_slice(label.as_bytes());
+    }
+    wire.push(0);
+    wire
+}
+
+// an uncompressed name at the start of the data, as in the record data of
+// DNSSEC types, along with how many bytes it took
+pub fn from_wire(data: &[u8]) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut pos = 0;
+    loop {
+        let len = *data.get(pos)? as usize;
+        pos += 1;
+        if len == 0 {
+            return Some((labels.join("."), pos));
+        }
+        if len > 63 {
+            return None;
+        }
+        let label = data.get(pos..pos + len)?;
+        labels.push(String::from_utf8_lossy(label).into_owned());
+        pos += len;
+    }
+}
+
+pub fn to_canonical_wire(name: &str) -> Vec<u8> {
+    to_wire(&name.to_ascii_lowercase())
+}
+
+// fully qualified, with the root shown as a lone dot
+pub fn to_presentation(name: &str) -> String {
+    format!("{}.", name.trim_end_matches('.'))
+}
+
+pub fn labels(name: &str) -> Vec<&str> {
+    name.trim_end_matches('.')
+        .split('.')
+        .filter(|label| !label.is_empty())
+        .collect()
+}
+
+// whether the name is the ancestor or below it, ignoring case
+pub fn is_below(name: &str, ancestor: &str) -> bool {
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    let ancestor = ancestor.trim_end_matches('.').to_ascii_lowercase();
+    ancestor.is_empty() || name == ancestor || name.ends_with(&format!(".{}", ancestor))
+}
+
+// the label prepended to the name, which may be the root
+pub fn join(label: &str, name: &str) -> String {
+    match name.is_empty() {
+        true => label.to_string(),
+        false => format!("{}.{}", label, name),
+    }
+}
+
+// RFC 4034 section 6.1: compare label by label starting from the root,
+// case-insensitively, with shorter names sorting first
+pub fn canonical_cmp(a: &str, b: &str) -> Ordering {
+    let a = a.to_ascii_lowercase();
+    let b = b.to_ascii_lowercase();
+    let a_labels = labels(&a);
+    let b_labels = labels(&b);
+
+    for (a_label, b_label) in a_labels.iter().rev().zip(b_labels.iter().rev()) {
+        match a_label.as_bytes().cmp(b_label.as_bytes()) {
+            Ordering::Equal => continue,
+            other => return other,
+        }
+    }
+    a_labels.len().cmp(&b_labels.len())
+}
+
+// lowercases the labels of an uncompressed wire format name found at the
+// start of buf, returning how many bytes the name occupied
+pub fn lowercase_wire(buf: &mut [u8]) -> usize {
+    let mut pos = 0;
+    while pos < buf.len() && buf[pos] != 0 {
+        let label_len = buf[pos] as usize;
+        let end = (pos + 1 + label_len).min(buf.len());
+        buf[pos + 1..end].make_ascii_lowercase();
+        pos = end;
+    }
+    (pos + 1).min(buf.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_wire() {
+        assert_eq!(
+            to_wire("google.com"),
+            vec![6, b'g', b'o', b'o', b'g', b'l', b'e', 3, b'c', b'o', b'm', 0]
+        );
+        assert_eq!(to_wire("google.com."), to_wire("google.com"));
+        assert_eq!(to_wire(""), vec![0]);
+        assert_eq!(to_canonical_wire("Google.COM"), to_wire("google.com"));
+        assert_eq!(to_presentation("google.com"), "google.com.");
+        assert_eq!(to_presentation(""), ".");
+
+        let mut wire = to_wire("www.example.com");
+        wire.push(0xAB);
+        assert_eq!(from_wire(&wire), Some(("www.example.com".to_string(), 17)));
+        assert_eq!(from_wire(&[0]), Some((String::new(), 1)));
+        assert_eq!(from_wire(&wire[..5]), None);
+    }
+
+    #[test]
+    fn test_is_below() {
+        assert!(is_below("www.example.com", "example.com"));
+        assert!(is_below("WWW.Example.COM.", "example.com"));
+        assert!(is_below("example.com", "EXAMPLE.com."));
+        assert!(is_below("example.com", ""));
+        assert!(!is_below("badexample.com", "example.com"));
+        assert!(!is_below("example.com", "www.example.com"));
+        assert_eq!(join("*", "example.com"), "*.example.com");
+        assert_eq!(join("com", ""), "com");
+    }
+
+    #[test]
+    fn test_canonical_order() {
+        // the example ordering from RFC 4034 section 6.1
+        let ordered = vec![
+            "example",
+            "a.example",
+            "yljkjljk.a.example",
+            "Z.a.example",
+            "zABC.a.EXAMPLE",
+            "z.example",
+            "\u{1}.z.example",
+            "*.z.example",
+            "\u{80}.z.example",
+        ];
+        let mut shuffled = ordered.clone();
+        shuffled.reverse();
+        shuffled.sort_by(|a, b| canonical_cmp(a, b));
+        assert_eq!(shuffled, ordered);
+    }
+
+    #[test]
+    fn test_lowercase_wire() {
+        let mut wire = to_wire("WWW.Example.com");
+        wire.extend_from_slice(&[0xAB, 0xCD]);
+        let len = lowercase_wire(&mut wire);
+        assert_eq!(len, 17);
+        assert_eq!(&wire[..len], to_wire("www.example.com").as_slice());
+        assert_eq!(&wire[len..], &[0xAB, 0xCD]);
+    }
+}
@@ -1,19 +1,46 @@
 use crate::answer::Answer;
 use crate::buf_reader::BufReader;
+use crate::decode::{self, DecodeError};
+use crate::edns::{Edns, EdnsOption, PADDING};
 use crate::header::Header;
+use crate::name;
 use crate::question::Question;
+use crate::r#type::RRType;
+use std::fmt;
 
-#[derive(Debug)]
+// the largest response a client without EDNS is guaranteed to accept
+pub const DEFAULT_UDP_PAYLOAD_SIZE: usize = 512;
 
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "json",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(
+        into = "crate::json::MessageJson",
+        try_from = "crate::json::MessageJson"
+    )
+)]
 pub struct Packet {
     pub header: Header,
     pub questions: Vec<Question>,
     pub answers: Vec<Answer>,
     pub authorities: Vec<Answer>,
     pub additionals: Vec<Answer>,
+    pub edns: Option<Edns>,
 }
 
 impl Packet {
+    pub fn new(header: Header) -> Self {
+        Self {
+            header,
+            questions: Vec::new(),
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+            edns: None,
+        }
+    }
+
     pub fn from_buf(buf: &[u8]) -> Self {
         let mut buf = BufReader::new(buf);
         let header = Header::from_buf(&mut buf);
@@ -37,7 +64,12 @@ impl Packet {
         }
 
         let mut additionals = Vec::new();
+        let mut edns = None;
         for _ in 0..header.additional_count {
+            if Edns::is_opt(&mut buf) {
+                edns = Some(Edns::from_buf(&mut buf));
+                continue;
+            }
             let a = Answer::from_buf(&mut buf);
             additionals.push(a);
         }
@@ -48,6 +80,435 @@ impl Packet {
             answers,
             authorities,
             additionals,
+            edns,
+        }
+    }
+
+    // checks the message before parsing it, for input that might be malformed
+    pub fn try_from_buf(buf: &[u8]) -> Result<Self, DecodeError> {
+        match decode::annotate(buf).error {
+            Some(error) => Err(error),
+            None => Ok(Self::from_buf(buf)),
+        }
+    }
+
+    // the full response code, the upper bits of which live in the OPT record
+    pub fn rcode(&self) -> u16 {
+        let upper = self
+            .edns
+            .as_ref()
+            .map_or(0, |edns| edns.extended_rcode as u16);
+        upper << 4 | self.header.resp_code as u16
+    }
+
+    // the other way round, adding an OPT record if the code needs one
+    pub fn set_rcode(&mut self, rcode: u16) {
+        self.header.resp_code = (rcode & 0b1111) as u8;
+        let upper = (rcode >> 4) as u8;
+        match self.edns.as_mut() {
+            Some(edns) => edns.extended_rcode = upper,
+            None if upper != 0 => {
+                let mut edns = Edns::new(DEFAULT_UDP_PAYLOAD_SIZE as u16);
+                edns.extended_rcode = upper;
+                self.edns = Some(edns);
+            }
+            None => {}
+        }
+    }
+
+    // the header's counts are taken from the sections rather than trusted
+    fn counted_header(&self) -> Header {
+        let mut header = self.header.clone();
+        header.question_count = self.questions.len() as u16;
+        header.answer_count = self.answers.len() as u16;
+        header.authority_count = self.authorities.len() as u16;
+        header.additional_count = (self.additionals.len() + self.edns.is_some() as usize) as u16;
+        header
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.counted_header().to_bytes();
+        for question in &self.questions {
+            bytes.extend(question.to_bytes());
+        }
+        for answer in self.answers.iter().chain(&self.authorities) {
+            bytes.extend(answer.to_bytes());
+        }
+        for additional in &self.additionals {
+            bytes.extend(additional.to_bytes());
+        }
+        if let Some(edns) = &self.edns {
+            bytes.extend(edns.to_bytes());
+        }
+        bytes
+    }
+
+    // the largest response the sender of this query will accept over UDP
+    pub fn max_response_size(&self) -> usize {
+        match &self.edns {
+            Some(edns) => (edns.udp_payload_size as usize).max(DEFAULT_UDP_PAYLOAD_SIZE),
+            None => DEFAULT_UDP_PAYLOAD_SIZE,
+        }
+    }
+
+    // shrinks a response to max_size: optional additional records go
+    // first, then a referral's in-domain glue, then the authority section
+    // and then answers. It is marked as truncated once anything the client
+    // needs has to be left out, a referral's glue included, RFC 9471
+    pub fn fit_to(&mut self, max_size: usize) {
+        let cuts = self.referral_cuts();
+        let is_glue = |record: &Answer| {
+            matches!(record.r#type, RRType::A | RRType::AAAA)
+                && cuts.iter().any(|cut| name::is_below(&record.name, cut))
+        };
+        let mut i = self.additionals.len();
+        while i > 0 && self.to_bytes().len() > max_size {
+            i -= 1;
+            if !is_glue(&self.additionals[i]) {
+                self.additionals.remove(i);
+            }
+        }
+        let optional_authorities = self.has_optional_authorities();
+        self.drop_to(max_size, |packet| &mut packet.additionals, false);
+        self.drop_to(
+            max_size,
+            |packet| &mut packet.authorities,
+            optional_authorities,
+        );
+        self.drop_to(max_size, |packet| &mut packet.answers, false);
+    }
+
+    // drops records from the end of a section until the message fits,
+    // marking it as truncated unless they were optional
+    fn drop_to(
+        &mut self,
+        max_size: usize,
+        section: fn(&mut Self) -> &mut Vec<Answer>,
+        optional: bool,
+    ) {
+        while self.to_bytes().len() > max_size && !section(self).is_empty() {
+            self.header.truncated |= !optional;
+            section(self).pop();
+        }
+    }
+
+    // the delegations a referral hands over, which are all the NS records
+    // in the authority section of a response that neither answers the
+    // question nor says there is no answer
+    fn referral_cuts(&self) -> Vec<String> {
+        let has_soa = self
+            .authorities
+            .iter()
+            .any(|record| record.r#type == RRType::SOA);
+        if has_soa || self.answers_question() {
+            return Vec::new();
+        }
+        self.authorities
+            .iter()
+            .filter(|record| record.r#type == RRType::NS)
+            .map(|record| record.name.clone())
+            .collect()
+    }
+
+    fn answers_question(&self) -> bool {
+        let Some(question) = self.questions.first() else {
+            return false;
+        };
+        self.answers
+            .iter()
+            .any(|record| record.r#type == question.r#type || question.r#type == RRType::ANY)
+    }
+
+    // only the NS records alongside an answer are there as a courtesy, a
+    // referral's delegation and a negative answer's SOA and proofs are the
+    // answer, RFC 2181 section 9
+    fn has_optional_authorities(&self) -> bool {
+        self.answers_question()
+            && self
+                .authorities
+                .iter()
+                .all(|record| matches!(record.r#type, RRType::NS | RRType::RRSIG))
+    }
+
+    pub fn is_padded(&self) -> bool {
+        self.edns
+            .as_ref()
+            .is_some_and(|edns| edns.option(PADDING).is_some())
+    }
+
+    // pads the message to a multiple of block_size (RFC 7830), without
+    // growing past max_size, this should happen after fit_to; a message with
+    // no room left for the option isn't padded at all
+    pub fn pad(&mut self, block_size: usize, max_size: usize) {
+        match self.edns.as_mut() {
+            Some(edns) => edns.options.retain(|option| option.code() != PADDING),
+            None => return,
         }
+        // the option's code and length take up four bytes themselves
+        let unpadded = self.to_bytes().len() + 4;
+        if unpadded > max_size {
+            return;
+        }
+        let len = match unpadded % block_size {
+            0 => 0,
+            remainder => block_size - remainder,
+        };
+        let len = len.min(max_size - unpadded);
+        if let Some(edns) = self.edns.as_mut() {
+            edns.options.push(EdnsOption::Padding(len as u16));
+        }
+    }
+}
+
+// the same layout dig uses, so output can be compared side by side
+impl fmt::Display for Packet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.counted_header().fmt_with_rcode(f, self.rcode())?;
+        if let Some(edns) = &self.edns {
+            write!(f, "\n\n{}", edns)?;
+        }
+        if !self.questions.is_empty() {
+            write!(f, "\n\n;; QUESTION SECTION:")?;
+            for question in &self.questions {
+                write!(f, "\n;{}", question)?;
+            }
+        }
+        let sections = [
+            ("ANSWER", &self.answers),
+            ("AUTHORITY", &self.authorities),
+            ("ADDITIONAL", &self.additionals),
+        ];
+        for (title, records) in sections {
+            if records.is_empty() {
+                continue;
+            }
+            write!(f, "\n\n;; {} SECTION:", title)?;
+            for record in records {
+                write!(f, "\n{}", record)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::class::RRClass;
+    use crate::edns::{QUERY_PADDING_BLOCK, RESPONSE_PADDING_BLOCK};
+    use crate::question::Question;
+    use crate::r#type::RRType;
+
+    fn response(answers: usize, additionals: usize) -> Packet {
+        let mut header = Header::new(1);
+        header.query = false;
+        header.response = true;
+        let mut packet = Packet::new(header);
+        packet
+            .questions
+            .push(Question::new("example.com", RRType::TXT, RRClass::IN));
+        for _ in 0..answers {
+            packet.answers.push(Answer::new(
+                "example.com",
+                RRType::TXT,
+                RRClass::IN,
+                300,
+                vec![b'x'; 100],
+            ));
+        }
+        for _ in 0..additionals {
+            packet.additionals.push(Answer::new(
+                "ns.example.com",
+                RRType::A,
+                RRClass::IN,
+                300,
+                vec![192, 0, 2, 1],
+            ));
+        }
+        packet
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut packet = response(2, 1);
+        packet.edns = Some(Edns::new(1232));
+        let bytes = packet.to_bytes();
+        let parsed = Packet::from_buf(&bytes);
+        assert_eq!(parsed.header.answer_count, 2);
+        assert_eq!(parsed.header.additional_count, 2);
+        assert_eq!(parsed.questions, packet.questions);
+        assert_eq!(parsed.answers, packet.answers);
+        assert_eq!(parsed.additionals, packet.additionals);
+        assert_eq!(parsed.edns, packet.edns);
+    }
+
+    #[test]
+    fn test_set_rcode() {
+        let mut packet = response(0, 0);
+        packet.set_rcode(3);
+        assert_eq!((packet.header.resp_code, packet.edns.is_none()), (3, true));
+        packet.set_rcode(23);
+        assert_eq!(packet.header.resp_code, 7);
+        assert_eq!(packet.edns.as_ref().unwrap().extended_rcode, 1);
+        assert_eq!(Packet::from_buf(&packet.to_bytes()).rcode(), 23);
+        packet.set_rcode(0);
+        assert_eq!(packet.rcode(), 0);
+    }
+
+    #[test]
+    fn test_max_response_size() {
+        let mut query = Packet::new(Header::new(1));
+        assert_eq!(query.max_response_size(), 512);
+        query.edns = Some(Edns::new(1232));
+        assert_eq!(query.max_response_size(), 1232);
+        query.edns = Some(Edns::new(100));
+        assert_eq!(query.max_response_size(), 512);
+    }
+
+    #[test]
+    fn test_fit_drops_additionals_first() {
+        let mut packet = response(3, 10);
+        let size = response(3, 2).to_bytes().len();
+        packet.fit_to(size);
+        assert_eq!(packet.answers.len(), 3);
+        assert_eq!(packet.additionals.len(), 2);
+        assert!(!packet.header.truncated);
+    }
+
+    #[test]
+    fn test_fit_truncates_answers() {
+        let mut packet = response(10, 10);
+        packet.fit_to(512);
+        assert!(packet.to_bytes().len() <= 512);
+        assert!(packet.additionals.is_empty());
+        assert_eq!(packet.answers.len(), 3);
+        assert!(packet.header.truncated);
+
+        let bytes = packet.to_bytes();
+        assert!(Packet::from_buf(&bytes).header.truncated);
+    }
+
+    fn record(name: &str, r#type: RRType, data: Vec<u8>) -> Answer {
+        Answer::new(name, r#type, RRClass::IN, 300, data)
+    }
+
+    #[test]
+    fn test_fit_referral() {
+        let mut referral = Packet::new(Header::new(1));
+        referral.header.response = true;
+        referral
+            .questions
+            .push(Question::new("www.sub.example.com", RRType::A, RRClass::IN));
+        for target in ["ns1.sub.example.com", "ns2.example.net"] {
+            let data = name::to_wire(target);
+            referral
+                .authorities
+                .push(record("sub.example.com", RRType::NS, data));
+        }
+        let glue = record("ns1.sub.example.com", RRType::A, vec![192, 0, 2, 1]);
+        let sibling = record("ns2.example.net", RRType::A, vec![192, 0, 2, 2]);
+        referral.additionals = vec![glue.clone(), sibling];
+
+        // glue from outside the delegation goes quietly
+        let mut packet = referral.clone();
+        packet.fit_to(referral.to_bytes().len() - 1);
+        assert_eq!(packet.additionals, vec![glue]);
+        assert!(!packet.header.truncated);
+        // the delegation's own glue doesn't
+        let mut packet = referral.clone();
+        packet.fit_to(packet.to_bytes().len() - 40);
+        assert!(packet.additionals.is_empty());
+        assert_eq!(packet.authorities.len(), 2);
+        assert!(packet.header.truncated);
+        // and neither do its NS records
+        let mut packet = referral.clone();
+        packet.additionals.clear();
+        packet.fit_to(packet.to_bytes().len() - 1);
+        assert_eq!(packet.authorities.len(), 1);
+        assert!(packet.header.truncated);
+
+        // a negative answer's SOA is needed too
+        let mut packet = referral.clone();
+        packet.additionals.clear();
+        packet.authorities = vec![record("example.com", RRType::SOA, vec![0; 40])];
+        packet.fit_to(packet.to_bytes().len() - 1);
+        assert!(packet.authorities.is_empty());
+        assert!(packet.header.truncated);
+
+        // but the NS records of an answer are only a courtesy
+        let mut packet = referral;
+        packet.additionals.clear();
+        packet
+            .answers
+            .push(record("www.sub.example.com", RRType::A, vec![192, 0, 2, 3]));
+        packet.fit_to(packet.to_bytes().len() - 1);
+        assert_eq!(packet.authorities.len(), 1);
+        assert!(!packet.header.truncated);
+    }
+
+    #[test]
+    fn test_padding() {
+        let mut query = Packet::new(Header::new(1));
+        query
+            .questions
+            .push(Question::new("example.com", RRType::A, RRClass::IN));
+        query.pad(QUERY_PADDING_BLOCK, 512);
+        assert!(!query.is_padded());
+
+        query.edns = Some(Edns::new(1232));
+        query.pad(QUERY_PADDING_BLOCK, 512);
+        assert!(query.is_padded());
+        assert_eq!(query.to_bytes().len(), 128);
+
+        // padding again replaces the existing option
+        query.pad(QUERY_PADDING_BLOCK, 512);
+        assert_eq!(query.to_bytes().len(), 128);
+        assert_eq!(Packet::from_buf(&query.to_bytes()).edns, query.edns);
+
+        let mut packet = response(5, 0);
+        packet.edns = Some(Edns::new(1232));
+        packet.pad(RESPONSE_PADDING_BLOCK, 1232);
+        assert_eq!(packet.to_bytes().len(), 936);
+
+        packet.pad(RESPONSE_PADDING_BLOCK, 700);
+        assert_eq!(packet.to_bytes().len(), 700);
+
+        // already too big for the option header, so it's left out
+        packet.pad(RESPONSE_PADDING_BLOCK, 300);
+        assert!(!packet.is_padded());
+        assert!(packet.to_bytes().len() > 300);
+    }
+
+    #[test]
+    fn test_display() {
+        let mut packet = response(0, 1);
+        packet.header.should_recurse = true;
+        packet.answers.push(Answer::new(
+            "example.com",
+            RRType::TXT,
+            RRClass::IN,
+            300,
+            b"\x05hello".to_vec(),
+        ));
+        let mut edns = Edns::new(1232);
+        edns.extended_rcode = 1;
+        packet.edns = Some(edns);
+        assert_eq!(
+            packet.to_string(),
+            ";; ->>HEADER<<- opcode: QUERY, status: BADVERS, id: 1\n\
+             ;; flags: qr rd; QUERY: 1, ANSWER: 1, AUTHORITY: 0, ADDITIONAL: 2\n\
+             \n\
+             ;; OPT PSEUDOSECTION:\n\
+             ; EDNS: version: 0, flags:; udp: 1232\n\
+             \n\
+             ;; QUESTION SECTION:\n\
+             ;example.com.\t\tIN\tTXT\n\
+             \n\
+             ;; ANSWER SECTION:\n\
+             example.com.\t300\tIN\tTXT\t\"hello\"\n\
+             \n\
+             ;; ADDITIONAL SECTION:\n\
+             ns.example.com.\t300\tIN\tA\t192.0.2.1"
+        );
     }
 }
@@ -1,6 +1,6 @@
 use std::convert::TryInto;
 
-pub fn print_bits(byte: &u8) -> () {
+pub fn print_bits(byte: &u8) {
     let mut s = "".to_string();
     let length = byte.count_ones() + byte.count_zeros();
     for n in (0..length).rev() {
@@ -0,0 +1,450 @@
+use std::io::{self, Read};
+use std::time::Duration;
+
+// the link types we know how to take apart, from the tcpdump.org registry
+pub const LINKTYPE_NULL: u32 = 0;
+pub const LINKTYPE_ETHERNET: u32 = 1;
+pub const LINKTYPE_RAW: u32 = 101;
+pub const LINKTYPE_LINUX_SLL: u32 = 113;
+pub const LINKTYPE_IPV4: u32 = 228;
+pub const LINKTYPE_IPV6: u32 = 229;
+
+const PCAP_MICROS: u32 = 0xa1b2c3d4;
+const PCAP_NANOS: u32 = 0xa1b23c4d;
+const PCAPNG_SECTION_HEADER: u32 = 0x0a0d0d0a;
+const PCAPNG_BYTE_ORDER: u32 = 0x1a2b3c4d;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 1;
+const PCAPNG_PACKET: u32 = 2;
+const PCAPNG_SIMPLE_PACKET: u32 = 3;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+const PCAPNG_IF_TSRESOL: u16 = 9;
+// anything bigger than this is taken to be a corrupt length
+const MAX_BLOCK_LEN: usize = 16 * 1024 * 1024;
+
+// a captured link layer frame
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    // since the Unix epoch
+    pub timestamp: Duration,
+    pub link_type: u32,
+    pub data: Vec<u8>,
+}
+
+// reads frames from either a classic pcap file or a pcapng one, whichever
+// byte order it was written in
+pub struct Reader<R> {
+    inner: R,
+    format: Format,
+    big_endian: bool,
+}
+
+enum Format {
+    Pcap {
+        link_type: u32,
+        units_per_second: u64,
+    },
+    // the link type and timestamp resolution of each interface in the section
+    Pcapng {
+        interfaces: Vec<(u32, u64)>,
+    },
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let mut magic = [0u8; 4];
+        inner.read_exact(&mut magic)?;
+
+        if u32::from_le_bytes(magic) == PCAPNG_SECTION_HEADER {
+            let mut reader = Self {
+                inner,
+                format: Format::Pcapng {
+                    interfaces: Vec::new(),
+                },
+                big_endian: false,
+            };
+            reader.section_header()?;
+            return Ok(reader);
+        }
+
+        let (big_endian, units_per_second) =
+            match (u32::from_be_bytes(magic), u32::from_le_bytes(magic)) {
+                (PCAP_MICROS, _) => (true, 1_000_000),
+                (PCAP_NANOS, _) => (true, 1_000_000_000),
+                (_, PCAP_MICROS) => (false, 1_000_000),
+                (_, PCAP_NANOS) => (false, 1_000_000_000),
+                _ => return Err(invalid("not a pcap or pcapng file")),
+            };
+        // version, time zone, sigfigs and snaplen, then the link type
+        let mut header = [0u8; 20];
+        inner.read_exact(&mut header)?;
+        let mut reader = Self {
+            inner,
+            format: Format::Pcap {
+                link_type: 0,
+                units_per_second,
+            },
+            big_endian,
+        };
+        let link_type = reader.u32(&header[16..20]) & 0xffff;
+        reader.format = Format::Pcap {
+            link_type,
+            units_per_second,
+        };
+        Ok(reader)
+    }
+
+    // None at a clean end of file, an error if it ends part way through
+    pub fn next_frame(&mut self) -> io::Result<Option<Frame>> {
+        match self.format {
+            Format::Pcap {
+                link_type,
+                units_per_second,
+            } => self.pcap_record(link_type, units_per_second),
+            Format::Pcapng { .. } => self.pcapng_block(),
+        }
+    }
+
+    fn pcap_record(&mut self, link_type: u32, units_per_second: u64) -> io::Result<Option<Frame>> {
+        let mut header = [0u8; 16];
+        if !self.read_or_eof(&mut header)? {
+            return Ok(None);
+        }
+        let seconds = self.u32(&header[0..4]) as u64;
+        let fraction = self.u32(&header[4..8]) as u64;
+        let len = self.u32(&header[8..12]) as usize;
+        if len > MAX_BLOCK_LEN {
+            return Err(invalid("packet record is too long"));
+        }
+        let mut data = vec![0u8; len];
+        self.inner.read_exact(&mut data)?;
+        Ok(Some(Frame {
+            timestamp: Duration::from_secs(seconds) + to_duration(fraction, units_per_second),
+            link_type,
+            data,
+        }))
+    }
+
+    fn pcapng_block(&mut self) -> io::Result<Option<Frame>> {
+        loop {
+            let mut header = [0u8; 8];
+            if !self.read_or_eof(&mut header)? {
+                return Ok(None);
+            }
+            // the block type reads the same in either byte order
+            if u32::from_le_bytes(header[0..4].try_into().unwrap()) == PCAPNG_SECTION_HEADER {
+                self.section_header_after(header[4..8].try_into().unwrap())?;
+                continue;
+            }
+            let block_type = self.u32(&header[0..4]);
+            let len = self.u32(&header[4..8]) as usize;
+            if !(12..=MAX_BLOCK_LEN).contains(&len) || !len.is_multiple_of(4) {
+                return Err(invalid("pcapng block has an invalid length"));
+            }
+            let mut body = vec![0u8; len - 8];
+            self.inner.read_exact(&mut body)?;
+            let body = &body[..body.len() - 4];
+
+            match block_type {
+                PCAPNG_INTERFACE_DESCRIPTION => self.interface(body)?,
+                PCAPNG_ENHANCED_PACKET | PCAPNG_PACKET => {
+                    return self.packet(block_type, body).map(Some);
+                }
+                PCAPNG_SIMPLE_PACKET => {
+                    if body.len() < 4 {
+                        return Err(invalid("simple packet block is too short"));
+                    }
+                    let original_len = self.u32(&body[0..4]) as usize;
+                    let data = &body[4..];
+                    let (link_type, _) = self.interface_info(0)?;
+                    return Ok(Some(Frame {
+                        timestamp: Duration::ZERO,
+                        link_type,
+                        data: data[..original_len.min(data.len())].to_vec(),
+                    }));
+                }
+                // statistics, name resolution and anything newer
+                _ => continue,
+            }
+        }
+    }
+
+    fn section_header(&mut self) -> io::Result<()> {
+        let mut len = [0u8; 4];
+        self.inner.read_exact(&mut len)?;
+        self.section_header_after(len)
+    }
+
+    // a new section can change the byte order, and starts without interfaces
+    fn section_header_after(&mut self, len: [u8; 4]) -> io::Result<()> {
+        let mut magic = [0u8; 4];
+        self.inner.read_exact(&mut magic)?;
+        self.big_endian = match (u32::from_be_bytes(magic), u32::from_le_bytes(magic)) {
+            (PCAPNG_BYTE_ORDER, _) => true,
+            (_, PCAPNG_BYTE_ORDER) => false,
+            _ => return Err(invalid("pcapng section has an unknown byte order")),
+        };
+        let len = self.u32(&len) as usize;
+        if !(28..=MAX_BLOCK_LEN).contains(&len) || !len.is_multiple_of(4) {
+            return Err(invalid("pcapng section header has an invalid length"));
+        }
+        let mut rest = vec![0u8; len - 12];
+        self.inner.read_exact(&mut rest)?;
+        self.format = Format::Pcapng {
+            interfaces: Vec::new(),
+        };
+        Ok(())
+    }
+
+    fn interface(&mut self, body: &[u8]) -> io::Result<()> {
+        if body.len() < 8 {
+            return Err(invalid("interface description block is too short"));
+        }
+        let link_type = self.u16(&body[0..2]) as u32;
+        let mut units_per_second = 1_000_000;
+
+        let mut options = &body[8..];
+        while options.len() >= 4 {
+            let code = self.u16(&options[0..2]);
+            let len = self.u16(&options[2..4]) as usize;
+            let value = options.get(4..4 + len).unwrap_or(&[]);
+            if code == PCAPNG_IF_TSRESOL && len == 1 {
+                let resolution = *value
+                    .first()
+                    .ok_or_else(|| invalid("interface timestamp resolution is cut short"))?;
+                // the high bit picks a power of two rather than of ten
+                let exponent = (resolution & 0x7f) as u32;
+                units_per_second = match resolution & 0x80 {
+                    0 => 10u64.checked_pow(exponent),
+                    _ => 2u64.checked_pow(exponent),
+                }
+                .ok_or_else(|| invalid("interface timestamp resolution is too fine"))?;
+            }
+            options = options.get(4 + len.div_ceil(4) * 4..).unwrap_or(&[]);
+        }
+
+        if let Format::Pcapng { interfaces } = &mut self.format {
+            interfaces.push((link_type, units_per_second));
+        }
+        Ok(())
+    }
+
+    fn packet(&self, block_type: u32, body: &[u8]) -> io::Result<Frame> {
+        if body.len() < 20 {
+            return Err(invalid("packet block is too short"));
+        }
+        let interface = match block_type {
+            PCAPNG_PACKET => self.u16(&body[0..2]) as u32,
+            _ => self.u32(&body[0..4]),
+        };
+        let (link_type, units_per_second) = self.interface_info(interface)?;
+        let timestamp = (self.u32(&body[4..8]) as u64) << 32 | self.u32(&body[8..12]) as u64;
+        let len = self.u32(&body[12..16]) as usize;
+        let data = body
+            .get(20..20 + len)
+            .ok_or_else(|| invalid("packet block is shorter than its captured length"))?;
+        Ok(Frame {
+            timestamp: to_duration(timestamp, units_per_second),
+            link_type,
+            data: data.to_vec(),
+        })
+    }
+
+    fn interface_info(&self, interface: u32) -> io::Result<(u32, u64)> {
+        match &self.format {
+            Format::Pcapng { interfaces } => interfaces.get(interface as usize).copied(),
+            Format::Pcap { .. } => None,
+        }
+        .ok_or_else(|| invalid("packet refers to an undescribed interface"))
+    }
+
+    // false if the file ended cleanly before the first byte
+    fn read_or_eof(&mut self, buf: &mut [u8]) -> io::Result<bool> {
+        let mut read = 0;
+        while read < buf.len() {
+            match self.inner.read(&mut buf[read..]) {
+                Ok(0) if read == 0 => return Ok(false),
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => read += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(true)
+    }
+
+    fn u16(&self, bytes: &[u8]) -> u16 {
+        let bytes = bytes.try_into().unwrap();
+        match self.big_endian {
+            true => u16::from_be_bytes(bytes),
+            false => u16::from_le_bytes(bytes),
+        }
+    }
+
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = bytes.try_into().unwrap();
+        match self.big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        }
+    }
+}
+
+impl<R: Read> Iterator for Reader<R> {
+    type Item = io::Result<Frame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_frame().transpose()
+    }
+}
+
+fn to_duration(units: u64, units_per_second: u64) -> Duration {
+    let seconds = units / units_per_second;
+    let nanos = (units % units_per_second) as u128 * 1_000_000_000 / units_per_second as u128;
+    Duration::new(seconds, nanos as u32)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn pcap(big_endian: bool, magic: u32) -> Vec<u8> {
+        let u32 = |value: u32| match big_endian {
+            true => value.to_be_bytes(),
+            false => value.to_le_bytes(),
+        };
+        let u16 = |value: u16| match big_endian {
+            true => value.to_be_bytes(),
+            false => value.to_le_bytes(),
+        };
+        let mut file = Vec::new();
+        file.extend(u32(magic));
+        file.extend(u16(2));
+        file.extend(u16(4));
+        file.extend(u32(0));
+        file.extend(u32(0));
+        file.extend(u32(65535));
+        file.extend(u32(LINKTYPE_ETHERNET));
+        for (seconds, fraction, data) in [(1700000000, 250, &[1, 2, 3][..]), (1700000001, 0, &[4])]
+        {
+            file.extend(u32(seconds));
+            file.extend(u32(fraction));
+            file.extend(u32(data.len() as u32));
+            file.extend(u32(data.len() as u32));
+            file.extend_from_slice(data);
+        }
+        file
+    }
+
+    #[test]
+    fn test_pcap() {
+        for big_endian in [false, true] {
+            let frames: Vec<Frame> = Reader::new(Cursor::new(pcap(big_endian, PCAP_MICROS)))
+                .unwrap()
+                .collect::<io::Result<_>>()
+                .unwrap();
+            assert_eq!(frames.len(), 2);
+            assert_eq!(frames[0].link_type, LINKTYPE_ETHERNET);
+            assert_eq!(
+                frames[0].timestamp,
+                Duration::from_secs(1700000000) + Duration::from_micros(250)
+            );
+            assert_eq!(frames[0].data, vec![1, 2, 3]);
+            assert_eq!(frames[1].data, vec![4]);
+        }
+
+        let mut reader = Reader::new(Cursor::new(pcap(false, PCAP_NANOS))).unwrap();
+        let frame = reader.next_frame().unwrap().unwrap();
+        assert_eq!(frame.timestamp.subsec_nanos(), 250);
+    }
+
+    #[test]
+    fn test_truncated_pcap() {
+        let mut file = pcap(false, PCAP_MICROS);
+        file.pop();
+        let mut reader = Reader::new(Cursor::new(file)).unwrap();
+        assert!(reader.next_frame().unwrap().is_some());
+        let err = reader.next_frame().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        assert!(Reader::new(Cursor::new(b"not a capture".to_vec())).is_err());
+    }
+
+    fn block(block_type: u32, body: &[u8]) -> Vec<u8> {
+        let mut body = body.to_vec();
+        while !body.len().is_multiple_of(4) {
+            body.push(0);
+        }
+        let len = (body.len() + 12) as u32;
+        [
+            block_type.to_le_bytes().to_vec(),
+            len.to_le_bytes().to_vec(),
+            body,
+            len.to_le_bytes().to_vec(),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn test_pcapng() {
+        let mut section = PCAPNG_BYTE_ORDER.to_le_bytes().to_vec();
+        section.extend([1, 0, 0, 0]);
+        section.extend(u64::MAX.to_le_bytes());
+
+        // an Ethernet interface with nanosecond timestamps
+        let mut interface = vec![1, 0, 0, 0, 0, 0, 1, 0];
+        interface.extend([9, 0, 1, 0, 9, 0, 0, 0]);
+        interface.extend([0, 0, 0, 0]);
+
+        let timestamp: u64 = 1_700_000_000_000_000_123;
+        let mut packet = 0u32.to_le_bytes().to_vec();
+        packet.extend(((timestamp >> 32) as u32).to_le_bytes());
+        packet.extend((timestamp as u32).to_le_bytes());
+        packet.extend(3u32.to_le_bytes());
+        packet.extend(3u32.to_le_bytes());
+        packet.extend([7, 8, 9]);
+
+        let mut simple = 2u32.to_le_bytes().to_vec();
+        simple.extend([5, 6]);
+
+        let file = [
+            block(PCAPNG_SECTION_HEADER, &section),
+            block(PCAPNG_INTERFACE_DESCRIPTION, &interface),
+            // a name resolution block to be skipped
+            block(4, &[0, 0, 0, 0]),
+            block(PCAPNG_ENHANCED_PACKET, &packet),
+            block(PCAPNG_SIMPLE_PACKET, &simple),
+        ]
+        .concat();
+
+        let frames: Vec<Frame> = Reader::new(Cursor::new(file))
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].link_type, LINKTYPE_ETHERNET);
+        assert_eq!(frames[0].timestamp, Duration::new(1700000000, 123));
+        assert_eq!(frames[0].data, vec![7, 8, 9]);
+        assert_eq!(frames[1].data, vec![5, 6]);
+    }
+
+    #[test]
+    fn test_truncated_interface_option() {
+        let mut section = PCAPNG_BYTE_ORDER.to_le_bytes().to_vec();
+        section.extend([1, 0, 0, 0]);
+        section.extend(u64::MAX.to_le_bytes());
+        // if_tsresol says it has a byte but the block ends first
+        let interface = [1, 0, 0, 0, 0, 0, 1, 0, 9, 0, 1, 0];
+        let file = [
+            block(PCAPNG_SECTION_HEADER, &section),
+            block(PCAPNG_INTERFACE_DESCRIPTION, &interface),
+        ]
+        .concat();
+        let mut reader = Reader::new(Cursor::new(file)).unwrap();
+        assert!(reader.next_frame().is_err());
+    }
+}
@@ -0,0 +1,476 @@
+use crate::cidr::Cidr;
+use crate::dnstap::Protocol;
+use crate::name;
+use crate::packet::Packet;
+use crate::rcode::RCode;
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+pub const DEFAULT_MAX_BYTES: u64 = 100 * 1024 * 1024;
+pub const DEFAULT_KEEP: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Logfmt,
+}
+
+// everything logged about one query and the response it got
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub time: SystemTime,
+    pub client: SocketAddr,
+    pub transport: Protocol,
+    pub name: String,
+    pub r#type: String,
+    pub class: String,
+    pub rcode: String,
+    pub answers: usize,
+    pub flags: Vec<&'static str>,
+    pub latency: Duration,
+}
+
+// writes a line per query, for a sample of the queries from the subnets of
+// interest
+pub struct QueryLog {
+    pub format: Format,
+    // the fraction of queries logged, from 0 to 1
+    pub sample_rate: f64,
+    // only queries from these subnets are logged, or all of them if empty
+    pub subnets: Vec<Cidr>,
+    seen: AtomicU64,
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+// a log file that is moved aside once it reaches a size, keeping a number of
+// older files as path.1, path.2 and so on
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    file: File,
+    written: u64,
+    // the start of a line that hasn't been finished yet
+    pending: Vec<u8>,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(Format::Json),
+            "logfmt" => Ok(Format::Logfmt),
+            _ => Err(format!("unknown log format {:?}", s)),
+        }
+    }
+}
+
+impl Entry {
+    pub fn new(
+        client: SocketAddr,
+        transport: Protocol,
+        query: &Packet,
+        response: &Packet,
+        latency: Duration,
+    ) -> Self {
+        // the response's question is the one that was answered, but a
+        // FORMERR may not have one
+        let question = response.questions.first().or(query.questions.first());
+        Self {
+            time: SystemTime::now(),
+            client,
+            transport,
+            name: question.map_or(String::new(), |q| name::to_presentation(&q.name)),
+            r#type: question.map_or(String::new(), |q| q.r#type.to_string()),
+            class: question.map_or(String::new(), |q| q.class.to_string()),
+            rcode: RCode::from_value(response.rcode()).to_string(),
+            answers: response.answers.len(),
+            flags: response.header.flags(),
+            latency,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut line = String::from("{");
+        for (i, (key, value)) in self.fields().iter().enumerate() {
+            if i > 0 {
+                line.push(',');
+            }
+            let _ = write!(line, "\"{}\":", key);
+            match value {
+                Value::Text(text) => json_string(&mut line, text),
+                Value::Number(number) => line.push_str(number),
+                Value::List(items) => {
+                    line.push('[');
+                    for (i, item) in items.iter().enumerate() {
+                        if i > 0 {
+                            line.push(',');
+                        }
+                        json_string(&mut line, item);
+                    }
+                    line.push(']');
+                }
+            }
+        }
+        line.push('}');
+        line
+    }
+
+    pub fn to_logfmt(&self) -> String {
+        let fields: Vec<String> = self
+            .fields()
+            .iter()
+            .map(|(key, value)| {
+                let value = match value {
+                    Value::Text(text) => logfmt_value(text),
+                    Value::Number(number) => number.clone(),
+                    Value::List(items) => logfmt_value(&items.join(",")),
+                };
+                format!("{}={}", key, value)
+            })
+            .collect();
+        fields.join(" ")
+    }
+
+    fn fields(&self) -> Vec<(&'static str, Value)> {
+        let time = self
+            .time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        vec![
+            (
+                "ts",
+                Value::Number(format!("{}.{:06}", time.as_secs(), time.subsec_micros())),
+            ),
+            ("client", Value::Text(self.client.to_string())),
+            (
+                "transport",
+                Value::Text(self.transport.to_string().to_ascii_lowercase()),
+            ),
+            ("name", Value::Text(self.name.clone())),
+            ("type", Value::Text(self.r#type.clone())),
+            ("class", Value::Text(self.class.clone())),
+            ("rcode", Value::Text(self.rcode.clone())),
+            ("answers", Value::Number(self.answers.to_string())),
+            (
+                "flags",
+                Value::List(self.flags.iter().map(|flag| flag.to_string()).collect()),
+            ),
+            (
+                "latency_ms",
+                Value::Number(format!("{:.3}", self.latency.as_secs_f64() * 1000.0)),
+            ),
+        ]
+    }
+}
+
+enum Value {
+    Text(String),
+    Number(String),
+    List(Vec<String>),
+}
+
+impl QueryLog {
+    pub fn new<W: Write + Send + 'static>(writer: W, format: Format) -> Self {
+        Self {
+            format,
+            sample_rate: 1.0,
+            subnets: Vec::new(),
+            seen: AtomicU64::new(0),
+            writer: Mutex::new(Box::new(writer)),
+        }
+    }
+
+    pub fn to_file(path: &str, format: Format, max_bytes: u64, keep: usize) -> io::Result<Self> {
+        Ok(Self::new(
+            RotatingFile::open(path, max_bytes, keep)?,
+            format,
+        ))
+    }
+
+    // whether a query from this client would be logged, which moves the
+    // sampling along
+    pub fn wants(&self, client: SocketAddr) -> bool {
+        if !self.subnets.is_empty()
+            && !self
+                .subnets
+                .iter()
+                .any(|subnet| subnet.contains(client.ip()))
+        {
+            return false;
+        }
+        // every query advances the running total of how many should have
+        // been logged, and one is logged whenever that passes a whole number,
+        // which spreads them out evenly without needing a random number
+        let rate = self.sample_rate.clamp(0.0, 1.0);
+        let seen = self.seen.fetch_add(1, Ordering::Relaxed) as f64;
+        ((seen + 1.0) * rate).floor() > (seen * rate).floor()
+    }
+
+    // returns whether the entry was logged
+    pub fn log(&self, entry: &Entry) -> io::Result<bool> {
+        if !self.wants(entry.client) {
+            return Ok(false);
+        }
+        let mut line = match self.format {
+            Format::Json => entry.to_json(),
+            Format::Logfmt => entry.to_logfmt(),
+        };
+        line.push('\n');
+        let mut writer = self.writer.lock().unwrap();
+        writer.write_all(line.as_bytes())?;
+        writer.flush()?;
+        Ok(true)
+    }
+}
+
+impl RotatingFile {
+    pub fn open(path: &str, max_bytes: u64, keep: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path: PathBuf::from(path),
+            max_bytes,
+            keep,
+            file,
+            written,
+            pending: Vec::new(),
+        })
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", n));
+        path.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.keep == 0 {
+            // nothing to keep, so start the same file again
+            self.file = File::create(&self.path)?;
+            self.written = 0;
+            return Ok(());
+        }
+        for n in (1..self.keep).rev() {
+            match fs::rename(self.rotated(n), self.rotated(n + 1)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        fs::rename(&self.path, self.rotated(1))?;
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl RotatingFile {
+    // lines that would take the file over the limit start a new one, so a
+    // line is never split across files
+    fn write_lines(&mut self, lines: &[u8]) -> io::Result<()> {
+        if self.written > 0 && self.written + lines.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        self.file.write_all(lines)?;
+        self.written += lines.len() as u64;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    // only whole lines reach the file, however the writes are split up
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        if let Some(end) = self.pending.iter().rposition(|byte| *byte == b'\n') {
+            let lines: Vec<u8> = self.pending.drain(..=end).collect();
+            self.write_lines(&lines)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.pending.is_empty() {
+            let partial = std::mem::take(&mut self.pending);
+            self.write_lines(&partial)?;
+        }
+        self.file.flush()
+    }
+}
+
+fn json_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+// values with spaces, quotes or equals signs are quoted, names from the wire
+// can contain any of them
+fn logfmt_value(value: &str) -> String {
+    let needs_quotes = value.is_empty()
+        || value
+            .chars()
+            .any(|c| c == ' ' || c == '"' || c == '=' || c == '\\' || c.is_control());
+    if !needs_quotes {
+        return value.to_string();
+    }
+    let mut out = String::new();
+    json_string(&mut out, value);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::answer::Answer;
+    use crate::class::RRClass;
+    use crate::client;
+    use crate::r#type::RRType;
+    use std::sync::Arc;
+
+    fn entry(client: &str) -> Entry {
+        let query = client::build_query("example.com", RRType::A, RRClass::IN);
+        let mut response = query.clone();
+        response.header.response = true;
+        response.header.can_recurse = true;
+        response.answers.push(Answer::new(
+            "example.com",
+            RRType::A,
+            RRClass::IN,
+            300,
+            vec![192, 0, 2, 1],
+        ));
+        let mut entry = Entry::new(
+            client.parse().unwrap(),
+            Protocol::Udp,
+            &query,
+            &response,
+            Duration::from_micros(1500),
+        );
+        entry.time = SystemTime::UNIX_EPOCH + Duration::new(1700000000, 123_000);
+        entry
+    }
+
+    // a writer the test can read back from
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_formats() {
+        let entry = entry("192.0.2.1:40000");
+        assert_eq!(
+            entry.to_json(),
+            "{\"ts\":1700000000.000123,\"client\":\"192.0.2.1:40000\",\"transport\":\"udp\",\
+             \"name\":\"example.com.\",\"type\":\"A\",\"class\":\"IN\",\"rcode\":\"NOERROR\",\
+             \"answers\":1,\"flags\":[\"qr\",\"rd\",\"ra\"],\"latency_ms\":1.500}"
+        );
+        assert_eq!(
+            entry.to_logfmt(),
+            "ts=1700000000.000123 client=192.0.2.1:40000 transport=udp name=example.com. \
+             type=A class=IN rcode=NOERROR answers=1 flags=qr,rd,ra latency_ms=1.500"
+        );
+
+        let mut odd = entry.clone();
+        odd.name = "a b\"c.".to_string();
+        assert!(odd.to_logfmt().contains(" name=\"a b\\\"c.\" "));
+        assert!(odd.to_json().contains("\"name\":\"a b\\\"c.\""));
+    }
+
+    #[test]
+    fn test_sampling_and_subnets() {
+        let shared = Shared::default();
+        let mut log = QueryLog::new(shared.clone(), Format::Logfmt);
+        log.sample_rate = 0.25;
+        log.subnets = vec!["192.0.2.0/24".parse().unwrap()];
+
+        let logged = (0..100)
+            .filter(|_| log.log(&entry("192.0.2.1:40000")).unwrap())
+            .count();
+        assert_eq!(logged, 25);
+        assert!(!log.log(&entry("198.51.100.1:40000")).unwrap());
+
+        let lines = String::from_utf8(shared.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(lines.lines().count(), 25);
+        assert!(lines.lines().all(|line| line.contains("client=192.0.2.1:")));
+    }
+
+    #[test]
+    fn test_rotation() {
+        let dir = std::env::temp_dir().join(format!("dns-rs-querylog-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("queries.log");
+        let path = path.to_str().unwrap();
+
+        let line = entry("192.0.2.1:40000").to_json();
+        let log = QueryLog::to_file(path, Format::Json, line.len() as u64 * 2 + 2, 2).unwrap();
+        for _ in 0..7 {
+            log.log(&entry("192.0.2.1:40000")).unwrap();
+        }
+        // two lines to a file, and only two old files kept
+        let lines = |path: String| fs::read_to_string(path).unwrap().lines().count();
+        assert_eq!(lines(path.to_string()), 1);
+        assert_eq!(lines(format!("{}.1", path)), 2);
+        assert_eq!(lines(format!("{}.2", path)), 2);
+        assert!(!dir.join("queries.log.3").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rotation_keeps_lines_whole() {
+        let dir =
+            std::env::temp_dir().join(format!("dns-rs-querylog-split-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("queries.log");
+        let path = path.to_str().unwrap();
+
+        let mut file = RotatingFile::open(path, 10, 1).unwrap();
+        file.write_all(b"first\n").unwrap();
+        // a line written in pieces, the second of which crosses the limit
+        file.write_all(b"sec").unwrap();
+        file.write_all(b"ond\nthi").unwrap();
+        assert_eq!(
+            fs::read_to_string(format!("{}.1", path)).unwrap(),
+            "first\n"
+        );
+        assert_eq!(fs::read_to_string(path).unwrap(), "second\n");
+        // flushing writes out what there is of the last line
+        file.flush().unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "second\nthi");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,10 +1,17 @@
+use crate::answer::Answer;
 use crate::buf_reader::BufReader;
 use crate::class::RRClass;
+use crate::name;
 use crate::r#type::RRType;
 use std::convert::TryInto;
-use std::str;
+use std::fmt;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "json",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "crate::json::QuestionJson", from = "crate::json::QuestionJson")
+)]
 pub struct Question {
     pub name: String,
     pub r#type: RRType,
@@ -12,6 +19,14 @@ pub struct Question {
 }
 
 impl Question {
+    pub fn new(name: &str, r#type: RRType, class: RRClass) -> Self {
+        Self {
+            name: name.to_string(),
+            r#type,
+            class,
+        }
+    }
+
     pub fn from_buf(buf: &mut BufReader) -> Self {
         let name = Self::read_labels(buf);
         Self {
@@ -21,22 +36,9 @@ impl Question {
         }
     }
 
+    // question names can be compressed just like those in records
     pub fn read_labels(buf: &mut BufReader) -> String {
-        let mut fragments = Vec::new();
-        let mut curr_byte = buf.read(1)[0];
-
-        while curr_byte != 0b00000000 {
-            let bytes = [curr_byte];
-            let label_len = u8::from_be_bytes(bytes.try_into().unwrap()) as usize;
-            let label_bytes = buf.read(label_len);
-
-            let label = str::from_utf8(label_bytes).expect("Unable to convert bytes to str");
-            fragments.push(label.to_owned());
-
-            // advance to the next label len
-            curr_byte = buf.read(1)[0];
-        }
-        fragments.join(".")
+        Answer::read_labels(buf)
     }
 
     pub fn read_type(buf: &mut BufReader) -> RRType {
@@ -50,6 +52,25 @@ impl Question {
         let value = u16::from_be_bytes(bytes.try_into().unwrap());
         RRClass::from_value(value)
     }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = name::to_wire(&self.name);
+        bytes.extend_from_slice(&self.r#type.to_value().to_be_bytes());
+        bytes.extend_from_slice(&self.class.to_value().to_be_bytes());
+        bytes
+    }
+}
+
+impl fmt::Display for Question {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}\t\t{}\t{}",
+            name::to_presentation(&self.name),
+            self.class,
+            self.r#type
+        )
+    }
 }
 
 #[cfg(test)]
@@ -69,6 +90,8 @@ mod tests {
         ];
         let packet = [packet, question].concat();
         let mut buf = BufReader::new(&packet);
+        // skip past the header
+        buf.goto(12);
         let question = Question::from_buf(&mut buf);
         assert_eq!(question.name, "google.com");
         assert_eq!(question.r#type, RRType::A);
@@ -88,6 +111,8 @@ mod tests {
         ];
         let packet = [packet, question].concat();
         let mut buf = BufReader::new(&packet);
+        // skip past the header
+        buf.goto(12);
         let question = Question::from_buf(&mut buf);
         assert_eq!(question.name, "google.com");
         assert_eq!(question.r#type, RRType::NS);
@@ -107,9 +132,11 @@ mod tests {
         ];
         let packet = [packet, question].concat();
         let mut buf = BufReader::new(&packet);
+        // skip past the header
+        buf.goto(12);
         let question = Question::from_buf(&mut buf);
         assert_eq!(question.name, "google.com");
         assert_eq!(question.r#type, RRType::A);
-        assert_eq!(question.class, RRClass::UNKNOWN);
+        assert_eq!(question.class, RRClass::UNKNOWN(2));
     }
 }
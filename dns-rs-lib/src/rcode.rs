@@ -0,0 +1,62 @@
+use std::fmt;
+
+// response codes including the extended ones that need an OPT record,
+// 16 is both BADVERS and BADSIG depending on where it appears
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RCode {
+    NOERROR = 0,
+    FORMERR = 1,
+    SERVFAIL = 2,
+    NXDOMAIN = 3,
+    NOTIMP = 4,
+    REFUSED = 5,
+    YXDOMAIN = 6,
+    YXRRSET = 7,
+    NXRRSET = 8,
+    NOTAUTH = 9,
+    NOTZONE = 10,
+    DSOTYPENI = 11,
+    BADVERS = 16,
+    BADKEY = 17,
+    BADTIME = 18,
+    BADMODE = 19,
+    BADNAME = 20,
+    BADALG = 21,
+    BADTRUNC = 22,
+    BADCOOKIE = 23,
+    UNKNOWN,
+}
+
+impl RCode {
+    pub fn from_value(value: u16) -> Self {
+        match value {
+            0 => RCode::NOERROR,
+            1 => RCode::FORMERR,
+            2 => RCode::SERVFAIL,
+            3 => RCode::NXDOMAIN,
+            4 => RCode::NOTIMP,
+            5 => RCode::REFUSED,
+            6 => RCode::YXDOMAIN,
+            7 => RCode::YXRRSET,
+            8 => RCode::NXRRSET,
+            9 => RCode::NOTAUTH,
+            10 => RCode::NOTZONE,
+            11 => RCode::DSOTYPENI,
+            16 => RCode::BADVERS,
+            17 => RCode::BADKEY,
+            18 => RCode::BADTIME,
+            19 => RCode::BADMODE,
+            20 => RCode::BADNAME,
+            21 => RCode::BADALG,
+            22 => RCode::BADTRUNC,
+            23 => RCode::BADCOOKIE,
+            _ => RCode::UNKNOWN,
+        }
+    }
+}
+
+impl fmt::Display for RCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
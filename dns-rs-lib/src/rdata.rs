@@ -0,0 +1,412 @@
+use crate::base64;
+use crate::name;
+use crate::r#type::RRType;
+use std::convert::TryInto;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+// record data in zone file syntax, anything that can't be understood falls
+// back to the generic form from RFC 3597 section 5
+pub fn to_presentation(r#type: RRType, data: &[u8]) -> String {
+    let mut reader = Reader { data, pos: 0 };
+    match reader.present(r#type) {
+        Some(text) if reader.pos == data.len() => text,
+        _ => generic(data),
+    }
+}
+
+pub fn generic(data: &[u8]) -> String {
+    match data.len() {
+        0 => "\\# 0".to_string(),
+        len => format!("\\# {} {}", len, to_hex(data)),
+    }
+}
+
+pub fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+pub fn type_name(value: u16) -> String {
+    RRType::from_value(value).to_string()
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn present(&mut self, r#type: RRType) -> Option<String> {
+        let text = match r#type {
+            RRType::A => Ipv4Addr::from(self.array::<4>()?).to_string(),
+            RRType::AAAA => Ipv6Addr::from(self.array::<16>()?).to_string(),
+            RRType::NS | RRType::CNAME | RRType::MB | RRType::MG | RRType::MR | RRType::PTR => {
+                self.name()?
+            }
+            RRType::MINFO => format!("{} {}", self.name()?, self.name()?),
+            RRType::SOA => format!(
+                "{} {} {} {} {} {} {}",
+                self.name()?,
+                self.name()?,
+                self.u32()?,
+                self.u32()?,
+                self.u32()?,
+                self.u32()?,
+                self.u32()?
+            ),
+            RRType::MX => format!("{} {}", self.u16()?, self.name()?),
+            RRType::SRV => format!(
+                "{} {} {} {}",
+                self.u16()?,
+                self.u16()?,
+                self.u16()?,
+                self.name()?
+            ),
+            RRType::TXT => {
+                let mut strings = Vec::new();
+                while self.pos < self.data.len() {
+                    strings.push(self.character_string()?);
+                }
+                strings.join(" ")
+            }
+            RRType::HINFO => format!("{} {}", self.character_string()?, self.character_string()?),
+            RRType::DS => format!(
+                "{} {} {} {}",
+                self.u16()?,
+                self.u8()?,
+                self.u8()?,
+                to_hex(self.rest())
+            ),
+            RRType::DNSKEY => format!(
+                "{} {} {} {}",
+                self.u16()?,
+                self.u8()?,
+                self.u8()?,
+                base64::encode(self.rest())
+            ),
+            RRType::RRSIG => format!(
+                "{} {} {} {} {} {} {} {} {}",
+                type_name(self.u16()?),
+                self.u8()?,
+                self.u8()?,
+                self.u32()?,
+                timestamp(self.u32()?),
+                timestamp(self.u32()?),
+                self.u16()?,
+                self.name()?,
+                base64::encode(self.rest())
+            ),
+            RRType::NSEC => format!("{} {}", self.name()?, self.type_bitmap()?),
+            RRType::NSEC3 => format!(
+                "{} {} {} {} {} {}",
+                self.u8()?,
+                self.u8()?,
+                self.u16()?,
+                self.salt()?,
+                {
+                    let len = self.u8()? as usize;
+                    base32hex(self.take(len)?)
+                },
+                self.type_bitmap()?
+            ),
+            RRType::NSEC3PARAM => format!(
+                "{} {} {} {}",
+                self.u8()?,
+                self.u8()?,
+                self.u16()?,
+                self.salt()?
+            ),
+            _ => return None,
+        };
+        Some(text)
+    }
+
+    fn take(&mut self, len: usize) -> Option<&[u8]> {
+        let bytes = self.data.get(self.pos..self.pos + len)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn rest(&mut self) -> &[u8] {
+        let bytes = &self.data[self.pos..];
+        self.pos = self.data.len();
+        bytes
+    }
+
+    fn array<const N: usize>(&mut self) -> Option<[u8; N]> {
+        self.take(N)?.try_into().ok()
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_be_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.array()?))
+    }
+
+    // names in record data have already been expanded while parsing
+    fn name(&mut self) -> Option<String> {
+        let mut labels = Vec::new();
+        loop {
+            let len = self.u8()? as usize;
+            if len == 0 {
+                break;
+            }
+            labels.push(String::from_utf8_lossy(self.take(len)?).into_owned());
+        }
+        Some(name::to_presentation(&labels.join(".")))
+    }
+
+    fn character_string(&mut self) -> Option<String> {
+        let len = self.u8()? as usize;
+        let mut text = String::from("\"");
+        for &byte in self.take(len)? {
+            match byte {
+                b'"' | b'\\' => {
+                    text.push('\\');
+                    text.push(byte as char);
+                }
+                0x20..=0x7e => text.push(byte as char),
+                _ => text.push_str(&format!("\\{:03}", byte)),
+            }
+        }
+        text.push('"');
+        Some(text)
+    }
+
+    fn salt(&mut self) -> Option<String> {
+        let len = self.u8()? as usize;
+        match len {
+            0 => Some("-".to_string()),
+            _ => Some(to_hex(self.take(len)?)),
+        }
+    }
+
+    // RFC 4034 section 4.1.2: windows of up to 256 types, each a bitmap
+    fn type_bitmap(&mut self) -> Option<String> {
+        let mut types = Vec::new();
+        while self.pos < self.data.len() {
+            let window = self.u8()? as u16;
+            let len = self.u8()? as usize;
+            for (i, byte) in self.take(len)?.iter().enumerate() {
+                for bit in 0..8 {
+                    if byte & (0b10000000 >> bit) != 0 {
+                        types.push(type_name(window << 8 | (i * 8 + bit) as u16));
+                    }
+                }
+            }
+        }
+        Some(types.join(" "))
+    }
+}
+
+const BASE32HEX: &[u8; 32] = b"0123456789ABCDEFGHIJKLMNOPQRSTUV";
+
+// the extended hex alphabet from RFC 4648 section 7 without padding, which
+// NSEC3 uses for hashed owner names
+pub fn base32hex(data: &[u8]) -> String {
+    let mut encoded = String::new();
+    let mut group = 0u32;
+    let mut bits = 0;
+    for &byte in data {
+        group = group << 8 | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32HEX[(group >> bits & 0b11111) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32HEX[(group << (5 - bits) & 0b11111) as usize] as char);
+    }
+    encoded
+}
+
+// either case, since hashed owner names are usually written in lowercase
+pub fn from_base32hex(text: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::new();
+    let mut group = 0u32;
+    let mut bits = 0;
+    for c in text.bytes() {
+        let value = BASE32HEX
+            .iter()
+            .position(|&digit| digit == c.to_ascii_uppercase())?;
+        group = (group << 5 | value as u32) & 0xffff;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((group >> bits) as u8);
+        }
+    }
+    // leftover bits are padding and have to be zero
+    match group & ((1 << bits) - 1) {
+        0 => Some(decoded),
+        _ => None,
+    }
+}
+
+// signature times are shown as YYYYMMDDHHmmSS in UTC, RFC 4034 section 3.2
+fn timestamp(seconds: u32) -> String {
+    let days = (seconds / 86400) as i64;
+    let secs = seconds % 86400;
+
+    // days since the epoch to a civil date, from Howard Hinnant's algorithms
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{:04}{:02}{:02}{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+// the other way, also taking plain seconds, RFC 4034 section 3.2; years past
+// 2106 wrap round as serial numbers do
+pub fn from_timestamp(text: &str) -> Option<u32> {
+    if !text.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    if text.len() != 14 {
+        return text.parse().ok();
+    }
+    let field = |at: usize, len: usize| text[at..at + len].parse::<i64>().ok();
+    let (year, month, day) = (field(0, 4)?, field(4, 2)?, field(6, 2)?);
+    let (hour, minute, second) = (field(8, 2)?, field(10, 2)?, field(12, 2)?);
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        return None;
+    }
+
+    // the inverse of the civil date conversion above
+    let year = year - (month <= 2) as i64;
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    let seconds = days * 86400 + hour * 3600 + minute * 60 + second;
+    Some(seconds.rem_euclid(1 << 32) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_addresses() {
+        assert_eq!(to_presentation(RRType::A, &[192, 0, 2, 1]), "192.0.2.1");
+        let mut v6 = [0u8; 16];
+        v6[0] = 0x20;
+        v6[1] = 0x01;
+        v6[2] = 0x0d;
+        v6[3] = 0xb8;
+        v6[15] = 1;
+        assert_eq!(to_presentation(RRType::AAAA, &v6), "2001:db8::1");
+        assert_eq!(to_presentation(RRType::A, &[192, 0, 2]), "\\# 3 C00002");
+    }
+
+    #[test]
+    fn test_names() {
+        let data = [vec![0, 10], name::to_wire("mail.example.com")].concat();
+        assert_eq!(to_presentation(RRType::MX, &data), "10 mail.example.com.");
+        let data = [
+            vec![0, 10, 0, 5, 0x13, 0xc4],
+            name::to_wire("sip.example.com"),
+        ]
+        .concat();
+        assert_eq!(
+            to_presentation(RRType::SRV, &data),
+            "10 5 5060 sip.example.com."
+        );
+
+        let mut soa = name::to_wire("ns.example.com");
+        soa.extend(name::to_wire("hostmaster.example.com"));
+        for value in [2024010101u32, 7200, 3600, 1209600, 300] {
+            soa.extend_from_slice(&value.to_be_bytes());
+        }
+        assert_eq!(
+            to_presentation(RRType::SOA, &soa),
+            "ns.example.com. hostmaster.example.com. 2024010101 7200 3600 1209600 300"
+        );
+    }
+
+    #[test]
+    fn test_txt() {
+        let data = [b"\x05hello".to_vec(), b"\x04a\"b\x01".to_vec()].concat();
+        assert_eq!(
+            to_presentation(RRType::TXT, &data),
+            "\"hello\" \"a\\\"b\\001\""
+        );
+    }
+
+    #[test]
+    fn test_dnssec() {
+        let mut rrsig = vec![0, 1, 8, 2, 0, 0, 0x0e, 0x10];
+        rrsig.extend_from_slice(&1700000000u32.to_be_bytes());
+        rrsig.extend_from_slice(&1699000000u32.to_be_bytes());
+        rrsig.extend_from_slice(&[0x30, 0x39]);
+        rrsig.extend(name::to_wire("example.com"));
+        rrsig.extend_from_slice(b"sig");
+        assert_eq!(
+            to_presentation(RRType::RRSIG, &rrsig),
+            "A 8 2 3600 20231114221320 20231103082640 12345 example.com. c2ln"
+        );
+
+        // next name, then a bitmap of A, MX, RRSIG, NSEC and type 1234
+        let mut nsec = name::to_wire("host.example.com");
+        nsec.extend_from_slice(&[0, 6, 0x40, 0x01, 0, 0, 0, 0x03]);
+        nsec.extend_from_slice(&[4, 27, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        nsec.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x20]);
+        assert_eq!(
+            to_presentation(RRType::NSEC, &nsec),
+            "host.example.com. A MX RRSIG NSEC TYPE1234"
+        );
+
+        let nsec3param = vec![1, 0, 0, 10, 4, 0xaa, 0xbb, 0xcc, 0xdd];
+        assert_eq!(
+            to_presentation(RRType::NSEC3PARAM, &nsec3param),
+            "1 0 10 AABBCCDD"
+        );
+        assert_eq!(base32hex(b"foobar"), "CPNMUOJ1E8");
+        assert_eq!(from_base32hex("cpnmuoj1e8"), Some(b"foobar".to_vec()));
+        assert_eq!(from_base32hex("CPNMUOJ1E9"), None);
+        assert_eq!(from_base32hex("CPNMUOJ1EW"), None);
+
+        assert_eq!(from_timestamp("20231114221320"), Some(1700000000));
+        assert_eq!(from_timestamp("1699000000"), Some(1699000000));
+        assert_eq!(from_timestamp("19700101000000"), Some(0));
+        // past 2106 the time wraps round
+        assert_eq!(from_timestamp("21060207062816"), Some(0));
+        assert_eq!(from_timestamp("20231314221320"), None);
+        assert_eq!(from_timestamp("2023-11-14"), None);
+    }
+
+    #[test]
+    fn test_unknown() {
+        assert_eq!(
+            to_presentation(RRType::UNKNOWN(65000), &[1, 2]),
+            "\\# 2 0102"
+        );
+        assert_eq!(to_presentation(RRType::NULL, &[]), "\\# 0");
+    }
+}
@@ -12,9 +12,8 @@ impl A {
 
     fn read_ip(buf: &[u8]) -> String {
         let mut fragments = Vec::new();
-        for i in 0..4 {
-            // str::from_utf8(buf[i]).expect("Unable to convert bytes to str")
-            fragments.push(buf[i].to_string());
+        for byte in &buf[..4] {
+            fragments.push(byte.to_string());
         }
         fragments.join(".")
     }
@@ -0,0 +1,723 @@
+use crate::answer::Answer;
+use crate::cidr::{self, Cidr};
+use crate::edns::EdnsOption;
+use crate::extended_error::{ExtendedError, InfoCode};
+use crate::name;
+use crate::packet::Packet;
+use crate::r#type::RRType;
+use crate::rdata;
+use crate::zone;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+
+// what a policy does to a response, given by the record at the trigger's
+// owner name
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Nxdomain,
+    Nodata,
+    // answer as if there were no policy, and stop looking at later zones
+    Passthru,
+    Drop,
+    // truncate UDP responses so the client has to come back over TCP
+    TcpOnly,
+    // answer with the policy's own records instead
+    LocalData(Vec<Answer>),
+}
+
+// what matched, in order of precedence within a zone
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trigger {
+    ClientIp(Cidr),
+    Qname(String),
+    ResponseIp(Cidr),
+    Nsdname(String),
+    Nsip(Cidr),
+}
+
+// a policy that fired, for logging and for rewriting the response
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hit {
+    pub zone: String,
+    pub qname: String,
+    pub trigger: Trigger,
+    pub action: Action,
+}
+
+// what the resolver should do with the response
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Passthru,
+    Drop,
+    Respond(Packet),
+}
+
+// everything the triggers are checked against; the client and query name are
+// known up front and the rest once the response is in
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Query {
+    pub client: Option<IpAddr>,
+    pub qname: String,
+    pub response_ips: Vec<IpAddr>,
+    pub nsdnames: Vec<String>,
+    pub nsips: Vec<IpAddr>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Names {
+    exact: HashMap<String, Action>,
+    // keyed on the name under the wildcard, *.example.com as example.com
+    wildcards: HashMap<String, Action>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PolicyZone {
+    pub name: String,
+    client_ips: Vec<(Cidr, Action)>,
+    qnames: Names,
+    response_ips: Vec<(Cidr, Action)>,
+    nsdnames: Names,
+    nsips: Vec<(Cidr, Action)>,
+}
+
+// policy zones in order, an earlier zone's policy beats a later one's. A
+// zone can be swapped for a newer copy while queries are being checked
+#[derive(Debug, Default)]
+pub struct Rpz {
+    zones: RwLock<Arc<Vec<PolicyZone>>>,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Action::Nxdomain => "NXDOMAIN",
+            Action::Nodata => "NODATA",
+            Action::Passthru => "PASSTHRU",
+            Action::Drop => "DROP",
+            Action::TcpOnly => "TCP-ONLY",
+            Action::LocalData(_) => "Local-Data",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Trigger {
+    fn kind(&self) -> &'static str {
+        match self {
+            Trigger::ClientIp(_) => "CLIENT-IP",
+            Trigger::Qname(_) => "QNAME",
+            Trigger::ResponseIp(_) => "IP",
+            Trigger::Nsdname(_) => "NSDNAME",
+            Trigger::Nsip(_) => "NSIP",
+        }
+    }
+
+    // the owner name of the policy record, relative to its zone
+    fn owner(&self) -> String {
+        match self {
+            Trigger::ClientIp(cidr) => format!("{}.rpz-client-ip", reversed(cidr)),
+            Trigger::Qname(name) => name.clone(),
+            Trigger::ResponseIp(cidr) => format!("{}.rpz-ip", reversed(cidr)),
+            Trigger::Nsdname(name) => format!("{}.rpz-nsdname", name),
+            Trigger::Nsip(cidr) => format!("{}.rpz-nsip", reversed(cidr)),
+        }
+    }
+}
+
+// in the style of BIND's rpz log category
+impl fmt::Display for Hit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "rpz {} {} rewrite {} via {}.{}",
+            self.trigger.kind(),
+            self.action,
+            name::to_presentation(&self.qname),
+            self.trigger.owner(),
+            name::to_presentation(&self.zone)
+        )
+    }
+}
+
+impl Query {
+    pub fn new(client: Option<IpAddr>, qname: &str) -> Self {
+        Self {
+            client,
+            qname: qname.trim_end_matches('.').to_ascii_lowercase(),
+            ..Self::default()
+        }
+    }
+
+    // picks the addresses from the answer and the nameservers and their glue
+    // from a response or referral
+    pub fn add_response(&mut self, response: &Packet) {
+        for record in &response.answers {
+            if let Some(address) = address(record) {
+                self.response_ips.push(address);
+            }
+        }
+        for record in &response.authorities {
+            if record.r#type == RRType::NS {
+                let target = rdata::to_presentation(RRType::NS, &record.data);
+                self.nsdnames
+                    .push(target.trim_end_matches('.').to_ascii_lowercase());
+            }
+        }
+        for record in &response.additionals {
+            let name = record.name.to_ascii_lowercase();
+            if self.nsdnames.contains(&name) {
+                if let Some(address) = address(record) {
+                    self.nsips.push(address);
+                }
+            }
+        }
+    }
+}
+
+impl Names {
+    // an exact match beats a wildcard, and a closer wildcard beats one
+    // further up
+    fn find(&self, name: &str) -> Option<(String, &Action)> {
+        if let Some(action) = self.exact.get(name) {
+            return Some((name.to_string(), action));
+        }
+        let labels = name::labels(name);
+        (1..=labels.len()).find_map(|i| {
+            let parent = labels[i..].join(".");
+            self.wildcards
+                .get(&parent)
+                .map(|action| (format!("*.{}", parent), action))
+        })
+    }
+
+    fn insert(&mut self, name: &str, action: Action) {
+        match name.strip_prefix("*.") {
+            Some(parent) => self.wildcards.insert(parent.to_string(), action),
+            None => self.exact.insert(name.to_string(), action),
+        };
+    }
+}
+
+impl PolicyZone {
+    // with no policies, as a zone is until it's first loaded
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.trim_end_matches('.').to_ascii_lowercase(),
+            ..Self::default()
+        }
+    }
+
+    pub fn from_zone_file(name: &str, text: &str) -> Result<Self, String> {
+        Self::from_records(name, &zone::parse(text, name)?)
+    }
+
+    // records from a zone file or a transfer; the SOA and NS at the apex
+    // are only there to make it a zone
+    pub fn from_records(name: &str, records: &[Answer]) -> Result<Self, String> {
+        let zone_name = name.trim_end_matches('.').to_ascii_lowercase();
+        let suffix = format!(".{}", zone_name);
+        // grouped by owner, in the order they first appear
+        let mut owners: Vec<(String, Vec<Answer>)> = Vec::new();
+        let mut index: HashMap<String, usize> = HashMap::new();
+        for record in records {
+            let owner = record.name.to_ascii_lowercase();
+            if owner == zone_name {
+                continue;
+            }
+            let relative = owner
+                .strip_suffix(&suffix)
+                .ok_or_else(|| format!("{} is outside the policy zone {}", owner, zone_name))?;
+            match index.get(relative) {
+                Some(&i) => owners[i].1.push(record.clone()),
+                None => {
+                    index.insert(relative.to_string(), owners.len());
+                    owners.push((relative.to_string(), vec![record.clone()]));
+                }
+            }
+        }
+
+        let mut zone = PolicyZone::new(&zone_name);
+        for (owner, records) in owners {
+            let action = action(&records);
+            if let Some(rest) = owner.strip_suffix(".rpz-client-ip") {
+                zone.client_ips.push((parse_reversed(rest)?, action));
+            } else if let Some(rest) = owner.strip_suffix(".rpz-ip") {
+                zone.response_ips.push((parse_reversed(rest)?, action));
+            } else if let Some(rest) = owner.strip_suffix(".rpz-nsip") {
+                zone.nsips.push((parse_reversed(rest)?, action));
+            } else if let Some(rest) = owner.strip_suffix(".rpz-nsdname") {
+                zone.nsdnames.insert(rest, action);
+            } else {
+                zone.qnames.insert(&owner, action);
+            }
+        }
+        Ok(zone)
+    }
+
+    // client IP, then QNAME, then response IP, then NSDNAME, then NSIP
+    pub fn check(&self, query: &Query) -> Option<Hit> {
+        let hit = |trigger, action: &Action| Hit {
+            zone: self.name.clone(),
+            qname: query.qname.clone(),
+            trigger,
+            action: action.clone(),
+        };
+        if let Some(client) = query.client {
+            if let Some((cidr, action)) = longest_match(&self.client_ips, &[client]) {
+                return Some(hit(Trigger::ClientIp(cidr), action));
+            }
+        }
+        if let Some((name, action)) = self.qnames.find(&query.qname) {
+            return Some(hit(Trigger::Qname(name), action));
+        }
+        if let Some((cidr, action)) = longest_match(&self.response_ips, &query.response_ips) {
+            return Some(hit(Trigger::ResponseIp(cidr), action));
+        }
+        for nsdname in &query.nsdnames {
+            if let Some((name, action)) = self.nsdnames.find(nsdname) {
+                return Some(hit(Trigger::Nsdname(name), action));
+            }
+        }
+        if let Some((cidr, action)) = longest_match(&self.nsips, &query.nsips) {
+            return Some(hit(Trigger::Nsip(cidr), action));
+        }
+        None
+    }
+}
+
+impl Rpz {
+    pub fn new(zones: Vec<PolicyZone>) -> Self {
+        Self {
+            zones: RwLock::new(Arc::new(zones)),
+        }
+    }
+
+    pub fn zones(&self) -> Arc<Vec<PolicyZone>> {
+        self.zones.read().unwrap().clone()
+    }
+
+    // keeps the zone's place in the order, false if there's no zone by that
+    // name to replace
+    pub fn replace_zone(&self, zone: PolicyZone) -> bool {
+        let mut zones = self.zones.write().unwrap();
+        let Some(i) = zones.iter().position(|other| other.name == zone.name) else {
+            return false;
+        };
+        let mut replaced = zones.as_ref().clone();
+        replaced[i] = zone;
+        *zones = Arc::new(replaced);
+        true
+    }
+
+    pub fn check(&self, query: &Query) -> Option<Hit> {
+        self.zones().iter().find_map(|zone| zone.check(query))
+    }
+}
+
+impl Hit {
+    // the response to send in place of the real one
+    pub fn apply(&self, query: &Packet, over_tcp: bool) -> Outcome {
+        let mut header = query.header.clone();
+        header.response = true;
+        header.can_recurse = true;
+        header.is_authoritative = false;
+        let mut response = Packet::new(header);
+        response.questions = query.questions.clone();
+        response.edns = query.edns.clone();
+
+        match &self.action {
+            Action::Passthru => return Outcome::Passthru,
+            Action::Drop => return Outcome::Drop,
+            Action::TcpOnly if over_tcp => return Outcome::Passthru,
+            Action::TcpOnly => response.header.truncated = true,
+            Action::Nxdomain => response.header.resp_code = 3,
+            Action::Nodata => {}
+            Action::LocalData(records) => {
+                let Some(question) = query.questions.first() else {
+                    return Outcome::Respond(response);
+                };
+                // a CNAME answers any type, otherwise only matching records do
+                let cname = records.iter().find(|record| record.r#type == RRType::CNAME);
+                let matching: Vec<&Answer> = match cname {
+                    Some(cname) => vec![cname],
+                    None => records
+                        .iter()
+                        .filter(|record| record.r#type == question.r#type)
+                        .collect(),
+                };
+                for record in matching {
+                    let mut answer = record.clone();
+                    answer.name = question.name.clone();
+                    if record.r#type == RRType::CNAME {
+                        answer.data = name::to_wire(&self.cname_target(record));
+                        answer.len = answer.data.len() as u16;
+                    }
+                    response.answers.push(answer);
+                }
+            }
+        }
+        // RFC 8914 section 4: policy data in place of the real answer is
+        // forged, anything else is blocked
+        let code = match self.action {
+            Action::TcpOnly => None,
+            Action::LocalData(_) => Some(InfoCode::ForgedAnswer),
+            _ => Some(InfoCode::Blocked),
+        };
+        if let (Some(code), Some(edns)) = (code, &mut response.edns) {
+            let text = format!("policy zone {}", name::to_presentation(&self.zone));
+            edns.set_option(EdnsOption::ExtendedError(ExtendedError::new(code, &text)));
+        }
+        Outcome::Respond(response)
+    }
+
+    // CNAME *.example.net rewrites to the query name under example.net
+    fn cname_target(&self, record: &Answer) -> String {
+        let target = rdata::to_presentation(RRType::CNAME, &record.data);
+        let target = target.trim_end_matches('.');
+        match target.strip_prefix("*.") {
+            Some(suffix) => format!("{}.{}", self.qname, suffix),
+            None => target.to_string(),
+        }
+    }
+}
+
+// special CNAME targets name the actions, anything else is local data
+fn action(records: &[Answer]) -> Action {
+    if let [record] = records {
+        if record.r#type == RRType::CNAME {
+            let target = rdata::to_presentation(RRType::CNAME, &record.data);
+            match target.trim_end_matches('.').to_ascii_lowercase().as_str() {
+                "" => return Action::Nxdomain,
+                "*" => return Action::Nodata,
+                "rpz-passthru" => return Action::Passthru,
+                "rpz-drop" => return Action::Drop,
+                "rpz-tcp-only" => return Action::TcpOnly,
+                _ => {}
+            }
+        }
+    }
+    Action::LocalData(records.to_vec())
+}
+
+// the longest prefix wins, RPZ's rule for every IP trigger
+fn longest_match<'a>(
+    rules: &'a [(Cidr, Action)],
+    addresses: &[IpAddr],
+) -> Option<(Cidr, &'a Action)> {
+    rules
+        .iter()
+        .filter(|(cidr, _)| addresses.iter().any(|address| cidr.contains(*address)))
+        .max_by_key(|(cidr, _)| cidr.prefix)
+        .map(|(cidr, action)| (*cidr, action))
+}
+
+// 24.0.2.0.192 is 192.0.2.0/24, and 48.zz.db8.2001 is 2001:db8::/48
+fn parse_reversed(text: &str) -> Result<Cidr, String> {
+    let error = || format!("invalid address trigger {:?}", text);
+    let mut labels: Vec<&str> = text.split('.').collect();
+    let prefix: u8 = labels.remove(0).parse().map_err(|_| error())?;
+    labels.reverse();
+
+    let address: IpAddr =
+        if labels.len() == 4 && labels.iter().all(|label| label.parse::<u8>().is_ok()) {
+            labels.join(".").parse().map_err(|_| error())?
+        } else {
+            let mut address = labels
+                .iter()
+                .map(|label| if *label == "zz" { "" } else { label })
+                .collect::<Vec<_>>()
+                .join(":");
+            if address.starts_with(':') {
+                address.insert(0, ':');
+            }
+            if address.ends_with(':') {
+                address.push(':');
+            }
+            address.parse().map_err(|_| error())?
+        };
+    if prefix > cidr::max_prefix(address) {
+        return Err(error());
+    }
+    Ok(Cidr::new(address, prefix))
+}
+
+fn reversed(cidr: &Cidr) -> String {
+    match cidr.address {
+        IpAddr::V4(address) => {
+            let mut octets: Vec<String> = address.octets().iter().map(u8::to_string).collect();
+            octets.reverse();
+            format!("{}.{}", cidr.prefix, octets.join("."))
+        }
+        IpAddr::V6(address) => {
+            // the compressed form's :: becomes zz
+            let text = address.to_string();
+            let mut groups: Vec<&str> = text
+                .split(':')
+                .map(|group| if group.is_empty() { "zz" } else { group })
+                .collect();
+            groups.dedup_by(|a, b| *a == "zz" && *b == "zz");
+            groups.reverse();
+            format!("{}.{}", cidr.prefix, groups.join("."))
+        }
+    }
+}
+
+fn address(record: &Answer) -> Option<IpAddr> {
+    match record.r#type {
+        RRType::A => <[u8; 4]>::try_from(record.data.as_slice())
+            .ok()
+            .map(IpAddr::from),
+        RRType::AAAA => <[u8; 16]>::try_from(record.data.as_slice())
+            .ok()
+            .map(IpAddr::from),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::class::RRClass;
+    use crate::client;
+    use crate::edns::Edns;
+
+    const POLICY: &str = "$TTL 300
+@ SOA localhost. root.localhost. 1 3600 600 86400 300
+  NS localhost.
+bad.example.com CNAME .
+*.bad.example.com CNAME .
+empty.example.com CNAME *.
+ok.bad.example.com CNAME rpz-passthru.
+drop.example.com CNAME rpz-drop.
+tcp.example.com CNAME rpz-tcp-only.
+garden.example.com A 192.0.2.80
+  TXT \"blocked\"
+*.ads.example.com CNAME *.sinkhole.example.net.
+32.1.2.0.192.rpz-client-ip CNAME rpz-passthru.
+24.0.2.0.198.rpz-client-ip CNAME .
+24.0.113.0.203.rpz-ip CNAME .
+32.7.113.0.203.rpz-ip CNAME rpz-drop.
+48.zz.db8.2001.rpz-ip CNAME *.
+ns.evil.example.rpz-nsdname CNAME .
+32.9.2.0.192.rpz-nsip CNAME rpz-drop.
+";
+
+    fn rpz() -> Rpz {
+        Rpz::new(vec![
+            PolicyZone::from_zone_file("rpz.local", POLICY).unwrap()
+        ])
+    }
+
+    fn check(qname: &str) -> Option<Hit> {
+        rpz().check(&Query::new(Some("192.0.2.200".parse().unwrap()), qname))
+    }
+
+    #[test]
+    fn test_qname_triggers() {
+        assert_eq!(check("bad.example.com").unwrap().action, Action::Nxdomain);
+        assert_eq!(check("BAD.example.com.").unwrap().action, Action::Nxdomain);
+        let hit = check("www.bad.example.com").unwrap();
+        assert_eq!(hit.trigger, Trigger::Qname("*.bad.example.com".to_string()));
+        // an exact match beats the wildcard
+        assert_eq!(
+            check("ok.bad.example.com").unwrap().action,
+            Action::Passthru
+        );
+        assert_eq!(check("empty.example.com").unwrap().action, Action::Nodata);
+        assert_eq!(check("drop.example.com").unwrap().action, Action::Drop);
+        assert_eq!(check("tcp.example.com").unwrap().action, Action::TcpOnly);
+        assert!(check("good.example.com").is_none());
+        assert_eq!(
+            check("www.bad.example.com").unwrap().to_string(),
+            "rpz QNAME NXDOMAIN rewrite www.bad.example.com. via *.bad.example.com.rpz.local."
+        );
+    }
+
+    #[test]
+    fn test_client_ip_comes_first() {
+        let rpz = rpz();
+        // a passthru for this client beats the QNAME policy
+        let query = Query::new(Some("192.0.2.1".parse().unwrap()), "bad.example.com");
+        let hit = rpz.check(&query).unwrap();
+        assert_eq!(hit.action, Action::Passthru);
+        assert_eq!(
+            hit.trigger,
+            Trigger::ClientIp("192.0.2.1/32".parse().unwrap())
+        );
+        assert_eq!(
+            hit.to_string(),
+            "rpz CLIENT-IP PASSTHRU rewrite bad.example.com. via 32.1.2.0.192.rpz-client-ip.rpz.local."
+        );
+
+        let query = Query::new(Some("198.0.2.9".parse().unwrap()), "good.example.com");
+        assert_eq!(rpz.check(&query).unwrap().action, Action::Nxdomain);
+    }
+
+    #[test]
+    fn test_response_triggers() {
+        let rpz = rpz();
+        let mut response = client::build_query("www.example.org", RRType::A, RRClass::IN);
+        for address in [[203, 0, 113, 7], [203, 0, 113, 8]] {
+            response.answers.push(Answer::new(
+                "www.example.org",
+                RRType::A,
+                RRClass::IN,
+                300,
+                address.to_vec(),
+            ));
+        }
+        let mut query = Query::new(None, "www.example.org");
+        query.add_response(&response);
+        // the /32 is longer than the /24
+        let hit = rpz.check(&query).unwrap();
+        assert_eq!(hit.action, Action::Drop);
+        assert_eq!(
+            hit.trigger,
+            Trigger::ResponseIp("203.0.113.7/32".parse().unwrap())
+        );
+
+        let mut query = Query::new(None, "www.example.org");
+        query.response_ips = vec!["2001:db8::1".parse().unwrap()];
+        assert_eq!(rpz.check(&query).unwrap().action, Action::Nodata);
+
+        let mut referral = client::build_query("www.example.org", RRType::A, RRClass::IN);
+        referral.authorities.push(Answer::new(
+            "example.org",
+            RRType::NS,
+            RRClass::IN,
+            300,
+            name::to_wire("NS.evil.example"),
+        ));
+        referral.additionals.push(Answer::new(
+            "ns.evil.example",
+            RRType::A,
+            RRClass::IN,
+            300,
+            vec![192, 0, 2, 9],
+        ));
+        let mut query = Query::new(None, "www.example.org");
+        query.add_response(&referral);
+        assert_eq!(query.nsips, vec!["192.0.2.9".parse::<IpAddr>().unwrap()]);
+        let hit = rpz.check(&query).unwrap();
+        assert_eq!(hit.trigger, Trigger::Nsdname("ns.evil.example".to_string()));
+        query.nsdnames.clear();
+        assert_eq!(rpz.check(&query).unwrap().action, Action::Drop);
+    }
+
+    #[test]
+    fn test_earlier_zones_win() {
+        let allow = PolicyZone::from_zone_file(
+            "allow.local",
+            "$TTL 60\nbad.example.com CNAME rpz-passthru.\n",
+        )
+        .unwrap();
+        let rpz = Rpz::new(vec![allow, rpz().zones()[0].clone()]);
+        let hit = rpz.check(&Query::new(None, "bad.example.com")).unwrap();
+        assert_eq!(hit.zone, "allow.local");
+        assert_eq!(hit.action, Action::Passthru);
+    }
+
+    #[test]
+    fn test_replace_zone() {
+        let rpz = rpz();
+        let newer = PolicyZone::from_zone_file(
+            "rpz.local",
+            "$TTL 60
+good.example.com CNAME .
+",
+        )
+        .unwrap();
+        assert!(rpz.replace_zone(newer));
+        assert_eq!(rpz.zones().len(), 1);
+        assert!(rpz.check(&Query::new(None, "bad.example.com")).is_none());
+        assert!(rpz.check(&Query::new(None, "good.example.com")).is_some());
+
+        let other = PolicyZone::from_zone_file(
+            "other.local",
+            "$TTL 60
+",
+        )
+        .unwrap();
+        assert!(!rpz.replace_zone(other));
+    }
+
+    #[test]
+    fn test_apply() {
+        let query = client::build_query("garden.example.com", RRType::A, RRClass::IN);
+        let Outcome::Respond(response) = check("garden.example.com").unwrap().apply(&query, false)
+        else {
+            panic!("expected a response");
+        };
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(response.answers[0].data, vec![192, 0, 2, 80]);
+
+        let query = client::build_query("garden.example.com", RRType::MX, RRClass::IN);
+        let Outcome::Respond(response) = check("garden.example.com").unwrap().apply(&query, false)
+        else {
+            panic!("expected a response");
+        };
+        assert!(response.answers.is_empty());
+        assert_eq!(response.rcode(), 0);
+
+        let query = client::build_query("x.ads.example.com", RRType::A, RRClass::IN);
+        let Outcome::Respond(response) = check("x.ads.example.com").unwrap().apply(&query, false)
+        else {
+            panic!("expected a response");
+        };
+        assert_eq!(
+            rdata::to_presentation(RRType::CNAME, &response.answers[0].data),
+            "x.ads.example.com.sinkhole.example.net."
+        );
+
+        let mut query = client::build_query("bad.example.com", RRType::A, RRClass::IN);
+        query.edns = Some(Edns::new(1232));
+        let Outcome::Respond(response) = check("bad.example.com").unwrap().apply(&query, false)
+        else {
+            panic!("expected a response");
+        };
+        assert_eq!(response.rcode(), 3);
+        let edns = response.edns.unwrap();
+        let errors = edns.extended_errors();
+        assert_eq!(errors[0].info_code(), InfoCode::Blocked);
+        assert_eq!(errors[0].extra_text, "policy zone rpz.local.");
+        query.questions[0].name = "garden.example.com".to_string();
+        let Outcome::Respond(response) = check("garden.example.com").unwrap().apply(&query, false)
+        else {
+            panic!("expected a response");
+        };
+        let edns = response.edns.unwrap();
+        assert_eq!(
+            edns.extended_errors()[0].info_code(),
+            InfoCode::ForgedAnswer
+        );
+
+        let query = client::build_query("tcp.example.com", RRType::A, RRClass::IN);
+        let hit = check("tcp.example.com").unwrap();
+        assert_eq!(hit.apply(&query, true), Outcome::Passthru);
+        let Outcome::Respond(response) = hit.apply(&query, false) else {
+            panic!("expected a response");
+        };
+        assert!(response.header.truncated);
+        assert_eq!(
+            check("drop.example.com").unwrap().apply(&query, false),
+            Outcome::Drop
+        );
+    }
+
+    #[test]
+    fn test_reversed_addresses() {
+        for (text, cidr) in [
+            ("24.0.2.0.192", "192.0.2.0/24"),
+            ("48.zz.db8.2001", "2001:db8::/48"),
+            ("128.1.zz.db8.2001", "2001:db8::1/128"),
+        ] {
+            let parsed = parse_reversed(text).unwrap();
+            assert_eq!(parsed, cidr.parse().unwrap());
+            assert_eq!(reversed(&parsed), text);
+        }
+        assert!(parse_reversed("33.1.2.0.192").is_err());
+        assert!(parse_reversed("x.1.2.0.192").is_err());
+    }
+}
@@ -0,0 +1,462 @@
+use crate::cidr;
+use crate::packet::Packet;
+use crate::r#type::RRType;
+use crate::rcode::RCode;
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// where the limiter gets the time from, so tests can move it themselves
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+// defaults follow BIND's suggested starting points
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub responses_per_second: u32,
+    pub nxdomains_per_second: u32,
+    pub errors_per_second: u32,
+    // every slip'th limited response is sent truncated instead of dropped,
+    // 0 drops them all
+    pub slip: u32,
+    // how long a client that kept going over the limit stays limited
+    pub window: Duration,
+    pub ipv4_prefix: u8,
+    pub ipv6_prefix: u8,
+    // at most this many buckets are kept, letting go of those that have
+    // fully recovered first and then the oldest
+    pub max_entries: usize,
+}
+
+// what a response is counted against, so that a flood of one answer doesn't
+// hold up the client's other answers
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Token {
+    // the owner is the wildcard rather than the query name when the answer
+    // was synthesized from one
+    Answer(String, RRType),
+    // the zone, so random names under it all share a bucket
+    Nxdomain(String),
+    Error(u16),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Send,
+    // send a truncated response so a real client retries over TCP
+    Slip,
+    Drop,
+}
+
+#[derive(Debug, Clone)]
+struct Bucket {
+    balance: f64,
+    updated: Instant,
+    // limited responses so far, for working out which ones slip
+    limited: u64,
+}
+
+type Key = (IpAddr, Token);
+
+#[derive(Debug, Default)]
+struct Table {
+    buckets: HashMap<Key, Bucket>,
+    // the same keys, oldest first
+    order: VecDeque<Key>,
+    purged: Option<Instant>,
+}
+
+// response rate limiting the way BIND does it; responses over TCP shouldn't
+// be checked since the client's address can't be spoofed there
+pub struct Rrl<C: Clock = SystemClock> {
+    pub config: Config,
+    clock: C,
+    table: Mutex<Table>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            responses_per_second: 5,
+            nxdomains_per_second: 5,
+            errors_per_second: 5,
+            slip: 2,
+            window: Duration::from_secs(15),
+            ipv4_prefix: 24,
+            ipv6_prefix: 56,
+            max_entries: 100_000,
+        }
+    }
+}
+
+impl Token {
+    // the zone for an NXDOMAIN comes from the SOA in the authority section;
+    // wildcard is the owner of the wildcard an answer was synthesized from,
+    // which only whoever answered the query knows
+    pub fn from_response(response: &Packet, wildcard: Option<&str>) -> Self {
+        let rcode = response.rcode();
+        let question = response.questions.first();
+        match RCode::from_value(rcode) {
+            RCode::NOERROR => match question {
+                Some(question) => Token::Answer(
+                    wildcard.unwrap_or(&question.name).to_ascii_lowercase(),
+                    question.r#type,
+                ),
+                None => Token::Error(rcode),
+            },
+            RCode::NXDOMAIN => {
+                let zone = response
+                    .authorities
+                    .iter()
+                    .find(|record| record.r#type == RRType::SOA)
+                    .map(|record| &record.name)
+                    .or(question.map(|question| &question.name));
+                match zone {
+                    Some(zone) => Token::Nxdomain(zone.to_ascii_lowercase()),
+                    None => Token::Error(rcode),
+                }
+            }
+            _ => Token::Error(rcode),
+        }
+    }
+}
+
+impl Rrl<SystemClock> {
+    pub fn new(config: Config) -> Self {
+        Self::with_clock(config, SystemClock)
+    }
+}
+
+impl<C: Clock> Rrl<C> {
+    pub fn with_clock(config: Config, clock: C) -> Self {
+        Self {
+            config,
+            clock,
+            table: Mutex::new(Table::default()),
+        }
+    }
+
+    pub fn check(&self, client: IpAddr, response: &Packet, wildcard: Option<&str>) -> Action {
+        self.check_token(client, Token::from_response(response, wildcard))
+    }
+
+    pub fn check_token(&self, client: IpAddr, token: Token) -> Action {
+        let rate = self.rate(&token);
+        if rate == 0.0 {
+            return Action::Send;
+        }
+        let prefix = match client {
+            IpAddr::V4(_) => self.config.ipv4_prefix,
+            IpAddr::V6(_) => self.config.ipv6_prefix,
+        };
+        let key = (cidr::truncate(client, prefix), token);
+        let now = self.clock.now();
+
+        let mut table = self.table.lock().unwrap();
+        if !table.buckets.contains_key(&key) {
+            self.make_room(&mut table, now);
+            table.order.push_back(key.clone());
+        }
+        let bucket = table.buckets.entry(key).or_insert(Bucket {
+            balance: rate,
+            updated: now,
+            limited: 0,
+        });
+
+        // credit builds back up at the rate, to at most a second's worth, and
+        // debt can run to a window's worth
+        let elapsed = now.saturating_duration_since(bucket.updated);
+        bucket.updated = now;
+        bucket.balance = (bucket.balance + elapsed.as_secs_f64() * rate).min(rate) - 1.0;
+        if bucket.balance >= 0.0 {
+            return Action::Send;
+        }
+        bucket.balance = bucket.balance.max(-rate * self.config.window.as_secs_f64());
+        bucket.limited += 1;
+        match self.config.slip {
+            0 => Action::Drop,
+            slip if bucket.limited.is_multiple_of(slip as u64) => Action::Slip,
+            _ => Action::Drop,
+        }
+    }
+
+    fn rate(&self, token: &Token) -> f64 {
+        let rate = match token {
+            Token::Answer(..) => self.config.responses_per_second,
+            Token::Nxdomain(_) => self.config.nxdomains_per_second,
+            Token::Error(_) => self.config.errors_per_second,
+        };
+        rate as f64
+    }
+
+    // a bucket with its full balance back is the same as no bucket, so those
+    // go first, but looking for them takes a pass over the whole table and
+    // happens at most once a second; after that the oldest buckets go
+    fn make_room(&self, table: &mut Table, now: Instant) {
+        if table.buckets.len() < self.config.max_entries {
+            return;
+        }
+        let due = table
+            .purged
+            .is_none_or(|purged| now.saturating_duration_since(purged) >= Duration::from_secs(1));
+        if due {
+            table.purged = Some(now);
+            table.buckets.retain(|(_, token), bucket| {
+                let rate = self.rate(token);
+                let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+                bucket.balance + elapsed * rate < rate
+            });
+            let buckets = &table.buckets;
+            table.order.retain(|key| buckets.contains_key(key));
+        }
+        while table.buckets.len() >= self.config.max_entries.max(1) {
+            match table.order.pop_front() {
+                Some(oldest) => table.buckets.remove(&oldest),
+                None => break,
+            };
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.table.lock().unwrap().buckets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// what gets sent in place of a limited response that slips: just the
+// question, truncated
+pub fn slip(response: &Packet) -> Packet {
+    let mut header = response.header.clone();
+    header.truncated = true;
+    header.is_authoritative = false;
+    let mut slipped = Packet::new(header);
+    slipped.questions = response.questions.clone();
+    slipped.edns = response.edns.clone();
+    slipped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::answer::Answer;
+    use crate::class::RRClass;
+    use crate::client;
+    use std::cell::Cell;
+
+    struct FakeClock(Cell<Instant>);
+
+    impl Clock for &FakeClock {
+        fn now(&self) -> Instant {
+            self.0.get()
+        }
+    }
+
+    impl FakeClock {
+        fn advance(&self, by: Duration) {
+            self.0.set(self.0.get() + by);
+        }
+    }
+
+    fn response(name: &str, rcode: u8) -> Packet {
+        let mut response = client::build_query(name, RRType::A, RRClass::IN);
+        response.header.response = true;
+        response.header.resp_code = rcode;
+        if rcode == 3 {
+            response.authorities.push(Answer::new(
+                "example.com",
+                RRType::SOA,
+                RRClass::IN,
+                300,
+                vec![],
+            ));
+        }
+        response
+    }
+
+    fn config() -> Config {
+        Config {
+            responses_per_second: 2,
+            nxdomains_per_second: 1,
+            slip: 2,
+            window: Duration::from_secs(5),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn test_limits_and_slips() {
+        let clock = FakeClock(Cell::new(Instant::now()));
+        let rrl = Rrl::with_clock(config(), &clock);
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+        let answer = response("www.example.com", 0);
+
+        let actions: Vec<Action> = (0..6).map(|_| rrl.check(client, &answer, None)).collect();
+        assert_eq!(
+            actions,
+            vec![
+                Action::Send,
+                Action::Send,
+                Action::Drop,
+                Action::Slip,
+                Action::Drop,
+                Action::Slip
+            ]
+        );
+
+        // a different answer to the same client has its own budget, and so
+        // does a client in another /24
+        assert_eq!(
+            rrl.check(client, &response("mail.example.com", 0), None),
+            Action::Send
+        );
+        assert_eq!(
+            rrl.check("192.0.3.1".parse().unwrap(), &answer, None),
+            Action::Send
+        );
+        // but the same /24 shares it
+        assert_eq!(
+            rrl.check("192.0.2.200".parse().unwrap(), &answer, None),
+            Action::Drop
+        );
+    }
+
+    #[test]
+    fn test_recovers_after_window() {
+        let clock = FakeClock(Cell::new(Instant::now()));
+        let rrl = Rrl::with_clock(config(), &clock);
+        let client: IpAddr = "2001:db8::1".parse().unwrap();
+        let answer = response("www.example.com", 0);
+
+        for _ in 0..100 {
+            rrl.check(client, &answer, None);
+        }
+        // debt is capped at the window, 5s at 2 per second
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(rrl.check(client, &answer, None), Action::Drop);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(rrl.check(client, &answer, None), Action::Send);
+    }
+
+    #[test]
+    fn test_nxdomain_tokens_share_the_zone() {
+        let clock = FakeClock(Cell::new(Instant::now()));
+        let rrl = Rrl::with_clock(config(), &clock);
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+
+        assert_eq!(
+            Token::from_response(&response("a.example.com", 3), None),
+            Token::Nxdomain("example.com".to_string())
+        );
+        assert_eq!(
+            rrl.check(client, &response("a.example.com", 3), None),
+            Action::Send
+        );
+        assert_eq!(
+            rrl.check(client, &response("b.example.com", 3), None),
+            Action::Drop
+        );
+        assert_eq!(
+            rrl.check(client, &response("c.example.com", 3), None),
+            Action::Slip
+        );
+        assert_eq!(
+            Token::from_response(&response("a.example.com", 2), None),
+            Token::Error(2)
+        );
+    }
+
+    #[test]
+    fn test_purges_recovered_buckets() {
+        let clock = FakeClock(Cell::new(Instant::now()));
+        let config = Config {
+            max_entries: 2,
+            ..config()
+        };
+        let rrl = Rrl::with_clock(config, &clock);
+        let answer = response("www.example.com", 0);
+        rrl.check("192.0.2.1".parse().unwrap(), &answer, None);
+        rrl.check("192.0.3.1".parse().unwrap(), &answer, None);
+        clock.advance(Duration::from_secs(1));
+        rrl.check("192.0.4.1".parse().unwrap(), &answer, None);
+        assert_eq!(rrl.len(), 1);
+    }
+
+    #[test]
+    fn test_evicts_the_oldest() {
+        let clock = FakeClock(Cell::new(Instant::now()));
+        let config = Config {
+            max_entries: 2,
+            ..config()
+        };
+        let rrl = Rrl::with_clock(config, &clock);
+        let answer = response("www.example.com", 0);
+        let first: IpAddr = "192.0.2.1".parse().unwrap();
+        // none of these recover, so the table is kept small by letting the
+        // oldest go
+        for _ in 0..3 {
+            rrl.check(first, &answer, None);
+        }
+        for client in ["192.0.3.1", "192.0.4.1", "192.0.5.1"] {
+            rrl.check(client.parse().unwrap(), &answer, None);
+            rrl.check(client.parse().unwrap(), &answer, None);
+            assert!(rrl.len() <= 2);
+        }
+        // the first client was forgotten and starts over
+        assert_eq!(rrl.check(first, &answer, None), Action::Send);
+    }
+
+    #[test]
+    fn test_wildcard_tokens() {
+        let answer = response("a.example.com", 0);
+        assert_eq!(
+            Token::from_response(&answer, Some("*.Example.com")),
+            Token::Answer("*.example.com".to_string(), RRType::A)
+        );
+        let clock = FakeClock(Cell::new(Instant::now()));
+        let rrl = Rrl::with_clock(config(), &clock);
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+        // different names from the same wildcard share a bucket
+        for name in ["a.example.com", "b.example.com"] {
+            let answer = response(name, 0);
+            assert_eq!(
+                rrl.check(client, &answer, Some("*.example.com")),
+                Action::Send
+            );
+        }
+        assert_eq!(
+            rrl.check(client, &response("c.example.com", 0), Some("*.example.com")),
+            Action::Drop
+        );
+    }
+
+    #[test]
+    fn test_slip() {
+        let mut answer = response("www.example.com", 0);
+        answer.header.is_authoritative = true;
+        answer.answers.push(Answer::new(
+            "www.example.com",
+            RRType::A,
+            RRClass::IN,
+            300,
+            vec![192, 0, 2, 1],
+        ));
+        let slipped = slip(&answer);
+        assert!(slipped.header.truncated);
+        assert!(slipped.answers.is_empty());
+        assert_eq!(slipped.questions, answer.questions);
+    }
+}
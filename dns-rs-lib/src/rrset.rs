@@ -0,0 +1,179 @@
+use crate::answer::Answer;
+use crate::class::RRClass;
+use crate::name;
+use crate::r#type::RRType;
+use std::cmp::Ordering;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RRset {
+    pub name: String,
+    pub r#type: RRType,
+    pub class: RRClass,
+    pub ttl: u32,
+    pub data: Vec<Vec<u8>>,
+}
+
+impl RRset {
+    pub fn new(name: &str, r#type: RRType, class: RRClass, ttl: u32) -> Self {
+        Self {
+            name: name.to_string(),
+            r#type,
+            class,
+            ttl,
+            data: Vec::new(),
+        }
+    }
+
+    // groups records sharing an owner name, type and class, returning the
+    // sets in canonical order
+    pub fn from_answers(answers: &[Answer]) -> Vec<Self> {
+        let mut sets: Vec<Self> = Vec::new();
+        for answer in answers {
+            let existing = sets.iter_mut().find(|set| set.contains_answer(answer));
+            match existing {
+                Some(set) => {
+                    set.ttl = set.ttl.min(answer.ttl);
+                    set.data.push(answer.data.clone());
+                }
+                None => {
+                    let mut set = Self::new(&answer.name, answer.r#type, answer.class, answer.ttl);
+                    set.data.push(answer.data.clone());
+                    sets.push(set);
+                }
+            }
+        }
+        for set in sets.iter_mut() {
+            set.canonicalize();
+        }
+        sets.sort_by(Self::canonical_cmp);
+        sets
+    }
+
+    fn contains_answer(&self, answer: &Answer) -> bool {
+        self.r#type == answer.r#type
+            && self.class == answer.class
+            && self.name.eq_ignore_ascii_case(&answer.name)
+    }
+
+    // RFC 4034 section 6.2 and 6.3: lowercase the owner and any names in the
+    // record data, then sort the records and drop duplicates
+    pub fn canonicalize(&mut self) {
+        self.name = self.name.to_ascii_lowercase();
+        for data in self.data.iter_mut() {
+            Self::lowercase_data(self.r#type, data);
+        }
+        self.data.sort();
+        self.data.dedup();
+    }
+
+    fn lowercase_data(r#type: RRType, data: &mut [u8]) {
+        match r#type {
+            RRType::NS | RRType::CNAME | RRType::MB | RRType::MG | RRType::MR | RRType::PTR => {
+                name::lowercase_wire(data);
+            }
+            RRType::SOA | RRType::MINFO => {
+                let len = name::lowercase_wire(data);
+                name::lowercase_wire(&mut data[len..]);
+            }
+            RRType::MX if data.len() > 2 => {
+                name::lowercase_wire(&mut data[2..]);
+            }
+            // the signer's name follows the 18 fixed bytes of the record
+            RRType::RRSIG if data.len() > 18 => {
+                name::lowercase_wire(&mut data[18..]);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn canonical_cmp(a: &Self, b: &Self) -> Ordering {
+        name::canonical_cmp(&a.name, &b.name)
+            .then(a.r#type.to_value().cmp(&b.r#type.to_value()))
+            .then(a.class.to_value().cmp(&b.class.to_value()))
+    }
+
+    // the RRs as they're fed into a signature, RFC 4034 section 3.1.8.1,
+    // using the set's TTL as the original TTL
+    pub fn to_canonical_wire(&self) -> Vec<u8> {
+        let mut canonical = self.clone();
+        canonical.canonicalize();
+
+        let owner = name::to_wire(&canonical.name);
+        let mut wire = Vec::new();
+        for data in &canonical.data {
+            wire.extend_from_slice(&owner);
+            wire.extend_from_slice(&canonical.r#type.to_value().to_be_bytes());
+            wire.extend_from_slice(&canonical.class.to_value().to_be_bytes());
+            wire.extend_from_slice(&canonical.ttl.to_be_bytes());
+            wire.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            wire.extend_from_slice(data);
+        }
+        wire
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answer(name: &str, r#type: RRType, ttl: u32, data: Vec<u8>) -> Answer {
+        Answer::new(name, r#type, RRClass::IN, ttl, data)
+    }
+
+    #[test]
+    fn test_grouping_answers() {
+        let answers = vec![
+            answer("www.example.com", RRType::A, 300, vec![10, 0, 0, 2]),
+            answer(
+                "example.com",
+                RRType::NS,
+                300,
+                name::to_wire("ns.example.com"),
+            ),
+            answer("WWW.example.com", RRType::A, 60, vec![10, 0, 0, 1]),
+            answer("www.example.com", RRType::TXT, 300, vec![2, b'h', b'i']),
+        ];
+        let sets = RRset::from_answers(&answers);
+        assert_eq!(sets.len(), 3);
+
+        assert_eq!(sets[0].name, "example.com");
+        assert_eq!(sets[0].r#type, RRType::NS);
+
+        assert_eq!(sets[1].name, "www.example.com");
+        assert_eq!(sets[1].r#type, RRType::A);
+        assert_eq!(sets[1].ttl, 60);
+        assert_eq!(sets[1].data, vec![vec![10, 0, 0, 1], vec![10, 0, 0, 2]]);
+
+        assert_eq!(sets[2].r#type, RRType::TXT);
+    }
+
+    #[test]
+    fn test_canonicalize_data() {
+        let mut mx = name::to_wire("Mail.Example.COM");
+        mx.splice(0..0, vec![0, 10]);
+        let answers = vec![
+            answer("Example.com", RRType::MX, 300, mx.clone()),
+            answer("example.com", RRType::MX, 300, mx),
+        ];
+        let sets = RRset::from_answers(&answers);
+        assert_eq!(sets.len(), 1);
+
+        let mut expected = name::to_wire("mail.example.com");
+        expected.splice(0..0, vec![0, 10]);
+        assert_eq!(sets[0].data, vec![expected]);
+    }
+
+    #[test]
+    fn test_canonical_wire() {
+        let mut set = RRset::new("A.example", RRType::A, RRClass::IN, 3600);
+        set.data.push(vec![192, 0, 2, 2]);
+        set.data.push(vec![192, 0, 2, 1]);
+
+        let owner = vec![1, b'a', 7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0];
+        let rr = |ip: u8| {
+            let fixed = vec![0, 1, 0, 1, 0, 0, 0x0e, 0x10, 0, 4, 192, 0, 2, ip];
+            [owner.clone(), fixed].concat()
+        };
+        assert_eq!(set.to_canonical_wire(), [rr(1), rr(2)].concat());
+    }
+}
@@ -0,0 +1,1937 @@
+use crate::acl::{self, Operation, Policy};
+use crate::blocklist::Filter;
+use crate::buf_reader::BufReader;
+use crate::cidr::{self, Cidr};
+#[cfg(feature = "dnssec")]
+use crate::class::RRClass;
+use crate::client;
+use crate::client_subnet::{ClientSubnet, SubnetCache, SubnetHook};
+use crate::cookie::{CookieStatus, RotatingCookies, FORMERR};
+use crate::dnstap::{Logger, Message, MessageType, Protocol};
+use crate::doh;
+#[cfg(feature = "quic")]
+use crate::doq;
+use crate::edns::{Edns, EdnsOption, RESPONSE_PADDING_BLOCK};
+use crate::extended_error::{ExtendedError, InfoCode};
+use crate::forward::Forwarder;
+use crate::header::Header;
+use crate::metrics::Metrics;
+use crate::opcode::OpCode;
+use crate::packet::Packet;
+use crate::querylog::{Entry, QueryLog};
+use crate::r#type::RRType;
+use crate::rcode::RCode;
+use crate::rpz::{self, Outcome, Rpz};
+use crate::rrl::{self, Rrl};
+use crate::tcp::{self, Deadline};
+#[cfg(feature = "tsig")]
+use crate::tsig;
+#[cfg(feature = "dnssec")]
+use crate::validator::{Security, Validator};
+use crate::views::{View, Views};
+use crate::zone::Zone;
+use std::collections::HashMap;
+#[cfg(feature = "https")]
+use std::convert::Infallible;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// what the server advertises and sends at most over UDP, the size
+// recommended since DNS flag day 2020
+pub const UDP_PAYLOAD_SIZE: u16 = 1232;
+// a TCP client that has been quiet this long is let go, RFC 7766 section 6.2.3
+pub const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+// threads reading from each UDP socket, so a slow upstream doesn't hold up
+// every other query
+pub const UDP_WORKERS: usize = 8;
+// connections beyond this many at once are closed straight away
+const MAX_TCP_CONNECTIONS: usize = 256;
+// forwarded answers kept at once, expired ones are purged to make room and
+// after that nothing more is kept until something expires
+pub const CACHE_SIZE: usize = 10_000;
+// however long upstream says an answer lasts
+const MAX_CACHE_TTL: u32 = 86_400;
+
+// where a query came from and what it came over
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Request {
+    pub client: SocketAddr,
+    pub local: SocketAddr,
+    pub transport: Protocol,
+}
+
+// everything queries are answered from, shared by all the listeners
+pub struct Server {
+    // behind a lock so a zone can be swapped for a newer copy, re-signed or
+    // transferred again, while queries are being answered
+    zones: RwLock<Vec<Arc<Zone>>>,
+    // once there are views every query has to match one, and is answered
+    // from its zones; swapped whole like the zones, so a query keeps the
+    // views it started with
+    views: RwLock<Arc<Views>>,
+    // recursive queries for names outside the zones go here
+    pub forwarder: Option<Arc<Forwarder>>,
+    // how much of each client's address goes upstream with its queries, as
+    // IPv4 and IPv6 prefix lengths, RFC 7871. None of it unless set
+    pub client_subnet: Option<(u8, u8)>,
+    pub acl: Policy,
+    // what signed queries are checked against, a query whose signature
+    // checks out has its key matched by the ACLs and views
+    #[cfg(feature = "tsig")]
+    pub keys: Vec<tsig::Key>,
+    // RFC 7873 server cookies, checked on every query that has one
+    pub cookies: Option<RotatingCookies>,
+    pub filter: Option<Filter>,
+    pub rpz: Option<Rpz>,
+    // only UDP responses are limited, TCP clients can't spoof their address
+    pub rrl: Option<Rrl>,
+    pub dnstap: Option<Logger>,
+    pub metrics: Option<Arc<Metrics>>,
+    pub query_log: Option<QueryLog>,
+    // forwarded answers are checked against a chain of trust when set
+    #[cfg(feature = "dnssec")]
+    pub validator: Option<Validator>,
+    cache: Mutex<SubnetCache<Cached>>,
+    // by zone name, for zones whose answers depend on where the client is
+    subnet_hooks: HashMap<String, Box<dyn SubnetHook>>,
+}
+
+// a forwarded response and when it was stored, so its TTLs can count down
+#[derive(Debug, Clone)]
+struct Cached {
+    response: Packet,
+    stored: u64,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Self {
+            zones: RwLock::new(Vec::new()),
+            views: RwLock::new(Arc::new(Views::default())),
+            forwarder: None,
+            client_subnet: None,
+            acl: Policy::default(),
+            #[cfg(feature = "tsig")]
+            keys: Vec::new(),
+            cookies: None,
+            filter: None,
+            rpz: None,
+            rrl: None,
+            dnstap: None,
+            metrics: None,
+            query_log: None,
+            #[cfg(feature = "dnssec")]
+            validator: None,
+            cache: Mutex::new(SubnetCache::new()),
+            subnet_hooks: HashMap::new(),
+        }
+    }
+
+    // queries for names in the zone ask the hook before the zone's records
+    pub fn add_subnet_hook(&mut self, zone: &str, hook: impl SubnetHook + 'static) {
+        let zone = zone.trim_end_matches('.').to_ascii_lowercase();
+        self.subnet_hooks.insert(zone, Box::new(hook));
+    }
+
+    pub fn add_zone(&mut self, zone: Zone) {
+        self.zones.get_mut().unwrap().push(Arc::new(zone));
+    }
+
+    // views are tried in the order they're added
+    pub fn add_view(&mut self, view: View) {
+        let views = self.views.get_mut().unwrap();
+        Arc::make_mut(views).views.push(view);
+    }
+
+    // queries already being answered from the old copy finish with it
+    pub fn replace_zone(&self, zone: Zone) {
+        self.replace_zone_in(None, zone);
+    }
+
+    // a zone of the named view, or of the server itself for None. A view
+    // that doesn't exist is left alone
+    pub fn replace_zone_in(&self, view: Option<&str>, zone: Zone) {
+        let Some(view) = view else {
+            let mut zones = self.zones.write().unwrap();
+            match zones.iter_mut().find(|old| old.name == zone.name) {
+                Some(old) => *old = Arc::new(zone),
+                None => zones.push(Arc::new(zone)),
+            }
+            return;
+        };
+        let mut views = self.views.write().unwrap();
+        if let Some(view) = Arc::make_mut(&mut views)
+            .views
+            .iter_mut()
+            .find(|other| other.name == view)
+        {
+            view.replace_zone(zone);
+        }
+    }
+
+    pub fn zone(&self, name: &str) -> Option<Arc<Zone>> {
+        self.zone_in(None, name)
+    }
+
+    pub fn zone_in(&self, view: Option<&str>, name: &str) -> Option<Arc<Zone>> {
+        let Some(view) = view else {
+            let name = name.trim_end_matches('.');
+            let zones = self.zones.read().unwrap();
+            return zones
+                .iter()
+                .find(|zone| zone.name.eq_ignore_ascii_case(name))
+                .cloned();
+        };
+        let views = self.views.read().unwrap().clone();
+        let view = views.views.iter().find(|other| other.name == view)?;
+        view.zone(name).cloned()
+    }
+
+    // the closest enclosing zone
+    pub fn zone_for(&self, name: &str) -> Option<Arc<Zone>> {
+        closest_zone(&self.zones.read().unwrap(), name)
+    }
+
+    // the message to send back, or None if nothing should be
+    pub fn handle(&self, request: &Request, message: &[u8]) -> Option<Vec<u8>> {
+        let received = SystemTime::now();
+        self.tap(request, MessageType::ClientQuery, received, message);
+        // a signed query whose signature doesn't check out is turned away
+        // with the reason in the response's TSIG record, RFC 8945 section 5.2
+        #[cfg(feature = "tsig")]
+        let signature = tsig::verify(message, &self.keys, now());
+        #[cfg(feature = "tsig")]
+        let (key, rejected) = match &signature {
+            Some(signature) => (signature.verified_key(), signature.error.is_some()),
+            None => (None, false),
+        };
+        #[cfg(not(feature = "tsig"))]
+        let (key, rejected) = (None, false);
+        let (query, response) = match Packet::try_from_buf(message) {
+            Ok(query) if query.header.response => return None,
+            Ok(query) => {
+                if let Some(metrics) = &self.metrics {
+                    metrics.record_query(request.transport, &query);
+                }
+                let mut response = match rejected {
+                    true => error(&query, RCode::NOTAUTH),
+                    false => self.respond(request, &query, key)?,
+                };
+                finish(&query, &mut response, request.transport);
+                (Some(query), response)
+            }
+            Err(_) => (None, format_error(message)?),
+        };
+        if let Some(metrics) = &self.metrics {
+            metrics.record_response(request.transport, &response);
+        }
+        // losing a line of the log isn't worth failing the query over
+        if let Some(log) = &self.query_log {
+            let query = query.as_ref().unwrap_or(&response);
+            let latency = received.elapsed().unwrap_or_default();
+            let entry = Entry::new(request.client, request.transport, query, &response, latency);
+            let _ = log.log(&entry);
+        }
+        let response = response.to_bytes();
+        #[cfg(feature = "tsig")]
+        let response = match (&signature, &query) {
+            (Some(signature), Some(_)) => {
+                let mut response = response;
+                tsig::sign_response(&mut response, signature, &self.keys, now());
+                response
+            }
+            _ => response,
+        };
+        self.tap(request, MessageType::ClientResponse, received, &response);
+        Some(response)
+    }
+
+    // the response to a parsed query, after checking its cookie and rate
+    // limiting, with a server cookie for a client that sent one. key is the
+    // TSIG key the query's signature was verified with
+    pub fn respond(&self, request: &Request, query: &Packet, key: Option<&str>) -> Option<Packet> {
+        let (status, cookie) = match &self.cookies {
+            Some(cookies) => cookies.check(query.edns.as_ref(), request.client.ip(), now() as u32),
+            None => (CookieStatus::Missing, None),
+        };
+        let mut response = match status.rcode(false) {
+            // BADCOOKIE only matters where the client's address could be
+            // spoofed, RFC 7873 section 5.2.3
+            Some(rcode) if rcode == FORMERR || request.transport == Protocol::Udp => {
+                let mut response = error(query, RCode::NOERROR);
+                response.set_rcode(rcode);
+                response
+            }
+            _ => self.limit(request, query, key)?,
+        };
+        if let Some(cookie) = cookie {
+            response
+                .edns
+                .get_or_insert_with(|| Edns::new(UDP_PAYLOAD_SIZE))
+                .set_option(EdnsOption::Cookie(cookie));
+        }
+        Some(response)
+    }
+
+    fn limit(&self, request: &Request, query: &Packet, key: Option<&str>) -> Option<Packet> {
+        let (response, wildcard) = self.answer(request, query, key)?;
+        let Some(rrl) = &self.rrl else {
+            return Some(response);
+        };
+        if request.transport != Protocol::Udp {
+            return Some(response);
+        }
+        match rrl.check(request.client.ip(), &response, wildcard.as_deref()) {
+            rrl::Action::Send => Some(response),
+            rrl::Action::Slip => Some(rrl::slip(&response)),
+            rrl::Action::Drop => None,
+        }
+    }
+
+    fn answer(
+        &self,
+        request: &Request,
+        query: &Packet,
+        key: Option<&str>,
+    ) -> Option<(Packet, Option<String>)> {
+        let client = request.client.ip();
+        let operation = Operation::of(query);
+        if OpCode::from_value(query.header.op_code) != OpCode::QUERY {
+            if !self.acl.allows(operation, client, key) {
+                return Some((acl::refuse(query, operation), None));
+            }
+            let mut response = error(query, RCode::NOTIMP);
+            let opcode = OpCode::from_value(query.header.op_code);
+            explain(
+                &mut response,
+                InfoCode::NotSupported,
+                &format!("{} isn't supported", opcode),
+            );
+            return Some((response, None));
+        }
+        let [question] = query.questions.as_slice() else {
+            return Some((error(query, RCode::FORMERR), None));
+        };
+
+        let views = self.views.read().unwrap().clone();
+        let view = views.select(client, request.local.ip(), key);
+        if view.is_none() && !views.views.is_empty() {
+            return Some((acl::refuse(query, operation), None));
+        }
+
+        // recursion only matters for names the server has to look up
+        // elsewhere
+        let zone = match view {
+            Some(view) => zone_for_query(&view.zones, &question.name, question.r#type),
+            None => zone_for_query(&self.zones.read().unwrap(), &question.name, question.r#type),
+        };
+        let operation = match operation {
+            Operation::Recursion if zone.is_some() => Operation::Query,
+            operation => operation,
+        };
+        if !self.allows(view, operation, client, key) {
+            return Some((acl::refuse(query, operation), None));
+        }
+        if operation == Operation::Transfer {
+            let mut response = error(query, RCode::NOTIMP);
+            explain(
+                &mut response,
+                InfoCode::NotSupported,
+                "zone transfers aren't served",
+            );
+            return Some((response, None));
+        }
+        if let Some(response) = self.filter.as_ref().and_then(|filter| filter.check(query)) {
+            return Some((response, None));
+        }
+
+        let mut policy_query = rpz::Query::new(Some(client), &question.name);
+        let mut passthru = false;
+        if let Some(hit) = self.rpz.as_ref().and_then(|rpz| rpz.check(&policy_query)) {
+            match hit.apply(query, request.transport != Protocol::Udp) {
+                Outcome::Passthru => passthru = true,
+                Outcome::Drop => return None,
+                Outcome::Respond(response) => return Some((response, None)),
+            }
+        }
+
+        let (response, wildcard) = match (&zone, &self.forwarder) {
+            (Some(zone), _) => self.respond_from(zone, query, client),
+            (None, Some(forwarder)) if operation == Operation::Recursion => {
+                (self.recurse(forwarder, query, client), None)
+            }
+            _ => {
+                let mut response = error(query, RCode::REFUSED);
+                explain(&mut response, InfoCode::NotAuthoritative, "");
+                (response, None)
+            }
+        };
+        // the response triggers only apply to answers from elsewhere
+        if zone.is_some() || passthru {
+            return Some((response, wildcard));
+        }
+        policy_query.add_response(&response);
+        match self.rpz.as_ref().and_then(|rpz| rpz.check(&policy_query)) {
+            Some(hit) => match hit.apply(query, request.transport != Protocol::Udp) {
+                Outcome::Passthru => Some((response, wildcard)),
+                Outcome::Drop => None,
+                Outcome::Respond(response) => Some((response, None)),
+            },
+            None => Some((response, wildcard)),
+        }
+    }
+
+    // a view decides who may query and recurse, the server's ACL decides
+    // everything else
+    fn allows(
+        &self,
+        view: Option<&View>,
+        operation: Operation,
+        client: IpAddr,
+        key: Option<&str>,
+    ) -> bool {
+        match (view, operation) {
+            (Some(view), Operation::Query) => view.allows_query(client, key),
+            (Some(view), Operation::Recursion) => {
+                view.allows_query(client, key) && view.allows_recursion(client, key)
+            }
+            _ => self.acl.allows(operation, client, key),
+        }
+    }
+
+    // the zone's answer, unless its subnet hook has one for the client. The
+    // client's own address stands in for a subnet it didn't send
+    fn respond_from(
+        &self,
+        zone: &Zone,
+        query: &Packet,
+        client: IpAddr,
+    ) -> (Packet, Option<String>) {
+        let (mut response, wildcard) = zone.respond(query);
+        let Some(hook) = self.subnet_hooks.get(&zone.name.to_ascii_lowercase()) else {
+            return (response, wildcard);
+        };
+        let subnet = match query.edns.as_ref().and_then(Edns::client_subnet) {
+            Some(subnet) => subnet.clone(),
+            None => ClientSubnet::new(client, cidr::max_prefix(client)),
+        };
+        let question = &query.questions[0];
+        let Some((records, scope)) = hook.answer(&question.name, question.r#type, &subnet) else {
+            return (response, wildcard);
+        };
+        response.header.resp_code = RCode::NOERROR as u8;
+        response.answers = records;
+        response.authorities.clear();
+        response.additionals.clear();
+        response
+            .edns
+            .get_or_insert_with(|| Edns::new(UDP_PAYLOAD_SIZE))
+            .set_option(EdnsOption::ClientSubnet(subnet.with_scope(scope)));
+        (response, None)
+    }
+
+    // what goes upstream about the client: its own option cut down to the
+    // configured prefixes, or its address cut down to them. A client that
+    // sent a source prefix of 0 asked for none, RFC 7871 section 7.1.2
+    fn upstream_subnet(&self, query: &Packet, client: IpAddr) -> Option<ClientSubnet> {
+        let (max_v4_prefix, max_v6_prefix) = self.client_subnet?;
+        let subnet = match query.edns.as_ref().and_then(Edns::client_subnet) {
+            Some(subnet) if subnet.source_prefix == 0 => return None,
+            Some(subnet) => subnet.truncated(max_v4_prefix, max_v6_prefix),
+            None => ClientSubnet::from_client(client, max_v4_prefix, max_v6_prefix),
+        };
+        Some(subnet).filter(|subnet| subnet.source_prefix > 0)
+    }
+
+    // answers from the cache where it can. Only plain queries are cached,
+    // DO and CD change what upstream sends back. An answer is kept for the
+    // scope upstream gave it, never narrower than the subnet sent, or for
+    // every client of the family if upstream didn't say, RFC 7871 section
+    // 7.3.1
+    fn recurse(&self, forwarder: &Forwarder, query: &Packet, client: IpAddr) -> Packet {
+        let question = &query.questions[0];
+        let subnet = self.upstream_subnet(query, client);
+        let dnssec = query.header.checking_disabled
+            || query.edns.as_ref().is_some_and(|edns| edns.dnssec_ok);
+        if dnssec {
+            return self.resolve(forwarder, query, subnet.as_ref());
+        }
+        let now = now();
+        let address = subnet.as_ref().map_or(client, |subnet| subnet.address);
+        let cached = self
+            .cache
+            .lock()
+            .unwrap()
+            .get(
+                &question.name,
+                question.r#type,
+                question.class,
+                address,
+                now,
+            )
+            .cloned();
+        if let Some(metrics) = &self.metrics {
+            match cached {
+                Some(_) => metrics.cache_hit(),
+                None => metrics.cache_miss(),
+            }
+        }
+        if let Some(cached) = cached {
+            return cached.answer(query, now);
+        }
+
+        let response = self.resolve(forwarder, query, subnet.as_ref());
+        if let Some(ttl) = cache_ttl(&response) {
+            let mut cache = self.cache.lock().unwrap();
+            if cache.len() >= CACHE_SIZE {
+                cache.purge(now);
+            }
+            if cache.len() < CACHE_SIZE {
+                let cached = Cached {
+                    response: response.clone(),
+                    stored: now,
+                };
+                let scope = match response.edns.as_ref().and_then(Edns::client_subnet) {
+                    Some(answered) => Cidr::new(
+                        answered.address,
+                        answered.scope_prefix.min(answered.source_prefix),
+                    ),
+                    None => Cidr::new(address, 0),
+                };
+                cache.insert(
+                    &question.name,
+                    question.r#type,
+                    question.class,
+                    scope,
+                    ttl,
+                    now,
+                    cached,
+                );
+            }
+        }
+        response
+    }
+
+    // forwards the query, validating the answer unless the server has no
+    // anchors or the client turned checking off
+    fn resolve(
+        &self,
+        forwarder: &Forwarder,
+        query: &Packet,
+        subnet: Option<&ClientSubnet>,
+    ) -> Packet {
+        #[cfg(feature = "dnssec")]
+        if let Some(validator) = &self.validator {
+            if !query.header.checking_disabled {
+                return validate(validator, forwarder, query, subnet);
+            }
+        }
+        forward(forwarder, query, subnet)
+    }
+
+    // from the server's side the query comes from the client
+    fn tap(&self, request: &Request, r#type: MessageType, received: SystemTime, bytes: &[u8]) {
+        let Some(logger) = &self.dnstap else {
+            return;
+        };
+        let mut message = Message::new(r#type);
+        message.protocol = Some(request.transport);
+        message.query_address = Some(request.client);
+        message.response_address = Some(request.local);
+        message.query_time = crate::dnstap::since_epoch(received);
+        match r#type.is_query() {
+            true => message.query_message = Some(bytes.to_vec()),
+            false => {
+                message.response_time = crate::dnstap::since_epoch(SystemTime::now());
+                message.response_message = Some(bytes.to_vec());
+            }
+        }
+        logger.log(message);
+    }
+}
+
+// a response with just the question and an error code
+fn closest_zone(zones: &[Arc<Zone>], name: &str) -> Option<Arc<Zone>> {
+    zones
+        .iter()
+        .filter(|zone| zone.contains(name))
+        .max_by_key(|zone| zone.name.len())
+        .cloned()
+}
+
+// the zone to answer from, which for DS at a child's apex is the parent if
+// there's that too, RFC 4035 section 3.1.4.1
+fn zone_for_query(zones: &[Arc<Zone>], name: &str, r#type: RRType) -> Option<Arc<Zone>> {
+    let zone = closest_zone(zones, name)?;
+    let apex = zone.name.eq_ignore_ascii_case(name.trim_end_matches('.'));
+    if r#type != RRType::DS || !apex || zone.name.is_empty() {
+        return Some(zone);
+    }
+    let parent = zones
+        .iter()
+        .filter(|parent| parent.name.len() < zone.name.len() && parent.contains(name))
+        .max_by_key(|parent| parent.name.len());
+    parent.cloned().or(Some(zone))
+}
+
+fn error(query: &Packet, rcode: RCode) -> Packet {
+    let mut header = query.header.clone();
+    header.response = true;
+    header.is_authoritative = false;
+    header.truncated = false;
+    header.authentic_data = false;
+    header.resp_code = rcode as u8;
+    let mut response = Packet::new(header);
+    response.questions = query.questions.clone();
+    response
+}
+
+// FORMERR for a message that couldn't be parsed, as long as it has a header
+// to answer to
+fn format_error(message: &[u8]) -> Option<Packet> {
+    if message.len() < 12 {
+        return None;
+    }
+    let mut header = Header::from_buf(&mut BufReader::new(&message[..12]));
+    if header.response {
+        return None;
+    }
+    header.query = false;
+    header.response = true;
+    header.is_authoritative = false;
+    header.truncated = false;
+    header.can_recurse = false;
+    header.authentic_data = false;
+    header.resp_code = RCode::FORMERR as u8;
+    Some(Packet::new(header))
+}
+
+// asks upstream with a query of the server's own, so the client's ID and
+// options don't go any further than here, only the subnet given for it
+fn forward(forwarder: &Forwarder, query: &Packet, subnet: Option<&ClientSubnet>) -> Packet {
+    let question = &query.questions[0];
+    let mut upstream = client::build_query(&question.name, question.r#type, question.class);
+    upstream.header.should_recurse = true;
+    upstream.header.checking_disabled = query.header.checking_disabled;
+    let mut edns = Edns::new(UDP_PAYLOAD_SIZE);
+    edns.dnssec_ok = query.edns.as_ref().is_some_and(|edns| edns.dnssec_ok);
+    if let Some(subnet) = subnet {
+        edns.set_option(EdnsOption::ClientSubnet(subnet.clone()));
+    }
+    upstream.edns = Some(edns);
+
+    let mut response = error(query, RCode::SERVFAIL);
+    response.header.can_recurse = true;
+    match forwarder.forward(&upstream) {
+        Ok(answer) => {
+            response.header.resp_code = answer.header.resp_code;
+            response.header.authentic_data = answer.header.authentic_data;
+            response.answers = answer.answers;
+            response.authorities = answer.authorities;
+            response.additionals = answer.additionals;
+            // upstream's cookie was for the server, not the client, and a
+            // subnet that isn't the one sent says nothing about the scope,
+            // RFC 7871 section 7.3
+            response.edns = answer.edns.map(|mut edns| {
+                edns.options.retain(|option| match option {
+                    EdnsOption::ExtendedError(_) => true,
+                    EdnsOption::ClientSubnet(answered) => subnet.is_some_and(|sent| {
+                        answered.address == sent.address
+                            && answered.source_prefix == sent.source_prefix
+                    }),
+                    _ => false,
+                });
+                edns
+            });
+        }
+        Err(e) if client::is_timeout(&e) => {
+            explain(
+                &mut response,
+                InfoCode::NoReachableAuthority,
+                "no forwarder answered",
+            );
+        }
+        Err(e) => explain(&mut response, InfoCode::NetworkError, &e.to_string()),
+    }
+    response
+}
+
+// the forwarded answer checked against the chain of trust. Upstream is
+// asked with DO and CD so it hands over the signatures and whatever it
+// thinks is bogus, and a bogus answer is a SERVFAIL saying why, RFC 4035
+// section 4.3
+#[cfg(feature = "dnssec")]
+fn validate(
+    validator: &Validator,
+    forwarder: &Forwarder,
+    query: &Packet,
+    subnet: Option<&ClientSubnet>,
+) -> Packet {
+    let dnssec_ok = query.edns.as_ref().is_some_and(|edns| edns.dnssec_ok);
+    let mut checked = query.clone();
+    checked.header.checking_disabled = true;
+    checked
+        .edns
+        .get_or_insert_with(|| Edns::new(UDP_PAYLOAD_SIZE))
+        .dnssec_ok = true;
+    let mut response = forward(forwarder, &checked, subnet);
+    response.header.checking_disabled = false;
+    response.header.authentic_data = false;
+    if !matches!(
+        RCode::from_value(response.rcode()),
+        RCode::NOERROR | RCode::NXDOMAIN
+    ) {
+        return response;
+    }
+
+    let lookup = |name: &str, r#type: RRType| {
+        let mut lookup = client::build_query(name, r#type, RRClass::IN);
+        lookup.header.checking_disabled = true;
+        let mut edns = Edns::new(UDP_PAYLOAD_SIZE);
+        edns.dnssec_ok = true;
+        lookup.edns = Some(edns);
+        forwarder.forward(&lookup)
+    };
+    match validator.validate(&response, &lookup) {
+        // AD only goes to clients that show they understand it, RFC
+        // 6840 section 5.7
+        Security::Secure => {
+            response.header.authentic_data = dnssec_ok || query.header.authentic_data;
+        }
+        Security::Insecure => {}
+        Security::Bogus(code, text) => {
+            let mut failed = error(query, RCode::SERVFAIL);
+            failed.header.can_recurse = true;
+            explain(&mut failed, code, &text);
+            return failed;
+        }
+    }
+    if !dnssec_ok {
+        strip_dnssec(&mut response);
+    }
+    response
+}
+
+// a client that didn't set DO gets no signatures or proofs it didn't ask
+// for by type, RFC 4035 section 3.2.1
+#[cfg(feature = "dnssec")]
+fn strip_dnssec(response: &mut Packet) {
+    let asked = response.questions.first().map(|question| question.r#type);
+    let dnssec = |r#type: RRType| matches!(r#type, RRType::RRSIG | RRType::NSEC | RRType::NSEC3);
+    response
+        .answers
+        .retain(|record| !dnssec(record.r#type) || Some(record.r#type) == asked);
+    response.authorities.retain(|record| !dnssec(record.r#type));
+    response.additionals.retain(|record| !dnssec(record.r#type));
+}
+
+// an extended error saying why, which only goes out if the client sent EDNS
+fn explain(response: &mut Packet, code: InfoCode, text: &str) {
+    let edns = response
+        .edns
+        .get_or_insert_with(|| Edns::new(UDP_PAYLOAD_SIZE));
+    edns.options
+        .push(EdnsOption::ExtendedError(ExtendedError::new(code, text)));
+}
+
+impl Cached {
+    // the stored response to this query, with what is left of its TTLs
+    fn answer(&self, query: &Packet, now: u64) -> Packet {
+        let elapsed = now.saturating_sub(self.stored).min(u32::MAX as u64) as u32;
+        let mut response = self.response.clone();
+        response.header.checking_disabled = query.header.checking_disabled;
+        response.questions = query.questions.clone();
+        for record in response
+            .answers
+            .iter_mut()
+            .chain(response.authorities.iter_mut())
+            .chain(response.additionals.iter_mut())
+        {
+            record.ttl = record.ttl.saturating_sub(elapsed);
+        }
+        response
+    }
+}
+
+// how long a forwarded response may be cached, if at all: only whole
+// answers and NXDOMAINs with a TTL to go by
+fn cache_ttl(response: &Packet) -> Option<u32> {
+    let rcode = RCode::from_value(response.rcode());
+    if !matches!(rcode, RCode::NOERROR | RCode::NXDOMAIN) || response.header.truncated {
+        return None;
+    }
+    doh::cache_max_age(response)
+        .filter(|ttl| *ttl > 0)
+        .map(|ttl| ttl.min(MAX_CACHE_TTL))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0)
+}
+
+// gives the response the query's ID and the server's own EDNS, only if the
+// query had any and keeping any extended errors and the cookie, and fits it
+// into what the client can take over UDP. A client that sent a subnet gets
+// it back with the scope the answer holds for, RFC 7871 section 7.2, and
+// one that padded its query over an encrypted transport gets a padded
+// response, RFC 7830 section 4 and RFC 8467 section 4.1
+fn finish(query: &Packet, response: &mut Packet, transport: Protocol) {
+    response.header.identifier = query.header.identifier;
+    response.header.response = true;
+    response.header.op_code = query.header.op_code;
+    response.header.should_recurse = query.header.should_recurse;
+    response.edns = match &query.edns {
+        Some(edns) => {
+            let mut own = Edns::new(UDP_PAYLOAD_SIZE);
+            own.dnssec_ok = edns.dnssec_ok;
+            if let Some(answered) = &response.edns {
+                own.extended_rcode = answered.extended_rcode;
+                own.options = answered
+                    .options
+                    .iter()
+                    .filter(|option| {
+                        matches!(option, EdnsOption::ExtendedError(_) | EdnsOption::Cookie(_))
+                    })
+                    .cloned()
+                    .collect();
+            }
+            if let Some(subnet) = edns.client_subnet() {
+                let scope = response
+                    .edns
+                    .as_ref()
+                    .and_then(Edns::client_subnet)
+                    .map_or(0, |answered| answered.scope_prefix);
+                own.set_option(EdnsOption::ClientSubnet(subnet.with_scope(scope)));
+            }
+            Some(own)
+        }
+        None => None,
+    };
+    match transport {
+        Protocol::Udp => {
+            response.fit_to(query.max_response_size().min(UDP_PAYLOAD_SIZE as usize));
+        }
+        Protocol::Dot | Protocol::Doh | Protocol::Doq if query.is_padded() => {
+            response.pad(RESPONSE_PADDING_BLOCK, u16::MAX as usize);
+        }
+        _ => {}
+    }
+}
+
+// answers queries on the socket until it fails, from a few threads at once
+pub fn serve_udp(socket: UdpSocket, server: Arc<Server>) -> io::Result<JoinHandle<()>> {
+    let local = socket.local_addr()?;
+    let mut workers = Vec::new();
+    for _ in 0..UDP_WORKERS {
+        let socket = socket.try_clone()?;
+        let server = server.clone();
+        workers.push(thread::spawn(move || {
+            let mut buf = [0u8; 65535];
+            loop {
+                let (len, client) = match socket.recv_from(&mut buf) {
+                    Ok(received) => received,
+                    // an ICMP error for an earlier response
+                    Err(e) if e.kind() == io::ErrorKind::ConnectionReset => continue,
+                    Err(_) => return,
+                };
+                let request = Request {
+                    client,
+                    local,
+                    transport: Protocol::Udp,
+                };
+                if let Some(response) = server.handle(&request, &buf[..len]) {
+                    let _ = socket.send_to(&response, client);
+                }
+            }
+        }));
+    }
+    Ok(thread::spawn(move || {
+        for worker in workers {
+            let _ = worker.join();
+        }
+    }))
+}
+
+// answers queries on each connection until the client closes it or goes
+// quiet, a connection per thread
+pub fn serve_tcp(listener: TcpListener, server: Arc<Server>) -> io::Result<JoinHandle<()>> {
+    let local = listener.local_addr()?;
+    Ok(thread::spawn(move || {
+        let connections = Arc::new(AtomicUsize::new(0));
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if connections.fetch_add(1, Ordering::Relaxed) >= MAX_TCP_CONNECTIONS {
+                        connections.fetch_sub(1, Ordering::Relaxed);
+                        continue;
+                    }
+                    let server = server.clone();
+                    let connections = connections.clone();
+                    thread::spawn(move || {
+                        let _ = handle_tcp(stream, local, &server);
+                        connections.fetch_sub(1, Ordering::Relaxed);
+                    });
+                }
+                Err(e) if e.kind() == io::ErrorKind::ConnectionAborted => continue,
+                Err(_) => return,
+            }
+        }
+    }))
+}
+
+fn handle_tcp(stream: TcpStream, local: SocketAddr, server: &Server) -> io::Result<()> {
+    let client = stream.peer_addr()?;
+    stream.set_write_timeout(Some(TCP_IDLE_TIMEOUT))?;
+    let request = Request {
+        client,
+        local,
+        transport: Protocol::Tcp,
+    };
+    let stream = Deadline::new(stream, TCP_IDLE_TIMEOUT);
+    handle_stream(stream, &request, server, TCP_IDLE_TIMEOUT)
+}
+
+// DNS over TLS, RFC 7858: the same connections and framing as TCP, once
+// the handshake is done
+#[cfg(feature = "tls")]
+pub fn serve_tls(
+    listener: TcpListener,
+    config: Arc<rustls::ServerConfig>,
+    server: Arc<Server>,
+) -> io::Result<JoinHandle<()>> {
+    let local = listener.local_addr()?;
+    Ok(thread::spawn(move || {
+        let connections = Arc::new(AtomicUsize::new(0));
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if connections.fetch_add(1, Ordering::Relaxed) >= MAX_TCP_CONNECTIONS {
+                        connections.fetch_sub(1, Ordering::Relaxed);
+                        continue;
+                    }
+                    let server = server.clone();
+                    let config = config.clone();
+                    let connections = connections.clone();
+                    thread::spawn(move || {
+                        let _ = handle_tls(stream, config, local, &server);
+                        connections.fetch_sub(1, Ordering::Relaxed);
+                    });
+                }
+                Err(e) if e.kind() == io::ErrorKind::ConnectionAborted => continue,
+                Err(_) => return,
+            }
+        }
+    }))
+}
+
+#[cfg(feature = "tls")]
+fn handle_tls(
+    stream: TcpStream,
+    config: Arc<rustls::ServerConfig>,
+    local: SocketAddr,
+    server: &Server,
+) -> io::Result<()> {
+    let client = stream.peer_addr()?;
+    stream.set_write_timeout(Some(TCP_IDLE_TIMEOUT))?;
+    let connection = rustls::ServerConnection::new(config)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    // the deadline is under TLS, so a client can't trickle in a record a
+    // byte at a time; the first message's covers the handshake too
+    let stream = Deadline::new(stream, TCP_IDLE_TIMEOUT);
+    let mut stream = rustls::StreamOwned::new(connection, stream);
+    let request = Request {
+        client,
+        local,
+        transport: Protocol::Dot,
+    };
+    let result = handle_stream(&mut stream, &request, server, TCP_IDLE_TIMEOUT);
+    stream.conn.send_close_notify();
+    let _ = stream.conn.complete_io(&mut stream.sock);
+    result
+}
+
+// DNS over HTTPS, RFC 8484: requests are answered as they come in on each
+// connection, by a blocking thread as the other listeners do
+#[cfg(feature = "https")]
+pub fn serve_https(
+    listener: TcpListener,
+    config: Arc<rustls::ServerConfig>,
+    server: Arc<Server>,
+) -> io::Result<JoinHandle<()>> {
+    let local = listener.local_addr()?;
+    listener.set_nonblocking(true)?;
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    Ok(thread::spawn(move || {
+        runtime.block_on(async move {
+            let Ok(listener) = tokio::net::TcpListener::from_std(listener) else {
+                return;
+            };
+            let acceptor = tokio_rustls::TlsAcceptor::from(config);
+            let connections = Arc::new(AtomicUsize::new(0));
+            loop {
+                let (stream, client) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) if e.kind() == io::ErrorKind::ConnectionAborted => continue,
+                    Err(_) => return,
+                };
+                if connections.fetch_add(1, Ordering::Relaxed) >= MAX_TCP_CONNECTIONS {
+                    connections.fetch_sub(1, Ordering::Relaxed);
+                    continue;
+                }
+                let request = Request {
+                    client,
+                    local,
+                    transport: Protocol::Doh,
+                };
+                let acceptor = acceptor.clone();
+                let server = server.clone();
+                let connections = connections.clone();
+                tokio::spawn(async move {
+                    let _ = handle_https(stream, acceptor, request, server).await;
+                    connections.fetch_sub(1, Ordering::Relaxed);
+                });
+            }
+        })
+    }))
+}
+
+#[cfg(feature = "https")]
+async fn handle_https(
+    stream: tokio::net::TcpStream,
+    acceptor: tokio_rustls::TlsAcceptor,
+    request: Request,
+    server: Arc<Server>,
+) -> io::Result<()> {
+    let stream = tokio::time::timeout(TCP_IDLE_TIMEOUT, acceptor.accept(stream))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "TLS handshake timed out"))??;
+    let service = hyper::service::service_fn(move |http| {
+        let server = server.clone();
+        async move {
+            let message = match doh::request_message(http).await {
+                Ok(message) => message,
+                Err(status) => return Ok::<_, Infallible>(doh::status_response(status)),
+            };
+            let response =
+                tokio::task::spawn_blocking(move || server.handle(&request, &message)).await;
+            Ok(match response {
+                Ok(Some(response)) => doh::response(response),
+                Ok(None) => doh::status_response(hyper::StatusCode::BAD_REQUEST),
+                Err(_) => doh::status_response(hyper::StatusCode::INTERNAL_SERVER_ERROR),
+            })
+        }
+    });
+    hyper_util::server::conn::auto::Builder::new(hyper_util::rt::TokioExecutor::new())
+        .serve_connection(hyper_util::rt::TokioIo::new(stream), service)
+        .await
+        .map_err(io::Error::other)
+}
+
+// DNS over QUIC, RFC 9250: a query on each stream a client opens, answered
+// by a blocking thread as the other listeners do
+#[cfg(feature = "quic")]
+pub fn serve_quic(
+    socket: UdpSocket,
+    config: Arc<rustls::ServerConfig>,
+    server: Arc<Server>,
+) -> io::Result<JoinHandle<()>> {
+    let local = socket.local_addr()?;
+    let mut tls = (*config).clone();
+    // 0-RTT is taken, but only queries that are safe to replay are
+    // answered before the handshake is done
+    tls.max_early_data_size = u32::MAX;
+    let crypto = quinn::crypto::rustls::QuicServerConfig::try_from(tls)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut transport = quinn::TransportConfig::default();
+    transport
+        .max_idle_timeout(TCP_IDLE_TIMEOUT.try_into().ok())
+        .max_concurrent_uni_streams(0u32.into());
+    let mut quic = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+    quic.transport_config(Arc::new(transport));
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    let endpoint = {
+        let _context = runtime.enter();
+        quinn::Endpoint::new(
+            quinn::EndpointConfig::default(),
+            Some(quic),
+            socket,
+            Arc::new(quinn::TokioRuntime),
+        )?
+    };
+    Ok(thread::spawn(move || {
+        runtime.block_on(async move {
+            let connections = Arc::new(AtomicUsize::new(0));
+            while let Some(incoming) = endpoint.accept().await {
+                let request = Request {
+                    client: incoming.remote_address(),
+                    local,
+                    transport: Protocol::Doq,
+                };
+                let server = server.clone();
+                let connections = connections.clone();
+                let busy = connections.fetch_add(1, Ordering::Relaxed) >= MAX_TCP_CONNECTIONS;
+                tokio::spawn(async move {
+                    let _ = handle_quic(incoming, busy, request, server).await;
+                    connections.fetch_sub(1, Ordering::Relaxed);
+                });
+            }
+        })
+    }))
+}
+
+#[cfg(feature = "quic")]
+async fn handle_quic(
+    incoming: quinn::Incoming,
+    busy: bool,
+    request: Request,
+    server: Arc<Server>,
+) -> io::Result<()> {
+    let connecting = incoming.accept().map_err(io::Error::other)?;
+    let (done, handshake) = tokio::sync::watch::channel(false);
+    let connection = match connecting.into_0rtt() {
+        Ok((connection, accepted)) => {
+            tokio::spawn(async move {
+                accepted.await;
+                let _ = done.send(true);
+            });
+            connection
+        }
+        Err(connecting) => {
+            let connection = connecting.await.map_err(io::Error::other)?;
+            let _ = done.send(true);
+            connection
+        }
+    };
+    if busy {
+        connection.close(doq::ErrorCode::ExcessiveLoad.var_int(), b"");
+        return Ok(());
+    }
+    while let Ok((send, recv)) = connection.accept_bi().await {
+        let connection = connection.clone();
+        let handshake = handshake.clone();
+        let server = server.clone();
+        tokio::spawn(async move {
+            handle_quic_stream(send, recv, &connection, handshake, request, server).await
+        });
+    }
+    Ok(())
+}
+
+#[cfg(feature = "quic")]
+async fn handle_quic_stream(
+    mut send: quinn::SendStream,
+    mut recv: quinn::RecvStream,
+    connection: &quinn::Connection,
+    mut handshake: tokio::sync::watch::Receiver<bool>,
+    request: Request,
+    server: Arc<Server>,
+) {
+    // breaking the transport's rules is fatal to the connection, RFC 9250
+    // section 4.3.3
+    let message = match recv.read_to_end(doq::MAX_STREAM).await {
+        Ok(framed) => doq::decode_message(&framed).map(<[u8]>::to_vec),
+        Err(quinn::ReadToEndError::TooLong) => Err(doq::ErrorCode::ProtocolError),
+        // the client gave up on the query
+        Err(_) => {
+            let _ = send.reset(doq::ErrorCode::RequestCancelled.var_int());
+            return;
+        }
+    };
+    let message = match message {
+        Ok(message) => message,
+        // until the handshake is done the code would be sent as a plain
+        // APPLICATION_ERROR, RFC 9000 section 10.2.3
+        Err(code) => {
+            let _ = handshake.wait_for(|done| *done).await;
+            connection.close(code.var_int(), b"");
+            return;
+        }
+    };
+    let replayable =
+        Packet::try_from_buf(&message).is_ok_and(|query| doq::allowed_in_early_data(&query));
+    if !replayable && handshake.wait_for(|done| *done).await.is_err() {
+        return;
+    }
+    let response = tokio::task::spawn_blocking(move || server.handle(&request, &message)).await;
+    match response {
+        Ok(Some(response)) => {
+            let mut framed = Vec::new();
+            if tcp::write_message(&mut framed, &response).is_ok()
+                && send.write_all(&framed).await.is_ok()
+            {
+                let _ = send.finish();
+                return;
+            }
+            let _ = send.reset(doq::ErrorCode::InternalError.var_int());
+        }
+        _ => {
+            let _ = send.reset(doq::ErrorCode::InternalError.var_int());
+        }
+    }
+}
+
+// a connection whose reads have to be done by a deadline, which starts
+// again for each message
+trait Timed: Read + Write {
+    fn set_deadline(&mut self, deadline: Instant);
+}
+
+impl Timed for Deadline<TcpStream> {
+    fn set_deadline(&mut self, deadline: Instant) {
+        self.deadline = deadline;
+    }
+}
+
+#[cfg(feature = "tls")]
+impl Timed for rustls::StreamOwned<rustls::ServerConnection, Deadline<TcpStream>> {
+    fn set_deadline(&mut self, deadline: Instant) {
+        self.sock.deadline = deadline;
+    }
+}
+
+impl<T: Timed> Timed for &mut T {
+    fn set_deadline(&mut self, deadline: Instant) {
+        (**self).set_deadline(deadline);
+    }
+}
+
+// each message has to arrive whole within the timeout of the connection
+// going quiet, however slowly its bytes come in, RFC 7766 section 6.2.3
+fn handle_stream<S: Timed>(
+    mut stream: S,
+    request: &Request,
+    server: &Server,
+    timeout: Duration,
+) -> io::Result<()> {
+    loop {
+        stream.set_deadline(Instant::now() + timeout);
+        let message = match tcp::read_message(&mut stream) {
+            Ok(message) => message,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        // a query that gets no answer ends the connection, so the client
+        // isn't left waiting for one
+        match server.handle(request, &message) {
+            Some(response) => tcp::write_message(&mut stream, &response)?,
+            None => return Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::answer::Answer;
+    use crate::blocklist::{Mode, Source};
+    use crate::class::RRClass;
+    use crate::client::Client;
+    use crate::forward::Pool;
+    use crate::querylog::Format;
+    use crate::r#type::RRType;
+    use crate::rpz::PolicyZone;
+    #[cfg(feature = "tls")]
+    use crate::tls;
+
+    const ZONE: &str = "$TTL 300
+@ SOA ns1 hostmaster 1 7200 3600 1209600 60
+@ NS ns1
+ns1 A 192.0.2.53
+www A 192.0.2.1
+";
+
+    fn request(client: &str, transport: Protocol) -> Request {
+        Request {
+            client: SocketAddr::new(client.parse().unwrap(), 5300),
+            local: "127.0.0.1:53".parse().unwrap(),
+            transport,
+        }
+    }
+
+    fn query(name: &str, r#type: RRType) -> Packet {
+        let mut query = client::build_query(name, r#type, RRClass::IN);
+        query.header.should_recurse = true;
+        query.edns = Some(Edns::new(4096));
+        query
+    }
+
+    fn ask(server: &Server, client: &str, query: &Packet) -> Packet {
+        let response = server
+            .handle(&request(client, Protocol::Udp), &query.to_bytes())
+            .unwrap();
+        Packet::from_buf(&response)
+    }
+
+    fn info_codes(response: &Packet) -> Vec<InfoCode> {
+        let edns = response.edns.as_ref().unwrap();
+        edns.extended_errors()
+            .iter()
+            .map(|error| error.info_code())
+            .collect()
+    }
+
+    fn server() -> Server {
+        let mut server = Server::new();
+        server.add_zone(Zone::from_text("example.com", ZONE).unwrap());
+        server
+    }
+
+    // an upstream that answers every query with one address
+    fn upstream() -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            while let Ok((len, from)) = socket.recv_from(&mut buf) {
+                let query = Packet::from_buf(&buf[..len]);
+                let mut header = query.header.clone();
+                header.response = true;
+                header.can_recurse = true;
+                let mut response = Packet::new(header);
+                response.questions = query.questions.clone();
+                let question = &query.questions[0];
+                response.answers.push(Answer::new(
+                    &question.name,
+                    RRType::A,
+                    RRClass::IN,
+                    300,
+                    vec![198, 51, 100, 7],
+                ));
+                socket.send_to(&response.to_bytes(), from).unwrap();
+            }
+        });
+        address
+    }
+
+    #[test]
+    fn test_authoritative() {
+        let server = server();
+        let query = query("www.example.com", RRType::A);
+        let response = ask(&server, "203.0.113.1", &query);
+        assert_eq!(response.header.identifier, query.header.identifier);
+        assert!(response.header.is_authoritative);
+        assert!(response.header.should_recurse);
+        assert_eq!(response.answers[0].data, vec![192, 0, 2, 1]);
+        assert_eq!(response.edns.unwrap().udp_payload_size, UDP_PAYLOAD_SIZE);
+
+        // recursion isn't needed for the server's own zones, so anyone may
+        // ask for them with RD set, but nothing else
+        let response = ask(
+            &server,
+            "203.0.113.1",
+            &self::query("example.net", RRType::A),
+        );
+        assert_eq!(response.rcode(), RCode::REFUSED as u16);
+        let response = ask(&server, "127.0.0.1", &self::query("example.net", RRType::A));
+        assert_eq!(response.rcode(), RCode::REFUSED as u16);
+        assert_eq!(info_codes(&response), vec![InfoCode::NotAuthoritative]);
+        let response = ask(
+            &server,
+            "127.0.0.1",
+            &self::query("example.com", RRType::AXFR),
+        );
+        assert_eq!(response.rcode(), RCode::REFUSED as u16);
+
+        // no EDNS in the query, none in the response
+        let mut query = self::query("www.example.com", RRType::A);
+        query.edns = None;
+        assert!(ask(&server, "127.0.0.1", &query).edns.is_none());
+    }
+
+    #[test]
+    fn test_malformed() {
+        let server = server();
+        let request = request("127.0.0.1", Protocol::Udp);
+        let mut message = query("www.example.com", RRType::A).to_bytes();
+        message.truncate(20);
+        let response = Packet::from_buf(&server.handle(&request, &message).unwrap());
+        assert_eq!(response.rcode(), RCode::FORMERR as u16);
+        assert!(response.header.response);
+        // too short to have an ID to answer to
+        assert_eq!(server.handle(&request, &message[..5]), None);
+        // responses are never answered
+        let mut response = query("www.example.com", RRType::A);
+        response.header.response = true;
+        assert_eq!(server.handle(&request, &response.to_bytes()), None);
+
+        let mut notify = query("example.com", RRType::SOA);
+        notify.header.op_code = OpCode::NOTIFY as u8;
+        let response = ask(&server, "127.0.0.1", &notify);
+        assert_eq!(response.rcode(), RCode::REFUSED as u16);
+        assert_eq!(info_codes(&response), vec![InfoCode::Prohibited]);
+        let mut server = server;
+        server.acl.notify = "any".parse().unwrap();
+        let response = ask(&server, "127.0.0.1", &notify);
+        assert_eq!(response.rcode(), RCode::NOTIMP as u16);
+        assert_eq!(info_codes(&response), vec![InfoCode::NotSupported]);
+    }
+
+    #[test]
+    fn test_forward() {
+        let mut server = server();
+        let mut pool = Pool::new(&[upstream()]);
+        pool.upstreams[0].client.timeout = Duration::from_millis(500);
+        server.forwarder = Some(Arc::new(Forwarder::new(pool)));
+
+        let query = query("example.net", RRType::A);
+        let response = ask(&server, "127.0.0.1", &query);
+        assert_eq!(response.header.identifier, query.header.identifier);
+        assert!(response.header.can_recurse);
+        assert!(!response.header.is_authoritative);
+        assert_eq!(response.answers[0].data, vec![198, 51, 100, 7]);
+        // recursion is only for the local host by default
+        let response = ask(&server, "203.0.113.1", &query);
+        assert_eq!(response.rcode(), RCode::REFUSED as u16);
+
+        // an upstream that never answers is a SERVFAIL
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut pool = Pool::new(&[silent.local_addr().unwrap()]);
+        pool.upstreams[0].client.timeout = Duration::from_millis(20);
+        pool.upstreams[0].client.retries = 0;
+        server.forwarder = Some(Arc::new(Forwarder::new(pool)));
+        let query = self::query("example.org", RRType::A);
+        let response = ask(&server, "127.0.0.1", &query);
+        assert_eq!(response.rcode(), RCode::SERVFAIL as u16);
+        assert_eq!(info_codes(&response), vec![InfoCode::NoReachableAuthority]);
+    }
+
+    #[test]
+    fn test_cache() {
+        let mut server = server();
+        let metrics = Arc::new(Metrics::new());
+        let mut pool = Pool::new(&[upstream()]);
+        pool.upstreams[0].client.timeout = Duration::from_millis(500);
+        server.forwarder = Some(Arc::new(Forwarder::new(pool)));
+        server.metrics = Some(metrics.clone());
+
+        let query = query("example.net", RRType::A);
+        let first = ask(&server, "127.0.0.1", &query);
+        let mut again = query.clone();
+        again.header.identifier = query.header.identifier.wrapping_add(1);
+        let second = ask(&server, "127.0.0.1", &again);
+        assert_eq!(second.header.identifier, again.header.identifier);
+        assert_eq!(second.answers, first.answers);
+        // DNSSEC queries always go upstream
+        let mut dnssec = query.clone();
+        dnssec.edns.as_mut().unwrap().dnssec_ok = true;
+        assert_eq!(ask(&server, "127.0.0.1", &dnssec).answers.len(), 1);
+
+        let rendered = metrics.render();
+        assert!(
+            rendered.contains("dns_cache_hits_total 1\n"),
+            "{}",
+            rendered
+        );
+        assert!(
+            rendered.contains("dns_cache_misses_total 1\n"),
+            "{}",
+            rendered
+        );
+        assert!(
+            rendered.contains(
+                "dns_queries_total{transport=\"udp\",type=\"A\",class=\"IN\",opcode=\"QUERY\"} 3\n"
+            ),
+            "{}",
+            rendered
+        );
+        assert!(
+            rendered.contains(
+                "dns_responses_total{transport=\"udp\",rcode=\"NOERROR\",truncated=\"false\"} 3\n"
+            ),
+            "{}",
+            rendered
+        );
+    }
+
+    // an upstream that answers from the /24 of the subnet it is sent, for
+    // its /16, and with 0.0.0.0 when sent none
+    fn subnet_upstream() -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            while let Ok((len, from)) = socket.recv_from(&mut buf) {
+                let query = Packet::from_buf(&buf[..len]);
+                let mut header = query.header.clone();
+                header.response = true;
+                header.can_recurse = true;
+                let mut response = Packet::new(header);
+                response.questions = query.questions.clone();
+                let mut edns = Edns::new(UDP_PAYLOAD_SIZE);
+                let data = match query.edns.as_ref().and_then(Edns::client_subnet) {
+                    Some(subnet) => {
+                        let IpAddr::V4(address) = subnet.address else {
+                            unreachable!()
+                        };
+                        let [a, b, c, _] = address.octets();
+                        edns.set_option(EdnsOption::ClientSubnet(subnet.with_scope(16)));
+                        vec![a, b, c, 1]
+                    }
+                    _ => vec![0, 0, 0, 0],
+                };
+                response.edns = Some(edns);
+                response.answers.push(Answer::new(
+                    &query.questions[0].name,
+                    RRType::A,
+                    RRClass::IN,
+                    300,
+                    data,
+                ));
+                socket.send_to(&response.to_bytes(), from).unwrap();
+            }
+        });
+        address
+    }
+
+    fn with_subnet(mut query: Packet, subnet: &str, source_prefix: u8) -> Packet {
+        let subnet = ClientSubnet::new(subnet.parse().unwrap(), source_prefix);
+        query
+            .edns
+            .as_mut()
+            .unwrap()
+            .set_option(EdnsOption::ClientSubnet(subnet));
+        query
+    }
+
+    #[test]
+    fn test_client_subnet() {
+        let mut server = server();
+        let mut pool = Pool::new(&[subnet_upstream()]);
+        pool.upstreams[0].client.timeout = Duration::from_millis(500);
+        server.forwarder = Some(Arc::new(Forwarder::new(pool)));
+        server.acl.recursion = "any".parse().unwrap();
+        let query = query("example.net", RRType::A);
+
+        // nothing about the client goes upstream unless configured
+        let response = ask(&server, "127.0.0.1", &query);
+        assert_eq!(response.answers[0].data, vec![0, 0, 0, 0]);
+        server.client_subnet = Some((24, 56));
+        let query = self::query("example.org", RRType::A);
+
+        // the client's /24 goes up, and a client that sent no subnet gets
+        // none back
+        let response = ask(&server, "203.0.113.9", &query);
+        assert_eq!(response.answers[0].data, vec![203, 0, 113, 1]);
+        assert!(response.edns.unwrap().client_subnet().is_none());
+
+        // a subnet the client sent is cut down to a /24 and echoed with the
+        // scope upstream gave
+        let sent = with_subnet(query.clone(), "198.51.100.77", 32);
+        let response = ask(&server, "127.0.0.1", &sent);
+        assert_eq!(response.answers[0].data, vec![198, 51, 100, 1]);
+        let echoed = response.edns.unwrap().client_subnet().cloned().unwrap();
+        assert_eq!(echoed.address, "198.51.100.77".parse::<IpAddr>().unwrap());
+        assert_eq!((echoed.source_prefix, echoed.scope_prefix), (32, 16));
+
+        // that answer is cached for the whole /16 but no further
+        let sent = with_subnet(query.clone(), "198.51.7.1", 32);
+        assert_eq!(
+            ask(&server, "127.0.0.1", &sent).answers[0].data,
+            vec![198, 51, 100, 1]
+        );
+        let sent = with_subnet(query.clone(), "198.52.7.1", 32);
+        assert_eq!(
+            ask(&server, "127.0.0.1", &sent).answers[0].data,
+            vec![198, 52, 7, 1]
+        );
+
+        // a source prefix of 0 keeps the client's address to itself
+        let sent = with_subnet(self::query("example.edu", RRType::A), "0.0.0.0", 0);
+        let response = ask(&server, "203.0.113.9", &sent);
+        assert_eq!(response.answers[0].data, vec![0, 0, 0, 0]);
+    }
+
+    // answers www.example.com from 10.0.0.0/8 with an address of its own
+    struct Internal;
+
+    impl SubnetHook for Internal {
+        fn answer(
+            &self,
+            name: &str,
+            r#type: RRType,
+            subnet: &ClientSubnet,
+        ) -> Option<(Vec<Answer>, u8)> {
+            let internal: Cidr = "10.0.0.0/8".parse().unwrap();
+            if name != "www.example.com"
+                || r#type != RRType::A
+                || !internal.contains(subnet.address)
+            {
+                return None;
+            }
+            let record = Answer::new(name, RRType::A, RRClass::IN, 60, vec![10, 0, 0, 1]);
+            Some((vec![record], 8))
+        }
+    }
+
+    #[test]
+    fn test_subnet_hook() {
+        let mut server = server();
+        server.add_subnet_hook("example.com.", Internal);
+        let query = query("www.example.com", RRType::A);
+
+        let response = ask(&server, "203.0.113.1", &query);
+        assert_eq!(response.answers[0].data, vec![192, 0, 2, 1]);
+        assert!(response.edns.unwrap().client_subnet().is_none());
+        // the client's address stands in for a subnet it didn't send
+        let response = ask(&server, "10.9.9.9", &query);
+        assert_eq!(response.answers[0].data, vec![10, 0, 0, 1]);
+        assert!(response.header.is_authoritative);
+        assert!(response.edns.unwrap().client_subnet().is_none());
+
+        // a subnet sent is answered for, with the scope the hook gave
+        let response = ask(
+            &server,
+            "203.0.113.1",
+            &with_subnet(query.clone(), "10.1.2.0", 24),
+        );
+        assert_eq!(response.answers[0].data, vec![10, 0, 0, 1]);
+        let echoed = response.edns.unwrap().client_subnet().cloned().unwrap();
+        assert_eq!((echoed.source_prefix, echoed.scope_prefix), (24, 8));
+        // and an answer the hook leaves to the zone holds for everyone
+        let response = ask(&server, "10.9.9.9", &with_subnet(query, "192.0.2.0", 24));
+        assert_eq!(response.answers[0].data, vec![192, 0, 2, 1]);
+        let echoed = response.edns.unwrap().client_subnet().cloned().unwrap();
+        assert_eq!(echoed.scope_prefix, 0);
+    }
+
+    // a log writer the test can read back
+    #[derive(Clone, Default)]
+    struct Lines(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Lines {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_query_log() {
+        let mut server = server();
+        let lines = Lines::default();
+        server.query_log = Some(QueryLog::new(lines.clone(), Format::Logfmt));
+
+        ask(&server, "127.0.0.1", &query("www.example.com", RRType::A));
+        ask(
+            &server,
+            "127.0.0.1",
+            &query("missing.example.com", RRType::A),
+        );
+        let mut message = query("www.example.com", RRType::A).to_bytes();
+        message.truncate(20);
+        server
+            .handle(&request("127.0.0.1", Protocol::Udp), &message)
+            .unwrap();
+
+        let written = String::from_utf8(lines.0.lock().unwrap().clone()).unwrap();
+        let written: Vec<&str> = written.lines().collect();
+        assert_eq!(written.len(), 3, "{:?}", written);
+        assert!(
+            written[0].contains(
+                " client=127.0.0.1:5300 transport=udp name=www.example.com. type=A class=IN rcode=NOERROR answers=1 flags=qr,aa,rd "
+            ),
+            "{}",
+            written[0]
+        );
+        assert!(written[1].contains(" rcode=NXDOMAIN "), "{}", written[1]);
+        assert!(written[2].contains(" rcode=FORMERR "), "{}", written[2]);
+    }
+
+    #[test]
+    fn test_policy() {
+        let mut server = server();
+        let path = std::env::temp_dir().join(format!("dns-rs-server-{}", std::process::id()));
+        std::fs::write(&path, "ads.example.com\n").unwrap();
+        let source = Source {
+            path: path.clone(),
+            allow: false,
+        };
+        let filter = Filter::new(Mode::Nxdomain, vec![source]);
+        filter.reload().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        server.filter = Some(filter);
+        let policy = "$TTL 60\n@ SOA ns hostmaster 1 1 1 1 1\nwww.example.com CNAME rpz-drop.\n";
+        let zone = PolicyZone::from_zone_file("rpz.local", policy).unwrap();
+        server.rpz = Some(Rpz::new(vec![zone]));
+
+        let response = ask(&server, "127.0.0.1", &query("ads.example.com", RRType::A));
+        assert_eq!(response.rcode(), RCode::NXDOMAIN as u16);
+        assert_eq!(info_codes(&response), vec![InfoCode::Blocked]);
+        let request = request("127.0.0.1", Protocol::Udp);
+        let message = query("www.example.com", RRType::A).to_bytes();
+        assert_eq!(server.handle(&request, &message), None);
+    }
+
+    #[test]
+    fn test_views() {
+        let mut server = server();
+        let mut internal = View::new("internal");
+        internal.match_clients = vec!["10.0.0.0/8".parse().unwrap()];
+        server.add_view(internal);
+        let mut external = View::new("external");
+        external.match_clients = vec!["192.0.2.0/24".parse().unwrap()];
+        external.recursion = false;
+        server.add_view(external);
+        let internal_zone = ZONE.replace("192.0.2.1", "10.0.0.1");
+        let internal_zone = Zone::from_text("example.com", &internal_zone).unwrap();
+        server.replace_zone_in(Some("internal"), internal_zone);
+        server.replace_zone_in(
+            Some("external"),
+            Zone::from_text("example.com", ZONE).unwrap(),
+        );
+
+        let www = query("www.example.com", RRType::A);
+        let response = ask(&server, "10.1.2.3", &www);
+        assert_eq!(response.answers[0].data, vec![10, 0, 0, 1]);
+        let response = ask(&server, "192.0.2.99", &www);
+        assert_eq!(response.answers[0].data, vec![192, 0, 2, 1]);
+        assert!(server.zone_in(Some("internal"), "example.com").is_some());
+        assert!(server.zone_in(Some("internal"), "example.net").is_none());
+
+        // the server's own zones aren't used once there are views, and a
+        // client no view matches is turned away
+        let response = ask(&server, "127.0.0.1", &www);
+        assert_eq!(response.rcode(), RCode::REFUSED as u16);
+        assert_eq!(info_codes(&response), vec![InfoCode::Prohibited]);
+        let response = ask(&server, "10.1.2.3", &query("example.net", RRType::A));
+        assert_eq!(response.rcode(), RCode::REFUSED as u16);
+    }
+
+    #[cfg(feature = "tsig")]
+    #[test]
+    fn test_tsig() {
+        let mut server = server();
+        let key = tsig::Key::new("view-key", tsig::Algorithm::HmacSha256, b"secret".to_vec());
+        server.keys = vec![key.clone()];
+        let mut signed = View::new("signed");
+        signed.match_keys = vec!["view-key".to_string()];
+        server.add_view(signed);
+        server.add_view(View::new("public"));
+        server.replace_zone_in(
+            Some("public"),
+            Zone::from_text("example.com", ZONE).unwrap(),
+        );
+
+        let request = request("127.0.0.1", Protocol::Udp);
+        let mut message = query("www.example.com", RRType::A).to_bytes();
+        let mac = tsig::sign_query(&mut message, &key, now());
+        let response = server.handle(&request, &message).unwrap();
+        assert_eq!(tsig::verify_response(&response, &key, &mac, now()), Ok(()));
+        // the signed view has no zones
+        assert_eq!(Packet::from_buf(&response).rcode(), RCode::REFUSED as u16);
+
+        let message = query("www.example.com", RRType::A).to_bytes();
+        let response = ask(&server, "127.0.0.1", &Packet::from_buf(&message));
+        assert_eq!(response.answers[0].data, vec![192, 0, 2, 1]);
+
+        let other = tsig::Key::new("view-key", tsig::Algorithm::HmacSha256, b"guess".to_vec());
+        let mut message = query("www.example.com", RRType::A).to_bytes();
+        let mac = tsig::sign_query(&mut message, &other, now());
+        let response = server.handle(&request, &message).unwrap();
+        assert_eq!(Packet::from_buf(&response).rcode(), RCode::NOTAUTH as u16);
+        assert_eq!(
+            tsig::verify_response(&response, &other, &mac, now()),
+            Err(tsig::BADSIG)
+        );
+    }
+
+    #[test]
+    fn test_stream_deadline() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let timeout = Duration::from_millis(300);
+        let handler = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let request = request("127.0.0.1", Protocol::Tcp);
+            let stream = Deadline::new(stream, timeout);
+            let started = Instant::now();
+            let result = handle_stream(stream, &request, &server(), timeout);
+            (result, started.elapsed())
+        });
+
+        // one message on time, then the next a byte at a time, each byte
+        // well within the timeout but the whole message not
+        let mut client = TcpStream::connect(address).unwrap();
+        let message = query("www.example.com", RRType::A).to_bytes();
+        tcp::write_message(&mut client, &message).unwrap();
+        let response = Packet::from_buf(&tcp::read_message(&mut client).unwrap());
+        assert_eq!(response.answers[0].data, vec![192, 0, 2, 1]);
+        let mut framed = (message.len() as u16).to_be_bytes().to_vec();
+        framed.extend(&message);
+        for byte in framed {
+            if client.write_all(&[byte]).is_err() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        let (result, elapsed) = handler.join().unwrap();
+        assert!(result.is_err());
+        assert!(elapsed < Duration::from_secs(2), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let mut server = server();
+        server.rrl = Some(Rrl::new(rrl::Config {
+            responses_per_second: 1,
+            slip: 0,
+            ..rrl::Config::default()
+        }));
+        let request = request("198.51.100.1", Protocol::Udp);
+        let message = query("www.example.com", RRType::A).to_bytes();
+        assert!(server.handle(&request, &message).is_some());
+        assert_eq!(server.handle(&request, &message), None);
+        // TCP isn't limited
+        let request = self::request("198.51.100.1", Protocol::Tcp);
+        assert!(server.handle(&request, &message).is_some());
+    }
+
+    #[test]
+    fn test_padding() {
+        let server = server();
+        let mut padded = query("www.example.com", RRType::A);
+        padded.pad(crate::edns::QUERY_PADDING_BLOCK, 512);
+        let respond = |query: &Packet, transport| {
+            let response = server.handle(&request("127.0.0.1", transport), &query.to_bytes());
+            Packet::from_buf(&response.unwrap())
+        };
+
+        let response = respond(&padded, Protocol::Dot);
+        assert!(response.is_padded());
+        assert_eq!(response.to_bytes().len(), RESPONSE_PADDING_BLOCK);
+        assert!(respond(&padded, Protocol::Doq).is_padded());
+        // only a padded query gets padding back, and never over UDP
+        assert!(!respond(&query("www.example.com", RRType::A), Protocol::Dot).is_padded());
+        assert!(!respond(&padded, Protocol::Udp).is_padded());
+    }
+
+    #[test]
+    fn test_cookies() {
+        let mut server = server();
+        server.cookies = Some(RotatingCookies::new());
+        let server = Arc::new(server);
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let udp = socket.local_addr().unwrap();
+        let tcp = listener.local_addr().unwrap();
+        serve_udp(socket, server.clone()).unwrap();
+        serve_tcp(listener, server).unwrap();
+
+        // the client sends a cookie and keeps the server's for next time
+        let client = Client::new(udp);
+        let response = client.query(&query("www.example.com", RRType::A)).unwrap();
+        let edns = response.packet.edns.unwrap();
+        let cookie = edns.cookie().unwrap().clone();
+        assert_eq!(cookie.server.len(), 16);
+
+        // one the server didn't make gets BADCOOKIE and a new one to use
+        let mut bad = cookie;
+        bad.server[15] ^= 1;
+        let mut query = query("www.example.com", RRType::A);
+        query
+            .edns
+            .as_mut()
+            .unwrap()
+            .set_option(EdnsOption::Cookie(bad.clone()));
+        let mut plain = Client::new(udp);
+        plain.cookies = None;
+        let response = plain.query(&query).unwrap().packet;
+        assert_eq!(response.rcode(), RCode::BADCOOKIE as u16);
+        assert!(response.answers.is_empty());
+        let fresh = response.edns.unwrap().cookie().unwrap().clone();
+        assert_eq!(fresh.client, bad.client);
+        assert_ne!(fresh.server, bad.server);
+
+        // which the client does by itself
+        let localhost: IpAddr = "127.0.0.1".parse().unwrap();
+        let cookies = client.cookies.as_ref().unwrap();
+        assert!(cookies.lock().unwrap().remember(localhost, localhost, &bad));
+        let response = client.query(&self::query("www.example.com", RRType::A));
+        let response = response.unwrap().packet;
+        assert_eq!(response.rcode(), RCode::NOERROR as u16);
+        assert_eq!(response.answers.len(), 1);
+
+        // the address of a TCP client can't be spoofed, so it's answered
+        plain.server = tcp;
+        plain.tcp = true;
+        let response = plain.query(&query).unwrap().packet;
+        assert_eq!(response.rcode(), RCode::NOERROR as u16);
+        assert_eq!(response.answers.len(), 1);
+        assert!(response.edns.unwrap().cookie().is_some());
+    }
+
+    #[test]
+    fn test_listeners() {
+        let server = Arc::new(server());
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let udp = socket.local_addr().unwrap();
+        let tcp = listener.local_addr().unwrap();
+        serve_udp(socket, server.clone()).unwrap();
+        serve_tcp(listener, server).unwrap();
+
+        let query = query("www.example.com", RRType::A);
+        let response = Client::new(udp).query(&query).unwrap();
+        assert!(!response.over_tcp);
+        assert_eq!(response.packet.answers.len(), 1);
+
+        // several queries over one connection
+        let mut stream = TcpStream::connect(tcp).unwrap();
+        for _ in 0..2 {
+            tcp::write_message(&mut stream, &query.to_bytes()).unwrap();
+            let response = Packet::from_buf(&tcp::read_message(&mut stream).unwrap());
+            assert_eq!(response.answers.len(), 1);
+        }
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn test_tls_listener() {
+        let dir = std::env::temp_dir().join(format!("dns-rs-server-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let (certificate, private_key) = (dir.join("cert.pem"), dir.join("key.pem"));
+        std::fs::write(&certificate, certified.cert.pem()).unwrap();
+        std::fs::write(&private_key, certified.signing_key.serialize_pem()).unwrap();
+        let config = tls::server_config(&certificate, &private_key, &[tls::ALPN]).unwrap();
+
+        let mut server = server();
+        let lines = Lines::default();
+        server.query_log = Some(QueryLog::new(lines.clone(), Format::Logfmt));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        serve_tls(listener, config, Arc::new(server)).unwrap();
+
+        let pin = tls::spki_pin(certified.cert.der()).unwrap();
+        let tls = tls::ClientTls::pinned("localhost", vec![pin], tls::ALPN).unwrap();
+        let client = Client::with_tls(address, tls);
+        for _ in 0..2 {
+            let response = client.query(&query("www.example.com", RRType::A)).unwrap();
+            assert!(response.packet.header.is_authoritative);
+            assert_eq!(response.packet.answers.len(), 1);
+        }
+        let written = String::from_utf8(lines.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(written.matches(" transport=dot ").count(), 2, "{}", written);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,102 @@
+use std::borrow::Borrow;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+pub const PORT: u16 = 53;
+pub const TLS_PORT: u16 = 853;
+
+// messages over stream transports (TCP, and TLS on top of it) are prefixed
+// with their length as two bytes, RFC 1035 section 4.2.2
+pub fn write_message<W: Write>(stream: &mut W, message: &[u8]) -> io::Result<()> {
+    if message.len() > u16::MAX as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "message is too long for a stream transport",
+        ));
+    }
+    let mut framed = (message.len() as u16).to_be_bytes().to_vec();
+    framed.extend_from_slice(message);
+    stream.write_all(&framed)?;
+    stream.flush()
+}
+
+pub fn read_message<R: Read>(stream: &mut R) -> io::Result<Vec<u8>> {
+    let mut len = [0u8; 2];
+    stream.read_exact(&mut len)?;
+    let mut message = vec![0u8; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut message)?;
+    Ok(message)
+}
+
+// a stream with a timeout for each read and a deadline for all of them, so
+// the other side can't keep a transfer or a message going by trickling bytes
+#[derive(Debug)]
+pub struct Deadline<S> {
+    pub stream: S,
+    pub timeout: Duration,
+    pub deadline: Instant,
+}
+
+impl<S: Borrow<TcpStream>> Deadline<S> {
+    pub fn new(stream: S, timeout: Duration) -> Self {
+        Self {
+            stream,
+            timeout,
+            deadline: Instant::now() + timeout,
+        }
+    }
+}
+
+impl<S: Borrow<TcpStream>> Read for Deadline<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "took too long"));
+        }
+        let mut stream = self.stream.borrow();
+        stream.set_read_timeout(Some(remaining.min(self.timeout)))?;
+        stream.read(buf)
+    }
+}
+
+impl<S: Borrow<TcpStream>> Write for Deadline<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.borrow().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.borrow().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_framing() {
+        let mut stream = Vec::new();
+        write_message(&mut stream, &[1, 2, 3]).unwrap();
+        write_message(&mut stream, &[]).unwrap();
+        assert_eq!(stream, vec![0, 3, 1, 2, 3, 0, 0]);
+
+        let mut stream = Cursor::new(stream);
+        assert_eq!(read_message(&mut stream).unwrap(), vec![1, 2, 3]);
+        assert!(read_message(&mut stream).unwrap().is_empty());
+        let eof = read_message(&mut stream).unwrap_err();
+        assert_eq!(eof.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_short_message() {
+        let mut stream = Cursor::new(vec![0, 5, 1, 2]);
+        let err = read_message(&mut stream).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        let mut stream = Vec::new();
+        let err = write_message(&mut stream, &vec![0; 70000]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
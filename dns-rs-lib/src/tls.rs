@@ -0,0 +1,299 @@
+use ring::digest;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{self, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{
+    CertificateError, ClientConfig, ClientConnection, DigitallySignedStruct, RootCertStore,
+    ServerConfig, SignatureScheme, StreamOwned,
+};
+use std::fmt;
+use std::io;
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::Arc;
+
+// the ALPN token for DNS over TLS, from the IANA registry
+pub const ALPN: &[u8] = b"dot";
+
+pub type ClientStream = StreamOwned<ClientConnection, TcpStream>;
+
+// how a client talks TLS to one server: the name it sends and checks the
+// certificate against, or the pins it checks the key against instead
+#[derive(Debug, Clone)]
+pub struct ClientTls {
+    pub server_name: String,
+    config: Arc<ClientConfig>,
+}
+
+impl ClientTls {
+    // the certificate has to chain to one of the roots, the public ones if
+    // none are given, and be for the name, RFC 8310 section 8.1
+    pub fn new(server_name: &str, roots: Option<RootCertStore>, alpn: &[u8]) -> io::Result<Self> {
+        let roots = roots.unwrap_or_else(|| RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        });
+        let config = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .map_err(invalid)?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Self::with_config(server_name, config, alpn)
+    }
+
+    // RFC 7858 section 4.2: the server's key has to hash to one of the pins,
+    // whatever its certificate says about names and issuers
+    pub fn pinned(server_name: &str, pins: Vec<Vec<u8>>, alpn: &[u8]) -> io::Result<Self> {
+        let provider = provider();
+        let verifier = PinVerifier {
+            pins,
+            algorithms: provider.signature_verification_algorithms,
+        };
+        let config = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(invalid)?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+            .with_no_client_auth();
+        Self::with_config(server_name, config, alpn)
+    }
+
+    fn with_config(server_name: &str, mut config: ClientConfig, alpn: &[u8]) -> io::Result<Self> {
+        ServerName::try_from(server_name).map_err(invalid)?;
+        config.alpn_protocols = vec![alpn.to_vec()];
+        Ok(Self {
+            server_name: server_name.to_string(),
+            config: Arc::new(config),
+        })
+    }
+
+    pub fn config(&self) -> Arc<ClientConfig> {
+        self.config.clone()
+    }
+
+    // the handshake is done before returning, so a certificate that isn't
+    // trusted fails here rather than on the first query
+    pub fn connect(&self, mut stream: TcpStream) -> io::Result<ClientStream> {
+        let name = ServerName::try_from(self.server_name.clone()).map_err(invalid)?;
+        let mut connection = ClientConnection::new(self.config.clone(), name).map_err(invalid)?;
+        while connection.is_handshaking() {
+            connection.complete_io(&mut stream)?;
+        }
+        Ok(StreamOwned::new(connection, stream))
+    }
+}
+
+// the certificate chain and private key from PEM files, offering the ALPN
+// tokens given
+pub fn server_config(
+    certificate: &Path,
+    private_key: &Path,
+    alpn: &[&[u8]],
+) -> io::Result<Arc<ServerConfig>> {
+    let in_file = |path: &Path, e: &dyn fmt::Display| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), e),
+        )
+    };
+    let chain = CertificateDer::pem_file_iter(certificate)
+        .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| in_file(certificate, &e))?;
+    if chain.is_empty() {
+        return Err(in_file(certificate, &"no certificates"));
+    }
+    let key = PrivateKeyDer::from_pem_file(private_key).map_err(|e| in_file(private_key, &e))?;
+    let mut config = ServerConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .map_err(invalid)?
+        .with_no_client_auth()
+        .with_single_cert(chain, key)
+        .map_err(|e| in_file(certificate, &e))?;
+    config.alpn_protocols = alpn.iter().map(|token| token.to_vec()).collect();
+    Ok(Arc::new(config))
+}
+
+// the certificates in a PEM file, for a client to trust
+pub fn load_roots(path: &Path) -> io::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    let certificates = CertificateDer::pem_file_iter(path)
+        .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| invalid(format!("{}: {}", path.display(), e)))?;
+    for certificate in certificates {
+        roots
+            .add(certificate)
+            .map_err(|e| invalid(format!("{}: {}", path.display(), e)))?;
+    }
+    Ok(roots)
+}
+
+// the SHA-256 of a certificate's SubjectPublicKeyInfo, RFC 7469 section 2.4
+pub fn spki_pin(certificate: &[u8]) -> Option<Vec<u8>> {
+    let spki = subject_public_key_info(certificate)?;
+    Some(digest::digest(&digest::SHA256, spki).as_ref().to_vec())
+}
+
+// Certificate and TBSCertificate from RFC 5280 section 4.1: the key info is
+// the seventh field, after an optional version
+fn subject_public_key_info(certificate: &[u8]) -> Option<&[u8]> {
+    let (SEQUENCE, certificate, _) = der(certificate)? else {
+        return None;
+    };
+    let (SEQUENCE, tbs, _) = der(certificate)? else {
+        return None;
+    };
+    let mut rest = tbs;
+    let (tag, _, _) = der(rest)?;
+    if tag == VERSION {
+        rest = der(rest)?.2;
+    }
+    // serial, signature, issuer, validity and subject
+    for _ in 0..5 {
+        rest = der(rest)?.2;
+    }
+    let (SEQUENCE, contents, after) = der(rest)? else {
+        return None;
+    };
+    let header = rest.len() - after.len() - contents.len();
+    Some(&rest[..header + contents.len()])
+}
+
+const SEQUENCE: u8 = 0x30;
+const VERSION: u8 = 0xa0;
+
+// the tag, contents and what follows of the DER element the data starts
+// with, for definite lengths up to four bytes
+fn der(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let tag = *data.first()?;
+    let first = *data.get(1)? as usize;
+    let (len, header) = match first {
+        0..=0x7f => (first, 2),
+        0x81..=0x84 => {
+            let bytes = first & 0x7f;
+            let len = data
+                .get(2..2 + bytes)?
+                .iter()
+                .fold(0usize, |len, byte| len << 8 | *byte as usize);
+            (len, 2 + bytes)
+        }
+        _ => return None,
+    };
+    let end = header.checked_add(len)?;
+    Some((tag, data.get(header..end)?, &data[end..]))
+}
+
+#[derive(Debug)]
+struct PinVerifier {
+    pins: Vec<Vec<u8>>,
+    algorithms: crypto::WebPkiSupportedAlgorithms,
+}
+
+impl ServerCertVerifier for PinVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        match spki_pin(end_entity) {
+            Some(pin) if self.pins.contains(&pin) => Ok(ServerCertVerified::assertion()),
+            _ => Err(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            )),
+        }
+    }
+
+    // the handshake still has to be signed by the pinned key
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        certificate: &CertificateDer<'_>,
+        signature: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(message, certificate, signature, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        certificate: &CertificateDer<'_>,
+        signature: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(message, certificate, signature, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+// chosen here rather than left to whichever the process defaults to
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(crypto::ring::default_provider())
+}
+
+fn invalid(e: impl fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::PublicKeyData;
+    use std::fs;
+
+    #[test]
+    fn test_spki_pin() {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let spki = certified.signing_key.subject_public_key_info();
+        assert_eq!(
+            subject_public_key_info(certified.cert.der()),
+            Some(spki.as_slice())
+        );
+        let pin = spki_pin(certified.cert.der()).unwrap();
+        assert_eq!(pin, digest::digest(&digest::SHA256, &spki).as_ref());
+
+        assert_eq!(spki_pin(&[]), None);
+        assert_eq!(spki_pin(&certified.cert.der()[..100]), None);
+        assert_eq!(spki_pin(&[SEQUENCE, 0x85, 1, 1, 1, 1, 1]), None);
+    }
+
+    #[test]
+    fn test_der() {
+        assert_eq!(der(&[0x02, 1, 5, 9]), Some((0x02, &[5][..], &[9][..])));
+        let long = [&[0x04, 0x82, 0x01, 0x00][..], &[7; 256]].concat();
+        let (tag, contents, rest) = der(&long).unwrap();
+        assert_eq!((tag, contents.len(), rest.len()), (0x04, 256, 0));
+        assert_eq!(der(&[0x04, 0x81]), None);
+        assert_eq!(der(&[0x04, 3, 1, 2]), None);
+    }
+
+    #[test]
+    fn test_server_config() {
+        let dir = std::env::temp_dir().join(format!("dns-rs-tls-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let (certificate, private_key) = (dir.join("cert.pem"), dir.join("key.pem"));
+        fs::write(&certificate, certified.cert.pem()).unwrap();
+        fs::write(&private_key, certified.signing_key.serialize_pem()).unwrap();
+
+        let config = server_config(&certificate, &private_key, &[ALPN]).unwrap();
+        assert_eq!(config.alpn_protocols, vec![ALPN.to_vec()]);
+        let roots = load_roots(&certificate).unwrap();
+        assert_eq!(roots.len(), 1);
+
+        // the key where the certificate should be and the other way round
+        let err = server_config(&private_key, &private_key, &[]).unwrap_err();
+        assert!(err.to_string().contains("no certificates"), "{}", err);
+        let err = server_config(&certificate, &certificate, &[]).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with(&format!("{}: ", certificate.display())));
+        let other = rcgen::KeyPair::generate().unwrap();
+        fs::write(&private_key, other.serialize_pem()).unwrap();
+        assert!(server_config(&certificate, &private_key, &[]).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,468 @@
+use crate::name;
+use crate::r#type::RRType;
+use ring::hmac;
+use std::fmt;
+use std::str::FromStr;
+
+// the error field of a TSIG record, RFC 8945 section 5.3.2; a response
+// carrying one has NOTAUTH as its rcode
+pub const BADSIG: u16 = 16;
+pub const BADKEY: u16 = 17;
+pub const BADTIME: u16 = 18;
+
+// the time a signature is good for either side of when it was made, the
+// RFC's recommended five minutes
+pub const FUDGE: u16 = 300;
+// class ANY, which TSIG records are always in
+const CLASS_ANY: u16 = 255;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    HmacSha1,
+    HmacSha256,
+    HmacSha384,
+    HmacSha512,
+}
+
+// a secret shared with the other side, named the same on both
+#[derive(Clone, PartialEq, Eq)]
+pub struct Key {
+    pub name: String,
+    pub algorithm: Algorithm,
+    pub secret: Vec<u8>,
+}
+
+// a signed query, kept to sign the response with. A signature that didn't
+// check out says why, and its key mustn't be trusted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub key: String,
+    pub error: Option<u16>,
+    algorithm: String,
+    mac: Vec<u8>,
+}
+
+// the fields of a TSIG record, RFC 8945 section 4.2
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Record {
+    name: String,
+    algorithm: String,
+    time: u64,
+    fudge: u16,
+    mac: Vec<u8>,
+    original_id: u16,
+    error: u16,
+    other: Vec<u8>,
+}
+
+impl Algorithm {
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::HmacSha1 => "hmac-sha1",
+            Algorithm::HmacSha256 => "hmac-sha256",
+            Algorithm::HmacSha384 => "hmac-sha384",
+            Algorithm::HmacSha512 => "hmac-sha512",
+        }
+    }
+
+    fn hmac(self) -> hmac::Algorithm {
+        match self {
+            Algorithm::HmacSha1 => hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY,
+            Algorithm::HmacSha256 => hmac::HMAC_SHA256,
+            Algorithm::HmacSha384 => hmac::HMAC_SHA384,
+            Algorithm::HmacSha512 => hmac::HMAC_SHA512,
+        }
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim_end_matches('.').to_ascii_lowercase().as_str() {
+            "hmac-sha1" => Ok(Algorithm::HmacSha1),
+            "hmac-sha256" => Ok(Algorithm::HmacSha256),
+            "hmac-sha384" => Ok(Algorithm::HmacSha384),
+            "hmac-sha512" => Ok(Algorithm::HmacSha512),
+            _ => Err(format!(
+                "unknown TSIG algorithm {:?}, expected hmac-sha1, hmac-sha256, hmac-sha384 or hmac-sha512",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl Key {
+    pub fn new(name: &str, algorithm: Algorithm, secret: Vec<u8>) -> Self {
+        Self {
+            name: name.trim_end_matches('.').to_ascii_lowercase(),
+            algorithm,
+            secret,
+        }
+    }
+
+    fn mac(&self, data: &[u8]) -> Vec<u8> {
+        let key = hmac::Key::new(self.algorithm.hmac(), &self.secret);
+        hmac::sign(&key, data).as_ref().to_vec()
+    }
+
+    fn verify(&self, data: &[u8], mac: &[u8]) -> bool {
+        let key = hmac::Key::new(self.algorithm.hmac(), &self.secret);
+        hmac::verify(&key, data, mac).is_ok()
+    }
+}
+
+// the secret stays out of logs
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Key")
+            .field("name", &self.name)
+            .field("algorithm", &self.algorithm)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Signature {
+    // the key the query was signed with, as long as the signature checked out
+    pub fn verified_key(&self) -> Option<&str> {
+        match self.error {
+            None => Some(&self.key),
+            Some(_) => None,
+        }
+    }
+}
+
+impl Record {
+    fn parse(data: &[u8], name: String) -> Option<Self> {
+        let (algorithm, mut pos) = name::from_wire(data)?;
+        let mut take = |len: usize| {
+            let field = data.get(pos..pos + len)?;
+            pos += len;
+            Some(field)
+        };
+        let time = take(6)?.iter().fold(0u64, |time, &b| time << 8 | b as u64);
+        let fudge = u16::from_be_bytes(take(2)?.try_into().unwrap());
+        let mac_len = u16::from_be_bytes(take(2)?.try_into().unwrap());
+        let mac = take(mac_len as usize)?.to_vec();
+        let original_id = u16::from_be_bytes(take(2)?.try_into().unwrap());
+        let error = u16::from_be_bytes(take(2)?.try_into().unwrap());
+        let other_len = u16::from_be_bytes(take(2)?.try_into().unwrap());
+        let other = take(other_len as usize)?.to_vec();
+        Some(Self {
+            name,
+            algorithm: algorithm.to_ascii_lowercase(),
+            time,
+            fudge,
+            mac,
+            original_id,
+            error,
+            other,
+        })
+    }
+
+    // everything but the MAC and the original ID, which go into the MAC,
+    // RFC 8945 section 4.3.3
+    fn variables(&self) -> Vec<u8> {
+        let mut bytes = name::to_canonical_wire(&self.name);
+        bytes.extend(CLASS_ANY.to_be_bytes());
+        bytes.extend(0u32.to_be_bytes());
+        bytes.extend(name::to_canonical_wire(&self.algorithm));
+        bytes.extend(&self.time.to_be_bytes()[2..]);
+        bytes.extend(self.fudge.to_be_bytes());
+        bytes.extend(self.error.to_be_bytes());
+        bytes.extend((self.other.len() as u16).to_be_bytes());
+        bytes.extend(&self.other);
+        bytes
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut data = name::to_wire(&self.algorithm);
+        data.extend(&self.time.to_be_bytes()[2..]);
+        data.extend(self.fudge.to_be_bytes());
+        data.extend((self.mac.len() as u16).to_be_bytes());
+        data.extend(&self.mac);
+        data.extend(self.original_id.to_be_bytes());
+        data.extend(self.error.to_be_bytes());
+        data.extend((self.other.len() as u16).to_be_bytes());
+        data.extend(&self.other);
+
+        let mut bytes = name::to_wire(&self.name);
+        bytes.extend(RRType::TSIG.to_value().to_be_bytes());
+        bytes.extend(CLASS_ANY.to_be_bytes());
+        bytes.extend(0u32.to_be_bytes());
+        bytes.extend((data.len() as u16).to_be_bytes());
+        bytes.extend(data);
+        bytes
+    }
+
+    // what the MAC is made over: the request's MAC for a response, the
+    // message as it was before the TSIG record went on, and the variables
+    fn signed_data(&self, request_mac: Option<&[u8]>, message: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        if let Some(mac) = request_mac {
+            data.extend((mac.len() as u16).to_be_bytes());
+            data.extend(mac);
+        }
+        data.extend(message);
+        data.extend(self.variables());
+        data
+    }
+}
+
+// the message's TSIG record, which has to be the last additional record,
+// and the message as it was before it was added: without the record, one
+// fewer additional and the ID it was signed with
+fn split(message: &[u8]) -> Option<(Vec<u8>, Record)> {
+    let count = |at: usize| u16::from_be_bytes([message[at], message[at + 1]]) as usize;
+    if message.len() < 12 || count(10) == 0 {
+        return None;
+    }
+    let mut pos = 12;
+    for _ in 0..count(4) {
+        pos = skip_name(message, pos)? + 4;
+    }
+    let records = count(6) + count(8) + count(10);
+    let mut start = pos;
+    for _ in 0..records {
+        start = pos;
+        pos = skip_name(message, pos)?;
+        let header = message.get(pos..pos + 10)?;
+        let len = u16::from_be_bytes([header[8], header[9]]) as usize;
+        pos += 10 + len;
+    }
+    if pos != message.len() {
+        return None;
+    }
+    let name_end = skip_name(message, start)?;
+    if u16::from_be_bytes([message[name_end], message[name_end + 1]]) != RRType::TSIG.to_value() {
+        return None;
+    }
+    // the owner is the key's name, which isn't compressed
+    let (name, _) = name::from_wire(&message[start..])?;
+    let record = Record::parse(&message[name_end + 10..], name.to_ascii_lowercase())?;
+
+    let mut unsigned = message[..start].to_vec();
+    unsigned[..2].copy_from_slice(&record.original_id.to_be_bytes());
+    unsigned[10..12].copy_from_slice(&(count(10) as u16 - 1).to_be_bytes());
+    Some((unsigned, record))
+}
+
+fn skip_name(message: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *message.get(pos)? as usize;
+        match len {
+            0 => return Some(pos + 1),
+            len if len & 0xc0 == 0xc0 => return Some(pos + 2),
+            len => pos += 1 + len,
+        }
+    }
+}
+
+// appends the record and counts it as one more additional
+fn append(message: &mut Vec<u8>, record: &Record) {
+    let additionals = u16::from_be_bytes([message[10], message[11]]) + 1;
+    message[10..12].copy_from_slice(&additionals.to_be_bytes());
+    message.extend(record.to_bytes());
+}
+
+// checks a query's signature, RFC 8945 section 5.2. None if it isn't
+// signed, or its TSIG record is too broken to answer with one
+pub fn verify(message: &[u8], keys: &[Key], now: u64) -> Option<Signature> {
+    let (unsigned, record) = split(message)?;
+    let mut signature = Signature {
+        key: record.name.clone(),
+        error: None,
+        algorithm: record.algorithm.clone(),
+        mac: record.mac.clone(),
+    };
+    let key = keys.iter().find(|key| {
+        key.name == record.name && key.algorithm.name() == record.algorithm.trim_end_matches('.')
+    });
+    let Some(key) = key else {
+        signature.error = Some(BADKEY);
+        return Some(signature);
+    };
+    // truncated MACs aren't accepted
+    if !key.verify(&record.signed_data(None, &unsigned), &record.mac) {
+        signature.error = Some(BADSIG);
+        return Some(signature);
+    }
+    if now.abs_diff(record.time) > record.fudge as u64 {
+        signature.error = Some(BADTIME);
+    }
+    Some(signature)
+}
+
+// signs the response to a signed query with the same key, RFC 8945 section
+// 5.3. A query whose key wasn't known or whose MAC was wrong gets a TSIG
+// record saying so, without a MAC since there's nothing to make it with
+pub fn sign_response(message: &mut Vec<u8>, signature: &Signature, keys: &[Key], now: u64) {
+    let id = u16::from_be_bytes([message[0], message[1]]);
+    let mut record = Record {
+        name: signature.key.clone(),
+        algorithm: signature.algorithm.clone(),
+        time: now,
+        fudge: FUDGE,
+        mac: Vec::new(),
+        original_id: id,
+        error: signature.error.unwrap_or(0),
+        other: Vec::new(),
+    };
+    let key = keys.iter().find(|key| key.name == signature.key);
+    match (signature.error, key) {
+        (None, Some(key)) | (Some(BADTIME), Some(key)) => {
+            // the server's time, so the client can see how far off it is
+            if signature.error == Some(BADTIME) {
+                record.other = now.to_be_bytes()[2..].to_vec();
+            }
+            record.mac = key.mac(&record.signed_data(Some(&signature.mac), message));
+        }
+        _ => {}
+    }
+    append(message, &record);
+}
+
+// signs a query, returning its MAC to check the response against
+pub fn sign_query(message: &mut Vec<u8>, key: &Key, now: u64) -> Vec<u8> {
+    let mut record = Record {
+        name: key.name.clone(),
+        algorithm: key.algorithm.name().to_string(),
+        time: now,
+        fudge: FUDGE,
+        mac: Vec::new(),
+        original_id: u16::from_be_bytes([message[0], message[1]]),
+        error: 0,
+        other: Vec::new(),
+    };
+    record.mac = key.mac(&record.signed_data(None, message));
+    append(message, &record);
+    record.mac
+}
+
+// checks the response to a query signed with the key, the error field of
+// its TSIG record if the server turned the query away
+pub fn verify_response(message: &[u8], key: &Key, request_mac: &[u8], now: u64) -> Result<(), u16> {
+    let (unsigned, record) = split(message).ok_or(BADSIG)?;
+    if record.error != 0 {
+        return Err(record.error);
+    }
+    if record.name != key.name
+        || !key.verify(
+            &record.signed_data(Some(request_mac), &unsigned),
+            &record.mac,
+        )
+    {
+        return Err(BADSIG);
+    }
+    match now.abs_diff(record.time) > record.fudge as u64 {
+        true => Err(BADTIME),
+        false => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::class::RRClass;
+    use crate::client;
+    use crate::edns::Edns;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn key() -> Key {
+        Key::new(
+            "xfr-key.",
+            Algorithm::HmacSha256,
+            b"a shared secret".to_vec(),
+        )
+    }
+
+    fn query() -> Vec<u8> {
+        let mut query = client::build_query("example.com", RRType::SOA, RRClass::IN);
+        query.edns = Some(Edns::new(1232));
+        query.to_bytes()
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let mut message = query();
+        let mac = sign_query(&mut message, &key(), NOW);
+        assert_eq!(mac.len(), 32);
+        let signature = verify(&message, &[key()], NOW + 10).unwrap();
+        assert_eq!(signature.verified_key(), Some("xfr-key"));
+
+        let mut response = query();
+        response[2] |= 0x80;
+        sign_response(&mut response, &signature, &[key()], NOW + 10);
+        assert_eq!(verify_response(&response, &key(), &mac, NOW + 20), Ok(()));
+        // the response was signed for this query and no other
+        assert_eq!(
+            verify_response(&response, &key(), &[0; 32], NOW + 20),
+            Err(BADSIG)
+        );
+
+        assert_eq!(verify(&query(), &[key()], NOW), None);
+    }
+
+    #[test]
+    fn test_verify_errors() {
+        let mut message = query();
+        sign_query(&mut message, &key(), NOW);
+
+        let others = [Key::new(
+            "other-key",
+            Algorithm::HmacSha256,
+            b"secret".to_vec(),
+        )];
+        let signature = verify(&message, &others, NOW).unwrap();
+        assert_eq!(signature.error, Some(BADKEY));
+        assert_eq!(signature.verified_key(), None);
+
+        let wrong = Key::new("xfr-key", Algorithm::HmacSha256, b"another".to_vec());
+        assert_eq!(verify(&message, &[wrong], NOW).unwrap().error, Some(BADSIG));
+        let sha512 = Key::new("xfr-key", Algorithm::HmacSha512, key().secret);
+        assert_eq!(
+            verify(&message, &[sha512], NOW).unwrap().error,
+            Some(BADKEY)
+        );
+
+        // anything changed after signing
+        let mut changed = message.clone();
+        changed[13] ^= 1;
+        assert_eq!(verify(&changed, &[key()], NOW).unwrap().error, Some(BADSIG));
+
+        let late = verify(&message, &[key()], NOW + FUDGE as u64 + 1).unwrap();
+        assert_eq!(late.error, Some(BADTIME));
+        assert_eq!(late.verified_key(), None);
+        // the server's time goes back signed
+        let mut response = query();
+        sign_response(&mut response, &late, &[key()], NOW + 400);
+        let (_, record) = split(&response).unwrap();
+        assert_eq!(record.error, BADTIME);
+        assert!(!record.mac.is_empty());
+        assert_eq!(record.other, (NOW + 400).to_be_bytes()[2..].to_vec());
+
+        let mut response = query();
+        sign_response(&mut response, &signature, &others, NOW);
+        assert_eq!(verify_response(&response, &key(), &[], NOW), Err(BADKEY));
+    }
+
+    #[test]
+    fn test_algorithms() {
+        for name in ["hmac-sha1", "hmac-sha256", "HMAC-SHA384.", "hmac-sha512"] {
+            let algorithm: Algorithm = name.parse().unwrap();
+            let key = Key::new("key", algorithm, b"secret".to_vec());
+            let mut message = query();
+            sign_query(&mut message, &key, NOW);
+            let signature = verify(&message, &[key], NOW).unwrap();
+            assert_eq!(signature.error, None);
+        }
+        assert!("hmac-md5".parse::<Algorithm>().is_err());
+    }
+}
@@ -1,4 +1,8 @@
-#[derive(Debug, PartialEq)]
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u16)]
 pub enum RRType {
     A = 1,
     NS = 2,
@@ -13,7 +17,23 @@ pub enum RRType {
     MINFO = 14,
     MX = 15,
     TXT = 16,
-    UNKNOWN,
+    AAAA = 28,
+    SRV = 33,
+    OPT = 41,
+    DS = 43,
+    RRSIG = 46,
+    NSEC = 47,
+    DNSKEY = 48,
+    NSEC3 = 50,
+    NSEC3PARAM = 51,
+    SVCB = 64,
+    HTTPS = 65,
+    TSIG = 250,
+    IXFR = 251,
+    AXFR = 252,
+    ANY = 255,
+    // anything else, keeping the value so it goes back on the wire as it came
+    UNKNOWN(u16),
 }
 
 impl RRType {
@@ -32,7 +52,137 @@ impl RRType {
             14 => RRType::MINFO,
             15 => RRType::MX,
             16 => RRType::TXT,
-            _ => RRType::UNKNOWN,
+            28 => RRType::AAAA,
+            33 => RRType::SRV,
+            41 => RRType::OPT,
+            43 => RRType::DS,
+            46 => RRType::RRSIG,
+            47 => RRType::NSEC,
+            48 => RRType::DNSKEY,
+            50 => RRType::NSEC3,
+            51 => RRType::NSEC3PARAM,
+            64 => RRType::SVCB,
+            65 => RRType::HTTPS,
+            250 => RRType::TSIG,
+            251 => RRType::IXFR,
+            252 => RRType::AXFR,
+            255 => RRType::ANY,
+            value => RRType::UNKNOWN(value),
         }
     }
+
+    pub fn to_value(&self) -> u16 {
+        match self {
+            RRType::A => 1,
+            RRType::NS => 2,
+            RRType::CNAME => 5,
+            RRType::SOA => 6,
+            RRType::MB => 7,
+            RRType::MG => 8,
+            RRType::MR => 9,
+            RRType::NULL => 10,
+            RRType::PTR => 12,
+            RRType::HINFO => 13,
+            RRType::MINFO => 14,
+            RRType::MX => 15,
+            RRType::TXT => 16,
+            RRType::AAAA => 28,
+            RRType::SRV => 33,
+            RRType::OPT => 41,
+            RRType::DS => 43,
+            RRType::RRSIG => 46,
+            RRType::NSEC => 47,
+            RRType::DNSKEY => 48,
+            RRType::NSEC3 => 50,
+            RRType::NSEC3PARAM => 51,
+            RRType::SVCB => 64,
+            RRType::HTTPS => 65,
+            RRType::TSIG => 250,
+            RRType::IXFR => 251,
+            RRType::AXFR => 252,
+            RRType::ANY => 255,
+            RRType::UNKNOWN(value) => *value,
+        }
+    }
+}
+
+impl fmt::Display for RRType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RRType::UNKNOWN(value) => write!(f, "TYPE{}", value),
+            r#type => write!(f, "{:?}", r#type),
+        }
+    }
+}
+
+// mnemonics are matched case-insensitively, and the TYPEn form from RFC 3597
+// is accepted for any type
+impl FromStr for RRType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.to_ascii_uppercase();
+        let r#type = match name.as_str() {
+            "A" => RRType::A,
+            "NS" => RRType::NS,
+            "CNAME" => RRType::CNAME,
+            "SOA" => RRType::SOA,
+            "MB" => RRType::MB,
+            "MG" => RRType::MG,
+            "MR" => RRType::MR,
+            "NULL" => RRType::NULL,
+            "PTR" => RRType::PTR,
+            "HINFO" => RRType::HINFO,
+            "MINFO" => RRType::MINFO,
+            "MX" => RRType::MX,
+            "TXT" => RRType::TXT,
+            "AAAA" => RRType::AAAA,
+            "SRV" => RRType::SRV,
+            "OPT" => RRType::OPT,
+            "DS" => RRType::DS,
+            "RRSIG" => RRType::RRSIG,
+            "NSEC" => RRType::NSEC,
+            "DNSKEY" => RRType::DNSKEY,
+            "NSEC3" => RRType::NSEC3,
+            "NSEC3PARAM" => RRType::NSEC3PARAM,
+            "SVCB" => RRType::SVCB,
+            "HTTPS" => RRType::HTTPS,
+            "TSIG" => RRType::TSIG,
+            "IXFR" => RRType::IXFR,
+            "AXFR" => RRType::AXFR,
+            "ANY" => RRType::ANY,
+            _ => name
+                .strip_prefix("TYPE")
+                .and_then(|value| value.parse().ok())
+                .map(RRType::from_value)
+                .ok_or_else(|| format!("unknown type {:?}", s))?,
+        };
+        Ok(r#type)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!("aaaa".parse(), Ok(RRType::AAAA));
+        assert_eq!("NSEC3PARAM".parse(), Ok(RRType::NSEC3PARAM));
+        assert_eq!("TYPE15".parse(), Ok(RRType::MX));
+        assert_eq!("https".parse(), Ok(RRType::HTTPS));
+        assert_eq!("TYPE33".parse(), Ok(RRType::SRV));
+        assert_eq!("TYPE65000".parse(), Ok(RRType::UNKNOWN(65000)));
+        assert!("TYPE65536".parse::<RRType>().is_err());
+        assert!("example.com".parse::<RRType>().is_err());
+    }
+
+    #[test]
+    fn test_unknown_round_trip() {
+        let r#type = RRType::from_value(65000);
+        assert_eq!(r#type.to_value(), 65000);
+        assert_eq!(r#type.to_string(), "TYPE65000");
+        assert_eq!(r#type.to_string().parse(), Ok(r#type));
+        assert_eq!(RRType::MX.to_value(), 15);
+    }
 }
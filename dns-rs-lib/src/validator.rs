@@ -0,0 +1,1106 @@
+use crate::answer::Answer;
+use crate::dnssec::{self, Dnskey, Ds, Nsec, Nsec3, Rrsig, MAX_NSEC3_ITERATIONS, NSEC3_SHA1};
+use crate::extended_error::InfoCode;
+use crate::name;
+use crate::packet::Packet;
+use crate::r#type::RRType;
+use crate::rcode::RCode;
+use crate::rdata;
+use crate::rrset::RRset;
+use crate::zone;
+use std::collections::HashMap;
+use std::io;
+use std::sync::Mutex;
+
+// the root zone's KSK-2017 and KSK-2024, as published at
+// https://data.iana.org/root-anchors/root-anchors.xml
+pub const ROOT_ANCHORS: &str = "\
+. 0 IN DS 20326 8 2 E06D44B80B8F1D39A95C0B0D7C65D08458E880409BBC683457104237C7F8EC8D
+. 0 IN DS 38696 8 2 683D2D0ACB8C9B712A1948B27F741219298D0A450D612C483AF444A4C0FB2B16
+";
+
+// how long a zone's keys are trusted at most, whatever their TTL says
+const MAX_TRUST_TTL: u64 = 86_400;
+// how long what was learnt about an unsigned or missing zone is kept
+const NEGATIVE_TTL: u64 = 3_600;
+// bogus zones are retried soon, RFC 4035 section 4.7
+const BOGUS_TTL: u64 = 60;
+// names whose trust is kept, after that expired ones are purged
+const CACHE_SIZE: usize = 10_000;
+
+// how a validator gets the DS and DNSKEY records it needs, which should be
+// asked for with DO and CD set
+pub type Lookup<'a> = dyn Fn(&str, RRType) -> io::Result<Packet> + 'a;
+
+// what validation made of a response, RFC 4035 section 4.3
+#[derive(Debug, Clone, PartialEq)]
+pub enum Security {
+    Secure,
+    Insecure,
+    Bogus(InfoCode, String),
+}
+
+// checks responses against a chain of trust from the anchors down, RFC 4035
+// section 5, remembering what it learnt about each name on the way
+#[derive(Debug)]
+pub struct Validator {
+    // DS or DNSKEY records for the zones trusted from the start
+    pub anchors: Vec<Answer>,
+    cache: Mutex<HashMap<String, (Step, u64)>>,
+}
+
+// what is known about a name on the way down from an anchor
+#[derive(Debug, Clone)]
+enum Step {
+    // the name isn't a zone cut, it belongs to the zone above
+    Same,
+    // the name doesn't exist, so neither does anything below it
+    Missing,
+    // the name is a signed zone with these keys
+    Secure(Vec<Dnskey>),
+    // the name is a zone without a DS, and whatever is below is unsigned
+    Insecure,
+    Bogus(InfoCode, String),
+}
+
+// what an NSEC or NSEC3 proof showed
+#[derive(Debug, PartialEq)]
+enum Denial {
+    NoName,
+    // whether the name is a delegation without a DS
+    NoData { delegation: bool },
+    // opt-out or too many NSEC3 iterations, either way the answer can't be
+    // proven and counts as unsigned
+    Insecure,
+}
+
+type Checked<T> = Result<T, (InfoCode, String)>;
+
+impl Validator {
+    pub fn new(anchors: Vec<Answer>) -> Self {
+        Self {
+            anchors,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // trusting the root zone's keys, as most resolvers do
+    pub fn with_root_anchors() -> Self {
+        Self::new(zone::parse(ROOT_ANCHORS, "").unwrap())
+    }
+
+    // anchors in master file format, DS or DNSKEY records
+    pub fn from_text(text: &str) -> Result<Self, String> {
+        let anchors = zone::parse(text, "")?;
+        if let Some(other) = anchors
+            .iter()
+            .find(|anchor| !matches!(anchor.r#type, RRType::DS | RRType::DNSKEY))
+        {
+            return Err(format!(
+                "{} {} isn't a trust anchor, only DS and DNSKEY records are",
+                name::to_presentation(&other.name),
+                other.r#type
+            ));
+        }
+        if anchors.is_empty() {
+            return Err("no trust anchors".to_string());
+        }
+        Ok(Self::new(anchors))
+    }
+
+    // the response needs the RRSIGs and proofs that come with DO, and the
+    // records that CD lets through, to be judged
+    pub fn validate(&self, response: &Packet, lookup: &Lookup) -> Security {
+        match self.check(response, lookup, dnssec::unix_time()) {
+            Ok(true) => Security::Secure,
+            Ok(false) => Security::Insecure,
+            Err((code, text)) => Security::Bogus(code, text),
+        }
+    }
+
+    // whether the response is secure, rather than insecure, if it isn't
+    // bogus
+    fn check(&self, response: &Packet, lookup: &Lookup, now: u64) -> Checked<bool> {
+        let Some(question) = response.questions.first() else {
+            return Ok(false);
+        };
+        let mut secure = true;
+        let mut proofs = Vec::new();
+        let mut wildcards = Vec::new();
+        for (section, answers) in [(true, &response.answers), (false, &response.authorities)] {
+            let records: Vec<Answer> = answers
+                .iter()
+                .filter(|record| record.r#type != RRType::RRSIG)
+                .cloned()
+                .collect();
+            for rrset in RRset::from_answers(&records) {
+                // referrals and their NS sets aren't signed, only what
+                // proves the answer is
+                let proving = matches!(
+                    rrset.r#type,
+                    RRType::SOA | RRType::NSEC | RRType::NSEC3 | RRType::DS
+                );
+                if !section && !proving {
+                    continue;
+                }
+                let signatures = signatures(answers, &rrset);
+                match self.check_rrset(&rrset, &signatures, lookup, now)? {
+                    Some(expanded) => {
+                        if matches!(rrset.r#type, RRType::NSEC | RRType::NSEC3) {
+                            proofs.extend(records_of(&records, &rrset));
+                        }
+                        if expanded {
+                            wildcards.push(rrset.name.clone());
+                        }
+                    }
+                    None => secure = false,
+                }
+            }
+        }
+        if !secure {
+            return Ok(false);
+        }
+
+        // an answer from a wildcard needs a proof that the name itself
+        // doesn't exist, RFC 4035 section 5.3.4
+        for owner in &wildcards {
+            if !wildcard_proven(owner, &proofs, response) {
+                return Err((
+                    InfoCode::NsecMissing,
+                    format!(
+                        "no proof {} doesn't exist for its wildcard answer",
+                        name::to_presentation(owner)
+                    ),
+                ));
+            }
+        }
+
+        // a query for the CNAME itself doesn't follow it
+        let target = match question.r#type {
+            RRType::CNAME => question.name.trim_end_matches('.').to_string(),
+            _ => final_name(&question.name, &response.answers),
+        };
+        let answered = response.answers.iter().any(|record| {
+            record.name.eq_ignore_ascii_case(&target)
+                && (record.r#type == question.r#type || question.r#type == RRType::ANY)
+        });
+        let rcode = RCode::from_value(response.rcode());
+        let negative = rcode == RCode::NXDOMAIN || (rcode == RCode::NOERROR && !answered);
+        if !negative {
+            return Ok(true);
+        }
+        // with nothing to prove a negative answer, it's only acceptable if
+        // the name is known to be unsigned
+        if proofs.is_empty() {
+            return match self.trust(&target, lookup, now).1 {
+                Step::Secure(_) => Err((
+                    InfoCode::NsecMissing,
+                    format!(
+                        "no NSEC or NSEC3 records for {} {}",
+                        name::to_presentation(&target),
+                        question.r#type
+                    ),
+                )),
+                Step::Bogus(code, text) => Err((code, text)),
+                _ => Ok(false),
+            };
+        }
+        match deny(&target, question.r#type, rcode == RCode::NXDOMAIN, &proofs)? {
+            Denial::Insecure => Ok(false),
+            _ => Ok(true),
+        }
+    }
+
+    // checks the set's signatures with the keys of the zone that made them,
+    // giving whether it was expanded from a wildcard if it's secure and None
+    // if it's unsigned
+    fn check_rrset(
+        &self,
+        rrset: &RRset,
+        signatures: &[Rrsig],
+        lookup: &Lookup,
+        now: u64,
+    ) -> Checked<Option<bool>> {
+        let described = || format!("{} {}", name::to_presentation(&rrset.name), rrset.r#type);
+        // the parent signs a DS set, so its trust is the parent's
+        let owner = match rrset.r#type {
+            RRType::DS => parent(&rrset.name).unwrap_or_default(),
+            _ => rrset.name.clone(),
+        };
+        let Some(signer) = signatures.first().map(|rrsig| rrsig.signer.clone()) else {
+            return match self.trust(&owner, lookup, now).1 {
+                Step::Secure(_) => Err((
+                    InfoCode::RrsigsMissing,
+                    format!("no signatures for {}", described()),
+                )),
+                Step::Bogus(code, text) => Err((code, text)),
+                _ => Ok(None),
+            };
+        };
+        if !name::is_below(&owner, &signer) {
+            return Err((
+                InfoCode::DnssecBogus,
+                format!(
+                    "{} is signed by {}, which is outside its zone",
+                    described(),
+                    name::to_presentation(&signer)
+                ),
+            ));
+        }
+        let keys = match self.trust(&signer, lookup, now) {
+            (apex, Step::Secure(keys)) if apex.eq_ignore_ascii_case(&signer) => keys,
+            (_, Step::Secure(_)) => {
+                return Err((
+                    InfoCode::DnskeyMissing,
+                    format!(
+                        "{} is signed by {}, which isn't a signed zone",
+                        described(),
+                        name::to_presentation(&signer)
+                    ),
+                ))
+            }
+            (_, Step::Bogus(code, text)) => return Err((code, text)),
+            _ => return Ok(None),
+        };
+        let expanded = verify(rrset, signatures, &keys, now)
+            .map_err(|(code, text)| (code, format!("{} for {}", text, described())))?;
+        Ok(Some(expanded))
+    }
+
+    // the zone the name is in and what is known of its keys, walking down
+    // from the closest anchor one label at a time
+    fn trust(&self, target: &str, lookup: &Lookup, now: u64) -> (String, Step) {
+        let target = target.trim_end_matches('.').to_ascii_lowercase();
+        let anchor = self
+            .anchors
+            .iter()
+            .map(|anchor| anchor.name.trim_end_matches('.').to_ascii_lowercase())
+            .filter(|anchor| name::is_below(&target, anchor))
+            .max_by_key(|anchor| name::labels(anchor).len());
+        let Some(mut apex) = anchor else {
+            return (target, Step::Insecure);
+        };
+        let mut step = self.cached(&apex, now, || self.anchor_keys(&apex, lookup, now));
+
+        let labels = name::labels(&target);
+        let below = labels.len() - name::labels(&apex).len();
+        for skip in (0..below).rev() {
+            let Step::Secure(keys) = &step else {
+                break;
+            };
+            let child = labels[skip..].join(".");
+            let keys = keys.clone();
+            let found = self.cached(&child, now, || {
+                self.delegation(&apex, &keys, &child, lookup, now)
+            });
+            match found {
+                Step::Same => {}
+                Step::Missing => break,
+                Step::Secure(_) => {
+                    apex = child;
+                    step = found;
+                }
+                Step::Insecure | Step::Bogus(..) => {
+                    apex = child;
+                    step = found;
+                    break;
+                }
+            }
+        }
+        (apex, step)
+    }
+
+    fn cached(&self, name: &str, now: u64, find: impl FnOnce() -> (Step, u64)) -> Step {
+        if let Some((step, expires)) = self.cache.lock().unwrap().get(name) {
+            if *expires > now {
+                return step.clone();
+            }
+        }
+        let (step, ttl) = find();
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= CACHE_SIZE {
+            cache.retain(|_, (_, expires)| *expires > now);
+        }
+        if cache.len() < CACHE_SIZE {
+            cache.insert(name.to_string(), (step.clone(), now + ttl));
+        }
+        step
+    }
+
+    // an anchor's keys, when one of them matches a DS or DNSKEY it's
+    // anchored by
+    fn anchor_keys(&self, apex: &str, lookup: &Lookup, now: u64) -> (Step, u64) {
+        let anchors: Vec<&Answer> = self
+            .anchors
+            .iter()
+            .filter(|anchor| anchor.name.trim_end_matches('.').eq_ignore_ascii_case(apex))
+            .collect();
+        let ds: Vec<Ds> = anchors
+            .iter()
+            .filter(|anchor| anchor.r#type == RRType::DS)
+            .filter_map(|anchor| Ds::from_data(&anchor.data))
+            .filter(Ds::is_supported)
+            .collect();
+        let keys: Vec<Dnskey> = anchors
+            .iter()
+            .filter(|anchor| anchor.r#type == RRType::DNSKEY)
+            .filter_map(|anchor| Dnskey::from_data(&anchor.data))
+            .collect();
+        if ds.is_empty() && keys.is_empty() {
+            return (Step::Insecure, NEGATIVE_TTL);
+        }
+        let trusted =
+            |key: &Dnskey| keys.contains(key) || ds.iter().any(|ds| ds.matches(apex, key));
+        self.zone_keys(apex, lookup, now, &trusted)
+    }
+
+    // whether the child is a zone cut, and if it is whether it's signed,
+    // from the DS records the parent has for it or the proof it has none
+    fn delegation(
+        &self,
+        apex: &str,
+        keys: &[Dnskey],
+        child: &str,
+        lookup: &Lookup,
+        now: u64,
+    ) -> (Step, u64) {
+        let bogus = |code, text: String| (Step::Bogus(code, text), BOGUS_TTL);
+        let response = match lookup(child, RRType::DS) {
+            Ok(response) => response,
+            Err(e) => {
+                return bogus(
+                    InfoCode::DnssecIndeterminate,
+                    format!(
+                        "couldn't look up DS for {}: {}",
+                        name::to_presentation(child),
+                        e
+                    ),
+                )
+            }
+        };
+        let signed = |answers: &[Answer], r#type: RRType| -> Checked<Vec<(RRset, Vec<Answer>)>> {
+            let records: Vec<Answer> = answers
+                .iter()
+                .filter(|record| record.r#type == r#type)
+                .cloned()
+                .collect();
+            let mut sets = Vec::new();
+            for rrset in RRset::from_answers(&records) {
+                let signatures = signatures(answers, &rrset);
+                if signatures
+                    .iter()
+                    .any(|rrsig| !rrsig.signer.eq_ignore_ascii_case(apex))
+                {
+                    return Err((
+                        InfoCode::DnssecBogus,
+                        format!(
+                            "{} {} isn't signed by {}",
+                            name::to_presentation(&rrset.name),
+                            rrset.r#type,
+                            name::to_presentation(apex)
+                        ),
+                    ));
+                }
+                verify(&rrset, &signatures, keys, now)?;
+                let records = records_of(&records, &rrset);
+                sets.push((rrset, records));
+            }
+            Ok(sets)
+        };
+
+        let ds = match signed(&response.answers, RRType::DS) {
+            Ok(sets) => sets,
+            Err((code, text)) => return bogus(code, format!("DS: {}", text)),
+        };
+        if let Some((rrset, _)) = ds
+            .iter()
+            .find(|(rrset, _)| rrset.name.eq_ignore_ascii_case(child))
+        {
+            let ds: Vec<Ds> = rrset
+                .data
+                .iter()
+                .filter_map(|data| Ds::from_data(data))
+                .filter(Ds::is_supported)
+                .collect();
+            // a DS for algorithms we can't check makes the zone unsigned as
+            // far as we're concerned, RFC 4035 section 5.2
+            if ds.is_empty() {
+                return (Step::Insecure, NEGATIVE_TTL);
+            }
+            let trusted = |key: &Dnskey| ds.iter().any(|ds| ds.matches(child, key));
+            return self.zone_keys(child, lookup, now, &trusted);
+        }
+        // an alias can't be a zone cut
+        if response
+            .answers
+            .iter()
+            .any(|record| record.r#type == RRType::CNAME)
+        {
+            return (Step::Same, NEGATIVE_TTL);
+        }
+
+        let mut proofs = Vec::new();
+        for r#type in [RRType::NSEC, RRType::NSEC3] {
+            match signed(&response.authorities, r#type) {
+                Ok(sets) => proofs.extend(sets.into_iter().flat_map(|(_, records)| records)),
+                Err((code, text)) => return bogus(code, text),
+            }
+        }
+        let nxdomain = RCode::from_value(response.rcode()) == RCode::NXDOMAIN;
+        match deny(child, RRType::DS, nxdomain, &proofs) {
+            Ok(Denial::NoName) => (Step::Missing, NEGATIVE_TTL),
+            Ok(Denial::NoData { delegation: false }) => (Step::Same, NEGATIVE_TTL),
+            Ok(Denial::NoData { delegation: true }) | Ok(Denial::Insecure) => {
+                (Step::Insecure, NEGATIVE_TTL)
+            }
+            Err((code, text)) => bogus(code, text),
+        }
+    }
+
+    // the zone's DNSKEY set, if one of the keys the parent or an anchor
+    // vouches for signed it
+    fn zone_keys(
+        &self,
+        apex: &str,
+        lookup: &Lookup,
+        now: u64,
+        trusted: &dyn Fn(&Dnskey) -> bool,
+    ) -> (Step, u64) {
+        let shown = name::to_presentation(apex);
+        let bogus = |code, text: String| (Step::Bogus(code, text), BOGUS_TTL);
+        let response = match lookup(apex, RRType::DNSKEY) {
+            Ok(response) => response,
+            Err(e) => {
+                return bogus(
+                    InfoCode::DnssecIndeterminate,
+                    format!("couldn't look up DNSKEY for {}: {}", shown, e),
+                )
+            }
+        };
+        let records: Vec<Answer> = response
+            .answers
+            .iter()
+            .filter(|record| {
+                record.r#type == RRType::DNSKEY && record.name.eq_ignore_ascii_case(apex)
+            })
+            .cloned()
+            .collect();
+        let Some(rrset) = RRset::from_answers(&records).into_iter().next() else {
+            return bogus(InfoCode::DnskeyMissing, format!("no DNSKEY for {}", shown));
+        };
+        let keys: Vec<Dnskey> = rrset
+            .data
+            .iter()
+            .filter_map(|data| Dnskey::from_data(data))
+            .collect();
+        let entry: Vec<Dnskey> = keys.iter().filter(|key| trusted(key)).cloned().collect();
+        if entry.is_empty() {
+            return bogus(
+                InfoCode::DnskeyMissing,
+                format!("no DNSKEY for {} matches its DS", shown),
+            );
+        }
+        if let Err((code, text)) =
+            verify(&rrset, &signatures(&response.answers, &rrset), &entry, now)
+        {
+            return bogus(code, format!("{} for {} DNSKEY", text, shown));
+        }
+        let signing: Vec<Dnskey> = keys.into_iter().filter(Dnskey::can_sign).collect();
+        (Step::Secure(signing), (rrset.ttl as u64).min(MAX_TRUST_TTL))
+    }
+}
+
+// a good signature from one of the keys that is valid now, and whether the
+// set was expanded from a wildcard, RFC 4035 section 5.3
+fn verify(rrset: &RRset, signatures: &[Rrsig], keys: &[Dnskey], now: u64) -> Checked<bool> {
+    let now = now as u32;
+    let mut failure = (InfoCode::DnssecBogus, "no valid signature".to_string());
+    if signatures.is_empty() {
+        failure = (InfoCode::RrsigsMissing, "no signatures".to_string());
+    }
+    for rrsig in signatures {
+        for key in keys.iter().filter(|key| key.key_tag() == rrsig.key_tag) {
+            if !rrsig.verify(rrset, key) {
+                continue;
+            }
+            // a wildcard's own records aren't an expansion of it
+            if rrsig.is_current(now) {
+                let labels = name::labels(&rrset.name);
+                let wildcard = labels.first() == Some(&"*");
+                return Ok((rrsig.labels as usize) < labels.len() - wildcard as usize);
+            }
+            failure = match now.wrapping_sub(rrsig.inception) as i32 >= 0 {
+                true => (
+                    InfoCode::SignatureExpired,
+                    "the signature has expired".to_string(),
+                ),
+                false => (
+                    InfoCode::SignatureNotYetValid,
+                    "the signature isn't valid yet".to_string(),
+                ),
+            };
+        }
+    }
+    Err(failure)
+}
+
+// the RRSIGs in the section that cover the set
+fn signatures(answers: &[Answer], rrset: &RRset) -> Vec<Rrsig> {
+    answers
+        .iter()
+        .filter(|record| {
+            record.r#type == RRType::RRSIG && record.name.eq_ignore_ascii_case(&rrset.name)
+        })
+        .filter_map(|record| Rrsig::from_data(&record.data))
+        .filter(|rrsig| rrsig.type_covered == rrset.r#type)
+        .collect()
+}
+
+fn records_of(records: &[Answer], rrset: &RRset) -> Vec<Answer> {
+    records
+        .iter()
+        .filter(|record| {
+            record.r#type == rrset.r#type && record.name.eq_ignore_ascii_case(&rrset.name)
+        })
+        .cloned()
+        .collect()
+}
+
+// where a chain of CNAMEs in the answer leads
+fn final_name(name: &str, answers: &[Answer]) -> String {
+    let mut name = name.trim_end_matches('.').to_string();
+    for _ in 0..answers.len() {
+        let cname = answers.iter().find(|record| {
+            record.r#type == RRType::CNAME && record.name.eq_ignore_ascii_case(&name)
+        });
+        match cname {
+            Some(cname) => {
+                let target = rdata::to_presentation(RRType::CNAME, &cname.data);
+                name = target.trim_end_matches('.').to_string();
+            }
+            None => break,
+        }
+    }
+    name
+}
+
+// the NSEC or NSEC3 records show there's no such name, or no such type at
+// it, RFC 4035 section 5.4 and RFC 5155 section 8
+fn deny(name: &str, r#type: RRType, nxdomain: bool, proofs: &[Answer]) -> Checked<Denial> {
+    let nsec: Vec<(String, Nsec)> = proofs
+        .iter()
+        .filter(|record| record.r#type == RRType::NSEC)
+        .filter_map(|record| Some((record.name.clone(), Nsec::from_data(&record.data)?)))
+        .collect();
+    let denial = match nsec.is_empty() {
+        false => deny_nsec(name, r#type, nxdomain, &nsec),
+        true => deny_nsec3(name, r#type, nxdomain, &nsec3_records(proofs))?,
+    };
+    denial.ok_or_else(|| {
+        let what = match nxdomain {
+            true => "doesn't exist".to_string(),
+            false => format!("has no {} records", r#type),
+        };
+        (
+            InfoCode::NsecMissing,
+            format!("no proof that {} {}", name::to_presentation(name), what),
+        )
+    })
+}
+
+fn deny_nsec(
+    name: &str,
+    r#type: RRType,
+    nxdomain: bool,
+    nsec: &[(String, Nsec)],
+) -> Option<Denial> {
+    let matching = |name: &str| {
+        nsec.iter()
+            .find(|(owner, _)| owner.eq_ignore_ascii_case(name))
+            .map(|(_, nsec)| nsec)
+    };
+    let covering = |name: &str| nsec.iter().find(|(owner, nsec)| nsec.covers(owner, name));
+    let no_type = |nsec: &Nsec| !nsec.has(r#type) && !nsec.has(RRType::CNAME);
+
+    if !nxdomain {
+        if let Some(found) = matching(name) {
+            // the parent's side of a cut has NS but no SOA, RFC 4035
+            // section 5.2
+            let delegation = found.has(RRType::NS) && !found.has(RRType::SOA);
+            return no_type(found).then_some(Denial::NoData { delegation });
+        }
+    }
+    let (owner, cover) = covering(name)?;
+    // an empty non-terminal is covered by a record whose next name is
+    // below it, RFC 4035 section 3.1.3.2
+    if !nxdomain && name::is_below(&cover.next, name) {
+        return Some(Denial::NoData { delegation: false });
+    }
+    let encloser = [owner.as_str(), cover.next.as_str()]
+        .into_iter()
+        .map(|other| common_ancestor(name, other))
+        .max_by_key(|ancestor| name::labels(ancestor).len())?;
+    let wildcard = name::join("*", &encloser);
+    match nxdomain {
+        true => covering(&wildcard).map(|_| Denial::NoName),
+        false => matching(&wildcard)
+            .filter(|found| no_type(found))
+            .map(|_| Denial::NoData { delegation: false }),
+    }
+}
+
+struct Hashed {
+    hash: Vec<u8>,
+    nsec3: Nsec3,
+}
+
+fn nsec3_records(proofs: &[Answer]) -> Vec<Hashed> {
+    proofs
+        .iter()
+        .filter(|record| record.r#type == RRType::NSEC3)
+        .filter_map(|record| {
+            let label = name::labels(&record.name).first().copied()?;
+            let nsec3 = Nsec3::from_data(&record.data)?;
+            let hash = rdata::from_base32hex(label)?;
+            (nsec3.hash_algorithm == NSEC3_SHA1).then_some(Hashed { hash, nsec3 })
+        })
+        .collect()
+}
+
+fn deny_nsec3(
+    name: &str,
+    r#type: RRType,
+    nxdomain: bool,
+    nsec3: &[Hashed],
+) -> Checked<Option<Denial>> {
+    let Some(first) = nsec3.first() else {
+        return Ok(None);
+    };
+    // RFC 9276 section 3.2
+    if nsec3
+        .iter()
+        .any(|record| record.nsec3.iterations > MAX_NSEC3_ITERATIONS)
+    {
+        return Ok(Some(Denial::Insecure));
+    }
+    let (salt, iterations) = (&first.nsec3.salt, first.nsec3.iterations);
+    let hash = |name: &str| dnssec::nsec3_hash(name, salt, iterations);
+    let matching = |name: &str| {
+        let hash = hash(name);
+        nsec3
+            .iter()
+            .find(|record| record.hash == hash)
+            .map(|record| &record.nsec3)
+    };
+    let covering = |name: &str| {
+        let hash = hash(name);
+        nsec3
+            .iter()
+            .find(|record| record.nsec3.covers(&record.hash, &hash))
+            .map(|record| &record.nsec3)
+    };
+    let no_type = |nsec3: &Nsec3| !nsec3.has(r#type) && !nsec3.has(RRType::CNAME);
+    // RFC 5155 section 8.3: the closest encloser has a matching record and
+    // the next closer name, one label longer, a covering one
+    let closest_encloser = || {
+        let labels = name::labels(name);
+        (1..=labels.len()).find_map(|skip| {
+            let encloser = labels[skip..].join(".");
+            let next_closer = labels[skip - 1..].join(".");
+            matching(&encloser)?;
+            Some((encloser, covering(&next_closer)?))
+        })
+    };
+
+    if !nxdomain {
+        if let Some(found) = matching(name) {
+            let delegation = found.has(RRType::NS) && !found.has(RRType::SOA);
+            return Ok(no_type(found).then_some(Denial::NoData { delegation }));
+        }
+    }
+    let Some((encloser, next_closer)) = closest_encloser() else {
+        return Ok(None);
+    };
+    // an opt-out span may hide unsigned delegations, RFC 5155 section 6
+    if next_closer.opt_out() && (nxdomain || r#type == RRType::DS) {
+        return Ok(Some(Denial::Insecure));
+    }
+    let wildcard = name::join("*", &encloser);
+    Ok(match nxdomain {
+        true => covering(&wildcard).map(|_| Denial::NoName),
+        false => matching(&wildcard)
+            .filter(|found| no_type(found))
+            .map(|_| Denial::NoData { delegation: false }),
+    })
+}
+
+// the name doesn't exist, which a wildcard answer has to show along with it
+fn wildcard_proven(owner: &str, proofs: &[Answer], response: &Packet) -> bool {
+    let nsec: Vec<(String, Nsec)> = proofs
+        .iter()
+        .filter(|record| record.r#type == RRType::NSEC)
+        .filter_map(|record| Some((record.name.clone(), Nsec::from_data(&record.data)?)))
+        .collect();
+    if nsec
+        .iter()
+        .any(|(nsec_owner, nsec)| nsec.covers(nsec_owner, owner))
+    {
+        return true;
+    }
+    // the signature's label count gives the closest encloser, and the next
+    // closer name is one label longer
+    let labels = response
+        .answers
+        .iter()
+        .filter(|record| record.r#type == RRType::RRSIG && record.name.eq_ignore_ascii_case(owner))
+        .filter_map(|record| Rrsig::from_data(&record.data))
+        .map(|rrsig| rrsig.labels as usize)
+        .next();
+    let Some(labels) = labels else {
+        return false;
+    };
+    let names = name::labels(owner);
+    let Some(next_closer) = names
+        .len()
+        .checked_sub(labels + 1)
+        .map(|skip| names[skip..].join("."))
+    else {
+        return false;
+    };
+    nsec3_records(proofs).iter().any(|record| {
+        let hash = dnssec::nsec3_hash(&next_closer, &record.nsec3.salt, record.nsec3.iterations);
+        record.nsec3.covers(&record.hash, &hash)
+    })
+}
+
+fn parent(name: &str) -> Option<String> {
+    let labels = name::labels(name);
+    (!labels.is_empty()).then(|| labels[1..].join("."))
+}
+
+fn common_ancestor(a: &str, b: &str) -> String {
+    let a = name::labels(a);
+    let b = name::labels(b);
+    let common = a
+        .iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(a, b)| a.eq_ignore_ascii_case(b))
+        .count();
+    a[a.len() - common..].join(".")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::class::RRClass;
+    use crate::client::{self, Client};
+    use crate::dnssec::{Algorithm, DigestType, Nsec3Param, SigningKey, OPT_OUT};
+    use crate::dnssec::{SECURE_ENTRY_POINT, ZONE_KEY};
+    use crate::edns::Edns;
+    use crate::forward::{Forwarder, Pool};
+    use crate::server::{self, Request, Server};
+    use crate::sign::Signer;
+    use crate::zone::Zone;
+    use std::net::{SocketAddr, TcpListener, UdpSocket};
+    use std::sync::Arc;
+
+    const KSK: u16 = ZONE_KEY | SECURE_ENTRY_POINT;
+
+    fn ds(owner: &str, key: &SigningKey) -> Answer {
+        let ds = Ds::from_key(owner, &key.dnskey(), DigestType::Sha256);
+        Answer::new(owner, RRType::DS, RRClass::IN, 300, ds.to_data())
+    }
+
+    struct Authority {
+        address: SocketAddr,
+        // a DS for the root's KSK, to anchor the tests' own root
+        anchor: Answer,
+    }
+
+    // the root, test. and example.test. signed with different algorithms and
+    // denial, an expired zone, unsigned zones and an in-process authority
+    // serving all of them over UDP and TCP
+    fn authority() -> Authority {
+        let now = dnssec::unix_time() as u32;
+        let valid = (now - 3600, now + 30 * 86400);
+        let generate = |algorithm, flags| SigningKey::generate(algorithm, flags).unwrap();
+        let root_signer = Signer::new(vec![
+            generate(Algorithm::EcdsaP256Sha256, KSK),
+            generate(Algorithm::EcdsaP256Sha256, ZONE_KEY),
+        ]);
+        let mut test_signer = Signer::new(vec![generate(Algorithm::Ed25519, KSK)]);
+        test_signer.nsec3 = Some(Nsec3Param {
+            hash_algorithm: NSEC3_SHA1,
+            flags: OPT_OUT,
+            iterations: 1,
+            salt: vec![0xab, 0xcd],
+        });
+        let mut example_signer = Signer::new(vec![
+            generate(Algorithm::EcdsaP384Sha384, KSK),
+            generate(Algorithm::EcdsaP384Sha384, ZONE_KEY),
+        ]);
+        example_signer.nsec3 = Some(Nsec3Param {
+            hash_algorithm: NSEC3_SHA1,
+            flags: 0,
+            iterations: 0,
+            salt: Vec::new(),
+        });
+        let old_signer = Signer::new(vec![generate(Algorithm::Ed25519, KSK)]);
+        let sign = |signer: &Signer, zone: &Zone, (inception, expiration)| {
+            signer.sign_between(zone, inception, expiration).unwrap()
+        };
+
+        let soa =
+            "$TTL 300\n@ SOA ns hostmaster 1 7200 3600 1209600 300\n@ NS ns\nns A 192.0.2.1\n";
+        let mut root = Zone::from_text(
+            ".",
+            &format!(
+                "{}*.wild A 192.0.2.9\ntest NS ns.test.\nns.test A 192.0.2.2\n\
+                 unsigned NS ns.unsigned.\nns.unsigned A 192.0.2.3\n",
+                soa
+            ),
+        )
+        .unwrap();
+        root.records.push(ds("test", &test_signer.keys[0]));
+        let root = sign(&root_signer, &root, valid);
+
+        let mut test = Zone::from_text(
+            "test",
+            &format!(
+                "{}example NS ns.example\nns.example A 192.0.2.4\nold NS ns.old\n\
+                 ns.old A 192.0.2.5\noptout NS ns.optout\nns.optout A 192.0.2.6\n\
+                 *.wild A 192.0.2.10\na.b.ent TXT deep\n",
+                soa
+            ),
+        )
+        .unwrap();
+        test.records
+            .push(ds("example.test", &example_signer.keys[0]));
+        test.records.push(ds("old.test", &old_signer.keys[0]));
+        let test = sign(&test_signer, &test, valid);
+
+        let example = Zone::from_text(
+            "example.test",
+            &format!(
+                "{}www A 192.0.2.20\nalias CNAME www\ntampered A 192.0.2.22\n",
+                soa
+            ),
+        )
+        .unwrap();
+        let mut example = sign(&example_signer, &example, valid);
+        for record in example.records.iter_mut() {
+            if record.name == "tampered.example.test" && record.r#type == RRType::A {
+                record.data = vec![192, 0, 2, 23];
+            }
+        }
+
+        let old = Zone::from_text("old.test", &format!("{}www A 192.0.2.30\n", soa)).unwrap();
+        let old = sign(&old_signer, &old, (now - 7200, now - 3600));
+
+        let unsigned = format!("{}www A 192.0.2.40\n", soa);
+        let mut server = Server::new();
+        for zone in [
+            root,
+            test,
+            example,
+            old,
+            Zone::from_text("unsigned", &unsigned).unwrap(),
+            Zone::from_text("optout.test", &unsigned).unwrap(),
+        ] {
+            server.add_zone(zone);
+        }
+        let server = Arc::new(server);
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        server::serve_udp(socket, server.clone()).unwrap();
+        server::serve_tcp(TcpListener::bind(address).unwrap(), server).unwrap();
+        Authority {
+            address,
+            anchor: ds("", &root_signer.keys[0]),
+        }
+    }
+
+    fn dnssec_query(name: &str, r#type: RRType) -> Packet {
+        let mut query = client::build_query(name, r#type, RRClass::IN);
+        let mut edns = Edns::new(server::UDP_PAYLOAD_SIZE);
+        edns.dnssec_ok = true;
+        query.edns = Some(edns);
+        query.header.checking_disabled = true;
+        query
+    }
+
+    fn fetch(address: SocketAddr, name: &str, r#type: RRType) -> io::Result<Packet> {
+        let response = Client::new(address).query(&dnssec_query(name, r#type))?;
+        Ok(response.packet)
+    }
+
+    fn bogus_code(security: Security) -> InfoCode {
+        match security {
+            Security::Bogus(code, _) => code,
+            other => panic!("{:?} isn't bogus", other),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let authority = authority();
+        let validator = Validator::new(vec![authority.anchor.clone()]);
+        let address = authority.address;
+        let lookup = |name: &str, r#type| fetch(address, name, r#type);
+        let validate = |name: &str, r#type| {
+            let response = fetch(address, name, r#type).unwrap();
+            validator.validate(&response, &lookup)
+        };
+
+        // answers, through a CNAME, from wildcards with NSEC and NSEC3
+        assert_eq!(validate("www.example.test", RRType::A), Security::Secure);
+        assert_eq!(validate("ALIAS.example.test", RRType::A), Security::Secure);
+        assert_eq!(validate("x.wild", RRType::A), Security::Secure);
+        assert_eq!(validate("x.y.wild.test", RRType::A), Security::Secure);
+        assert_eq!(validate("test", RRType::DS), Security::Secure);
+
+        // NXDOMAIN and NODATA with NSEC and NSEC3, an empty non-terminal too
+        assert_eq!(validate("nothing", RRType::A), Security::Secure);
+        assert_eq!(
+            validate("nothing.example.test", RRType::A),
+            Security::Secure
+        );
+        assert_eq!(validate("www.example.test", RRType::MX), Security::Secure);
+        assert_eq!(validate("ent.test", RRType::A), Security::Secure);
+        // an opt-out span can't prove there's no unsigned delegation
+        assert_eq!(validate("nothing.test", RRType::A), Security::Insecure);
+
+        // below a delegation without a DS, proven by NSEC and NSEC3 opt-out
+        assert_eq!(validate("www.unsigned", RRType::A), Security::Insecure);
+        assert_eq!(validate("www.optout.test", RRType::A), Security::Insecure);
+        assert_eq!(validate("nothing.unsigned", RRType::A), Security::Insecure);
+
+        assert_eq!(
+            bogus_code(validate("tampered.example.test", RRType::A)),
+            InfoCode::DnssecBogus
+        );
+        assert_eq!(
+            bogus_code(validate("www.old.test", RRType::A)),
+            InfoCode::SignatureExpired
+        );
+
+        // signatures and proofs that go missing on the way
+        let mut response = fetch(address, "www.example.test", RRType::A).unwrap();
+        response
+            .answers
+            .retain(|record| record.r#type != RRType::RRSIG);
+        assert_eq!(
+            bogus_code(validator.validate(&response, &lookup)),
+            InfoCode::RrsigsMissing
+        );
+        let mut response = fetch(address, "nothing.example.test", RRType::A).unwrap();
+        response.authorities.retain(|record| match record.r#type {
+            RRType::NSEC3 => false,
+            RRType::RRSIG => Rrsig::from_data(&record.data).unwrap().type_covered == RRType::SOA,
+            _ => true,
+        });
+        assert_eq!(
+            bogus_code(validator.validate(&response, &lookup)),
+            InfoCode::NsecMissing
+        );
+        // an answer from the wildcard passed off as the name itself
+        let mut response = fetch(address, "www.example.test", RRType::A).unwrap();
+        response.questions[0].name = "forged.example.test".to_string();
+        for record in response.answers.iter_mut() {
+            record.name = "forged.example.test".to_string();
+        }
+        assert_eq!(
+            bogus_code(validator.validate(&response, &lookup)),
+            InfoCode::DnssecBogus
+        );
+    }
+
+    #[test]
+    fn test_anchors() {
+        let authority = authority();
+        let address = authority.address;
+        let lookup = |name: &str, r#type| fetch(address, name, r#type);
+        let response = fetch(address, "www.example.test", RRType::A).unwrap();
+
+        // the real root's keys don't sign the tests' root
+        let validator = Validator::with_root_anchors();
+        assert_eq!(validator.anchors.len(), 2);
+        assert_eq!(
+            bogus_code(validator.validate(&response, &lookup)),
+            InfoCode::DnskeyMissing
+        );
+
+        // an anchor further down is enough, and names outside it are
+        // unsigned
+        let key = fetch(address, "example.test", RRType::DNSKEY).unwrap();
+        let anchors: String = key
+            .answers
+            .iter()
+            .filter(|record| record.r#type == RRType::DNSKEY)
+            .map(|record| format!("{}\n", record))
+            .collect();
+        let validator = Validator::from_text(&anchors).unwrap();
+        assert_eq!(validator.validate(&response, &lookup), Security::Secure);
+        let response = fetch(address, "x.y.wild.test", RRType::A).unwrap();
+        assert_eq!(validator.validate(&response, &lookup), Security::Insecure);
+
+        assert!(Validator::from_text("").is_err());
+        assert!(Validator::from_text(". 0 IN A 192.0.2.1")
+            .unwrap_err()
+            .contains("isn't a trust anchor"));
+    }
+
+    #[test]
+    fn test_server_validates() {
+        let authority = authority();
+        let mut server = Server::new();
+        server.forwarder = Some(Arc::new(Forwarder::new(Pool::new(&[authority.address]))));
+        server.validator = Some(Validator::new(vec![authority.anchor.clone()]));
+        let request = Request {
+            client: "127.0.0.1:5300".parse().unwrap(),
+            local: "127.0.0.1:53".parse().unwrap(),
+            transport: crate::dnstap::Protocol::Udp,
+        };
+        let ask =
+            |query: &Packet| Packet::from_buf(&server.handle(&request, &query.to_bytes()).unwrap());
+        let has_rrsig = |response: &Packet| {
+            response
+                .answers
+                .iter()
+                .any(|record| record.r#type == RRType::RRSIG)
+        };
+
+        // secure answers get AD, and the signatures only with DO
+        let mut query = dnssec_query("www.example.test", RRType::A);
+        query.header.checking_disabled = false;
+        let response = ask(&query);
+        assert_eq!(response.rcode(), RCode::NOERROR as u16);
+        assert!(response.header.authentic_data);
+        assert!(has_rrsig(&response));
+        let mut plain = client::build_query("www.example.test", RRType::A, RRClass::IN);
+        plain.header.authentic_data = true;
+        let response = ask(&plain);
+        assert!(response.header.authentic_data);
+        assert!(!has_rrsig(&response));
+        assert_eq!(response.answers.len(), 1);
+
+        let response = ask(&client::build_query("www.unsigned", RRType::A, RRClass::IN));
+        assert_eq!(response.rcode(), RCode::NOERROR as u16);
+        assert!(!response.header.authentic_data);
+
+        // bogus is a SERVFAIL saying why, unless the client checks itself
+        let mut query = dnssec_query("tampered.example.test", RRType::A);
+        query.header.checking_disabled = false;
+        let response = ask(&query);
+        assert_eq!(response.rcode(), RCode::SERVFAIL as u16);
+        assert!(response.answers.is_empty());
+        let errors = response.edns.as_ref().unwrap().extended_errors();
+        assert_eq!(errors[0].info_code(), InfoCode::DnssecBogus);
+        query.header.checking_disabled = true;
+        let response = ask(&query);
+        assert_eq!(response.rcode(), RCode::NOERROR as u16);
+        assert!(!response.header.authentic_data);
+        assert!(has_rrsig(&response));
+    }
+}
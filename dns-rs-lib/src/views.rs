@@ -0,0 +1,231 @@
+use crate::acl::Acl;
+use crate::cidr::Cidr;
+use crate::packet::Packet;
+use crate::r#type::RRType;
+use crate::zone::Zone;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+// a view answers the queries its rules match with its own zones and
+// policy; every rule given has to match, and an empty rule matches anything
+#[derive(Debug, Clone, PartialEq)]
+pub struct View {
+    pub name: String,
+    pub match_clients: Vec<Cidr>,
+    // the server address the query was sent to
+    pub match_destinations: Vec<Cidr>,
+    // names of the TSIG keys the query may be signed with
+    pub match_keys: Vec<String>,
+    // shared with the server's queries, so a zone can be swapped for a newer
+    // copy without waiting for the ones answering from it
+    pub zones: Vec<Arc<Zone>>,
+    pub recursion: bool,
+    pub allow_query: Acl,
+    pub allow_recursion: Acl,
+}
+
+// views in the order they are tried, the first match answers
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Views {
+    pub views: Vec<View>,
+}
+
+impl View {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            match_clients: Vec::new(),
+            match_destinations: Vec::new(),
+            match_keys: Vec::new(),
+            zones: Vec::new(),
+            recursion: false,
+            allow_query: Acl::any(),
+            allow_recursion: Acl::any(),
+        }
+    }
+
+    // the key name has to have been verified already, it's only compared here
+    pub fn matches(&self, client: IpAddr, destination: IpAddr, key: Option<&str>) -> bool {
+        let key_matches = match key {
+            Some(key) => self.match_keys.iter().any(|name| same_name(name, key)),
+            None => false,
+        };
+        any_contains(&self.match_clients, client)
+            && any_contains(&self.match_destinations, destination)
+            && (self.match_keys.is_empty() || key_matches)
+    }
+
+    pub fn zone(&self, name: &str) -> Option<&Arc<Zone>> {
+        let name = name.trim_end_matches('.');
+        self.zones
+            .iter()
+            .find(|zone| zone.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn replace_zone(&mut self, zone: Zone) {
+        match self.zones.iter_mut().find(|old| old.name == zone.name) {
+            Some(old) => *old = Arc::new(zone),
+            None => self.zones.push(Arc::new(zone)),
+        }
+    }
+
+    // the closest enclosing zone
+    pub fn zone_for(&self, name: &str) -> Option<&Arc<Zone>> {
+        self.zones
+            .iter()
+            .filter(|zone| zone.contains(name))
+            .max_by_key(|zone| zone.name.len())
+    }
+
+    pub fn allows_query(&self, client: IpAddr, key: Option<&str>) -> bool {
+        self.allow_query.allows(client, key)
+    }
+
+    pub fn allows_recursion(&self, client: IpAddr, key: Option<&str>) -> bool {
+        self.recursion && self.allow_recursion.allows(client, key)
+    }
+}
+
+impl Views {
+    pub fn new(views: Vec<View>) -> Self {
+        Self { views }
+    }
+
+    // verified_key is the name of the TSIG key whose signature on the query
+    // checked out, never just a name read from the message
+    pub fn select(
+        &self,
+        client: IpAddr,
+        destination: IpAddr,
+        verified_key: Option<&str>,
+    ) -> Option<&View> {
+        self.views
+            .iter()
+            .find(|view| view.matches(client, destination, verified_key))
+    }
+}
+
+// the name of the key a message claims to be signed with, TSIG has to be the
+// last additional record, RFC 8945 section 5.1; anyone can add one, so this
+// only says which key to verify with and isn't to be matched against views
+// or ACLs
+pub fn tsig_key_name(packet: &Packet) -> Option<String> {
+    packet
+        .additionals
+        .last()
+        .filter(|record| record.r#type == RRType::TSIG)
+        .map(|record| record.name.to_ascii_lowercase())
+}
+
+fn any_contains(cidrs: &[Cidr], address: IpAddr) -> bool {
+    cidrs.is_empty() || cidrs.iter().any(|cidr| cidr.contains(address))
+}
+
+fn same_name(a: &str, b: &str) -> bool {
+    a.trim_end_matches('.')
+        .eq_ignore_ascii_case(b.trim_end_matches('.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::answer::Answer;
+    use crate::class::RRClass;
+    use crate::client;
+
+    fn views() -> Views {
+        let mut internal = View::new("internal");
+        internal.match_clients = vec!["10.0.0.0/8".parse().unwrap()];
+        internal.recursion = true;
+        internal.zones = vec![
+            Arc::new(Zone::from_text("example.com", "$TTL 60\nwww A 10.0.0.80\n").unwrap()),
+            Arc::new(Zone::from_text("lab.example.com", "$TTL 60\nhost A 10.1.0.1\n").unwrap()),
+        ];
+
+        let mut transfers = View::new("transfers");
+        transfers.match_keys = vec!["xfr-key.".to_string()];
+        transfers.match_destinations = vec!["192.0.2.53/32".parse().unwrap()];
+
+        let mut external = View::new("external");
+        external
+            .replace_zone(Zone::from_text("example.com", "$TTL 60\nwww A 192.0.2.80\n").unwrap());
+        Views::new(vec![internal, transfers, external])
+    }
+
+    fn select(client: &str, destination: &str, key: Option<&str>) -> String {
+        let views = views();
+        let view = views
+            .select(client.parse().unwrap(), destination.parse().unwrap(), key)
+            .unwrap();
+        view.name.clone()
+    }
+
+    #[test]
+    fn test_select() {
+        assert_eq!(select("10.1.2.3", "192.0.2.53", None), "internal");
+        assert_eq!(select("203.0.113.1", "192.0.2.53", None), "external");
+        assert_eq!(
+            select("203.0.113.1", "192.0.2.53", Some("XFR-key")),
+            "transfers"
+        );
+        // the key alone isn't enough when the destination doesn't match
+        assert_eq!(
+            select("203.0.113.1", "192.0.2.54", Some("xfr-key")),
+            "external"
+        );
+        // and views are tried in order
+        assert_eq!(
+            select("10.1.2.3", "192.0.2.53", Some("xfr-key")),
+            "internal"
+        );
+        assert!(Views::default()
+            .select(
+                "10.1.2.3".parse().unwrap(),
+                "10.0.0.1".parse().unwrap(),
+                None
+            )
+            .is_none());
+    }
+
+    #[test]
+    fn test_view_policy() {
+        let views = views();
+        let internal = &views.views[0];
+        assert_eq!(
+            internal.zone_for("host.lab.example.com").unwrap().name,
+            "lab.example.com"
+        );
+        assert_eq!(
+            internal.zone_for("www.example.com").unwrap().name,
+            "example.com"
+        );
+        assert!(internal.zone_for("example.net").is_none());
+        assert!(internal.allows_recursion("10.0.0.1".parse().unwrap(), None));
+
+        let external = &views.views[2];
+        let answers = external
+            .zone_for("www.example.com")
+            .unwrap()
+            .lookup("www.example.com", RRType::A);
+        assert_eq!(answers[0].data, vec![192, 0, 2, 80]);
+        assert!(!external.allows_recursion("203.0.113.1".parse().unwrap(), None));
+        assert!(external.allows_query("203.0.113.1".parse().unwrap(), None));
+    }
+
+    #[test]
+    fn test_tsig_must_be_last() {
+        let mut query = client::build_query("www.example.com", RRType::A, RRClass::IN);
+        query
+            .additionals
+            .push(Answer::new("key", RRType::TSIG, RRClass::IN, 0, vec![]));
+        assert_eq!(tsig_key_name(&query).as_deref(), Some("key"));
+        query.additionals.push(Answer::new(
+            "ns.example.com",
+            RRType::A,
+            RRClass::IN,
+            60,
+            vec![192, 0, 2, 1],
+        ));
+        assert_eq!(tsig_key_name(&query), None);
+    }
+}
@@ -0,0 +1,1105 @@
+use crate::answer::Answer;
+use crate::base64;
+use crate::class::RRClass;
+use crate::dnssec::{self, Algorithm, Nsec, Nsec3Param, Rrsig};
+use crate::name;
+use crate::packet::Packet;
+use crate::r#type::RRType;
+use crate::rcode::RCode;
+use crate::rdata;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+// CNAMEs followed inside the zone before giving up on a loop
+const MAX_CNAME_CHAIN: usize = 8;
+
+// a zone's name and records, as served from one view or another
+#[derive(Debug, Clone, PartialEq)]
+pub struct Zone {
+    pub name: String,
+    pub records: Vec<Answer>,
+}
+
+// the numbers that end an SOA record, RFC 1035 section 3.3.13
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Soa {
+    pub serial: u32,
+    pub refresh: u32,
+    pub retry: u32,
+    pub expire: u32,
+    pub minimum: u32,
+}
+
+// what a signed zone has to prove about the names it answered for, beyond
+// signing the records it gave
+#[derive(Debug)]
+enum Proof {
+    // the name doesn't exist and neither does a wildcard to answer it
+    NoName(String),
+    // the name, or the wildcard standing in for it, has no such records
+    NoData { name: String, owner: String },
+    // an answer came from a wildcard, so the name itself doesn't exist
+    Wildcard(String),
+    // whether the child below the cut is signed
+    Referral(String),
+}
+
+// a field from a zone file, quoted strings keep their spaces
+#[derive(Debug, Clone, PartialEq)]
+struct Field {
+    text: String,
+    quoted: bool,
+}
+
+// a record's fields, which parentheses can spread over several lines
+#[derive(Debug)]
+struct Entry {
+    line: usize,
+    // a blank owner means the previous record's owner
+    indented: bool,
+    fields: Vec<Field>,
+}
+
+// parses a master file as in RFC 1035 section 5, handling $ORIGIN, $TTL,
+// parentheses, comments and relative names; $INCLUDE isn't supported
+pub fn parse(text: &str, origin: &str) -> Result<Vec<Answer>, String> {
+    let mut origin = absolute(origin, "")?;
+    let mut default_ttl = None;
+    let mut last_ttl = None;
+    let mut last_owner: Option<String> = None;
+    let mut last_class = RRClass::IN;
+    let mut records = Vec::new();
+
+    for entry in entries(text)? {
+        let error = |message: String| format!("line {}: {}", entry.line, message);
+        let mut fields = entry.fields.iter();
+
+        if !entry.indented {
+            let first = &entry.fields[0];
+            match first.text.to_ascii_uppercase().as_str() {
+                "$ORIGIN" if !first.quoted => {
+                    let value = entry
+                        .fields
+                        .get(1)
+                        .ok_or_else(|| error("$ORIGIN needs a name".into()))?;
+                    origin = absolute(&value.text, &origin).map_err(error)?;
+                    continue;
+                }
+                "$TTL" if !first.quoted => {
+                    let value = entry
+                        .fields
+                        .get(1)
+                        .ok_or_else(|| error("$TTL needs a value".into()))?;
+                    default_ttl = Some(
+                        parse_ttl(&value.text)
+                            .ok_or_else(|| error(format!("invalid TTL {:?}", value.text)))?,
+                    );
+                    continue;
+                }
+                "$INCLUDE" | "$GENERATE" if !first.quoted => {
+                    return Err(error(format!("{} isn't supported", first.text)));
+                }
+                _ => {}
+            }
+            last_owner = Some(absolute(&fields.next().unwrap().text, &origin).map_err(error)?);
+        }
+        let owner = last_owner
+            .clone()
+            .ok_or_else(|| error("no owner for the first record".into()))?;
+
+        // TTL and class can come in either order, and both are optional
+        let mut ttl = None;
+        let mut class = None;
+        let r#type = loop {
+            let field = fields
+                .next()
+                .ok_or_else(|| error("record has no type".into()))?;
+            if ttl.is_none() {
+                if let Some(value) = parse_ttl(&field.text) {
+                    ttl = Some(value);
+                    continue;
+                }
+            }
+            if class.is_none() {
+                if let Ok(value) = field.text.parse::<RRClass>() {
+                    class = Some(value);
+                    continue;
+                }
+            }
+            break field
+                .text
+                .parse::<RRType>()
+                .map_err(|_| error(format!("unknown type {:?}", field.text)))?;
+        };
+        let class = class.unwrap_or(last_class);
+        last_class = class;
+        // without $TTL a record takes the last TTL given, RFC 1035 section 5.1
+        let ttl = ttl
+            .or(default_ttl)
+            .or(last_ttl)
+            .ok_or_else(|| error("record has no TTL and there is no $TTL".into()))?;
+        last_ttl = Some(ttl);
+
+        let rdata: Vec<&Field> = fields.collect();
+        let data = to_rdata(r#type, &rdata, &origin).map_err(error)?;
+        records.push(Answer::new(&owner, r#type, class, ttl, data));
+    }
+    Ok(records)
+}
+
+impl Soa {
+    // the names before the numbers are skipped over
+    pub fn from_data(data: &[u8]) -> Option<Self> {
+        let start = data.len().checked_sub(20)?;
+        let field = |i: usize| {
+            let at = start + i * 4;
+            u32::from_be_bytes(data[at..at + 4].try_into().unwrap())
+        };
+        Some(Self {
+            serial: field(0),
+            refresh: field(1),
+            retry: field(2),
+            expire: field(3),
+            minimum: field(4),
+        })
+    }
+}
+
+impl Zone {
+    pub fn new(name: &str, records: Vec<Answer>) -> Self {
+        Self {
+            name: name.strip_suffix('.').unwrap_or(name).to_ascii_lowercase(),
+            records,
+        }
+    }
+
+    pub fn from_text(name: &str, text: &str) -> Result<Self, String> {
+        Ok(Self::new(name, parse(text, name)?))
+    }
+
+    // whether the name is at or below the apex
+    pub fn contains(&self, name: &str) -> bool {
+        name::is_below(name, &self.name)
+    }
+
+    pub fn soa(&self) -> Option<Soa> {
+        let soa = *self.lookup(&self.name, RRType::SOA).first()?;
+        Soa::from_data(&soa.data)
+    }
+
+    pub fn lookup(&self, name: &str, r#type: RRType) -> Vec<&Answer> {
+        let name = name.trim_end_matches('.');
+        self.records
+            .iter()
+            .filter(|record| record.r#type == r#type && record.name.eq_ignore_ascii_case(name))
+            .collect()
+    }
+
+    // the authoritative response to a query for a name in the zone, RFC 1034
+    // section 4.3.2, along with the owner of the wildcard the answer was
+    // synthesized from if it was
+    pub fn respond(&self, query: &Packet) -> (Packet, Option<String>) {
+        let mut header = query.header.clone();
+        header.response = true;
+        header.is_authoritative = true;
+        header.can_recurse = false;
+        header.truncated = false;
+        header.authentic_data = false;
+        header.resp_code = RCode::NOERROR as u8;
+        let mut response = Packet::new(header);
+        response.questions = query.questions.clone();
+        let Some(question) = query.questions.first() else {
+            return (response, None);
+        };
+
+        let mut name = question.name.trim_end_matches('.').to_string();
+        let mut wildcard = None;
+        let mut proofs = Vec::new();
+        for _ in 0..MAX_CNAME_CHAIN {
+            // a CNAME can lead out of the zone, the client follows it from there
+            if !self.contains(&name) {
+                break;
+            }
+            if let Some(cut) = self.delegation(&name, question.r#type) {
+                response.header.is_authoritative = !response.answers.is_empty();
+                self.add_referral(&mut response, &cut);
+                proofs.push(Proof::Referral(cut));
+                break;
+            }
+            let owner = match self.exists(&name) {
+                true => name.clone(),
+                false => match self.wildcard(&name) {
+                    Some(owner) => {
+                        wildcard = Some(owner.clone());
+                        owner
+                    }
+                    None => {
+                        response.header.resp_code = RCode::NXDOMAIN as u8;
+                        self.add_soa(&mut response);
+                        proofs.push(Proof::NoName(name));
+                        break;
+                    }
+                },
+            };
+            let synthesized = |record: &Answer| Answer {
+                name: name.clone(),
+                ..record.clone()
+            };
+            let matching = self.lookup(&owner, question.r#type);
+            let cname = self.lookup(&owner, RRType::CNAME).first().copied();
+            if owner != name && (!matching.is_empty() || cname.is_some()) {
+                proofs.push(Proof::Wildcard(name.clone()));
+            }
+            if !matching.is_empty() {
+                response
+                    .answers
+                    .extend(matching.into_iter().map(synthesized));
+                break;
+            }
+            match cname {
+                Some(cname) => {
+                    response.answers.push(synthesized(cname));
+                    let target = rdata::to_presentation(RRType::CNAME, &cname.data);
+                    name = target.trim_end_matches('.').to_string();
+                }
+                None => {
+                    self.add_soa(&mut response);
+                    proofs.push(Proof::NoData { name, owner });
+                    break;
+                }
+            }
+        }
+        // RFC 4035 section 3.1: signatures and proofs only for clients that
+        // ask for them, and only if there's anything to give
+        let dnssec_ok = query.edns.as_ref().is_some_and(|edns| edns.dnssec_ok);
+        if dnssec_ok && self.is_signed() {
+            for proof in &proofs {
+                self.add_proof(&mut response, proof);
+            }
+            self.add_signatures(&mut response);
+        }
+        (response, wildcard)
+    }
+
+    // whether the zone carries its own keys and signatures
+    pub fn is_signed(&self) -> bool {
+        !self.lookup(&self.name, RRType::DNSKEY).is_empty()
+    }
+
+    // the RRSIGs for every set in the answer and authority sections, taken
+    // from the wildcard and renamed for answers synthesized from one
+    fn add_signatures(&self, response: &mut Packet) {
+        for section in [&mut response.answers, &mut response.authorities] {
+            let mut signatures = Vec::new();
+            for (i, record) in section.iter().enumerate() {
+                let signed = section[..i].iter().any(|earlier| {
+                    earlier.r#type == record.r#type
+                        && earlier.name.eq_ignore_ascii_case(&record.name)
+                });
+                if signed || record.r#type == RRType::RRSIG {
+                    continue;
+                }
+                let owner = match self.exists(&record.name) {
+                    true => Some(record.name.clone()),
+                    false => self.wildcard(&record.name),
+                };
+                let Some(owner) = owner else {
+                    continue;
+                };
+                let covering = self
+                    .lookup(&owner, RRType::RRSIG)
+                    .into_iter()
+                    .filter(|rrsig| {
+                        Rrsig::from_data(&rrsig.data)
+                            .is_some_and(|rrsig| rrsig.type_covered == record.r#type)
+                    });
+                signatures.extend(covering.map(|rrsig| Answer {
+                    name: record.name.clone(),
+                    ..rrsig.clone()
+                }));
+            }
+            section.extend(signatures);
+        }
+    }
+
+    // the NSEC or NSEC3 records that back up a negative answer, a wildcard
+    // answer or an unsigned delegation, RFC 4035 section 3.1.3 and RFC 5155
+    // section 7.2
+    fn add_proof(&self, response: &mut Packet, proof: &Proof) {
+        if let Proof::Referral(cut) = proof {
+            let ds = self.lookup(cut, RRType::DS);
+            if !ds.is_empty() {
+                response.authorities.extend(ds.into_iter().cloned());
+                return;
+            }
+        }
+        // hashing names for NSEC3 takes the dnssec feature, without it an
+        // NSEC3 zone's answers go out without proofs
+        let proving = match self.nsec3_param() {
+            #[cfg(feature = "dnssec")]
+            Some(param) => self.nsec3_proof(proof, &param),
+            #[cfg(not(feature = "dnssec"))]
+            Some(_) => Vec::new(),
+            None => self.nsec_proof(proof),
+        };
+        for record in proving {
+            if !response.authorities.contains(record) {
+                response.authorities.push(record.clone());
+            }
+        }
+    }
+
+    fn nsec_proof(&self, proof: &Proof) -> Vec<&Answer> {
+        let matching = |name: &str| self.lookup(name, RRType::NSEC);
+        let covering = |name: &str| {
+            self.records
+                .iter()
+                .filter(|record| record.r#type == RRType::NSEC)
+                .find(|record| {
+                    Nsec::from_data(&record.data)
+                        .is_some_and(|nsec| nsec.covers(&record.name, name))
+                })
+        };
+        match proof {
+            Proof::NoName(name) => {
+                let wildcard = format!("*.{}", self.closest_encloser(name));
+                let wildcard = wildcard.trim_end_matches('.');
+                covering(name)
+                    .into_iter()
+                    .chain(covering(wildcard))
+                    .collect()
+            }
+            Proof::NoData { name, owner } if name != owner => {
+                covering(name).into_iter().chain(matching(owner)).collect()
+            }
+            // an empty non-terminal has no NSEC, the one covering it shows
+            // there are names below it
+            Proof::NoData { name, .. } | Proof::Referral(name) => match matching(name) {
+                found if !found.is_empty() => found,
+                _ => covering(name).into_iter().collect(),
+            },
+            Proof::Wildcard(name) => covering(name).into_iter().collect(),
+        }
+    }
+
+    #[cfg(feature = "dnssec")]
+    fn nsec3_proof(&self, proof: &Proof, param: &Nsec3Param) -> Vec<&Answer> {
+        let hash = |name: &str| dnssec::nsec3_hash(name, &param.salt, param.iterations);
+        let matching = |name: &str| {
+            let owner = format!("{}.{}", rdata::base32hex(&hash(name)), self.name);
+            self.lookup(owner.trim_end_matches('.'), RRType::NSEC3)
+        };
+        let covering = |name: &str| {
+            let hash = hash(name);
+            self.records
+                .iter()
+                .filter(|record| record.r#type == RRType::NSEC3)
+                .find(|record| {
+                    let owner_hash = name::labels(&record.name)
+                        .first()
+                        .and_then(|label| rdata::from_base32hex(label));
+                    let nsec3 = dnssec::Nsec3::from_data(&record.data);
+                    matches!((owner_hash, nsec3), (Some(owner_hash), Some(nsec3)) if nsec3.covers(&owner_hash, &hash))
+                })
+        };
+        // RFC 5155 section 7.2.1: the closest encloser exists and the name
+        // one label longer, the next closer name, doesn't
+        let closest_encloser = |name: &str| {
+            let encloser = self.closest_encloser(name);
+            let labels = name::labels(name);
+            let next_closer = labels[labels.len() - name::labels(&encloser).len() - 1..].join(".");
+            let mut proof = matching(&encloser);
+            proof.extend(covering(&next_closer));
+            (encloser, next_closer, proof)
+        };
+        match proof {
+            Proof::NoName(name) => {
+                let (encloser, _, mut proof) = closest_encloser(name);
+                let wildcard = format!("*.{}", encloser);
+                proof.extend(covering(wildcard.trim_end_matches('.')));
+                proof
+            }
+            Proof::NoData { name, owner } if name != owner => {
+                let (_, _, mut proof) = closest_encloser(name);
+                proof.extend(matching(owner));
+                proof
+            }
+            // with opt-out there may be no NSEC3 for an unsigned delegation,
+            // only one covering it, RFC 5155 section 7.2.4
+            Proof::NoData { name, .. } | Proof::Referral(name) => match matching(name) {
+                found if !found.is_empty() => found,
+                _ if name.eq_ignore_ascii_case(&self.name) => Vec::new(),
+                _ => closest_encloser(name).2,
+            },
+            // the wildcard's signature gives away the closest encloser
+            Proof::Wildcard(name) => {
+                let (_, next_closer, _) = closest_encloser(name);
+                covering(&next_closer).into_iter().collect()
+            }
+        }
+    }
+
+    fn nsec3_param(&self) -> Option<Nsec3Param> {
+        let param = self.lookup(&self.name, RRType::NSEC3PARAM);
+        Nsec3Param::from_data(&param.first()?.data)
+    }
+
+    // whether there are records at the name or below it, a name with only
+    // children is an empty non-terminal and still exists
+    fn exists(&self, name: &str) -> bool {
+        let suffix = format!(".{}", name.to_ascii_lowercase());
+        self.records.iter().any(|record| {
+            record.name.eq_ignore_ascii_case(name)
+                || record.name.to_ascii_lowercase().ends_with(&suffix)
+        })
+    }
+
+    // the names between the apex and the name, from the top down, the name
+    // itself last and the apex left out
+    fn below_apex(&self, name: &str) -> Vec<String> {
+        let labels = name::labels(name);
+        let depth = labels.len().saturating_sub(name::labels(&self.name).len());
+        (1..=depth)
+            .rev()
+            .map(|skip| labels[skip - 1..].join("."))
+            .collect()
+    }
+
+    // the highest zone cut above the name, or at it unless the query is for
+    // the DS records the parent side holds
+    fn delegation(&self, name: &str, r#type: RRType) -> Option<String> {
+        self.below_apex(name).into_iter().find(|cut| {
+            let parent_side = r#type == RRType::DS && cut.eq_ignore_ascii_case(name);
+            !parent_side && !self.lookup(cut, RRType::NS).is_empty()
+        })
+    }
+
+    // the longest existing ancestor of a name that doesn't exist, RFC 4592
+    // section 3.3.1
+    fn closest_encloser(&self, name: &str) -> String {
+        self.below_apex(name)
+            .into_iter()
+            .rev()
+            .skip(1)
+            .find(|ancestor| self.exists(ancestor))
+            .unwrap_or_else(|| self.name.clone())
+    }
+
+    // the wildcard at the closest encloser of a name that doesn't exist
+    fn wildcard(&self, name: &str) -> Option<String> {
+        let encloser = self.closest_encloser(name);
+        let owner = match encloser.is_empty() {
+            true => "*".to_string(),
+            false => format!("*.{}", encloser),
+        };
+        match self.exists(&owner) {
+            true => Some(owner),
+            false => None,
+        }
+    }
+
+    // the child's name servers, with their addresses when they're in the zone
+    fn add_referral(&self, response: &mut Packet, cut: &str) {
+        for ns in self.lookup(cut, RRType::NS) {
+            response.authorities.push(ns.clone());
+            let target = rdata::to_presentation(RRType::NS, &ns.data);
+            for r#type in [RRType::A, RRType::AAAA] {
+                let glue = self.lookup(&target, r#type);
+                response.additionals.extend(glue.into_iter().cloned());
+            }
+        }
+    }
+
+    // negative answers are cached for the lesser of the SOA's TTL and its
+    // minimum field, RFC 2308 section 5
+    fn add_soa(&self, response: &mut Packet) {
+        if let Some(soa) = self.lookup(&self.name, RRType::SOA).first() {
+            let mut soa = (*soa).clone();
+            if let Some(fields) = Soa::from_data(&soa.data) {
+                soa.ttl = soa.ttl.min(fields.minimum);
+            }
+            response.authorities.push(soa);
+        }
+    }
+}
+
+// names are kept without the trailing dot, and the root is empty
+fn absolute(name: &str, origin: &str) -> Result<String, String> {
+    let name = match (name, name.strip_suffix('.')) {
+        ("@", _) => origin.to_string(),
+        (_, Some(name)) => name.to_string(),
+        _ if origin.is_empty() => name.to_string(),
+        _ => format!("{}.{}", name, origin),
+    };
+    check_name(&name)?;
+    Ok(name)
+}
+
+// RFC 1035 section 2.3.4: labels of at most 63 bytes and names of at most 255
+// once they're on the wire, where each label has a length byte and the root
+// one more
+fn check_name(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Ok(());
+    }
+    let mut len = 1;
+    for label in name.split('.') {
+        if label.is_empty() {
+            return Err(format!("empty label in {:?}", name));
+        }
+        if label.len() > 63 {
+            return Err(format!("label {:?} is longer than 63 bytes", label));
+        }
+        len += 1 + label.len();
+    }
+    match len {
+        0..=255 => Ok(()),
+        len => Err(format!("name {:?} is {} bytes, more than 255", name, len)),
+    }
+}
+
+// seconds, or BIND's units like 1h30m
+fn parse_ttl(text: &str) -> Option<u32> {
+    if text.chars().all(|c| c.is_ascii_digit()) {
+        return text.parse().ok();
+    }
+    if !text.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    let mut total: u32 = 0;
+    let mut number: u32 = 0;
+    for c in text.chars() {
+        if let Some(digit) = c.to_digit(10) {
+            number = number.checked_mul(10)?.checked_add(digit)?;
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            'w' => 604800,
+            _ => return None,
+        };
+        total = total.checked_add(number.checked_mul(unit)?)?;
+        number = 0;
+    }
+    total.checked_add(number)
+}
+
+fn to_rdata(r#type: RRType, fields: &[&Field], origin: &str) -> Result<Vec<u8>, String> {
+    // RFC 3597's generic form works for every type
+    if fields
+        .first()
+        .is_some_and(|field| field.text == "\\#" && !field.quoted)
+    {
+        let len: usize = fields
+            .get(1)
+            .and_then(|field| field.text.parse().ok())
+            .ok_or("generic data needs a length")?;
+        let hex: String = fields[2..]
+            .iter()
+            .map(|field| field.text.as_str())
+            .collect();
+        let data = from_hex(&hex).ok_or("invalid hex in generic data")?;
+        if data.len() != len {
+            return Err(format!("generic data is {} bytes, not {}", data.len(), len));
+        }
+        return Ok(data);
+    }
+
+    let mut fields = fields.iter();
+    let mut next = |what: &str| {
+        fields
+            .next()
+            .map(|field| field.text.as_str())
+            .ok_or_else(|| format!("{} record is missing its {}", r#type, what))
+    };
+    let name = |text: &str| absolute(text, origin).map(|name| name::to_wire(&name));
+    let number = |text: &str| parse_ttl(text).ok_or_else(|| format!("invalid number {:?}", text));
+
+    let data = match r#type {
+        RRType::A => {
+            let text = next("address")?;
+            let address: Ipv4Addr = text
+                .parse()
+                .map_err(|_| format!("invalid IPv4 address {:?}", text))?;
+            address.octets().to_vec()
+        }
+        RRType::AAAA => {
+            let text = next("address")?;
+            let address: Ipv6Addr = text
+                .parse()
+                .map_err(|_| format!("invalid IPv6 address {:?}", text))?;
+            address.octets().to_vec()
+        }
+        RRType::NS | RRType::CNAME | RRType::PTR | RRType::MB | RRType::MG | RRType::MR => {
+            name(next("name")?)?
+        }
+        RRType::MX => {
+            let preference: u16 = next("preference")?
+                .parse()
+                .map_err(|_| "invalid MX preference".to_string())?;
+            let mut data = preference.to_be_bytes().to_vec();
+            data.extend(name(next("exchange")?)?);
+            data
+        }
+        RRType::SOA => {
+            let mut data = name(next("primary")?)?;
+            data.extend(name(next("mailbox")?)?);
+            for what in ["serial", "refresh", "retry", "expire", "minimum"] {
+                data.extend(number(next(what)?)?.to_be_bytes());
+            }
+            data
+        }
+        RRType::TXT => {
+            let mut data = Vec::new();
+            for field in fields.by_ref() {
+                if field.text.len() > 255 {
+                    return Err("TXT string is longer than 255 bytes".to_string());
+                }
+                data.push(field.text.len() as u8);
+                data.extend(field.text.as_bytes());
+            }
+            if data.is_empty() {
+                return Err("TXT record has no strings".to_string());
+            }
+            data
+        }
+        RRType::DS => {
+            let mut data = u16_field(next("key tag")?, "key tag")?
+                .to_be_bytes()
+                .to_vec();
+            data.push(algorithm(next("algorithm")?)?);
+            data.push(u8_field(next("digest type")?, "digest type")?);
+            let digest: String = fields.by_ref().map(|field| field.text.as_str()).collect();
+            data.extend(from_hex(&digest).ok_or("invalid hex in DS digest")?);
+            data
+        }
+        RRType::DNSKEY => {
+            let mut data = u16_field(next("flags")?, "flags")?.to_be_bytes().to_vec();
+            data.push(u8_field(next("protocol")?, "protocol")?);
+            data.push(algorithm(next("algorithm")?)?);
+            let key: String = fields.by_ref().map(|field| field.text.as_str()).collect();
+            data.extend(base64::decode(&key).ok_or("invalid base64 in DNSKEY")?);
+            data
+        }
+        RRType::RRSIG => {
+            let covered = next("type covered")?;
+            let covered: RRType = covered
+                .parse()
+                .map_err(|_| format!("unknown type {:?}", covered))?;
+            let mut data = covered.to_value().to_be_bytes().to_vec();
+            data.push(algorithm(next("algorithm")?)?);
+            data.push(u8_field(next("labels")?, "labels")?);
+            data.extend(number(next("original TTL")?)?.to_be_bytes());
+            for what in ["expiration", "inception"] {
+                let text = next(what)?;
+                let time = rdata::from_timestamp(text)
+                    .ok_or_else(|| format!("invalid {} {:?}", what, text))?;
+                data.extend(time.to_be_bytes());
+            }
+            data.extend(u16_field(next("key tag")?, "key tag")?.to_be_bytes());
+            data.extend(name(next("signer")?)?);
+            let signature: String = fields.by_ref().map(|field| field.text.as_str()).collect();
+            data.extend(base64::decode(&signature).ok_or("invalid base64 in RRSIG")?);
+            data
+        }
+        RRType::NSEC => {
+            let mut data = name(next("next name")?)?;
+            data.extend(dnssec::type_bitmap(&types(fields.by_ref())?));
+            data
+        }
+        RRType::NSEC3 | RRType::NSEC3PARAM => {
+            let mut data = vec![
+                u8_field(next("hash algorithm")?, "hash algorithm")?,
+                u8_field(next("flags")?, "flags")?,
+            ];
+            data.extend(u16_field(next("iterations")?, "iterations")?.to_be_bytes());
+            let salt = match next("salt")? {
+                "-" => Vec::new(),
+                text => from_hex(text).ok_or_else(|| format!("invalid salt {:?}", text))?,
+            };
+            data.push(salt.len() as u8);
+            data.extend(salt);
+            if r#type == RRType::NSEC3 {
+                let text = next("next hashed name")?;
+                let hash = rdata::from_base32hex(text)
+                    .ok_or_else(|| format!("invalid hashed name {:?}", text))?;
+                data.push(hash.len() as u8);
+                data.extend(hash);
+                data.extend(dnssec::type_bitmap(&types(fields.by_ref())?));
+            }
+            data
+        }
+        _ => {
+            return Err(format!(
+                "{} records can only be given in the generic form",
+                r#type
+            ))
+        }
+    };
+    if let Some(field) = fields.next() {
+        return Err(format!("unexpected {:?} after {} data", field.text, r#type));
+    }
+    Ok(data)
+}
+
+fn u8_field(text: &str, what: &str) -> Result<u8, String> {
+    text.parse()
+        .map_err(|_| format!("invalid {} {:?}", what, text))
+}
+
+fn u16_field(text: &str, what: &str) -> Result<u16, String> {
+    text.parse()
+        .map_err(|_| format!("invalid {} {:?}", what, text))
+}
+
+// DNSSEC algorithms by number, or by mnemonic for the ones we implement
+fn algorithm(text: &str) -> Result<u8, String> {
+    match text.parse() {
+        Ok(number) => Ok(number),
+        Err(_) => text.parse::<Algorithm>().map(Algorithm::to_value),
+    }
+}
+
+// the type list that ends NSEC and NSEC3 records
+fn types<'a>(fields: impl Iterator<Item = &'a &'a Field>) -> Result<Vec<RRType>, String> {
+    fields
+        .map(|field| {
+            field
+                .text
+                .parse()
+                .map_err(|_| format!("unknown type {:?}", field.text))
+        })
+        .collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// splits the text into records, joining lines inside parentheses and
+// dropping comments
+fn entries(text: &str) -> Result<Vec<Entry>, String> {
+    let mut entries = Vec::new();
+    let mut current: Option<Entry> = None;
+    let mut depth = 0;
+
+    for (i, line) in text.lines().enumerate() {
+        let line_no = i + 1;
+        if depth == 0 {
+            if let Some(entry) = current.take() {
+                if !entry.fields.is_empty() {
+                    entries.push(entry);
+                }
+            }
+            current = Some(Entry {
+                line: line_no,
+                indented: line.starts_with([' ', '\t']),
+                fields: Vec::new(),
+            });
+        }
+        let entry = current.as_mut().unwrap();
+
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                ';' => break,
+                '(' => depth += 1,
+                ')' => {
+                    if depth == 0 {
+                        return Err(format!("line {}: unbalanced parenthesis", line_no));
+                    }
+                    depth -= 1;
+                }
+                '"' => {
+                    let mut text = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('"') => break,
+                            Some('\\') => text.extend(chars.next()),
+                            Some(c) => text.push(c),
+                            None => return Err(format!("line {}: unterminated string", line_no)),
+                        }
+                    }
+                    entry.fields.push(Field { text, quoted: true });
+                }
+                c if c.is_whitespace() => {}
+                c => {
+                    let mut text = c.to_string();
+                    while let Some(&c) = chars.peek() {
+                        if c.is_whitespace() || matches!(c, ';' | '(' | ')' | '"') {
+                            break;
+                        }
+                        text.push(c);
+                        chars.next();
+                    }
+                    entry.fields.push(Field {
+                        text,
+                        quoted: false,
+                    });
+                }
+            }
+        }
+    }
+    if depth != 0 {
+        return Err("unbalanced parenthesis at the end of the file".to_string());
+    }
+    if let Some(entry) = current {
+        if !entry.fields.is_empty() {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rdata;
+
+    const ZONE: &str = "$ORIGIN example.com.
+$TTL 1h
+@   IN  SOA ns1 hostmaster (
+        2024010101 ; serial
+        7200 3600 1209600 300 )
+    IN  NS  ns1
+ns1 300 IN A 192.0.2.53
+www A 192.0.2.1
+    AAAA 2001:db8::1
+mail.example.net. IN 60 MX 10 mx
+txt TXT \"hello world\" two ; comment
+raw TYPE1 \\# 4 c0000201
+";
+
+    #[test]
+    fn test_parse_zone() {
+        let records = parse(ZONE, ".").unwrap();
+        let presented: Vec<String> = records
+            .iter()
+            .map(|record| {
+                format!(
+                    "{} {} {} {}",
+                    record.name,
+                    record.ttl,
+                    record.r#type,
+                    rdata::to_presentation(record.r#type, &record.data)
+                )
+            })
+            .collect();
+        assert_eq!(
+            presented,
+            vec![
+                "example.com 3600 SOA ns1.example.com. hostmaster.example.com. 2024010101 7200 3600 1209600 300",
+                "example.com 3600 NS ns1.example.com.",
+                "ns1.example.com 300 A 192.0.2.53",
+                "www.example.com 3600 A 192.0.2.1",
+                "www.example.com 3600 AAAA 2001:db8::1",
+                "mail.example.net 60 MX 10 mx.example.com.",
+                "txt.example.com 3600 TXT \"hello world\" \"two\"",
+                "raw.example.com 3600 A 192.0.2.1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            parse("www A 192.0.2.1", "example.com").unwrap_err(),
+            "line 1: record has no TTL and there is no $TTL"
+        );
+        assert!(parse("$TTL 60\nwww A 192.0.2", "example.com")
+            .unwrap_err()
+            .starts_with("line 2: invalid IPv4"));
+        assert!(parse("$TTL 60\nwww IN SOA ( a b", "example.com").is_err());
+        assert!(parse("$TTL 60\n A 192.0.2.1", "example.com").is_err());
+
+        let long = "a".repeat(64);
+        assert_eq!(
+            parse(&format!("$TTL 60\n{} A 192.0.2.1", long), "example.com").unwrap_err(),
+            format!("line 2: label {:?} is longer than 63 bytes", long)
+        );
+        assert!(
+            parse(&format!("$TTL 60\nwww CNAME {}.", long), "example.com")
+                .unwrap_err()
+                .contains("longer than 63 bytes")
+        );
+        // four labels of 63 bytes make 257 on the wire
+        let label = "a".repeat(63);
+        let name = [label.as_str(); 4].join(".");
+        assert!(parse(&format!("$TTL 60\n{}. A 192.0.2.1", name), "")
+            .unwrap_err()
+            .contains("more than 255"));
+        assert!(parse("$TTL 60\nwww NS a..example.com.", "example.com")
+            .unwrap_err()
+            .contains("empty label"));
+        assert!(parse("$TTL 60\n$ORIGIN x..example.\n", "example.com").is_err());
+        let name = [label.as_str(), &label, &label, &"a".repeat(61)].join(".");
+        assert!(parse(&format!("$TTL 60\n{}. A 192.0.2.1", name), "").is_ok());
+    }
+
+    #[test]
+    fn test_parse_dnssec() {
+        let text = "$TTL 3600
+@ DS 20326 RSASHA256 2 ( E06D44B80B8F1D39A95C0B0D7C65D084
+                     58E880409BBC683457104237C7F8EC8D )
+@ DNSKEY 257 3 13 ( mdsswUyr3DPW132mOi8V9xESWE8jTo0d
+                    xCjjnopKl+GqJxpVXckHAeF+KkxLbxILfDLUT0rAK9iUzy1L53eKGQ== )
+@ RRSIG DNSKEY 13 2 3600 20231114221320 1699000000 2371 example.com. c2ln
+www NSEC example.com. A RRSIG NSEC TYPE1234
+0p9mhaveqvm6t7vbl5lop2u3t2rp3tom NSEC3 1 1 12 aabbccdd ( 2t7b4g4vsa5smi47k61mv5bv1a22bojr MX DNSKEY NS SOA NSEC3PARAM RRSIG )
+@ NSEC3PARAM 1 0 12 -
+";
+        let records = parse(text, "example.com").unwrap();
+        let presented: Vec<String> = records
+            .iter()
+            .map(|record| rdata::to_presentation(record.r#type, &record.data))
+            .collect();
+        assert_eq!(
+            presented,
+            [
+                "20326 8 2 E06D44B80B8F1D39A95C0B0D7C65D08458E880409BBC683457104237C7F8EC8D",
+                "257 3 13 mdsswUyr3DPW132mOi8V9xESWE8jTo0dxCjjnopKl+GqJxpVXckHAeF+KkxLbxILfDLUT0rAK9iUzy1L53eKGQ==",
+                "DNSKEY 13 2 3600 20231114221320 20231103082640 2371 example.com. c2ln",
+                "example.com. A RRSIG NSEC TYPE1234",
+                "1 1 12 AABBCCDD 2T7B4G4VSA5SMI47K61MV5BV1A22BOJR NS SOA MX RRSIG DNSKEY NSEC3PARAM",
+                "1 0 12 -",
+            ]
+        );
+        // what's presented parses back the same
+        for (record, presented) in records.iter().zip(&presented) {
+            let line = format!("$TTL 60\n@ {} {}", record.r#type, presented);
+            let reparsed = parse(&line, "example.com").unwrap();
+            assert_eq!(reparsed[0].data, record.data, "{}", line);
+        }
+
+        assert!(parse("$TTL 60\n@ DS 1 8 2 XYZ", "example.com")
+            .unwrap_err()
+            .contains("invalid hex"));
+        assert!(parse("$TTL 60\n@ DNSKEY 257 3 RSAMD5 AAAA", "example.com")
+            .unwrap_err()
+            .contains("unsupported algorithm"));
+        assert!(parse("$TTL 60\n@ NSEC3PARAM 1 0 1 -", "example.com").is_ok());
+    }
+
+    #[test]
+    fn test_zone_lookup() {
+        let zone = Zone::from_text("Example.com.", ZONE).unwrap();
+        assert_eq!(zone.name, "example.com");
+        assert!(zone.contains("WWW.example.com."));
+        assert!(zone.contains("example.com"));
+        assert!(!zone.contains("badexample.com"));
+        assert_eq!(zone.lookup("www.EXAMPLE.com", RRType::A).len(), 1);
+        assert!(zone.lookup("www.example.com", RRType::MX).is_empty());
+    }
+
+    fn respond(zone: &Zone, name: &str, r#type: RRType) -> (Packet, Option<String>) {
+        zone.respond(&crate::client::build_query(name, r#type, RRClass::IN))
+    }
+
+    fn presented(records: &[Answer]) -> Vec<String> {
+        records
+            .iter()
+            .map(|record| {
+                format!(
+                    "{} {} {}",
+                    record.name,
+                    record.r#type,
+                    rdata::to_presentation(record.r#type, &record.data)
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_respond() {
+        let text = format!(
+            "{}alias CNAME www\nout CNAME www.example.net.\n*.wild A 192.0.2.9\n\
+             deep.ent TXT here\nsub NS ns.sub\nns.sub A 192.0.2.99\n",
+            ZONE
+        );
+        let zone = Zone::from_text("example.com", &text).unwrap();
+
+        let (response, wildcard) = respond(&zone, "WWW.example.com", RRType::A);
+        assert!(response.header.is_authoritative);
+        assert_eq!(
+            presented(&response.answers),
+            ["WWW.example.com A 192.0.2.1"]
+        );
+        assert_eq!(wildcard, None);
+
+        // the CNAME is followed inside the zone but not out of it
+        let (response, _) = respond(&zone, "alias.example.com", RRType::AAAA);
+        assert_eq!(
+            presented(&response.answers),
+            [
+                "alias.example.com CNAME www.example.com.",
+                "www.example.com AAAA 2001:db8::1"
+            ]
+        );
+        let (response, _) = respond(&zone, "out.example.com", RRType::A);
+        assert_eq!(response.answers.len(), 1);
+
+        // no data and no name both come with the SOA, its TTL capped at the
+        // minimum
+        let (response, _) = respond(&zone, "www.example.com", RRType::MX);
+        assert_eq!(response.rcode(), RCode::NOERROR as u16);
+        assert!(response.answers.is_empty());
+        assert_eq!(response.authorities[0].r#type, RRType::SOA);
+        assert_eq!(response.authorities[0].ttl, 300);
+        let (response, _) = respond(&zone, "nothing.example.com", RRType::A);
+        assert_eq!(response.rcode(), RCode::NXDOMAIN as u16);
+        assert_eq!(response.authorities[0].r#type, RRType::SOA);
+        // an empty non-terminal exists
+        let (response, _) = respond(&zone, "ent.example.com", RRType::A);
+        assert_eq!(response.rcode(), RCode::NOERROR as u16);
+
+        let (response, wildcard) = respond(&zone, "a.b.wild.example.com", RRType::A);
+        assert_eq!(
+            presented(&response.answers),
+            ["a.b.wild.example.com A 192.0.2.9"]
+        );
+        assert_eq!(wildcard.as_deref(), Some("*.wild.example.com"));
+
+        // below a zone cut there's only a referral, with glue
+        let (response, _) = respond(&zone, "www.sub.example.com", RRType::A);
+        assert!(!response.header.is_authoritative);
+        assert!(response.answers.is_empty());
+        assert_eq!(
+            presented(&response.authorities),
+            ["sub.example.com NS ns.sub.example.com."]
+        );
+        assert_eq!(
+            presented(&response.additionals),
+            ["ns.sub.example.com A 192.0.2.99"]
+        );
+        // except for DS, which the parent answers
+        let (response, _) = respond(&zone, "sub.example.com", RRType::DS);
+        assert!(response.header.is_authoritative);
+        assert_eq!(response.authorities[0].r#type, RRType::SOA);
+    }
+
+    #[test]
+    fn test_soa() {
+        let zone = Zone::from_text("example.com", ZONE).unwrap();
+        let soa = zone.soa().unwrap();
+        assert_eq!(soa.serial, 2024010101);
+        assert_eq!((soa.refresh, soa.retry, soa.expire), (7200, 3600, 1209600));
+        assert_eq!(soa.minimum, 300);
+        assert_eq!(Soa::from_data(&[0; 19]), None);
+    }
+
+    #[test]
+    fn test_ttl_units() {
+        assert_eq!(parse_ttl("300"), Some(300));
+        assert_eq!(parse_ttl("1h30m"), Some(5400));
+        assert_eq!(parse_ttl("1W"), Some(604800));
+        assert_eq!(parse_ttl("IN"), None);
+    }
+}
@@ -0,0 +1,1321 @@
+use std::fmt;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use dns_rs_lib::acl::{Acl, Element, Policy};
+use dns_rs_lib::base64;
+use dns_rs_lib::blocklist::{self, Mode, Source};
+use dns_rs_lib::cidr::Cidr;
+use dns_rs_lib::client_subnet;
+use dns_rs_lib::doh;
+use dns_rs_lib::forward;
+use dns_rs_lib::name;
+use dns_rs_lib::querylog::{self, Format};
+use dns_rs_lib::rpz::PolicyZone;
+use dns_rs_lib::rrl;
+use dns_rs_lib::sign;
+use dns_rs_lib::tcp;
+use dns_rs_lib::tls;
+use dns_rs_lib::tsig;
+use dns_rs_lib::zone;
+use serde::Deserialize;
+
+pub const USAGE: &str = "usage: dns-rs check-config FILE";
+
+const EXIT_ERROR: i32 = 1;
+
+// where Prometheus exporters for DNS servers usually listen
+pub const DEFAULT_METRICS_PORT: u16 = 9153;
+
+// the file as written, before it's checked and turned into a Config
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawConfig {
+    listener: Vec<RawListener>,
+    // TSIG keys, which ACLs and views refer to by name
+    key: Vec<RawKey>,
+    zone: Vec<RawZone>,
+    // zones go in the views once there are any
+    view: Vec<RawView>,
+    rpz: Vec<RawPolicyZone>,
+    // blocking is off unless the table is there
+    blocklist: Option<RawBlocklist>,
+    resolver: RawResolver,
+    acl: RawAcl,
+    // response rate limiting is off unless the table is there
+    rate_limit: Option<RawRateLimit>,
+    logging: RawLogging,
+    metrics: RawMetrics,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawListener {
+    address: String,
+    port: Option<u16>,
+    transport: Option<String>,
+    tls_certificate: Option<String>,
+    tls_key: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawKey {
+    name: String,
+    // hmac-sha256 unless given
+    algorithm: Option<String>,
+    // base64, as tsig-keygen prints it
+    secret: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawZone {
+    name: String,
+    #[serde(rename = "type")]
+    kind: String,
+    file: Option<String>,
+    #[serde(default)]
+    masters: Vec<String>,
+    #[serde(default)]
+    notify: Vec<String>,
+    // a directory with the zone's DNSSEC keys, signing it as it's served
+    keys: Option<String>,
+    #[serde(default)]
+    nsec3: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawView {
+    name: String,
+    #[serde(default)]
+    match_clients: Vec<String>,
+    #[serde(default)]
+    match_destinations: Vec<String>,
+    #[serde(default)]
+    match_keys: Vec<String>,
+    recursion: Option<bool>,
+    // the [acl] lists of the same name unless given
+    allow_query: Option<Vec<String>>,
+    allow_recursion: Option<Vec<String>>,
+    #[serde(default)]
+    zone: Vec<RawZone>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawPolicyZone {
+    name: String,
+    file: Option<String>,
+    #[serde(default)]
+    masters: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawBlocklist {
+    // nxdomain, null or sinkhole
+    mode: Option<String>,
+    // an IPv4 address, an IPv6 address or one of each
+    sinkhole: Vec<String>,
+    ttl: Option<u32>,
+    // hosts files, domain lists or adblock lists
+    block: Vec<String>,
+    allow: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawResolver {
+    forwarders: Vec<String>,
+    forward_zone: Vec<RawForwardZone>,
+    // in seconds
+    timeout: Option<f64>,
+    retries: Option<u32>,
+    probe_interval: Option<f64>,
+    validate: bool,
+    // DS or DNSKEY records in a master file, the root's by default
+    trust_anchor: Option<String>,
+    client_subnet: bool,
+    // prefix lengths, RFC 7871's recommended /24 and /56 by default
+    client_subnet_v4: Option<u8>,
+    client_subnet_v6: Option<u8>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawForwardZone {
+    name: String,
+    forwarders: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawAcl {
+    query: Option<Vec<String>>,
+    recursion: Option<Vec<String>>,
+    transfer: Option<Vec<String>>,
+    notify: Option<Vec<String>>,
+    update: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawRateLimit {
+    responses_per_second: Option<u32>,
+    nxdomains_per_second: Option<u32>,
+    errors_per_second: Option<u32>,
+    slip: Option<u32>,
+    // in seconds
+    window: Option<f64>,
+    ipv4_prefix: Option<u8>,
+    ipv6_prefix: Option<u8>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawLogging {
+    query_log: Option<String>,
+    format: Option<String>,
+    sample_rate: Option<f64>,
+    subnets: Vec<String>,
+    max_bytes: Option<u64>,
+    keep: Option<usize>,
+    dnstap: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawMetrics {
+    listen: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Udp,
+    Tcp,
+    Tls,
+    Https,
+    Quic,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Listener {
+    pub address: SocketAddr,
+    pub transport: Transport,
+    // the certificate chain and private key, for the encrypted transports
+    pub tls: Option<(PathBuf, PathBuf)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZoneKind {
+    Primary,
+    Secondary,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZoneConfig {
+    pub name: String,
+    pub kind: ZoneKind,
+    // a primary's zone file, or where a secondary keeps its copy
+    pub file: Option<PathBuf>,
+    pub masters: Vec<SocketAddr>,
+    pub notify: Vec<SocketAddr>,
+    pub keys: Option<PathBuf>,
+    // NSEC3 rather than NSEC for signed zones
+    pub nsec3: bool,
+}
+
+// the queries a view answers and what it answers them from; every match
+// list given has to match
+#[derive(Debug, Clone, PartialEq)]
+pub struct ViewConfig {
+    pub name: String,
+    pub match_clients: Vec<Cidr>,
+    pub match_destinations: Vec<Cidr>,
+    pub match_keys: Vec<String>,
+    pub recursion: bool,
+    pub allow_query: Acl,
+    pub allow_recursion: Acl,
+    pub zones: Vec<ZoneConfig>,
+}
+
+// a response policy zone read from its file or transferred from its
+// masters, both of which are checked again for changes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyZoneConfig {
+    pub name: String,
+    pub file: Option<PathBuf>,
+    pub masters: Vec<SocketAddr>,
+}
+
+// lists of names to block, read again as they change
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlocklistConfig {
+    pub mode: Mode,
+    pub ttl: u32,
+    pub sources: Vec<Source>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Resolver {
+    pub forwarders: Vec<SocketAddr>,
+    pub forward_zones: Vec<(String, Vec<SocketAddr>)>,
+    pub timeout: Duration,
+    pub retries: u32,
+    pub probe_interval: Duration,
+    pub validate: bool,
+    pub trust_anchor: Option<PathBuf>,
+    // how much of a client's address goes upstream, as IPv4 and IPv6
+    // prefix lengths
+    pub client_subnet: Option<(u8, u8)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Logging {
+    pub query_log: Option<PathBuf>,
+    pub format: Format,
+    pub sample_rate: f64,
+    pub subnets: Vec<Cidr>,
+    pub max_bytes: u64,
+    pub keep: usize,
+    // a file, or unix:PATH for a collector's socket
+    pub dnstap: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub listeners: Vec<Listener>,
+    pub keys: Vec<tsig::Key>,
+    pub zones: Vec<ZoneConfig>,
+    // in order, the first one a query matches answers it
+    pub views: Vec<ViewConfig>,
+    // in order, an earlier zone's policy beats a later one's
+    pub rpz: Vec<PolicyZoneConfig>,
+    pub blocklist: Option<BlocklistConfig>,
+    pub resolver: Resolver,
+    pub acl: Policy,
+    pub rate_limit: Option<rrl::Config>,
+    pub logging: Logging,
+    pub metrics: Option<SocketAddr>,
+}
+
+impl Transport {
+    pub fn default_port(self) -> u16 {
+        match self {
+            Transport::Udp | Transport::Tcp => tcp::PORT,
+            Transport::Tls | Transport::Quic => tcp::TLS_PORT,
+            Transport::Https => doh::PORT,
+        }
+    }
+
+    pub fn uses_tls(self) -> bool {
+        matches!(self, Transport::Tls | Transport::Https | Transport::Quic)
+    }
+
+    // listeners on the same address clash if they need the same socket
+    fn over_udp(self) -> bool {
+        matches!(self, Transport::Udp | Transport::Quic)
+    }
+}
+
+impl FromStr for Transport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "udp" => Ok(Transport::Udp),
+            "tcp" => Ok(Transport::Tcp),
+            "tls" => Ok(Transport::Tls),
+            "https" => Ok(Transport::Https),
+            "quic" => Ok(Transport::Quic),
+            _ => Err(format!(
+                "unknown transport {:?}, expected udp, tcp, tls, https or quic",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Transport::Udp => "udp",
+            Transport::Tcp => "tcp",
+            Transport::Tls => "tls",
+            Transport::Https => "https",
+            Transport::Quic => "quic",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for ZoneKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ZoneKind::Primary => write!(f, "primary"),
+            ZoneKind::Secondary => write!(f, "secondary"),
+        }
+    }
+}
+
+impl Config {
+    // relative paths in the file are taken from the directory it's in
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("couldn't read it: {}", e))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        Config::parse(&text, dir)
+    }
+
+    // every problem found is reported, one per line, starting with the key
+    // it's about
+    pub fn parse(text: &str, dir: &Path) -> Result<Config, String> {
+        let raw: RawConfig = toml::from_str(text).map_err(|e| syntax_error(text, &e))?;
+        let mut checker = Checker {
+            dir,
+            keys: Vec::new(),
+            errors: Vec::new(),
+        };
+        let config = checker.config(raw);
+        match checker.errors.is_empty() {
+            true => Ok(config),
+            false => Err(checker.errors.join("\n")),
+        }
+    }
+}
+
+// the parser's own message spans several lines to draw the spot, the line
+// itself is enough to find the key
+fn syntax_error(text: &str, error: &toml::de::Error) -> String {
+    let Some(span) = error.span() else {
+        return error.message().to_string();
+    };
+    let line = text[..span.start].matches('\n').count();
+    let source = text.lines().nth(line).unwrap_or("").trim();
+    format!("line {}: {}: {}", line + 1, source, error.message())
+}
+
+struct Checker<'a> {
+    dir: &'a Path,
+    // the names of the keys defined, for the ACLs and views that use them
+    keys: Vec<String>,
+    errors: Vec<String>,
+}
+
+impl Checker<'_> {
+    fn error(&mut self, key: &str, message: impl fmt::Display) {
+        self.errors.push(format!("{}: {}", key, message));
+    }
+
+    fn config(&mut self, raw: RawConfig) -> Config {
+        if raw.listener.is_empty() {
+            self.error("listener", "at least one listener is needed");
+        }
+        let mut listeners: Vec<Listener> = Vec::new();
+        for (i, raw) in raw.listener.iter().enumerate() {
+            let key = format!("listener[{}]", i);
+            if let Some(listener) = self.listener(&key, raw) {
+                let clash = listeners.iter().any(|other| {
+                    other.address == listener.address
+                        && other.transport.over_udp() == listener.transport.over_udp()
+                });
+                if clash {
+                    self.error(
+                        &key,
+                        format!("{} is already in use by another listener", listener.address),
+                    );
+                }
+                listeners.push(listener);
+            }
+        }
+
+        let mut keys: Vec<tsig::Key> = Vec::new();
+        for (i, raw) in raw.key.iter().enumerate() {
+            let key = format!("key[{}]", i);
+            if let Some(tsig_key) = self.key(&key, raw) {
+                if keys.iter().any(|other| other.name == tsig_key.name) {
+                    self.error(&key, format!("key {} is defined twice", tsig_key.name));
+                }
+                keys.push(tsig_key);
+            }
+        }
+        self.keys = keys.iter().map(|key| key.name.clone()).collect();
+
+        let zones = self.zones("zone", &raw.zone);
+        if !raw.zone.is_empty() && !raw.view.is_empty() {
+            self.error("zone", "zones go in the views once there are any");
+        }
+        let acl = self.acl(&raw.acl);
+        let mut views: Vec<ViewConfig> = Vec::new();
+        for (i, raw) in raw.view.iter().enumerate() {
+            let key = format!("view[{}]", i);
+            let view = self.view(&key, raw, &acl);
+            if views.iter().any(|other| other.name == view.name) {
+                self.error(&key, format!("view {} is defined twice", view.name));
+            }
+            views.push(view);
+        }
+
+        let mut rpz: Vec<PolicyZoneConfig> = Vec::new();
+        for (i, raw) in raw.rpz.iter().enumerate() {
+            let key = format!("rpz[{}]", i);
+            if let Some(zone) = self.policy_zone(&key, raw) {
+                if rpz.iter().any(|other| other.name == zone.name) {
+                    self.error(&key, format!("policy zone {} is defined twice", zone.name));
+                }
+                rpz.push(zone);
+            }
+        }
+
+        Config {
+            listeners,
+            keys,
+            zones,
+            views,
+            rpz,
+            blocklist: raw.blocklist.as_ref().map(|raw| self.blocklist(raw)),
+            resolver: self.resolver(&raw.resolver),
+            acl,
+            rate_limit: raw.rate_limit.as_ref().map(|raw| self.rate_limit(raw)),
+            logging: self.logging(&raw.logging),
+            metrics: raw
+                .metrics
+                .listen
+                .as_deref()
+                .and_then(|listen| self.address("metrics.listen", listen, DEFAULT_METRICS_PORT)),
+        }
+    }
+
+    fn listener(&mut self, key: &str, raw: &RawListener) -> Option<Listener> {
+        let transport = match raw
+            .transport
+            .as_deref()
+            .unwrap_or("udp")
+            .parse::<Transport>()
+        {
+            Ok(transport) => transport,
+            Err(e) => {
+                self.error(&format!("{}.transport", key), e);
+                return None;
+            }
+        };
+        let address = match raw.address.parse::<IpAddr>() {
+            Ok(address) => address,
+            Err(_) => {
+                self.error(
+                    &format!("{}.address", key),
+                    format!("invalid address {:?}, the port goes in port", raw.address),
+                );
+                return None;
+            }
+        };
+        let port = raw.port.unwrap_or(transport.default_port());
+
+        let tls = match (transport.uses_tls(), &raw.tls_certificate, &raw.tls_key) {
+            (true, Some(certificate), Some(private_key)) => {
+                let certificate = self.file(&format!("{}.tls_certificate", key), certificate);
+                let private_key = self.file(&format!("{}.tls_key", key), private_key);
+                let (certificate, private_key) = (certificate?, private_key?);
+                // otherwise a key that doesn't go with the certificate only
+                // shows up once the server starts
+                if let Err(e) = tls::server_config(&certificate, &private_key, &[]) {
+                    self.error(&format!("{}.tls_certificate", key), e);
+                    return None;
+                }
+                Some((certificate, private_key))
+            }
+            (true, certificate, _) => {
+                let missing = match certificate {
+                    Some(_) => "tls_key",
+                    None => "tls_certificate",
+                };
+                self.error(
+                    &format!("{}.{}", key, missing),
+                    format!("needed for {} listeners", transport),
+                );
+                return None;
+            }
+            (false, None, None) => None,
+            (false, certificate, _) => {
+                let extra = match certificate {
+                    Some(_) => "tls_certificate",
+                    None => "tls_key",
+                };
+                self.error(
+                    &format!("{}.{}", key, extra),
+                    format!("not used by {} listeners", transport),
+                );
+                return None;
+            }
+        };
+        Some(Listener {
+            address: SocketAddr::new(address, port),
+            transport,
+            tls,
+        })
+    }
+
+    fn key(&mut self, key: &str, raw: &RawKey) -> Option<tsig::Key> {
+        let name = self.name(&format!("{}.name", key), &raw.name);
+        let algorithm = match raw.algorithm.as_deref() {
+            None => Some(tsig::Algorithm::HmacSha256),
+            Some(algorithm) => match algorithm.parse() {
+                Ok(algorithm) => Some(algorithm),
+                Err(e) => {
+                    self.error(&format!("{}.algorithm", key), e);
+                    None
+                }
+            },
+        };
+        let secret = match base64::decode(&raw.secret) {
+            Some(secret) if !secret.is_empty() => Some(secret),
+            _ => {
+                self.error(&format!("{}.secret", key), "expected the secret in base64");
+                None
+            }
+        };
+        Some(tsig::Key::new(&name?, algorithm?, secret?))
+    }
+
+    // a list of zones, each named once
+    fn zones(&mut self, key: &str, raws: &[RawZone]) -> Vec<ZoneConfig> {
+        let mut zones: Vec<ZoneConfig> = Vec::new();
+        for (i, raw) in raws.iter().enumerate() {
+            let key = format!("{}[{}]", key, i);
+            if let Some(zone) = self.zone(&key, raw) {
+                if zones.iter().any(|other| other.name == zone.name) {
+                    self.error(&key, format!("zone {} is defined twice", zone.name));
+                }
+                zones.push(zone);
+            }
+        }
+        zones
+    }
+
+    // a view's ACLs and recursion default to the server's own
+    fn view(&mut self, key: &str, raw: &RawView, acl: &Policy) -> ViewConfig {
+        let match_keys = raw
+            .match_keys
+            .iter()
+            .enumerate()
+            .filter_map(|(i, name)| {
+                let key = format!("{}.match_keys[{}]", key, i);
+                let name = self.name(&key, name)?;
+                self.known_key(&key, &name);
+                Some(name)
+            })
+            .collect();
+        let allow_query = match &raw.allow_query {
+            Some(entries) => self.acl_list(&format!("{}.allow_query", key), entries),
+            None => acl.query.clone(),
+        };
+        let allow_recursion = match &raw.allow_recursion {
+            Some(entries) => self.acl_list(&format!("{}.allow_recursion", key), entries),
+            None => acl.recursion.clone(),
+        };
+        ViewConfig {
+            name: raw.name.clone(),
+            match_clients: self.cidrs(&format!("{}.match_clients", key), &raw.match_clients),
+            match_destinations: self.cidrs(
+                &format!("{}.match_destinations", key),
+                &raw.match_destinations,
+            ),
+            match_keys,
+            recursion: raw.recursion.unwrap_or(true),
+            allow_query,
+            allow_recursion,
+            zones: self.zones(&format!("{}.zone", key), &raw.zone),
+        }
+    }
+
+    fn zone(&mut self, key: &str, raw: &RawZone) -> Option<ZoneConfig> {
+        let name = self.name(&format!("{}.name", key), &raw.name)?;
+        let kind = match raw.kind.to_ascii_lowercase().as_str() {
+            "primary" => ZoneKind::Primary,
+            "secondary" => ZoneKind::Secondary,
+            _ => {
+                self.error(
+                    &format!("{}.type", key),
+                    format!(
+                        "unknown zone type {:?}, expected primary or secondary",
+                        raw.kind
+                    ),
+                );
+                return None;
+            }
+        };
+        let masters = self.addresses(&format!("{}.masters", key), &raw.masters);
+        let notify = self.addresses(&format!("{}.notify", key), &raw.notify);
+
+        let file = match kind {
+            ZoneKind::Primary => {
+                if !raw.masters.is_empty() {
+                    self.error(
+                        &format!("{}.masters", key),
+                        "only secondary zones have masters",
+                    );
+                }
+                let Some(file) = &raw.file else {
+                    self.error(&format!("{}.file", key), "needed for primary zones");
+                    return None;
+                };
+                // a primary's data has to be there and load
+                let key = format!("{}.file", key);
+                let path = self.file(&key, file)?;
+                match fs::read_to_string(&path) {
+                    Ok(text) => {
+                        if let Err(e) = zone::parse(&text, &name) {
+                            self.error(&key, format!("{}: {}", path.display(), e));
+                        }
+                    }
+                    Err(e) => self.error(&key, format!("{}: {}", path.display(), e)),
+                }
+                Some(path)
+            }
+            ZoneKind::Secondary => {
+                if raw.masters.is_empty() {
+                    self.error(
+                        &format!("{}.masters", key),
+                        "secondary zones need at least one master",
+                    );
+                }
+                raw.file.as_ref().map(|file| self.dir.join(file))
+            }
+        };
+        let keys = match &raw.keys {
+            Some(_) if kind == ZoneKind::Secondary => {
+                self.error(
+                    &format!("{}.keys", key),
+                    "only primary zones are signed here",
+                );
+                None
+            }
+            Some(dir) => self.keys(&format!("{}.keys", key), dir, &name),
+            None => None,
+        };
+        if raw.nsec3 && raw.keys.is_none() {
+            self.error(
+                &format!("{}.nsec3", key),
+                "only used for zones signed with keys",
+            );
+        }
+        Some(ZoneConfig {
+            name,
+            kind,
+            file,
+            masters,
+            notify,
+            keys,
+            nsec3: raw.nsec3,
+        })
+    }
+
+    fn policy_zone(&mut self, key: &str, raw: &RawPolicyZone) -> Option<PolicyZoneConfig> {
+        let name = self.name(&format!("{}.name", key), &raw.name)?;
+        let masters = self.addresses(&format!("{}.masters", key), &raw.masters);
+        let file = match (&raw.file, raw.masters.is_empty()) {
+            (Some(_), false) => {
+                self.error(
+                    &format!("{}.masters", key),
+                    "policy zones come from a file or from masters, not both",
+                );
+                return None;
+            }
+            (None, true) => {
+                self.error(
+                    &format!("{}.file", key),
+                    "needed for policy zones without masters",
+                );
+                return None;
+            }
+            (None, false) => None,
+            // the policies have to be there and load
+            (Some(file), true) => {
+                let key = format!("{}.file", key);
+                let path = self.file(&key, file)?;
+                match fs::read_to_string(&path) {
+                    Ok(text) => {
+                        if let Err(e) = PolicyZone::from_zone_file(&name, &text) {
+                            self.error(&key, format!("{}: {}", path.display(), e));
+                        }
+                    }
+                    Err(e) => self.error(&key, format!("{}: {}", path.display(), e)),
+                }
+                Some(path)
+            }
+        };
+        Some(PolicyZoneConfig {
+            name,
+            file,
+            masters,
+        })
+    }
+
+    fn blocklist(&mut self, raw: &RawBlocklist) -> BlocklistConfig {
+        let mut v4: Option<Ipv4Addr> = None;
+        let mut v6: Option<Ipv6Addr> = None;
+        for (i, text) in raw.sinkhole.iter().enumerate() {
+            let key = format!("blocklist.sinkhole[{}]", i);
+            match text.parse::<IpAddr>() {
+                Ok(IpAddr::V4(address)) if v4.is_none() => v4 = Some(address),
+                Ok(IpAddr::V6(address)) if v6.is_none() => v6 = Some(address),
+                Ok(_) => self.error(&key, "only one address of each family is used"),
+                Err(_) => self.error(&key, format!("invalid address {:?}", text)),
+            }
+        }
+        let mode = match raw.mode.as_deref().unwrap_or("nxdomain") {
+            "nxdomain" => Mode::Nxdomain,
+            "null" => Mode::NullAddress,
+            "sinkhole" => Mode::Sinkhole(v4, v6),
+            other => {
+                self.error(
+                    "blocklist.mode",
+                    format!(
+                        "unknown mode {:?}, expected nxdomain, null or sinkhole",
+                        other
+                    ),
+                );
+                Mode::Nxdomain
+            }
+        };
+        match mode {
+            Mode::Sinkhole(None, None) => {
+                self.error("blocklist.sinkhole", "needed for the sinkhole mode")
+            }
+            Mode::Nxdomain | Mode::NullAddress if !raw.sinkhole.is_empty() => {
+                self.error("blocklist.sinkhole", "only used with mode = \"sinkhole\"")
+            }
+            _ => {}
+        }
+        if raw.block.is_empty() {
+            self.error("blocklist.block", "at least one list is needed");
+        }
+
+        let mut sources = Vec::new();
+        for (name, paths, allow) in [("block", &raw.block, false), ("allow", &raw.allow, true)] {
+            for (i, path) in paths.iter().enumerate() {
+                if let Some(path) = self.file(&format!("blocklist.{}[{}]", name, i), path) {
+                    sources.push(Source { path, allow });
+                }
+            }
+        }
+        BlocklistConfig {
+            mode,
+            ttl: raw.ttl.unwrap_or(blocklist::DEFAULT_TTL),
+            sources,
+        }
+    }
+
+    // a signed zone needs at least one key that loads
+    fn keys(&mut self, key: &str, dir: &str, zone: &str) -> Option<PathBuf> {
+        let dir = self.dir.join(dir);
+        match sign::load_keys(&dir, zone) {
+            Ok(keys) if keys.is_empty() => {
+                self.error(
+                    key,
+                    format!(
+                        "no keys for {} in {}",
+                        name::to_presentation(zone),
+                        dir.display()
+                    ),
+                );
+                None
+            }
+            Ok(_) => Some(dir),
+            Err(e) => {
+                self.error(key, e);
+                None
+            }
+        }
+    }
+
+    fn resolver(&mut self, raw: &RawResolver) -> Resolver {
+        let forwarders = self.addresses("resolver.forwarders", &raw.forwarders);
+        let mut forward_zones: Vec<(String, Vec<SocketAddr>)> = Vec::new();
+        for (i, zone) in raw.forward_zone.iter().enumerate() {
+            let key = format!("resolver.forward_zone[{}]", i);
+            let Some(name) = self.name(&format!("{}.name", key), &zone.name) else {
+                continue;
+            };
+            if zone.forwarders.is_empty() {
+                self.error(
+                    &format!("{}.forwarders", key),
+                    "at least one forwarder is needed",
+                );
+            }
+            if forward_zones.iter().any(|(other, _)| *other == name) {
+                self.error(&key, format!("{} already has forwarders", name));
+            }
+            let servers = self.addresses(&format!("{}.forwarders", key), &zone.forwarders);
+            forward_zones.push((name, servers));
+        }
+        let trust_anchor = raw
+            .trust_anchor
+            .as_ref()
+            .and_then(|path| self.file("resolver.trust_anchor", path));
+        if trust_anchor.is_some() && !raw.validate {
+            self.error(
+                "resolver.trust_anchor",
+                "trust anchors are only used with validate = true",
+            );
+        }
+        let mut subnet_prefix = |key: &str, prefix: Option<u8>, max: u8, default: u8| match prefix {
+            Some(prefix) if !raw.client_subnet => {
+                self.error(key, "only used with client_subnet = true");
+                prefix
+            }
+            Some(prefix) if prefix > max => {
+                self.error(key, format!("{} isn't between 0 and {}", prefix, max));
+                prefix
+            }
+            Some(prefix) => prefix,
+            None => default,
+        };
+        let client_subnet = (
+            subnet_prefix(
+                "resolver.client_subnet_v4",
+                raw.client_subnet_v4,
+                32,
+                client_subnet::DEFAULT_V4_PREFIX,
+            ),
+            subnet_prefix(
+                "resolver.client_subnet_v6",
+                raw.client_subnet_v6,
+                128,
+                client_subnet::DEFAULT_V6_PREFIX,
+            ),
+        );
+        Resolver {
+            forwarders,
+            forward_zones,
+            timeout: self
+                .seconds("resolver.timeout", raw.timeout)
+                .unwrap_or(forward::DEFAULT_TIMEOUT),
+            retries: raw.retries.unwrap_or(forward::DEFAULT_RETRIES),
+            probe_interval: self
+                .seconds("resolver.probe_interval", raw.probe_interval)
+                .unwrap_or(forward::DEFAULT_PROBE_INTERVAL),
+            validate: raw.validate,
+            trust_anchor,
+            client_subnet: Some(client_subnet).filter(|_| raw.client_subnet),
+        }
+    }
+
+    // each entry is an element of the list: any, none, key NAME or an
+    // address or prefix, optionally negated with !
+    fn acl(&mut self, raw: &RawAcl) -> Policy {
+        let mut policy = Policy::default();
+        let lists = [
+            ("query", &raw.query, &mut policy.query),
+            ("recursion", &raw.recursion, &mut policy.recursion),
+            ("transfer", &raw.transfer, &mut policy.transfer),
+            ("notify", &raw.notify, &mut policy.notify),
+            ("update", &raw.update, &mut policy.update),
+        ];
+        for (name, entries, acl) in lists {
+            if let Some(entries) = entries {
+                *acl = self.acl_list(&format!("acl.{}", name), entries);
+            }
+        }
+        policy
+    }
+
+    fn acl_list(&mut self, key: &str, entries: &[String]) -> Acl {
+        let mut acl = Acl::none();
+        for (i, entry) in entries.iter().enumerate() {
+            let key = format!("{}[{}]", key, i);
+            match entry.parse::<Acl>() {
+                Ok(parsed) if parsed.rules.len() == 1 => {
+                    if let Element::Key(name) = &parsed.rules[0].element {
+                        self.known_key(&key, name.trim_end_matches('.'));
+                    }
+                    acl.rules.extend(parsed.rules);
+                }
+                Ok(_) => self.error(&key, format!("expected a single entry, not {:?}", entry)),
+                Err(e) => self.error(&key, e),
+            }
+        }
+        acl
+    }
+
+    // a key no [[key]] defines could never be verified, so never match
+    fn known_key(&mut self, key: &str, name: &str) {
+        if !self.keys.iter().any(|known| known == name) {
+            self.error(key, format!("no [[key]] named {}", name));
+        }
+    }
+
+    // BIND's defaults for whatever is left out
+    fn rate_limit(&mut self, raw: &RawRateLimit) -> rrl::Config {
+        let defaults = rrl::Config::default();
+        let mut prefix = |key: &str, prefix: Option<u8>, max: u8, default: u8| match prefix {
+            Some(prefix) if prefix > max => {
+                self.error(key, format!("{} isn't between 0 and {}", prefix, max));
+                default
+            }
+            Some(prefix) => prefix,
+            None => default,
+        };
+        let ipv4_prefix = prefix(
+            "rate_limit.ipv4_prefix",
+            raw.ipv4_prefix,
+            32,
+            defaults.ipv4_prefix,
+        );
+        let ipv6_prefix = prefix(
+            "rate_limit.ipv6_prefix",
+            raw.ipv6_prefix,
+            128,
+            defaults.ipv6_prefix,
+        );
+        rrl::Config {
+            responses_per_second: raw
+                .responses_per_second
+                .unwrap_or(defaults.responses_per_second),
+            nxdomains_per_second: raw
+                .nxdomains_per_second
+                .unwrap_or(defaults.nxdomains_per_second),
+            errors_per_second: raw.errors_per_second.unwrap_or(defaults.errors_per_second),
+            slip: raw.slip.unwrap_or(defaults.slip),
+            window: self
+                .seconds("rate_limit.window", raw.window)
+                .unwrap_or(defaults.window),
+            ipv4_prefix,
+            ipv6_prefix,
+            max_entries: defaults.max_entries,
+        }
+    }
+
+    fn logging(&mut self, raw: &RawLogging) -> Logging {
+        let format = match raw.format.as_deref().map(str::parse::<Format>) {
+            None => Format::Json,
+            Some(Ok(format)) => format,
+            Some(Err(e)) => {
+                self.error("logging.format", e);
+                Format::Json
+            }
+        };
+        let sample_rate = raw.sample_rate.unwrap_or(1.0);
+        if !(sample_rate > 0.0 && sample_rate <= 1.0) {
+            self.error(
+                "logging.sample_rate",
+                format!("{} isn't between 0 and 1", sample_rate),
+            );
+        }
+        let subnets = self.cidrs("logging.subnets", &raw.subnets);
+        let dnstap = raw
+            .dnstap
+            .as_ref()
+            .map(|dnstap| match dnstap.strip_prefix("unix:") {
+                Some(socket) => format!("unix:{}", self.dir.join(socket).display()),
+                None => self.dir.join(dnstap).display().to_string(),
+            });
+        Logging {
+            query_log: raw.query_log.as_ref().map(|path| self.dir.join(path)),
+            format,
+            sample_rate,
+            subnets,
+            max_bytes: raw.max_bytes.unwrap_or(querylog::DEFAULT_MAX_BYTES),
+            keep: raw.keep.unwrap_or(querylog::DEFAULT_KEEP),
+            dnstap,
+        }
+    }
+
+    fn name(&mut self, key: &str, name: &str) -> Option<String> {
+        let trimmed = name.trim_end_matches('.');
+        let valid = name == "."
+            || (!trimmed.is_empty()
+                && trimmed.len() <= 253
+                && trimmed
+                    .split('.')
+                    .all(|label| !label.is_empty() && label.len() <= 63));
+        match valid {
+            true => Some(trimmed.to_ascii_lowercase()),
+            false => {
+                self.error(key, format!("invalid domain name {:?}", name));
+                None
+            }
+        }
+    }
+
+    // the port can be left out
+    fn address(&mut self, key: &str, text: &str, default_port: u16) -> Option<SocketAddr> {
+        if let Ok(address) = text.parse::<SocketAddr>() {
+            return Some(address);
+        }
+        match text.parse::<IpAddr>() {
+            Ok(address) => Some(SocketAddr::new(address, default_port)),
+            Err(_) => {
+                self.error(key, format!("invalid address {:?}", text));
+                None
+            }
+        }
+    }
+
+    fn addresses(&mut self, key: &str, texts: &[String]) -> Vec<SocketAddr> {
+        texts
+            .iter()
+            .enumerate()
+            .filter_map(|(i, text)| self.address(&format!("{}[{}]", key, i), text, tcp::PORT))
+            .collect()
+    }
+
+    fn cidrs(&mut self, key: &str, texts: &[String]) -> Vec<Cidr> {
+        texts
+            .iter()
+            .enumerate()
+            .filter_map(|(i, text)| match text.parse::<Cidr>() {
+                Ok(cidr) => Some(cidr),
+                Err(e) => {
+                    self.error(&format!("{}[{}]", key, i), e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    fn seconds(&mut self, key: &str, seconds: Option<f64>) -> Option<Duration> {
+        let seconds = seconds?;
+        match Duration::try_from_secs_f64(seconds) {
+            Ok(duration) if !duration.is_zero() => Some(duration),
+            _ => {
+                self.error(
+                    key,
+                    format!("{} isn't a positive number of seconds", seconds),
+                );
+                None
+            }
+        }
+    }
+
+    // files the server reads have to exist when it starts
+    fn file(&mut self, key: &str, path: &str) -> Option<PathBuf> {
+        let path = self.dir.join(path);
+        match path.is_file() {
+            true => Some(path),
+            false => {
+                self.error(key, format!("{} doesn't exist", path.display()));
+                None
+            }
+        }
+    }
+}
+
+pub fn run(args: &[String]) -> i32 {
+    let [path] = args else {
+        eprintln!("{}", USAGE);
+        return EXIT_ERROR;
+    };
+    match Config::load(Path::new(path)) {
+        Ok(config) => {
+            print!("{}", config);
+            0
+        }
+        Err(e) => {
+            for line in e.lines() {
+                eprintln!("dns-rs: {}: {}", path, line);
+            }
+            EXIT_ERROR
+        }
+    }
+}
+
+fn join<T: fmt::Display>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(T::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn acl_text(acl: &Acl) -> String {
+    match acl.rules.is_empty() {
+        true => "none".to_string(),
+        false => acl.to_string(),
+    }
+}
+
+impl fmt::Display for ZoneConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}. {}", self.name, self.kind)?;
+        if let Some(file) = &self.file {
+            write!(f, " file {}", file.display())?;
+        }
+        if !self.masters.is_empty() {
+            write!(f, " masters {}", join(&self.masters))?;
+        }
+        if !self.notify.is_empty() {
+            write!(f, " notify {}", join(&self.notify))?;
+        }
+        if let Some(keys) = &self.keys {
+            let denial = if self.nsec3 { "NSEC3" } else { "NSEC" };
+            write!(
+                f,
+                " signed with keys from {} and {}",
+                keys.display(),
+                denial
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// what the server would run with, one line per setting
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for listener in &self.listeners {
+            write!(f, "listener {} {}", listener.transport, listener.address)?;
+            if let Some((certificate, private_key)) = &listener.tls {
+                write!(
+                    f,
+                    " certificate {} key {}",
+                    certificate.display(),
+                    private_key.display()
+                )?;
+            }
+            writeln!(f)?;
+        }
+        // never the secret
+        for key in &self.keys {
+            writeln!(f, "key {} {}", key.name, key.algorithm)?;
+        }
+        for zone in &self.zones {
+            writeln!(f, "zone {}", zone)?;
+        }
+        for view in &self.views {
+            write!(f, "view {}", view.name)?;
+            if !view.match_clients.is_empty() {
+                write!(f, " clients {}", join(&view.match_clients))?;
+            }
+            if !view.match_destinations.is_empty() {
+                write!(f, " destinations {}", join(&view.match_destinations))?;
+            }
+            if !view.match_keys.is_empty() {
+                write!(f, " keys {}", join(&view.match_keys))?;
+            }
+            write!(f, " allow query {}", acl_text(&view.allow_query))?;
+            match view.recursion {
+                true => writeln!(f, " recursion {}", acl_text(&view.allow_recursion))?,
+                false => writeln!(f, " no recursion")?,
+            }
+            for zone in &view.zones {
+                writeln!(f, "view {} zone {}", view.name, zone)?;
+            }
+        }
+
+        for zone in &self.rpz {
+            write!(f, "rpz {}.", zone.name)?;
+            if let Some(file) = &zone.file {
+                write!(f, " file {}", file.display())?;
+            }
+            if !zone.masters.is_empty() {
+                write!(f, " masters {}", join(&zone.masters))?;
+            }
+            writeln!(f)?;
+        }
+
+        if let Some(blocklist) = &self.blocklist {
+            write!(f, "blocklist ")?;
+            match blocklist.mode {
+                Mode::Nxdomain => write!(f, "nxdomain")?,
+                Mode::NullAddress => write!(f, "null")?,
+                Mode::Sinkhole(v4, v6) => {
+                    let addresses: Vec<IpAddr> = v4
+                        .map(IpAddr::V4)
+                        .into_iter()
+                        .chain(v6.map(IpAddr::V6))
+                        .collect();
+                    write!(f, "sinkhole {}", join(&addresses))?;
+                }
+            }
+            write!(f, " ttl {}", blocklist.ttl)?;
+            for (name, allow) in [("block", false), ("allow", true)] {
+                let paths: Vec<String> = blocklist
+                    .sources
+                    .iter()
+                    .filter(|source| source.allow == allow)
+                    .map(|source| source.path.display().to_string())
+                    .collect();
+                if !paths.is_empty() {
+                    write!(f, " {} {}", name, join(&paths))?;
+                }
+            }
+            writeln!(f)?;
+        }
+
+        let resolver = &self.resolver;
+        if !resolver.forwarders.is_empty() {
+            writeln!(f, "forwarders {}", join(&resolver.forwarders))?;
+        }
+        for (zone, forwarders) in &resolver.forward_zones {
+            writeln!(f, "forward {}. to {}", zone, join(forwarders))?;
+        }
+        writeln!(
+            f,
+            "resolver timeout {:?} retries {} probe interval {:?}",
+            resolver.timeout, resolver.retries, resolver.probe_interval
+        )?;
+        if resolver.validate {
+            match &resolver.trust_anchor {
+                Some(path) => writeln!(f, "validate with trust anchors from {}", path.display())?,
+                None => writeln!(f, "validate with the root's trust anchors")?,
+            }
+        }
+        if let Some((v4, v6)) = resolver.client_subnet {
+            writeln!(f, "send client subnets of /{} and /{}", v4, v6)?;
+        }
+
+        let acl = &self.acl;
+        for (name, acl) in [
+            ("query", &acl.query),
+            ("recursion", &acl.recursion),
+            ("transfer", &acl.transfer),
+            ("notify", &acl.notify),
+            ("update", &acl.update),
+        ] {
+            writeln!(f, "allow {} {}", name, acl_text(acl))?;
+        }
+
+        if let Some(limit) = &self.rate_limit {
+            writeln!(
+                f,
+                "rate limit {} responses {} nxdomains {} errors per second slip {} window {:?} prefixes /{} and /{}",
+                limit.responses_per_second,
+                limit.nxdomains_per_second,
+                limit.errors_per_second,
+                limit.slip,
+                limit.window,
+                limit.ipv4_prefix,
+                limit.ipv6_prefix
+            )?;
+        }
+
+        let logging = &self.logging;
+        if let Some(path) = &logging.query_log {
+            write!(
+                f,
+                "query log {} {} sample rate {} rotate at {} bytes keep {}",
+                path.display(),
+                format!("{:?}", logging.format).to_lowercase(),
+                logging.sample_rate,
+                logging.max_bytes,
+                logging.keep
+            )?;
+            if !logging.subnets.is_empty() {
+                write!(f, " subnets {}", join(&logging.subnets))?;
+            }
+            writeln!(f)?;
+        }
+        if let Some(dnstap) = &logging.dnstap {
+            writeln!(f, "dnstap {}", dnstap)?;
+        }
+        if let Some(metrics) = &self.metrics {
+            writeln!(f, "metrics {}", metrics)?;
+        }
+        Ok(())
+    }
+}
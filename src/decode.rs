@@ -0,0 +1,172 @@
+use std::fs;
+use std::io::{self, Read};
+
+use dns_rs_lib::base64;
+use dns_rs_lib::decode;
+use dns_rs_lib::doh;
+use dns_rs_lib::packet::Packet;
+
+pub const USAGE: &str = "usage: dns-rs decode [--format raw|hex|base64] [FILE | -]
+       dns-rs decode --hex HEX
+       dns-rs decode --base64 BASE64 | DOH-URL";
+
+const EXIT_ERROR: i32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Raw,
+    Hex,
+    Base64,
+}
+
+pub fn run(args: &[String]) -> i32 {
+    let message = match read_input(args) {
+        Ok(message) => message,
+        Err(e) => {
+            eprintln!("dns-rs: {}\n{}", e, USAGE);
+            return EXIT_ERROR;
+        }
+    };
+
+    let annotation = decode::annotate(&message);
+    print_fields(&annotation.fields);
+    match annotation.error {
+        Some(error) => {
+            eprintln!("dns-rs: malformed message at {}", error);
+            EXIT_ERROR
+        }
+        None => {
+            println!();
+            println!("{}", Packet::from_buf(&message));
+            0
+        }
+    }
+}
+
+fn read_input(args: &[String]) -> Result<Vec<u8>, String> {
+    let mut format = None;
+    let mut source = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("option {} needs a value", arg))
+        };
+        match arg.as_str() {
+            "--hex" => return parse(value()?, Format::Hex),
+            "--base64" => return parse(value()?, Format::Base64),
+            "--format" => {
+                format = Some(match value()?.as_str() {
+                    "raw" => Format::Raw,
+                    "hex" => Format::Hex,
+                    "base64" => Format::Base64,
+                    other => return Err(format!("unknown format {:?}", other)),
+                })
+            }
+            _ if source.is_none() => source = Some(arg.clone()),
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+
+    let input = match source.as_deref() {
+        None | Some("-") => {
+            let mut input = Vec::new();
+            io::stdin()
+                .read_to_end(&mut input)
+                .map_err(|e| format!("couldn't read stdin: {}", e))?;
+            input
+        }
+        Some(path) => fs::read(path).map_err(|e| format!("couldn't read {}: {}", path, e))?,
+    };
+
+    match format.unwrap_or_else(|| detect(&input)) {
+        Format::Raw => Ok(input),
+        format => {
+            let text = String::from_utf8(input)
+                .map_err(|_| "input isn't text, try --format raw".to_string())?;
+            parse(&text, format)
+        }
+    }
+}
+
+// text made only of hex digits is taken as hex, then base64, and anything
+// else as the message itself
+fn detect(input: &[u8]) -> Format {
+    let text: Vec<u8> = input
+        .iter()
+        .copied()
+        .filter(|byte| !byte.is_ascii_whitespace())
+        .collect();
+    if text.is_empty() {
+        return Format::Raw;
+    }
+    if text
+        .iter()
+        .all(|byte| byte.is_ascii_hexdigit() || *byte == b':')
+    {
+        return Format::Hex;
+    }
+    let is_base64 = |byte: &u8| byte.is_ascii_alphanumeric() || b"+/-_=".contains(byte);
+    if text.iter().all(is_base64) || text.windows(4).any(|window| window == b"dns=") {
+        return Format::Base64;
+    }
+    Format::Raw
+}
+
+fn parse(text: &str, format: Format) -> Result<Vec<u8>, String> {
+    match format {
+        Format::Raw => Ok(text.as_bytes().to_vec()),
+        Format::Hex => from_hex(text),
+        Format::Base64 => {
+            let text = text.trim();
+            // a whole DoH URL, or just its query string
+            let decoded = match text.split_once("dns=") {
+                Some(_) => doh::decode_get_query(text.rsplit('?').next().unwrap_or(text)),
+                None => base64::decode(text),
+            };
+            decoded.ok_or_else(|| "invalid base64".to_string())
+        }
+    }
+}
+
+// accepts whitespace or colons between bytes and a leading 0x
+pub fn from_hex(text: &str) -> Result<Vec<u8>, String> {
+    let text = text.trim();
+    let text = text.strip_prefix("0x").unwrap_or(text);
+    let digits: Vec<char> = text
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != ':')
+        .collect();
+    if let Some(c) = digits.iter().find(|c| !c.is_ascii_hexdigit()) {
+        return Err(format!("invalid hex digit {:?}", c));
+    }
+    if !digits.len().is_multiple_of(2) {
+        return Err(format!("odd number of hex digits ({})", digits.len()));
+    }
+    Ok(digits
+        .chunks(2)
+        .map(|pair| {
+            let pair: String = pair.iter().collect();
+            u8::from_str_radix(&pair, 16).unwrap()
+        })
+        .collect())
+}
+
+fn print_fields(fields: &[decode::Field]) {
+    let width = fields
+        .iter()
+        .map(|field| field.name.len())
+        .max()
+        .unwrap_or(0);
+    println!(
+        "{:>6}  {:>6}  {:<width$}  value",
+        "offset", "length", "field"
+    );
+    for field in fields {
+        println!(
+            "{:>6}  {:>6}  {:<width$}  {}",
+            field.offset, field.len, field.name, field.value
+        );
+    }
+}
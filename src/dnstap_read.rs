@@ -0,0 +1,131 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::net::SocketAddr;
+
+use dns_rs_lib::dnstap::{Dnstap, Reader};
+use dns_rs_lib::name;
+use dns_rs_lib::packet::Packet;
+use dns_rs_lib::rcode::RCode;
+
+pub const USAGE: &str = "usage: dns-rs dnstap-read [+verbose] [FILE|-]";
+
+const EXIT_ERROR: i32 = 1;
+
+#[derive(Debug)]
+struct Options {
+    path: String,
+    // print whole messages rather than a line each
+    verbose: bool,
+}
+
+pub fn run(args: &[String]) -> i32 {
+    let options = match parse_args(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("dns-rs: {}\n{}", e, USAGE);
+            return EXIT_ERROR;
+        }
+    };
+    let input: Box<dyn Read> = match options.path.as_str() {
+        "-" => Box::new(io::stdin()),
+        path => match File::open(path) {
+            Ok(file) => Box::new(BufReader::new(file)),
+            Err(e) => {
+                eprintln!("dns-rs: couldn't read {}: {}", path, e);
+                return EXIT_ERROR;
+            }
+        },
+    };
+    match read(input, options.verbose) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("dns-rs: couldn't read {}: {}", options.path, e);
+            EXIT_ERROR
+        }
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut path = None;
+    let mut verbose = false;
+    for arg in args {
+        match arg.as_str() {
+            "+verbose" => verbose = true,
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+    Ok(Options {
+        path: path.unwrap_or_else(|| "-".to_string()),
+        verbose,
+    })
+}
+
+fn read<R: Read>(input: R, verbose: bool) -> io::Result<()> {
+    for dnstap in Reader::new(input)? {
+        print_dnstap(&dnstap?, verbose);
+    }
+    Ok(())
+}
+
+// one line per message in the style of dnstap-ldns, with the arrow pointing
+// the way the message went
+fn print_dnstap(dnstap: &Dnstap, verbose: bool) {
+    let message = &dnstap.message;
+    let is_query = message.r#type.is_query();
+    let (time, data) = match is_query {
+        true => (message.query_time, &message.query_message),
+        false => (message.response_time, &message.response_message),
+    };
+
+    let mut line = match time {
+        Some(time) => format!("{}.{:06}", time.as_secs(), time.subsec_micros()),
+        None => "-".to_string(),
+    };
+    line.push_str(&format!(
+        " {} {} {} {}",
+        message.r#type.mnemonic(),
+        address(message.query_address),
+        if is_query { "->" } else { "<-" },
+        address(message.response_address)
+    ));
+    if let Some(protocol) = message.protocol {
+        line.push_str(&format!(" {}", protocol));
+    }
+    let Some(data) = data else {
+        println!("{}", line);
+        return;
+    };
+    line.push_str(&format!(" {}b", data.len()));
+
+    let packet = match Packet::try_from_buf(data) {
+        Ok(packet) => packet,
+        Err(e) => {
+            println!("{} malformed: {}", line, e);
+            return;
+        }
+    };
+    if let Some(question) = packet.questions.first() {
+        line.push_str(&format!(
+            " {} {} {}",
+            name::to_presentation(&question.name),
+            question.class,
+            question.r#type
+        ));
+    }
+    if packet.header.response {
+        line.push_str(&format!(
+            " {} {} answers",
+            RCode::from_value(packet.rcode()),
+            packet.answers.len()
+        ));
+    }
+    println!("{}", line);
+    if verbose {
+        println!("{}\n", packet);
+    }
+}
+
+fn address(address: Option<SocketAddr>) -> String {
+    address.map_or_else(|| "-".to_string(), |address| address.to_string())
+}
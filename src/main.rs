@@ -1,18 +1,36 @@
-use std::{fs::File, io::Read};
+use std::{env, process};
 
-use dns_rs_lib::packet::Packet;
+mod config;
+mod decode;
+mod dnstap_read;
+mod pcap;
+mod query;
+mod serve;
+mod sign_zone;
 
-pub fn from_file(filename: &str) -> Vec<u8> {
-    let mut buf: Vec<u8> = Vec::new();
-    let mut inf = File::open(filename).expect("Error opening file");
-    inf.read_to_end(&mut buf).expect("Error reading into vec");
-    buf
-}
-
-pub fn main() -> () {
-    let buf = from_file("query_packet.txt");
-    //let buf = from_file("response_packet.txt");
-
-    let packet = Packet::from_buf(&buf);
-    dbg!(packet);
+pub fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let code = match args.first().map(String::as_str) {
+        Some("query") => query::run(&args[1..]),
+        Some("decode") => decode::run(&args[1..]),
+        Some("pcap") => pcap::run(&args[1..]),
+        Some("dnstap-read") => dnstap_read::run(&args[1..]),
+        Some("check-config") => config::run(&args[1..]),
+        Some("serve") => serve::run(&args[1..]),
+        Some("sign-zone") => sign_zone::run(&args[1..]),
+        _ => {
+            eprintln!(
+                "{}\n{}\n{}\n{}\n{}\n{}\n{}",
+                query::USAGE,
+                decode::USAGE,
+                pcap::USAGE,
+                dnstap_read::USAGE,
+                config::USAGE,
+                serve::USAGE,
+                sign_zone::USAGE
+            );
+            1
+        }
+    };
+    process::exit(code);
 }
@@ -0,0 +1,282 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use dns_rs_lib::capture::{Dissector, Message};
+use dns_rs_lib::name;
+use dns_rs_lib::packet::Packet;
+use dns_rs_lib::pcap::Reader;
+use dns_rs_lib::r#type::RRType;
+use dns_rs_lib::rcode::RCode;
+
+pub const USAGE: &str = "usage: dns-rs pcap [-p port]... [+summary] [+verbose] FILE";
+
+const EXIT_ERROR: i32 = 1;
+
+#[derive(Debug)]
+struct Options {
+    path: String,
+    ports: Vec<u16>,
+    // only print the summary
+    summary: bool,
+    // print whole messages rather than a line each
+    verbose: bool,
+}
+
+// queries are matched to responses on the addresses, ID and question
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct Key {
+    client: SocketAddr,
+    server: SocketAddr,
+    id: u16,
+    question: Option<(String, RRType)>,
+}
+
+#[derive(Debug, Default)]
+struct Summary {
+    queries: usize,
+    responses: usize,
+    malformed: usize,
+    unmatched_responses: usize,
+    rcodes: HashMap<String, usize>,
+    types: HashMap<String, usize>,
+    latencies: Vec<Duration>,
+}
+
+pub fn run(args: &[String]) -> i32 {
+    let options = match parse_args(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("dns-rs: {}\n{}", e, USAGE);
+            return EXIT_ERROR;
+        }
+    };
+    match analyze(&options) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("dns-rs: couldn't read {}: {}", options.path, e);
+            EXIT_ERROR
+        }
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut path = None;
+    let mut ports = Vec::new();
+    let mut summary = false;
+    let mut verbose = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-p" => {
+                let port = args.next().ok_or("option -p needs a value")?;
+                ports.push(
+                    port.parse()
+                        .map_err(|_| format!("invalid port {:?}", port))?,
+                );
+            }
+            "+summary" => summary = true,
+            "+verbose" => verbose = true,
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+    Ok(Options {
+        path: path.ok_or("no capture file given")?,
+        ports,
+        summary,
+        verbose,
+    })
+}
+
+fn analyze(options: &Options) -> io::Result<()> {
+    let mut reader = Reader::new(BufReader::new(File::open(&options.path)?))?;
+    let mut dissector = Dissector::new();
+    if !options.ports.is_empty() {
+        dissector.ports = options.ports.clone();
+    }
+
+    let mut outstanding = HashMap::new();
+    let mut summary = Summary::default();
+    // a capture cut off part way through still gets summarized
+    let mut result = Ok(());
+    loop {
+        let frame = match reader.next_frame() {
+            Ok(Some(frame)) => frame,
+            Ok(None) => break,
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        };
+        for message in dissector.dissect(&frame) {
+            let latency = summary.record(&message, &mut outstanding);
+            if !options.summary {
+                print_message(&message, latency, options.verbose);
+            }
+        }
+    }
+
+    if !options.summary {
+        println!();
+    }
+    summary.print(outstanding.len());
+    result
+}
+
+impl Summary {
+    // returns how long the query took if this is a response to one
+    fn record(
+        &mut self,
+        message: &Message,
+        outstanding: &mut HashMap<Key, Duration>,
+    ) -> Option<Duration> {
+        let Ok(packet) = Packet::try_from_buf(&message.data) else {
+            self.malformed += 1;
+            return None;
+        };
+        let question = packet
+            .questions
+            .first()
+            .map(|question| (question.name.to_ascii_lowercase(), question.r#type));
+
+        if !packet.header.response {
+            self.queries += 1;
+            if let Some((_, r#type)) = &question {
+                *self.types.entry(r#type.to_string()).or_default() += 1;
+            }
+            let key = Key {
+                client: message.source,
+                server: message.destination,
+                id: packet.header.identifier,
+                question,
+            };
+            outstanding.insert(key, message.timestamp);
+            return None;
+        }
+
+        self.responses += 1;
+        let rcode = RCode::from_value(packet.rcode()).to_string();
+        *self.rcodes.entry(rcode).or_default() += 1;
+        let key = Key {
+            client: message.destination,
+            server: message.source,
+            id: packet.header.identifier,
+            question,
+        };
+        match outstanding.remove(&key) {
+            Some(sent) => {
+                let latency = message.timestamp.saturating_sub(sent);
+                self.latencies.push(latency);
+                Some(latency)
+            }
+            None => {
+                self.unmatched_responses += 1;
+                None
+            }
+        }
+    }
+
+    fn print(&mut self, unanswered: usize) {
+        println!(
+            ";; {} messages: {} queries, {} responses, {} malformed",
+            self.queries + self.responses + self.malformed,
+            self.queries,
+            self.responses,
+            self.malformed
+        );
+        println!(
+            ";; {} queries unanswered, {} responses without a query",
+            unanswered, self.unmatched_responses
+        );
+        print_counts("rcodes", &self.rcodes);
+        print_counts("query types", &self.types);
+
+        if self.latencies.is_empty() {
+            return;
+        }
+        self.latencies.sort();
+        let percentile = |p: usize| self.latencies[(self.latencies.len() - 1) * p / 100];
+        let total: Duration = self.latencies.iter().sum();
+        println!(
+            ";; latency: min {}, avg {}, p50 {}, p95 {}, max {}",
+            millis(self.latencies[0]),
+            millis(total / self.latencies.len() as u32),
+            millis(percentile(50)),
+            millis(percentile(95)),
+            millis(self.latencies[self.latencies.len() - 1])
+        );
+    }
+}
+
+// most common first
+fn print_counts(title: &str, counts: &HashMap<String, usize>) {
+    if counts.is_empty() {
+        return;
+    }
+    let mut counts: Vec<(&String, &usize)> = counts.iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+    println!(";; {}:", title);
+    for (name, count) in counts {
+        println!(";;   {:<10} {}", name, count);
+    }
+}
+
+fn print_message(message: &Message, latency: Option<Duration>, verbose: bool) {
+    let timestamp = format!(
+        "{}.{:06}",
+        message.timestamp.as_secs(),
+        message.timestamp.subsec_micros()
+    );
+    let route = format!(
+        "{} > {} {}",
+        message.source, message.destination, message.transport
+    );
+    let packet = match Packet::try_from_buf(&message.data) {
+        Ok(packet) => packet,
+        Err(e) => {
+            println!("{} {} malformed: {}", timestamp, route, e);
+            return;
+        }
+    };
+
+    let mut line = format!(
+        "{} {} {} id {}",
+        timestamp,
+        route,
+        if packet.header.response {
+            "response"
+        } else {
+            "query"
+        },
+        packet.header.identifier
+    );
+    if let Some(question) = packet.questions.first() {
+        line.push_str(&format!(
+            " {} {}",
+            name::to_presentation(&question.name),
+            question.r#type
+        ));
+    }
+    if packet.header.response {
+        line.push_str(&format!(
+            " {} {} answers",
+            RCode::from_value(packet.rcode()),
+            packet.answers.len()
+        ));
+    }
+    if let Some(latency) = latency {
+        line.push_str(&format!(" in {}", millis(latency)));
+    }
+    println!("{}", line);
+    if verbose {
+        println!("{}\n", packet);
+    }
+}
+
+fn millis(duration: Duration) -> String {
+    format!("{:.3} ms", duration.as_secs_f64() * 1000.0)
+}
@@ -0,0 +1,508 @@
+use std::fs;
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::path::Path;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use dns_rs_lib::base64;
+use dns_rs_lib::class::RRClass;
+use dns_rs_lib::client::{self, Client, Response, DEFAULT_RETRIES, DEFAULT_TIMEOUT};
+use dns_rs_lib::dnstap::{self, Logger, MessageType, Tap};
+use dns_rs_lib::doh::{self, Https};
+use dns_rs_lib::doq::{self, Quic};
+use dns_rs_lib::edns::Edns;
+use dns_rs_lib::name;
+use dns_rs_lib::packet::Packet;
+use dns_rs_lib::r#type::RRType;
+use dns_rs_lib::rdata;
+use dns_rs_lib::tcp;
+use dns_rs_lib::tls::{self, ClientTls};
+
+pub const USAGE: &str =
+    "usage: dns-rs query [@server] [-p port] [-t type] [-c class] name [type] [class]
+                     [+tcp] [+dnssec] [+norecurse] [+trace] [+noedns] [+nocookie] [+bufsize=N]
+                     [+timeout=SECONDS] [+retry=N] [+json] [+dnstap=FILE|unix:SOCKET]
+                     [+tls] [+tls-ca=FILE] [+tls-hostname=NAME] [+tls-pin=BASE64]
+                     [+https[=PATH]] [+https-get] [+quic]";
+
+// the payload size recommended since DNS flag day 2020
+const DEFAULT_BUFSIZE: u16 = 1232;
+// gives up on a trace that keeps being referred elsewhere
+const MAX_REFERRALS: usize = 16;
+
+// exit codes follow dig's
+const EXIT_USAGE: i32 = 1;
+const EXIT_NO_REPLY: i32 = 9;
+
+#[derive(Debug)]
+struct Options {
+    server: Option<String>,
+    // the transport's own port unless one is given
+    port: Option<u16>,
+    name: Option<String>,
+    r#type: Option<RRType>,
+    class: Option<RRClass>,
+    tcp: bool,
+    dnssec: bool,
+    recurse: bool,
+    trace: bool,
+    edns: bool,
+    // RFC 7873 cookies, which go with every query that has EDNS
+    cookie: bool,
+    bufsize: u16,
+    timeout: Duration,
+    retries: u32,
+    json: bool,
+    // where to log dnstap messages for every query and response
+    dnstap: Option<String>,
+    // DNS over TLS, checking the certificate against the CA file or the
+    // public roots, or the key against the pins
+    tls: bool,
+    tls_ca: Option<String>,
+    tls_hostname: Option<String>,
+    tls_pins: Vec<Vec<u8>>,
+    // DNS over HTTPS to the path, with the TLS settings above
+    https: Option<String>,
+    https_get: bool,
+    // DNS over QUIC, with the TLS settings above
+    quic: bool,
+}
+
+pub fn run(args: &[String]) -> i32 {
+    let options = match parse_args(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            return EXIT_USAGE;
+        }
+    };
+    let mut client = match options.client() {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("dns-rs: {}", e);
+            return EXIT_USAGE;
+        }
+    };
+    let writer = match &options.dnstap {
+        Some(path) => match open_dnstap(path) {
+            Ok((logger, writer)) => {
+                client.dnstap = Some(Tap::new(logger, MessageType::ToolQuery));
+                Some(writer)
+            }
+            Err(e) => {
+                eprintln!("dns-rs: couldn't open dnstap output {}: {}", path, e);
+                return EXIT_USAGE;
+            }
+        },
+        None => None,
+    };
+
+    let result = match options.trace {
+        true => trace(&options, &client),
+        false => query(&options, &client),
+    };
+    let server = client.server;
+    // the writer finishes the stream once the last logger is gone
+    drop(client);
+    if let Some(writer) = writer {
+        if let Ok(Err(e)) = writer.join() {
+            eprintln!(";; couldn't write dnstap output: {}", e);
+        }
+    }
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!(";; communications error to {}: {}", server, e);
+            EXIT_NO_REPLY
+        }
+    }
+}
+
+pub fn open_dnstap(path: &str) -> io::Result<(Logger, JoinHandle<io::Result<()>>)> {
+    let (mut logger, writer) = match path.strip_prefix("unix:") {
+        #[cfg(unix)]
+        Some(socket) => Logger::to_unix_socket(socket, dnstap::DEFAULT_QUEUE_SIZE)?,
+        _ => Logger::to_file(path, dnstap::DEFAULT_QUEUE_SIZE)?,
+    };
+    logger.version = Some(format!("dns-rs {}", env!("CARGO_PKG_VERSION")).into_bytes());
+    Ok((logger, writer))
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        server: None,
+        port: None,
+        name: None,
+        r#type: None,
+        class: None,
+        tcp: false,
+        dnssec: false,
+        recurse: true,
+        trace: false,
+        edns: true,
+        cookie: true,
+        bufsize: DEFAULT_BUFSIZE,
+        timeout: DEFAULT_TIMEOUT,
+        retries: DEFAULT_RETRIES,
+        json: false,
+        dnstap: None,
+        tls: false,
+        tls_ca: None,
+        tls_hostname: None,
+        tls_pins: Vec::new(),
+        https: None,
+        https_get: false,
+        quic: false,
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if let Some(server) = arg.strip_prefix('@') {
+            options.server = Some(server.to_string());
+        } else if let Some(flag) = arg.strip_prefix('+') {
+            options.set_flag(flag)?;
+        } else if arg.starts_with('-') && arg.len() > 1 {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("option {} needs a value", arg))?;
+            match arg.as_str() {
+                "-p" => options.port = Some(parse_value(arg, value)?),
+                "-t" => options.r#type = Some(value.parse()?),
+                "-c" => options.class = Some(value.parse()?),
+                _ => return Err(format!("unknown option {}", arg)),
+            }
+        } else if options.name.is_some()
+            && options.r#type.is_none()
+            && arg.parse::<RRType>().is_ok()
+        {
+            options.r#type = arg.parse().ok();
+        } else if options.name.is_some()
+            && options.class.is_none()
+            && arg.parse::<RRClass>().is_ok()
+        {
+            options.class = arg.parse().ok();
+        } else if options.name.is_none() {
+            options.name = Some(arg.clone());
+        } else {
+            return Err(format!("unexpected argument {}", arg));
+        }
+    }
+    if options.name.is_none() {
+        return Err("no name to query".to_string());
+    }
+    Ok(options)
+}
+
+fn parse_value<T: std::str::FromStr>(option: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value {:?} for {}", value, option))
+}
+
+impl Options {
+    fn set_flag(&mut self, flag: &str) -> Result<(), String> {
+        let (flag, value) = match flag.split_once('=') {
+            Some((flag, value)) => (flag, Some(value)),
+            None => (flag, None),
+        };
+        let optional = value;
+        let value = |option| value.ok_or_else(|| format!("+{} needs a value", option));
+        match flag {
+            "tcp" | "vc" => self.tcp = true,
+            "notcp" | "novc" => self.tcp = false,
+            "dnssec" => self.dnssec = true,
+            "nodnssec" => self.dnssec = false,
+            "recurse" => self.recurse = true,
+            "norecurse" => self.recurse = false,
+            "trace" => self.trace = true,
+            "notrace" => self.trace = false,
+            "edns" => self.edns = true,
+            "noedns" => self.edns = false,
+            "cookie" => self.cookie = true,
+            "nocookie" => self.cookie = false,
+            "json" => self.json = true,
+            "nojson" => self.json = false,
+            "dnstap" => self.dnstap = Some(value(flag)?.to_string()),
+            "nodnstap" => self.dnstap = None,
+            "bufsize" => self.bufsize = parse_value("+bufsize", value(flag)?)?,
+            "timeout" => {
+                let seconds: u64 = parse_value("+timeout", value(flag)?)?;
+                self.timeout = Duration::from_secs(seconds.max(1));
+            }
+            "retry" => self.retries = parse_value("+retry", value(flag)?)?,
+            "tls" => self.tls = true,
+            "notls" => self.tls = false,
+            // the rest imply +tls, as they do for kdig
+            "tls-ca" => {
+                self.tls_ca = Some(value(flag)?.to_string());
+                self.tls = true;
+            }
+            "tls-hostname" => {
+                self.tls_hostname = Some(value(flag)?.to_string());
+                self.tls = true;
+            }
+            "tls-pin" => {
+                let pin = base64::decode(value(flag)?)
+                    .filter(|pin| pin.len() == 32)
+                    .ok_or("+tls-pin needs the base64 SHA-256 of a key")?;
+                self.tls_pins.push(pin);
+                self.tls = true;
+            }
+            "https" => self.https = Some(optional.unwrap_or(doh::PATH).to_string()),
+            "https-get" => {
+                self.https_get = true;
+                self.https.get_or_insert_with(|| doh::PATH.to_string());
+            }
+            "nohttps" => self.https = None,
+            "quic" => self.quic = true,
+            "noquic" => self.quic = false,
+            "tries" => {
+                let tries: u32 = parse_value("+tries", value(flag)?)?;
+                self.retries = tries.saturating_sub(1);
+            }
+            _ => return Err(format!("unknown flag +{}", flag)),
+        }
+        Ok(())
+    }
+
+    fn client(&self) -> Result<Client, String> {
+        let server = match &self.server {
+            Some(server) => server.clone(),
+            None => system_resolver().unwrap_or_else(|| "127.0.0.1".to_string()),
+        };
+        let port = match (&self.https, self.quic, self.tls) {
+            (Some(_), _, _) => self.port.unwrap_or(doh::PORT),
+            (None, true, _) => self.port.unwrap_or(doq::PORT),
+            (None, false, true) => self.port.unwrap_or(tcp::TLS_PORT),
+            (None, false, false) => self.port.unwrap_or(tcp::PORT),
+        };
+        let address = (server.as_str(), port)
+            .to_socket_addrs()
+            .and_then(|mut addresses| {
+                addresses
+                    .next()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no addresses"))
+            })
+            .map_err(|e| format!("couldn't get address for {:?}: {}", server, e))?;
+
+        let mut client = Client::new(address);
+        client.tcp = self.tcp;
+        if !self.cookie {
+            client.cookies = None;
+        }
+        client.timeout = self.timeout;
+        client.retries = self.retries;
+        let hostname = self.tls_hostname.as_deref().unwrap_or(&server);
+        if let Some(path) = &self.https {
+            let mut https = self
+                .client_tls(hostname, doh::ALPN)
+                .and_then(Https::new)
+                .map_err(|e| format!("https: {}", e))?;
+            https.path = path.clone();
+            https.get = self.https_get;
+            client.https = Some(https);
+        } else if self.quic {
+            let quic = self
+                .client_tls(hostname, doq::ALPN)
+                .and_then(Quic::new)
+                .map_err(|e| format!("quic: {}", e))?;
+            client.quic = Some(quic);
+        } else if self.tls {
+            let tls = self
+                .client_tls(hostname, tls::ALPN)
+                .map_err(|e| format!("tls: {}", e))?;
+            client.tls = Some(tls);
+        }
+        Ok(client)
+    }
+
+    fn client_tls(&self, hostname: &str, alpn: &[u8]) -> io::Result<ClientTls> {
+        if !self.tls_pins.is_empty() {
+            return ClientTls::pinned(hostname, self.tls_pins.clone(), alpn);
+        }
+        let roots = match &self.tls_ca {
+            Some(path) => Some(tls::load_roots(Path::new(path))?),
+            None => None,
+        };
+        ClientTls::new(hostname, roots, alpn)
+    }
+
+    fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(".")
+    }
+
+    fn build_query(&self, name: &str, r#type: RRType, recurse: bool) -> Packet {
+        let class = self.class.unwrap_or(RRClass::IN);
+        let mut query = client::build_query(name, r#type, class);
+        query.header.should_recurse = recurse;
+        if self.edns {
+            let mut edns = Edns::new(self.bufsize);
+            edns.dnssec_ok = self.dnssec;
+            query.edns = Some(edns);
+        }
+        query
+    }
+
+    fn print(&self, response: &Response, server: SocketAddr) {
+        if self.json {
+            match serde_json::to_string_pretty(&response.packet) {
+                Ok(json) => println!("{}", json),
+                Err(e) => eprintln!(";; couldn't encode the response as JSON: {}", e),
+            }
+            return;
+        }
+        println!(";; Got answer:");
+        println!("{}", response.packet);
+        println!();
+        println!(";; Query time: {} msec", response.elapsed.as_millis());
+        println!(
+            ";; SERVER: {}#{}({}) ({})",
+            server.ip(),
+            server.port(),
+            server.ip(),
+            match (&self.https, self.quic, self.tls, response.over_tcp) {
+                (Some(_), _, _, _) => "HTTPS",
+                (None, true, _, _) => "QUIC",
+                (None, false, true, _) => "TLS",
+                (None, false, false, true) => "TCP",
+                (None, false, false, false) => "UDP",
+            }
+        );
+        println!(";; MSG SIZE  rcvd: {}", response.size);
+    }
+}
+
+// the first nameserver from resolv.conf, like any other stub resolver
+fn system_resolver() -> Option<String> {
+    let conf = fs::read_to_string("/etc/resolv.conf").ok()?;
+    conf.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        match fields.next() {
+            Some("nameserver") => fields.next().map(str::to_string),
+            _ => None,
+        }
+    })
+}
+
+fn query(options: &Options, client: &Client) -> io::Result<()> {
+    let r#type = options.r#type.unwrap_or(RRType::A);
+    let query = options.build_query(options.name(), r#type, options.recurse);
+    if !options.json {
+        println!(
+            "; <<>> dns-rs <<>> {} {}",
+            name::to_presentation(options.name()),
+            r#type
+        );
+    }
+    let response = client.query(&query)?;
+    options.print(&response, client.server);
+    Ok(())
+}
+
+// iterative resolution from the root, printing every referral on the way
+fn trace(options: &Options, resolver: &Client) -> io::Result<()> {
+    let r#type = options.r#type.unwrap_or(RRType::A);
+    let roots = resolver.query(&options.build_query(".", RRType::NS, true))?;
+    print_records(&roots, resolver.server);
+
+    let mut referral = roots.packet;
+    for _ in 0..MAX_REFERRALS {
+        let server = next_server(options, resolver, &referral)?;
+        // nameservers are asked directly, and few of them speak TLS
+        let mut client = resolver.clone();
+        client.server = SocketAddr::new(server, tcp::PORT);
+        client.tls = None;
+        client.https = None;
+        client.quic = None;
+
+        let response = client.query(&options.build_query(options.name(), r#type, false))?;
+        print_records(&response, client.server);
+
+        let packet = response.packet;
+        let is_referral = packet.answers.is_empty()
+            && packet.rcode() == 0
+            && packet
+                .authorities
+                .iter()
+                .any(|record| record.r#type == RRType::NS);
+        if !is_referral {
+            return Ok(());
+        }
+        referral = packet;
+    }
+    Err(io::Error::other("too many referrals"))
+}
+
+// dig's trace output shows the records but not the headers
+fn print_records(response: &Response, server: SocketAddr) {
+    for record in response
+        .packet
+        .answers
+        .iter()
+        .chain(&response.packet.authorities)
+    {
+        println!("{}", record);
+    }
+    // why a server failed is worth showing even without the headers
+    if let Some(edns) = &response.packet.edns {
+        for error in edns.extended_errors() {
+            println!("; EDE: {}", error);
+        }
+    }
+    println!(
+        ";; Received {} bytes from {}#{} in {} ms",
+        response.size,
+        server.ip(),
+        server.port(),
+        response.elapsed.as_millis()
+    );
+    println!();
+}
+
+// picks the first nameserver from a referral that has an address, using the
+// glue when there is any and asking the resolver otherwise
+fn next_server(options: &Options, resolver: &Client, referral: &Packet) -> io::Result<IpAddr> {
+    let nameservers: Vec<String> = referral
+        .answers
+        .iter()
+        .chain(&referral.authorities)
+        .filter(|record| record.r#type == RRType::NS)
+        .map(|record| rdata::to_presentation(RRType::NS, &record.data))
+        .collect();
+
+    for nameserver in &nameservers {
+        let glue = referral.additionals.iter().find_map(|record| {
+            match name::to_presentation(&record.name).eq_ignore_ascii_case(nameserver) {
+                true => address(record.r#type, &record.data),
+                false => None,
+            }
+        });
+        if let Some(address) = glue {
+            return Ok(address);
+        }
+    }
+    for nameserver in &nameservers {
+        let response = resolver.query(&options.build_query(nameserver, RRType::A, true))?;
+        let resolved = response
+            .packet
+            .answers
+            .iter()
+            .find_map(|record| address(record.r#type, &record.data));
+        if let Some(address) = resolved {
+            return Ok(address);
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "no address for any nameserver in the referral",
+    ))
+}
+
+fn address(r#type: RRType, data: &[u8]) -> Option<IpAddr> {
+    match r#type {
+        RRType::A => <[u8; 4]>::try_from(data).ok().map(IpAddr::from),
+        RRType::AAAA => <[u8; 16]>::try_from(data).ok().map(IpAddr::from),
+        _ => None,
+    }
+}
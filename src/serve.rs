@@ -0,0 +1,579 @@
+use std::fs;
+use std::io;
+use std::net::{SocketAddr, TcpListener, UdpSocket};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+use dns_rs_lib::answer::Answer;
+use dns_rs_lib::blocklist::Filter;
+use dns_rs_lib::class::RRClass;
+use dns_rs_lib::client::{self, Client};
+use dns_rs_lib::cookie::RotatingCookies;
+use dns_rs_lib::dnssec;
+use dns_rs_lib::dnstap::{Logger, MessageType, Tap};
+use dns_rs_lib::doh;
+use dns_rs_lib::doq;
+use dns_rs_lib::forward::{Forwarder, Pool};
+use dns_rs_lib::metrics::{self, Metrics};
+use dns_rs_lib::querylog::QueryLog;
+use dns_rs_lib::r#type::RRType;
+use dns_rs_lib::rpz::{PolicyZone, Rpz};
+use dns_rs_lib::rrl::Rrl;
+use dns_rs_lib::server::{self, Server};
+use dns_rs_lib::sign::{self, Signer};
+use dns_rs_lib::tls;
+use dns_rs_lib::validator::Validator;
+use dns_rs_lib::views::View;
+use dns_rs_lib::zone::{Soa, Zone};
+
+use crate::config::{
+    Config, Listener, PolicyZoneConfig, Resolver, Transport, ZoneConfig, ZoneKind,
+};
+use crate::query;
+
+pub const USAGE: &str = "usage: dns-rs serve FILE";
+
+const EXIT_ERROR: i32 = 1;
+
+// how often policy zone files and block lists are checked for changes
+const RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+// RFC 1912's suggested SOA timers, for zones transferred without one
+const DEFAULT_REFRESH: u32 = 3600;
+const DEFAULT_RETRY: u32 = 600;
+
+pub fn run(args: &[String]) -> i32 {
+    let [path] = args else {
+        eprintln!("{}", USAGE);
+        return EXIT_ERROR;
+    };
+    let config = match Config::load(Path::new(path)) {
+        Ok(config) => config,
+        Err(e) => {
+            for line in e.lines() {
+                eprintln!("dns-rs: {}: {}", path, line);
+            }
+            return EXIT_ERROR;
+        }
+    };
+
+    let mut server = Server::new();
+    server.acl = config.acl.clone();
+    server.keys = config.keys.clone();
+    server.rrl = config.rate_limit.clone().map(Rrl::new);
+    server.cookies = Some(RotatingCookies::new());
+    server.metrics = config.metrics.map(|_| Arc::new(Metrics::new()));
+    for view in &config.views {
+        let mut served = View::new(&view.name);
+        served.match_clients = view.match_clients.clone();
+        served.match_destinations = view.match_destinations.clone();
+        served.match_keys = view.match_keys.clone();
+        served.recursion = view.recursion;
+        served.allow_query = view.allow_query.clone();
+        served.allow_recursion = view.allow_recursion.clone();
+        server.add_view(served);
+    }
+    // each view's zones are its own, even where they share a name
+    let zones = config
+        .zones
+        .iter()
+        .map(|zone| (None, zone))
+        .chain(config.views.iter().flat_map(|view| {
+            view.zones
+                .iter()
+                .map(|zone| (Some(view.name.as_str()), zone))
+        }));
+    let mut signers = Vec::new();
+    let mut tracked = Vec::new();
+    for (view, zone) in zones {
+        // a secondary that couldn't be loaded is tried again when it's due
+        if zone.kind == ZoneKind::Secondary || !zone.notify.is_empty() {
+            tracked.push(Tracked::new(view, zone.clone()));
+        }
+        match load_zone(zone).and_then(|loaded| sign_zone(zone, loaded)) {
+            Ok((loaded, signer)) => {
+                server.replace_zone_in(view, loaded);
+                signers.extend(signer.map(|signer| (view, zone.name.clone(), signer)));
+            }
+            Err(e) => {
+                eprintln!("dns-rs: {}: {}", describe(view, zone), e);
+                if zone.kind == ZoneKind::Primary {
+                    return EXIT_ERROR;
+                }
+            }
+        }
+    }
+    // the writer thread finishes the stream when the server goes away,
+    // which it only does along with the process
+    if let Some(dnstap) = &config.logging.dnstap {
+        match query::open_dnstap(dnstap) {
+            Ok((logger, _writer)) => server.dnstap = Some(logger),
+            Err(e) => {
+                eprintln!("dns-rs: couldn't open dnstap output {}: {}", dnstap, e);
+                return EXIT_ERROR;
+            }
+        }
+    }
+    let mut policies: Vec<Policy> = config.rpz.iter().cloned().map(Policy::new).collect();
+    let mut policy_zones = Vec::new();
+    for policy in &mut policies {
+        match policy.load() {
+            Ok(zone) => policy_zones.push(zone),
+            Err(e) if policy.config.file.is_some() => {
+                eprintln!("dns-rs: rpz {}.: {}", policy.config.name, e);
+                return EXIT_ERROR;
+            }
+            // masters that are down are asked again once the zone is due
+            Err(e) => {
+                eprintln!("dns-rs: rpz {}.: {}", policy.config.name, e);
+                policy_zones.push(PolicyZone::new(&policy.config.name));
+            }
+        }
+    }
+    if !policy_zones.is_empty() {
+        server.rpz = Some(Rpz::new(policy_zones));
+    }
+    if let Some(blocklist) = &config.blocklist {
+        let mut filter = Filter::new(blocklist.mode, blocklist.sources.clone());
+        filter.ttl = blocklist.ttl;
+        if let Err(e) = filter.reload() {
+            eprintln!("dns-rs: blocklist: {}", e);
+            return EXIT_ERROR;
+        }
+        server.filter = Some(filter);
+    }
+    if let Some(forwarder) = forwarder(&config.resolver, &server.metrics, &server.dnstap) {
+        let forwarder = Arc::new(forwarder);
+        Forwarder::spawn_probes(&forwarder, config.resolver.probe_interval);
+        server.forwarder = Some(forwarder);
+    }
+    server.client_subnet = config.resolver.client_subnet;
+    if config.resolver.validate {
+        match validator(&config.resolver) {
+            Ok(validator) => server.validator = Some(validator),
+            Err(e) => {
+                eprintln!("dns-rs: trust anchors: {}", e);
+                return EXIT_ERROR;
+            }
+        }
+    }
+    if let Some(path) = &config.logging.query_log {
+        let logging = &config.logging;
+        match QueryLog::to_file(
+            &path.to_string_lossy(),
+            logging.format,
+            logging.max_bytes,
+            logging.keep,
+        ) {
+            Ok(mut log) => {
+                log.sample_rate = logging.sample_rate;
+                log.subnets = logging.subnets.clone();
+                server.query_log = Some(log);
+            }
+            Err(e) => {
+                eprintln!("dns-rs: couldn't open query log {}: {}", path.display(), e);
+                return EXIT_ERROR;
+            }
+        }
+    }
+
+    let mut handles = Vec::new();
+    if let (Some(address), Some(metrics)) = (config.metrics, &server.metrics) {
+        match TcpListener::bind(address) {
+            Ok(listener) => handles.push(metrics::serve(listener, metrics.clone())),
+            Err(e) => {
+                eprintln!("dns-rs: couldn't serve metrics on {}: {}", address, e);
+                return EXIT_ERROR;
+            }
+        }
+        eprintln!("dns-rs: serving metrics on {}", address);
+    }
+    let server = Arc::new(server);
+    for (view, zone, signer) in signers {
+        signer.spawn_resigning(&server, view, &zone, sign::RESIGN_INTERVAL);
+    }
+    if !policies.is_empty() || server.filter.is_some() || !tracked.is_empty() {
+        spawn_reloading(&server, policies, tracked);
+    }
+    for listener in &config.listeners {
+        match listen(listener, &server) {
+            Ok(handle) => handles.push(handle),
+            Err(e) => {
+                eprintln!(
+                    "dns-rs: couldn't listen on {} {}: {}",
+                    listener.transport, listener.address, e
+                );
+                return EXIT_ERROR;
+            }
+        }
+        eprintln!(
+            "dns-rs: listening on {} {}",
+            listener.transport, listener.address
+        );
+    }
+    for handle in handles {
+        let _ = handle.join();
+    }
+    EXIT_ERROR
+}
+
+fn listen(listener: &Listener, server: &Arc<Server>) -> io::Result<JoinHandle<()>> {
+    let address = listener.address;
+    match (listener.transport, &listener.tls) {
+        (Transport::Udp, _) => server::serve_udp(UdpSocket::bind(address)?, server.clone()),
+        (Transport::Tcp, _) => server::serve_tcp(TcpListener::bind(address)?, server.clone()),
+        (Transport::Tls, Some((certificate, private_key))) => {
+            let config = tls::server_config(certificate, private_key, &[tls::ALPN])?;
+            server::serve_tls(TcpListener::bind(address)?, config, server.clone())
+        }
+        (Transport::Https, Some((certificate, private_key))) => {
+            let alpn = [doh::ALPN, doh::HTTP1_ALPN];
+            let config = tls::server_config(certificate, private_key, &alpn)?;
+            server::serve_https(TcpListener::bind(address)?, config, server.clone())
+        }
+        (Transport::Quic, Some((certificate, private_key))) => {
+            let config = tls::server_config(certificate, private_key, &[doq::ALPN])?;
+            server::serve_quic(UdpSocket::bind(address)?, config, server.clone())
+        }
+        (transport, None) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} listeners need a certificate", transport),
+        )),
+    }
+}
+
+// a secondary starts from its copy if there is one and otherwise transfers
+// the zone from the first master that hands it over, keeping a copy
+fn load_zone(config: &ZoneConfig) -> Result<Zone, String> {
+    if let Some(file) = config.file.as_ref().filter(|file| file.is_file()) {
+        let text = fs::read_to_string(file).map_err(|e| format!("{}: {}", file.display(), e))?;
+        return Zone::from_text(&config.name, &text)
+            .map_err(|e| format!("{}: {}", file.display(), e));
+    }
+    if config.kind == ZoneKind::Primary {
+        return Err("no zone file".to_string());
+    }
+    let records = transfer(&config.name, &config.masters)?;
+    save_copy(config, &records);
+    Ok(Zone::new(&config.name, records))
+}
+
+// the transfer worked, so losing the copy isn't fatal
+fn save_copy(config: &ZoneConfig, records: &[Answer]) {
+    let Some(file) = &config.file else {
+        return;
+    };
+    let text: String = records
+        .iter()
+        .map(|record| format!("{}\n", record))
+        .collect();
+    if let Err(e) = fs::write(file, text) {
+        eprintln!("dns-rs: couldn't write {}: {}", file.display(), e);
+    }
+}
+
+fn describe(view: Option<&str>, zone: &ZoneConfig) -> String {
+    match view {
+        Some(view) => format!("view {} zone {}.", view, zone.name),
+        None => format!("zone {}.", zone.name),
+    }
+}
+
+// from the first master that hands the zone over
+fn transfer(zone: &str, masters: &[SocketAddr]) -> Result<Vec<Answer>, String> {
+    let mut error = "no masters".to_string();
+    for master in masters {
+        match Client::new(*master).transfer(zone) {
+            Ok(records) => return Ok(records),
+            Err(e) => error = format!("transfer from {} failed: {}", master, e),
+        }
+    }
+    Err(error)
+}
+
+// the zone's serial at the first master that answers for it
+fn master_serial(zone: &str, masters: &[SocketAddr]) -> Result<u32, String> {
+    let mut error = "no masters".to_string();
+    let mut query = client::build_query(zone, RRType::SOA, RRClass::IN);
+    query.header.should_recurse = false;
+    for master in masters {
+        let soa = match Client::new(*master).query(&query) {
+            Ok(response) => response
+                .packet
+                .answers
+                .iter()
+                .find(|record| record.r#type == RRType::SOA)
+                .and_then(|soa| Soa::from_data(&soa.data)),
+            Err(e) => {
+                error = format!("SOA query to {} failed: {}", master, e);
+                continue;
+            }
+        };
+        match soa {
+            Some(soa) => return Ok(soa.serial),
+            None => error = format!("{} has no SOA for the zone", master),
+        }
+    }
+    Err(error)
+}
+
+// serial number arithmetic, RFC 1982: the serial can wrap around
+fn is_newer(serial: u32, than: u32) -> bool {
+    (serial.wrapping_sub(than) as i32) > 0
+}
+
+// the refresh interval after a load, the retry interval after a failed one
+fn next_due(loaded: bool, soa: Option<Soa>) -> Instant {
+    let wait = match (loaded, soa) {
+        (true, Some(soa)) => soa.refresh,
+        (true, None) => DEFAULT_REFRESH,
+        (false, Some(soa)) => soa.retry,
+        (false, None) => DEFAULT_RETRY,
+    };
+    Instant::now() + Duration::from_secs(wait.into())
+}
+
+// a zone kept in step with other servers: a secondary asks its masters for
+// their serial when it's due and transfers the zone again if it's newer,
+// and the notify targets are sent a NOTIFY each time the serial changes,
+// signing it again included, RFC 1996
+struct Tracked {
+    view: Option<String>,
+    config: ZoneConfig,
+    due: Instant,
+    notified: Option<u32>,
+}
+
+impl Tracked {
+    // a secondary checks straight away, its copy may be out of date
+    fn new(view: Option<&str>, config: ZoneConfig) -> Self {
+        Self {
+            view: view.map(str::to_string),
+            config,
+            due: Instant::now(),
+            notified: None,
+        }
+    }
+
+    fn refresh(&mut self, server: &Server) {
+        if self.config.kind != ZoneKind::Secondary || Instant::now() < self.due {
+            return;
+        }
+        let view = self.view.as_deref();
+        let current = server
+            .zone_in(view, &self.config.name)
+            .and_then(|zone| zone.soa());
+        let result = master_serial(&self.config.name, &self.config.masters).and_then(|serial| {
+            if current.is_some_and(|soa| !is_newer(serial, soa.serial)) {
+                return Ok(current);
+            }
+            let records = transfer(&self.config.name, &self.config.masters)?;
+            save_copy(&self.config, &records);
+            let zone = Zone::new(&self.config.name, records);
+            let soa = zone.soa();
+            server.replace_zone_in(view, zone);
+            eprintln!(
+                "dns-rs: {}: transferred serial {}",
+                describe(view, &self.config),
+                serial
+            );
+            Ok(soa)
+        });
+        self.due = match result {
+            Ok(soa) => next_due(true, soa),
+            Err(e) => {
+                eprintln!("dns-rs: {}: {}", describe(view, &self.config), e);
+                next_due(false, current)
+            }
+        };
+    }
+
+    // the targets are sent theirs in the background, as each can take a
+    // few tries to get through
+    fn notify(&mut self, server: &Server) {
+        if self.config.notify.is_empty() {
+            return;
+        }
+        let zone = server.zone_in(self.view.as_deref(), &self.config.name);
+        let Some(serial) = zone.and_then(|zone| zone.soa()).map(|soa| soa.serial) else {
+            return;
+        };
+        if self.notified == Some(serial) {
+            return;
+        }
+        self.notified = Some(serial);
+        let name = self.config.name.clone();
+        let targets = self.config.notify.clone();
+        let described = describe(self.view.as_deref(), &self.config);
+        thread::spawn(move || {
+            for target in targets {
+                if let Err(e) = Client::new(target).notify(&name) {
+                    eprintln!("dns-rs: {}: notify to {} failed: {}", described, target, e);
+                }
+            }
+        });
+    }
+}
+
+// a response policy zone and when it's next loaded: once its file changes,
+// or at its SOA's refresh interval for one from masters, or the retry
+// interval after a failed transfer
+struct Policy {
+    config: PolicyZoneConfig,
+    modified: Option<SystemTime>,
+    due: Instant,
+    soa: Option<Soa>,
+}
+
+impl Policy {
+    fn new(config: PolicyZoneConfig) -> Self {
+        Self {
+            config,
+            modified: None,
+            due: Instant::now(),
+            soa: None,
+        }
+    }
+
+    fn is_due(&self) -> bool {
+        match &self.config.file {
+            Some(file) => modified(file) != self.modified,
+            None => Instant::now() >= self.due,
+        }
+    }
+
+    fn load(&mut self) -> Result<PolicyZone, String> {
+        let Some(file) = &self.config.file else {
+            let loaded = transfer(&self.config.name, &self.config.masters).and_then(|records| {
+                self.soa = records
+                    .iter()
+                    .find(|record| record.r#type == RRType::SOA)
+                    .and_then(|soa| Soa::from_data(&soa.data));
+                PolicyZone::from_records(&self.config.name, &records)
+            });
+            self.due = next_due(loaded.is_ok(), self.soa);
+            return loaded;
+        };
+        self.modified = modified(file);
+        let text = fs::read_to_string(file).map_err(|e| format!("{}: {}", file.display(), e))?;
+        PolicyZone::from_zone_file(&self.config.name, &text)
+            .map_err(|e| format!("{}: {}", file.display(), e))
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+// swaps in policy zones, block lists and secondary zones as they're loaded
+// again, and sends NOTIFYs for zones that changed, until the server is
+// dropped. Whatever fails to load keeps what it had before
+fn spawn_reloading(
+    server: &Arc<Server>,
+    mut policies: Vec<Policy>,
+    mut zones: Vec<Tracked>,
+) -> JoinHandle<()> {
+    let lists = |server: &Server| -> Vec<Option<SystemTime>> {
+        let sources = server.filter.iter().flat_map(|filter| &filter.sources);
+        sources.map(|source| modified(&source.path)).collect()
+    };
+    let mut loaded = lists(server);
+    let weak = Arc::downgrade(server);
+    // the zones are seen to straight away, so the notify targets hear about
+    // the zones as loaded without waiting for the first interval
+    thread::spawn(move || loop {
+        let Some(server) = weak.upgrade() else {
+            return;
+        };
+        for zone in &mut zones {
+            zone.refresh(&server);
+            zone.notify(&server);
+        }
+        if let Some(rpz) = &server.rpz {
+            for policy in policies.iter_mut().filter(|policy| policy.is_due()) {
+                match policy.load() {
+                    Ok(zone) => {
+                        rpz.replace_zone(zone);
+                        eprintln!("dns-rs: reloaded rpz {}.", policy.config.name);
+                    }
+                    Err(e) => eprintln!("dns-rs: rpz {}.: {}", policy.config.name, e),
+                }
+            }
+        }
+        if let Some(filter) = &server.filter {
+            let modified = lists(&server);
+            if modified != loaded {
+                loaded = modified;
+                match filter.reload() {
+                    Ok(blocklist) => eprintln!(
+                        "dns-rs: reloaded the blocklist, {} names blocked",
+                        blocklist.blocked_len()
+                    ),
+                    Err(e) => eprintln!("dns-rs: blocklist: {}", e),
+                }
+            }
+        }
+        drop(server);
+        thread::sleep(RELOAD_INTERVAL);
+    })
+}
+
+// a zone with keys is served signed, and its signer kept to sign it again
+// before the signatures run out
+fn sign_zone(config: &ZoneConfig, zone: Zone) -> Result<(Zone, Option<Signer>), String> {
+    let Some(dir) = &config.keys else {
+        return Ok((zone, None));
+    };
+    let mut signer = Signer::new(sign::load_keys(dir, &config.name)?);
+    if config.nsec3 {
+        signer.nsec3 = Some(sign::recommended_nsec3());
+    }
+    let signed = signer.sign(&zone, dnssec::unix_time() as u32)?;
+    Ok((signed, Some(signer)))
+}
+
+// the root's anchors unless the configuration names a file of its own
+fn validator(resolver: &Resolver) -> Result<Validator, String> {
+    let Some(path) = &resolver.trust_anchor else {
+        return Ok(Validator::with_root_anchors());
+    };
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Validator::from_text(&text).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// the forwarders' queries and answers are logged along with the clients'
+fn pool(
+    resolver: &Resolver,
+    servers: &[SocketAddr],
+    metrics: &Option<Arc<Metrics>>,
+    dnstap: &Option<Logger>,
+) -> Pool {
+    let mut pool = Pool::new(servers);
+    for upstream in &mut pool.upstreams {
+        upstream.client.timeout = resolver.timeout;
+        upstream.client.retries = resolver.retries;
+        upstream.client.metrics = metrics.clone();
+        upstream.client.dnstap = dnstap
+            .clone()
+            .map(|logger| Tap::new(logger, MessageType::ForwarderQuery));
+    }
+    pool
+}
+
+fn forwarder(
+    resolver: &Resolver,
+    metrics: &Option<Arc<Metrics>>,
+    dnstap: &Option<Logger>,
+) -> Option<Forwarder> {
+    if resolver.forwarders.is_empty() && resolver.forward_zones.is_empty() {
+        return None;
+    }
+    let mut forwarder = Forwarder::new(pool(resolver, &resolver.forwarders, metrics, dnstap));
+    for (zone, servers) in &resolver.forward_zones {
+        forwarder.add_zone(zone, pool(resolver, servers, metrics, dnstap));
+    }
+    Some(forwarder)
+}
@@ -0,0 +1,190 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use dns_rs_lib::answer::Answer;
+use dns_rs_lib::class::RRClass;
+use dns_rs_lib::dnssec::{
+    self, Algorithm, DigestType, Ds, Nsec3Param, SigningKey, MAX_NSEC3_ITERATIONS, OPT_OUT,
+    SECURE_ENTRY_POINT, ZONE_KEY,
+};
+use dns_rs_lib::name;
+use dns_rs_lib::r#type::RRType;
+use dns_rs_lib::sign::{self, Signer};
+use dns_rs_lib::zone::Zone;
+
+use crate::decode;
+
+pub const USAGE: &str = "usage: dns-rs sign-zone [-K DIR] [-a ALGORITHM] [-e DAYS] [-o OUTPUT]
+       [+nsec3] [+opt-out] [-s SALT] [-i ITERATIONS] ZONE FILE";
+
+const EXIT_ERROR: i32 = 1;
+
+#[derive(Debug)]
+struct Options {
+    zone: String,
+    path: PathBuf,
+    // where the keys are, and where new ones go if there aren't any
+    key_dir: PathBuf,
+    algorithm: Algorithm,
+    // in seconds
+    validity: u32,
+    output: PathBuf,
+    nsec3: Option<Nsec3Param>,
+}
+
+pub fn run(args: &[String]) -> i32 {
+    let options = match parse_args(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("dns-rs: {}\n{}", e, USAGE);
+            return EXIT_ERROR;
+        }
+    };
+    match sign_zone(&options) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("dns-rs: {}", e);
+            EXIT_ERROR
+        }
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut positional = Vec::new();
+    let mut key_dir = PathBuf::from(".");
+    let mut algorithm = Algorithm::EcdsaP256Sha256;
+    let mut validity = sign::DEFAULT_VALIDITY / 86_400;
+    let mut output = None;
+    let mut nsec3 = false;
+    let mut param = sign::recommended_nsec3();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("option {} needs a value", arg))
+        };
+        match arg.as_str() {
+            "-K" => key_dir = PathBuf::from(value()?),
+            "-a" => algorithm = value()?.parse()?,
+            "-e" => {
+                let days = value()?;
+                validity = days
+                    .parse()
+                    .ok()
+                    .filter(|days| (1..=365).contains(days))
+                    .ok_or_else(|| {
+                        format!("invalid validity {:?}, expected 1 to 365 days", days)
+                    })?;
+            }
+            "-o" => output = Some(PathBuf::from(value()?)),
+            "+nsec3" => nsec3 = true,
+            "+opt-out" => {
+                param.flags |= OPT_OUT;
+                nsec3 = true;
+            }
+            "-s" => {
+                let salt = value()?;
+                param.salt = match salt.as_str() {
+                    "-" => Vec::new(),
+                    salt => decode::from_hex(salt)?,
+                };
+                if param.salt.len() > 255 {
+                    return Err("salt is longer than 255 bytes".to_string());
+                }
+                nsec3 = true;
+            }
+            "-i" => {
+                let iterations = value()?;
+                param.iterations = iterations
+                    .parse()
+                    .ok()
+                    .filter(|iterations| *iterations <= MAX_NSEC3_ITERATIONS)
+                    .ok_or_else(|| format!("invalid iterations {:?}", iterations))?;
+                nsec3 = true;
+            }
+            _ if arg.starts_with(['-', '+']) && arg.len() > 1 => {
+                return Err(format!("unknown option {}", arg))
+            }
+            _ => positional.push(arg.clone()),
+        }
+    }
+    let [zone, path] = positional.as_slice() else {
+        return Err("expected a zone name and its file".to_string());
+    };
+    let path = PathBuf::from(path);
+    let output = output.unwrap_or_else(|| {
+        let mut signed = path.clone().into_os_string();
+        signed.push(".signed");
+        PathBuf::from(signed)
+    });
+    Ok(Options {
+        zone: zone.trim_end_matches('.').to_ascii_lowercase(),
+        path,
+        key_dir,
+        algorithm,
+        validity: validity * 86_400,
+        output,
+        nsec3: nsec3.then_some(param),
+    })
+}
+
+fn sign_zone(options: &Options) -> Result<(), String> {
+    let in_file = |e: String| format!("{}: {}", options.path.display(), e);
+    let text = fs::read_to_string(&options.path).map_err(|e| in_file(e.to_string()))?;
+    let zone = Zone::from_text(&options.zone, &text).map_err(in_file)?;
+
+    let mut keys = sign::load_keys(&options.key_dir, &options.zone)?;
+    if keys.is_empty() {
+        keys = generate_keys(options)?;
+    }
+    let mut signer = Signer::new(keys);
+    signer.validity = options.validity;
+    signer.nsec3 = options.nsec3.clone();
+    let signed = signer
+        .sign(&zone, dnssec::unix_time() as u32)
+        .map_err(in_file)?;
+
+    fs::write(&options.output, to_master_file(&signed))
+        .map_err(|e| format!("couldn't write {}: {}", options.output.display(), e))?;
+    eprintln!("dns-rs: wrote {}", options.output.display());
+    // what the parent needs to publish for the chain of trust
+    for key in signer.keys.iter().filter(|key| key.dnskey().is_sep()) {
+        let ds = Ds::from_key(&options.zone, &key.dnskey(), DigestType::Sha256);
+        let ttl = sign::DEFAULT_DNSKEY_TTL;
+        println!(
+            "{}",
+            Answer::new(&options.zone, RRType::DS, RRClass::IN, ttl, ds.to_data())
+        );
+    }
+    Ok(())
+}
+
+// a key-signing and a zone-signing key, saved alongside any others
+fn generate_keys(options: &Options) -> Result<Vec<SigningKey>, String> {
+    let mut keys = Vec::new();
+    for flags in [ZONE_KEY | SECURE_ENTRY_POINT, ZONE_KEY] {
+        let key = SigningKey::generate(options.algorithm, flags)?;
+        let name = save(&options.key_dir, &options.zone, &key)?;
+        eprintln!("dns-rs: generated {}", name);
+        keys.push(key);
+    }
+    Ok(keys)
+}
+
+fn save(dir: &Path, zone: &str, key: &SigningKey) -> Result<String, String> {
+    sign::save_key(dir, zone, key, sign::DEFAULT_DNSKEY_TTL)
+        .map_err(|e| format!("couldn't save a key in {}: {}", dir.display(), e))
+}
+
+// the records by owner in canonical order, each name's in the order the
+// signer left them
+fn to_master_file(zone: &Zone) -> String {
+    let mut records: Vec<&Answer> = zone.records.iter().collect();
+    records.sort_by(|a, b| name::canonical_cmp(&a.name, &b.name));
+    let mut text = format!("$ORIGIN {}\n", name::to_presentation(&zone.name));
+    for record in records {
+        text.push_str(&format!("{}\n", record));
+    }
+    text
+}
@@ -0,0 +1,412 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+use dns_rs_lib::dnssec::{Algorithm, SigningKey, ZONE_KEY};
+use dns_rs_lib::sign;
+
+fn dns_rs(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_dns-rs"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+// a directory with the config and the files it refers to
+fn config_dir(name: &str, config: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("dns-rs-config-{}-{}", name, std::process::id()));
+    fs::create_dir_all(dir.join("zones")).unwrap();
+    fs::write(
+        dir.join("zones/example.com.zone"),
+        "$TTL 300\n@ IN SOA ns1 hostmaster 1 7200 3600 1209600 300\n@ NS ns1\nns1 A 192.0.2.53\n",
+    )
+    .unwrap();
+    fs::write(dir.join("cert.pem"), "").unwrap();
+    fs::write(dir.join("key.pem"), "").unwrap();
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    fs::write(dir.join("server.pem"), certified.cert.pem()).unwrap();
+    fs::write(
+        dir.join("server.key"),
+        certified.signing_key.serialize_pem(),
+    )
+    .unwrap();
+    fs::write(dir.join("dns-rs.toml"), config).unwrap();
+    dir
+}
+
+const CONFIG: &str = r#"
+[[listener]]
+address = "0.0.0.0"
+transport = "udp"
+
+[[listener]]
+address = "0.0.0.0"
+transport = "tcp"
+
+[[listener]]
+address = "::"
+transport = "tcp"
+port = 5353
+
+[[listener]]
+address = "0.0.0.0"
+transport = "tls"
+tls_certificate = "server.pem"
+tls_key = "server.key"
+
+[[listener]]
+address = "0.0.0.0"
+transport = "https"
+tls_certificate = "server.pem"
+tls_key = "server.key"
+
+[[listener]]
+address = "0.0.0.0"
+transport = "quic"
+tls_certificate = "server.pem"
+tls_key = "server.key"
+
+[[key]]
+name = "local-key"
+secret = "bG9jYWwta2V5IHNlY3JldA=="
+
+[[key]]
+name = "xfr-key."
+algorithm = "hmac-sha512"
+secret = "eGZyLWtleSBzZWNyZXQ="
+
+[[zone]]
+name = "example.com"
+type = "primary"
+file = "zones/example.com.zone"
+notify = ["192.0.2.2"]
+keys = "keys"
+nsec3 = true
+
+[[zone]]
+name = "example.net."
+type = "secondary"
+masters = ["192.0.2.1", "[2001:db8::1]:5353"]
+
+[[rpz]]
+name = "rpz.local"
+file = "zones/rpz.local.zone"
+
+[[rpz]]
+name = "feed.example"
+masters = ["192.0.2.1"]
+
+[blocklist]
+mode = "sinkhole"
+sinkhole = ["192.0.2.80", "2001:db8::80"]
+block = ["zones/ads.txt"]
+allow = ["zones/allow.txt"]
+
+[resolver]
+forwarders = ["1.1.1.1", "9.9.9.9:53"]
+timeout = 1.5
+validate = true
+client_subnet = true
+client_subnet_v4 = 20
+
+[[resolver.forward_zone]]
+name = "corp.example"
+forwarders = ["10.0.0.53"]
+
+[acl]
+recursion = ["!10.0.0.1", "10.0.0.0/8", "key local-key"]
+transfer = ["key xfr-key"]
+
+[rate_limit]
+responses_per_second = 10
+slip = 0
+window = 5
+
+[logging]
+query_log = "queries.log"
+format = "logfmt"
+sample_rate = 0.5
+keep = 0
+
+[metrics]
+listen = "127.0.0.1"
+"#;
+
+#[test]
+fn test_check_config() {
+    let dir = config_dir("valid", CONFIG);
+    fs::write(
+        dir.join("zones/rpz.local.zone"),
+        "$TTL 60\nads.example.net CNAME .\n",
+    )
+    .unwrap();
+    fs::write(dir.join("zones/ads.txt"), "0.0.0.0 ads.example.net\n").unwrap();
+    fs::write(dir.join("zones/allow.txt"), "ok.ads.example.net\n").unwrap();
+    fs::create_dir_all(dir.join("keys")).unwrap();
+    let key = SigningKey::generate(Algorithm::Ed25519, ZONE_KEY).unwrap();
+    sign::save_key(&dir.join("keys"), "example.com", &key, 3600).unwrap();
+    let output = dns_rs(&["check-config", dir.join("dns-rs.toml").to_str().unwrap()]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(stdout.contains("listener udp 0.0.0.0:53\n"));
+    assert!(stdout.contains("listener tcp [::]:5353\n"));
+    assert!(stdout.contains(&format!(
+        "listener tls 0.0.0.0:853 certificate {} key {}\n",
+        dir.join("server.pem").display(),
+        dir.join("server.key").display()
+    )));
+    assert!(stdout.contains("listener https 0.0.0.0:443 certificate "));
+    assert!(stdout.contains("listener quic 0.0.0.0:853 certificate "));
+    assert!(stdout.contains("key local-key hmac-sha256\nkey xfr-key hmac-sha512\n"));
+    assert!(!stdout.contains("bG9jYWwta2V5IHNlY3JldA=="));
+    assert!(
+        stdout.contains("zone example.net. secondary masters 192.0.2.1:53, [2001:db8::1]:5353\n")
+    );
+    assert!(stdout.contains(&format!(
+        " notify 192.0.2.2:53 signed with keys from {} and NSEC3\n",
+        dir.join("keys").display()
+    )));
+    assert!(stdout.contains(&format!(
+        "rpz rpz.local. file {}\n",
+        dir.join("zones/rpz.local.zone").display()
+    )));
+    assert!(stdout.contains("rpz feed.example. masters 192.0.2.1:53\n"));
+    assert!(stdout.contains(&format!(
+        "blocklist sinkhole 192.0.2.80, 2001:db8::80 ttl 60 block {} allow {}\n",
+        dir.join("zones/ads.txt").display(),
+        dir.join("zones/allow.txt").display()
+    )));
+    assert!(stdout.contains("forwarders 1.1.1.1:53, 9.9.9.9:53\n"));
+    assert!(stdout.contains("forward corp.example. to 10.0.0.53:53\n"));
+    assert!(stdout.contains("resolver timeout 1.5s retries 1 probe interval 30s\n"));
+    assert!(stdout.contains("validate with the root's trust anchors\n"));
+    assert!(stdout.contains("send client subnets of /20 and /56\n"));
+    assert!(stdout.contains("allow recursion !10.0.0.1/32, 10.0.0.0/8, key local-key\n"));
+    assert!(stdout.contains("allow update none\n"));
+    assert!(stdout.contains(
+        "rate limit 10 responses 5 nxdomains 5 errors per second slip 0 window 5s prefixes /24 and /56\n"
+    ));
+    assert!(stdout.contains("logfmt sample rate 0.5"));
+    assert!(stdout.contains(" keep 0\n"));
+    assert!(stdout.contains("metrics 127.0.0.1:9153\n"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_check_config_views() {
+    let config = r#"
+[[listener]]
+address = "0.0.0.0"
+
+[[key]]
+name = "internal-key"
+secret = "aW50ZXJuYWwta2V5IHNlY3JldA=="
+
+[[view]]
+name = "internal"
+match_clients = ["10.0.0.0/8", "192.0.2.1"]
+match_keys = ["internal-key"]
+allow_recursion = ["any"]
+
+[[view.zone]]
+name = "example.com"
+type = "primary"
+file = "zones/example.com.zone"
+
+[[view]]
+name = "external"
+match_destinations = ["192.0.2.53"]
+recursion = false
+
+[[view.zone]]
+name = "example.com"
+type = "secondary"
+masters = ["192.0.2.1"]
+
+[acl]
+query = ["!192.0.2.66", "any"]
+"#;
+    let dir = config_dir("views", config);
+    let output = dns_rs(&["check-config", dir.join("dns-rs.toml").to_str().unwrap()]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(stdout.contains(
+        "view internal clients 10.0.0.0/8, 192.0.2.1/32 keys internal-key allow query !192.0.2.66/32, any recursion any\n"
+    ));
+    assert!(stdout.contains(&format!(
+        "view internal zone example.com. primary file {}\n",
+        dir.join("zones/example.com.zone").display()
+    )));
+    // the [acl] lists are the default
+    assert!(stdout.contains(
+        "view external destinations 192.0.2.53/32 allow query !192.0.2.66/32, any no recursion\n"
+    ));
+    assert!(stdout.contains("view external zone example.com. secondary masters 192.0.2.1:53\n"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_check_config_errors() {
+    let config = r#"
+[[listener]]
+address = "0.0.0.0:53"
+
+[[listener]]
+address = "0.0.0.0"
+transport = "quic"
+tls_certificate = "cert.pem"
+
+[[listener]]
+address = "127.0.0.1"
+transport = "udp"
+
+[[listener]]
+address = "127.0.0.1"
+transport = "tcp"
+port = 53
+tls_key = "key.pem"
+
+[[listener]]
+address = "127.0.0.1"
+transport = "tls"
+tls_certificate = "cert.pem"
+tls_key = "key.pem"
+
+[[listener]]
+address = "127.0.0.1"
+transport = "tls"
+port = 8853
+tls_certificate = "server.pem"
+tls_key = "key.pem"
+
+[[zone]]
+name = "example.com"
+type = "primary"
+file = "zones/missing.zone"
+
+[[zone]]
+name = "example.net"
+type = "secondary"
+keys = "zones"
+
+[[zone]]
+name = "example.org"
+type = "primary"
+file = "zones/example.com.zone"
+keys = "zones"
+
+[[zone]]
+name = "example.edu"
+type = "primary"
+file = "zones/example.com.zone"
+nsec3 = true
+
+[[rpz]]
+name = "both.local"
+file = "zones/example.com.zone"
+masters = ["192.0.2.1"]
+
+[[rpz]]
+name = "neither.local"
+
+[[rpz]]
+name = "missing.local"
+file = "zones/missing.local.zone"
+
+[blocklist]
+mode = "sinkhole"
+sinkhole = ["192.0.2.80", "192.0.2.81"]
+allow = ["zones/allow.txt"]
+
+[resolver]
+trust_anchor = "cert.pem"
+client_subnet_v6 = 64
+
+[[key]]
+name = "bad-key"
+algorithm = "hmac-md5"
+secret = "not base64!"
+
+[[view]]
+name = "internal"
+match_clients = ["10.0.0.0/40"]
+match_keys = ["missing-key"]
+
+[[view.zone]]
+name = "example.com"
+type = "primary"
+file = "zones/missing.zone"
+
+[acl]
+query = ["any", "10.0.0.0/33"]
+transfer = ["key missing-key"]
+
+[rate_limit]
+ipv6_prefix = 129
+window = 0
+
+[logging]
+sample_rate = 2.0
+"#;
+    let dir = config_dir("invalid", config);
+    let output = dns_rs(&["check-config", dir.join("dns-rs.toml").to_str().unwrap()]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for key in [
+        "listener[0].address: invalid address \"0.0.0.0:53\", the port goes in port",
+        "listener[1].tls_key: needed for quic listeners",
+        "listener[3].tls_key: not used by tcp listeners",
+        "listener[4].tls_certificate: ",
+        "cert.pem: no certificates",
+        "listener[5].tls_certificate: ",
+        "key.pem: ",
+        "zone[0].file: ",
+        "zone[1].masters: secondary zones need at least one master",
+        "zone[1].keys: only primary zones are signed here",
+        "zone[2].keys: no keys for example.org. in ",
+        "zone[3].nsec3: only used for zones signed with keys",
+        "rpz[0].masters: policy zones come from a file or from masters, not both",
+        "rpz[1].file: needed for policy zones without masters",
+        "rpz[2].file: ",
+        "blocklist.sinkhole[1]: only one address of each family is used",
+        "blocklist.block: at least one list is needed",
+        "blocklist.allow[0]: ",
+        "resolver.trust_anchor: trust anchors are only used with validate = true",
+        "resolver.client_subnet_v6: only used with client_subnet = true",
+        "key[0].algorithm: unknown TSIG algorithm \"hmac-md5\"",
+        "key[0].secret: expected the secret in base64",
+        "zone: zones go in the views once there are any",
+        "view[0].match_clients[0]: invalid prefix length \"40\"",
+        "view[0].match_keys[0]: no [[key]] named missing-key",
+        "view[0].zone[0].file: ",
+        "acl.query[1]: invalid prefix length \"33\"",
+        "acl.transfer[0]: no [[key]] named missing-key",
+        "rate_limit.ipv6_prefix: 129 isn't between 0 and 128",
+        "rate_limit.window: 0 isn't a positive number of seconds",
+        "logging.sample_rate: 2 isn't between 0 and 1",
+    ] {
+        assert!(stderr.contains(key), "{:?} not in {}", key, stderr);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_check_config_syntax_errors() {
+    let dir = config_dir("syntax", "[[listener]]\naddress = \"::\"\nprot = 53\n");
+    let output = dns_rs(&["check-config", dir.join("dns-rs.toml").to_str().unwrap()]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    // the parser's error points at the line with the unknown key
+    assert!(stderr.contains("line 3"), "{}", stderr);
+    assert!(stderr.contains("prot"), "{}", stderr);
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,83 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+use dns_rs_lib::base64;
+use dns_rs_lib::class::RRClass;
+use dns_rs_lib::client;
+use dns_rs_lib::r#type::RRType;
+use dns_rs_lib::rdata;
+
+fn dns_rs(args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_dns-rs"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn query() -> Vec<u8> {
+    client::build_query("www.example.com", RRType::AAAA, RRClass::IN).to_bytes()
+}
+
+#[test]
+fn test_decode_hex() {
+    let hex = rdata::to_hex(&query());
+    let output = dns_rs(&["decode", "--hex", &hex], b"");
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("    12      17  question 1 of 1 name   www.example.com."));
+    assert!(stdout.contains("    29       2  question 1 of 1 type   AAAA"));
+    assert!(stdout.contains(";www.example.com.\t\tIN\tAAAA"));
+}
+
+#[test]
+fn test_decode_stdin() {
+    let output = dns_rs(&["decode"], &query());
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("QDCOUNT"));
+
+    let hex = rdata::to_hex(&query());
+    let output = dns_rs(&["decode", "-"], hex.as_bytes());
+    assert!(output.status.success());
+}
+
+#[test]
+fn test_decode_doh_url() {
+    let url = format!(
+        "https://dns.example.com/dns-query?dns={}",
+        base64::encode_url(&query())
+    );
+    let output = dns_rs(&["decode", "--base64", &url], b"");
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("www.example.com."));
+}
+
+#[test]
+fn test_decode_reports_errors() {
+    let message = query();
+    let output = dns_rs(&["decode", "--format", "raw"], &message[..20]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("malformed message at offset 16: question 1 of 1: label runs past the end")
+    );
+    // the header was still shown
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("ARCOUNT"));
+
+    let output = dns_rs(&["decode", "--hex", "abc"], b"");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("odd number of hex digits"));
+}
@@ -0,0 +1,164 @@
+use std::env;
+use std::fs;
+use std::net::UdpSocket;
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use dns_rs_lib::answer::Answer;
+use dns_rs_lib::class::RRClass;
+use dns_rs_lib::dnstap::{MessageType, Protocol, Reader};
+use dns_rs_lib::packet::Packet;
+use dns_rs_lib::r#type::RRType;
+
+fn dns_rs(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_dns-rs"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn respond(query: &[u8]) -> Vec<u8> {
+    let query = Packet::from_buf(query);
+    let mut header = query.header.clone();
+    header.response = true;
+    let mut response = Packet::new(header);
+    response.questions = query.questions.clone();
+    response.answers.push(Answer::new(
+        "example.com",
+        RRType::A,
+        RRClass::IN,
+        300,
+        vec![192, 0, 2, 1],
+    ));
+    response.to_bytes()
+}
+
+#[test]
+fn test_query_logs_dnstap() {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let port = socket.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        let mut buf = [0u8; 512];
+        let (len, from) = socket.recv_from(&mut buf).unwrap();
+        socket.send_to(&respond(&buf[..len]), from).unwrap();
+    });
+
+    let path = env::temp_dir().join(format!("dns-rs-test-{}.dnstap", std::process::id()));
+    let path = path.to_str().unwrap();
+    let output = dns_rs(&[
+        "query",
+        "@127.0.0.1",
+        "-p",
+        &port.to_string(),
+        "example.com",
+        &format!("+dnstap={}", path),
+    ]);
+    assert!(output.status.success());
+    server.join().unwrap();
+
+    let frames: Vec<_> = Reader::new(fs::File::open(path).unwrap())
+        .unwrap()
+        .collect::<std::io::Result<_>>()
+        .unwrap();
+    assert_eq!(frames.len(), 2);
+    let (query, response) = (&frames[0].message, &frames[1].message);
+    assert_eq!(query.r#type, MessageType::ToolQuery);
+    assert_eq!(response.r#type, MessageType::ToolResponse);
+    assert_eq!(query.protocol, Some(Protocol::Udp));
+    assert_eq!(response.response_address.unwrap().port(), port);
+    assert!(response.query_time <= response.response_time);
+    assert!(frames[0].version.is_some());
+
+    let output = dns_rs(&["dnstap-read", path]);
+    fs::remove_file(path).unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].contains(" TQ 127.0.0.1:"));
+    // with EDNS and the client cookie
+    assert!(lines[0].ends_with(&format!("-> 127.0.0.1:{} UDP 52b example.com. IN A", port)));
+    assert!(lines[1].ends_with("UDP 56b example.com. IN A NOERROR 1 answers"));
+}
+
+// the messages written so far, which stop short of a stop frame while the
+// server is still running
+fn message_types(path: &std::path::Path) -> Vec<MessageType> {
+    let Ok(file) = fs::File::open(path) else {
+        return Vec::new();
+    };
+    let Ok(reader) = Reader::new(file) else {
+        return Vec::new();
+    };
+    reader
+        .map_while(Result::ok)
+        .map(|frame| frame.message.r#type)
+        .collect()
+}
+
+#[test]
+fn test_serve_logs_forwarder_dnstap() {
+    let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let forwarder = upstream.local_addr().unwrap();
+    thread::spawn(move || {
+        let mut buf = [0u8; 512];
+        while let Ok((len, from)) = upstream.recv_from(&mut buf) {
+            upstream.send_to(&respond(&buf[..len]), from).unwrap();
+        }
+    });
+
+    let port = UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let dir = env::temp_dir().join(format!("dns-rs-serve-dnstap-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let config = format!(
+        "[[listener]]\naddress = \"127.0.0.1\"\nport = {}\n\n[resolver]\nforwarders = [\"{}\"]\n\n[logging]\ndnstap = \"server.dnstap\"\n",
+        port, forwarder
+    );
+    fs::write(dir.join("dns-rs.toml"), config).unwrap();
+    let mut server = Command::new(env!("CARGO_BIN_EXE_dns-rs"))
+        .args(["serve", dir.join("dns-rs.toml").to_str().unwrap()])
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let port = port.to_string();
+    let args = ["query", "@127.0.0.1", "-p", &port, "+timeout=1", "+retry=0"];
+    let mut types = Vec::new();
+    for _ in 0..50 {
+        dns_rs(&[&args[..], &["example.com"]].concat());
+        types = message_types(&dir.join("server.dnstap"));
+        if types.contains(&MessageType::ClientResponse) {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    let _ = server.kill();
+    let _ = server.wait();
+    fs::remove_dir_all(&dir).unwrap();
+    for r#type in [
+        MessageType::ClientQuery,
+        MessageType::ForwarderQuery,
+        MessageType::ForwarderResponse,
+        MessageType::ClientResponse,
+    ] {
+        assert!(types.contains(&r#type), "{:?}", types);
+    }
+}
+
+#[test]
+fn test_dnstap_read_errors() {
+    let path = env::temp_dir().join(format!("dns-rs-test-{}.notdnstap", std::process::id()));
+    fs::write(&path, b"not a frame stream").unwrap();
+    let output = dns_rs(&["dnstap-read", path.to_str().unwrap()]);
+    fs::remove_file(&path).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        dns_rs(&["dnstap-read", "/nonexistent/file"]).status.code(),
+        Some(1)
+    );
+}
@@ -0,0 +1,97 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream, UdpSocket};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use dns_rs_lib::answer::Answer;
+use dns_rs_lib::class::RRClass;
+use dns_rs_lib::client::{self, Client};
+use dns_rs_lib::dnstap::Protocol;
+use dns_rs_lib::metrics::{self, Metrics};
+use dns_rs_lib::packet::Packet;
+use dns_rs_lib::r#type::RRType;
+
+fn respond(query: &[u8]) -> Vec<u8> {
+    let query = Packet::from_buf(query);
+    let mut header = query.header.clone();
+    header.response = true;
+    let mut response = Packet::new(header);
+    response.questions = query.questions.clone();
+    response.answers.push(Answer::new(
+        "example.com",
+        RRType::A,
+        RRClass::IN,
+        300,
+        vec![192, 0, 2, 1],
+    ));
+    response.to_bytes()
+}
+
+fn get(port: u16, request: &str) -> String {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn test_scrape_metrics() {
+    let metrics = Arc::new(Metrics::new());
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    metrics::serve(listener, metrics.clone());
+
+    // one upstream that answers and one that never does
+    let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let answering = upstream.local_addr().unwrap();
+    thread::spawn(move || {
+        let mut buf = [0u8; 512];
+        let (len, from) = upstream.recv_from(&mut buf).unwrap();
+        upstream.send_to(&respond(&buf[..len]), from).unwrap();
+    });
+
+    let query = client::build_query("example.com", RRType::A, RRClass::IN);
+    metrics.record_query(Protocol::Udp, &query);
+    metrics.cache_miss();
+    let mut client = Client::new(answering);
+    client.metrics = Some(metrics.clone());
+    let response = client.query(&query).unwrap();
+    metrics.record_response(Protocol::Udp, &response.packet);
+
+    client.server = silent.local_addr().unwrap();
+    client.timeout = Duration::from_millis(20);
+    client.retries = 0;
+    assert!(client.query(&query).is_err());
+
+    // a scraper that connects and says nothing doesn't hold up the others
+    let _stalled = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    let response = get(port, "GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(head.contains(&format!("Content-Type: {}", metrics::CONTENT_TYPE)));
+    assert!(body.contains(
+        "dns_queries_total{transport=\"udp\",type=\"A\",class=\"IN\",opcode=\"QUERY\"} 1\n"
+    ));
+    assert!(body.contains(
+        "dns_responses_total{transport=\"udp\",rcode=\"NOERROR\",truncated=\"false\"} 1\n"
+    ));
+    assert!(body.contains("dns_cache_misses_total 1\n"));
+    assert!(body.contains(&format!(
+        "dns_upstream_latency_seconds_count{{upstream=\"{}\"}} 1\n",
+        answering
+    )));
+    assert!(body.contains(&format!(
+        "dns_upstream_errors_total{{upstream=\"{}\"}} 1\n",
+        client.server
+    )));
+    assert!(body.contains(&format!(
+        "dns_upstream_in_flight{{upstream=\"{}\"}} 0\n",
+        answering
+    )));
+
+    assert!(get(port, "GET /other HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404"));
+    assert!(get(port, "POST /metrics HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 405"));
+}
@@ -0,0 +1,148 @@
+use std::env;
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::process::{Command, Output};
+
+use dns_rs_lib::answer::Answer;
+use dns_rs_lib::class::RRClass;
+use dns_rs_lib::client;
+use dns_rs_lib::packet::Packet;
+use dns_rs_lib::r#type::RRType;
+
+const LINKTYPE_ETHERNET: u32 = 1;
+
+fn dns_rs(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_dns-rs"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+// an Ethernet frame carrying IPv4 with the given transport header and data
+fn frame(protocol: u8, source: SocketAddr, destination: SocketAddr, transport: &[u8]) -> Vec<u8> {
+    let (IpAddr::V4(source), IpAddr::V4(destination)) = (source.ip(), destination.ip()) else {
+        panic!("not IPv4");
+    };
+    let mut frame = vec![0; 12];
+    frame.extend([0x08, 0x00, 0x45, 0]);
+    frame.extend(((20 + transport.len()) as u16).to_be_bytes());
+    frame.extend([0, 0, 0x40, 0, 64, protocol, 0, 0]);
+    frame.extend(source.octets());
+    frame.extend(destination.octets());
+    frame.extend_from_slice(transport);
+    frame
+}
+
+fn udp(source: SocketAddr, destination: SocketAddr, data: &[u8]) -> Vec<u8> {
+    let mut datagram = source.port().to_be_bytes().to_vec();
+    datagram.extend(destination.port().to_be_bytes());
+    datagram.extend(((8 + data.len()) as u16).to_be_bytes());
+    datagram.extend([0, 0]);
+    datagram.extend_from_slice(data);
+    frame(17, source, destination, &datagram)
+}
+
+fn tcp(source: SocketAddr, destination: SocketAddr, seq: u32, flags: u8, data: &[u8]) -> Vec<u8> {
+    let mut segment = source.port().to_be_bytes().to_vec();
+    segment.extend(destination.port().to_be_bytes());
+    segment.extend(seq.to_be_bytes());
+    segment.extend([0, 0, 0, 0, 5 << 4, flags, 0xff, 0xff, 0, 0, 0, 0]);
+    segment.extend_from_slice(data);
+    frame(6, source, destination, &segment)
+}
+
+fn pcap(frames: &[(u32, Vec<u8>)]) -> Vec<u8> {
+    let mut file = 0xa1b2c3d4u32.to_le_bytes().to_vec();
+    file.extend([2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    file.extend(65535u32.to_le_bytes());
+    file.extend(LINKTYPE_ETHERNET.to_le_bytes());
+    for (micros, data) in frames {
+        file.extend(1700000000u32.to_le_bytes());
+        file.extend(micros.to_le_bytes());
+        file.extend((data.len() as u32).to_le_bytes());
+        file.extend((data.len() as u32).to_le_bytes());
+        file.extend(data);
+    }
+    file
+}
+
+fn response(query: &Packet, rcode: u8) -> Vec<u8> {
+    let mut header = query.header.clone();
+    header.response = true;
+    header.resp_code = rcode;
+    let mut response = Packet::new(header);
+    response.questions = query.questions.clone();
+    if rcode == 0 {
+        response.answers.push(Answer::new(
+            &query.questions[0].name,
+            RRType::A,
+            RRClass::IN,
+            300,
+            vec![192, 0, 2, 1],
+        ));
+    }
+    response.to_bytes()
+}
+
+fn framed(message: &[u8]) -> Vec<u8> {
+    [
+        (message.len() as u16).to_be_bytes().to_vec(),
+        message.to_vec(),
+    ]
+    .concat()
+}
+
+#[test]
+fn test_pcap_matches_queries_and_responses() {
+    let client: SocketAddr = "192.0.2.1:40000".parse().unwrap();
+    let server: SocketAddr = "192.0.2.53:53".parse().unwrap();
+    let udp_query = client::build_query("www.example.com", RRType::A, RRClass::IN);
+    let tcp_query = client::build_query("missing.example.com", RRType::AAAA, RRClass::IN);
+    let unanswered = client::build_query("slow.example.com", RRType::MX, RRClass::IN);
+
+    let tcp_request = framed(&tcp_query.to_bytes());
+    let tcp_response = framed(&response(&tcp_query, 3));
+    let frames = vec![
+        (0, udp(client, server, &udp_query.to_bytes())),
+        (1500, udp(server, client, &response(&udp_query, 0))),
+        // the TCP query arrives in two segments
+        (2000, tcp(client, server, 100, 0b10, &[])),
+        (2100, tcp(client, server, 101, 0, &tcp_request[..10])),
+        (2200, tcp(client, server, 111, 0, &tcp_request[10..])),
+        (4200, tcp(server, client, 500, 0, &tcp_response)),
+        (5000, udp(client, server, &unanswered.to_bytes())),
+        (6000, udp(client, server, &[0xde, 0xad])),
+    ];
+
+    let path = env::temp_dir().join(format!("dns-rs-test-{}.pcap", std::process::id()));
+    fs::write(&path, pcap(&frames)).unwrap();
+    let output = dns_rs(&["pcap", path.to_str().unwrap()]);
+    fs::remove_file(&path).unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert!(lines[0].starts_with(&format!(
+        "1700000000.000000 192.0.2.1:40000 > 192.0.2.53:53 UDP query id {} www.example.com. A",
+        udp_query.header.identifier
+    )));
+    assert!(lines[1].ends_with("www.example.com. A NOERROR 1 answers in 1.500 ms"));
+    assert!(lines[2].contains("TCP query"));
+    assert!(lines[3].ends_with("missing.example.com. AAAA NXDOMAIN 0 answers in 2.000 ms"));
+    assert!(lines[5].contains("malformed: offset 0"));
+
+    assert!(stdout.contains(";; 6 messages: 3 queries, 2 responses, 1 malformed"));
+    assert!(stdout.contains(";; 1 queries unanswered, 0 responses without a query"));
+    assert!(stdout.contains(";;   NXDOMAIN   1"));
+    assert!(stdout.contains(";;   A          1"));
+    assert!(stdout.contains(
+        ";; latency: min 1.500 ms, avg 1.750 ms, p50 1.500 ms, p95 1.500 ms, max 2.000 ms"
+    ));
+}
+
+#[test]
+fn test_pcap_errors() {
+    let output = dns_rs(&["pcap", "/nonexistent/capture.pcap"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(dns_rs(&["pcap"]).status.code(), Some(1));
+}
@@ -0,0 +1,166 @@
+use std::net::{TcpListener, UdpSocket};
+use std::process::{Command, Output};
+use std::thread;
+
+use dns_rs_lib::answer::Answer;
+use dns_rs_lib::class::RRClass;
+use dns_rs_lib::packet::Packet;
+use dns_rs_lib::r#type::RRType;
+use dns_rs_lib::tcp;
+
+fn respond(query: &[u8]) -> Vec<u8> {
+    let query = Packet::from_buf(query);
+    let mut header = query.header.clone();
+    header.query = false;
+    header.response = true;
+    header.can_recurse = true;
+    let mut response = Packet::new(header);
+    response.questions = query.questions.clone();
+    response.edns = query.edns.clone();
+    response.answers.push(Answer::new(
+        "example.com",
+        RRType::A,
+        RRClass::IN,
+        300,
+        vec![192, 0, 2, 1],
+    ));
+    response.to_bytes()
+}
+
+fn dns_rs(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_dns-rs"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn udp_server() -> (u16, thread::JoinHandle<Packet>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let port = socket.local_addr().unwrap().port();
+    let handle = thread::spawn(move || {
+        let mut buf = [0u8; 512];
+        let (len, from) = socket.recv_from(&mut buf).unwrap();
+        socket.send_to(&respond(&buf[..len]), from).unwrap();
+        Packet::from_buf(&buf[..len])
+    });
+    (port, handle)
+}
+
+#[test]
+fn test_query_over_udp() {
+    let (port, server) = udp_server();
+    let output = dns_rs(&[
+        "query",
+        "@127.0.0.1",
+        "-p",
+        &port.to_string(),
+        "example.com",
+        "A",
+        "+dnssec",
+        "+bufsize=4096",
+    ]);
+    assert!(output.status.success());
+
+    let query = server.join().unwrap();
+    assert_eq!(query.questions[0].name, "example.com");
+    assert_eq!(query.questions[0].r#type, RRType::A);
+    assert!(query.header.should_recurse);
+    let edns = query.edns.unwrap();
+    assert_eq!(edns.udp_payload_size, 4096);
+    assert!(edns.dnssec_ok);
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("status: NOERROR"));
+    assert!(stdout.contains(";; flags: qr rd ra;"));
+    assert!(stdout.contains("example.com.\t300\tIN\tA\t192.0.2.1"));
+    assert!(stdout.contains(&format!(";; SERVER: 127.0.0.1#{}(127.0.0.1) (UDP)", port)));
+}
+
+#[test]
+fn test_query_over_tcp() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let query = tcp::read_message(&mut stream).unwrap();
+        tcp::write_message(&mut stream, &respond(&query)).unwrap();
+        Packet::from_buf(&query)
+    });
+
+    let output = dns_rs(&[
+        "query",
+        "@127.0.0.1",
+        "-p",
+        &port.to_string(),
+        "+tcp",
+        "+norecurse",
+        "+noedns",
+        "example.com",
+    ]);
+    assert!(output.status.success());
+
+    let query = server.join().unwrap();
+    assert!(!query.header.should_recurse);
+    assert!(query.edns.is_none());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("example.com.\t300\tIN\tA\t192.0.2.1"));
+    assert!(stdout.contains("(TCP)"));
+}
+
+#[test]
+fn test_query_json_output() {
+    let (port, server) = udp_server();
+    let output = dns_rs(&[
+        "query",
+        "@127.0.0.1",
+        "-p",
+        &port.to_string(),
+        "example.com",
+        "+json",
+    ]);
+    assert!(output.status.success());
+    server.join().unwrap();
+
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["QR"], true);
+    assert_eq!(json["answerRRs"][0]["NAME"], "example.com");
+    assert_eq!(json["answerRRs"][0]["RDATAHEX"], "C0000201");
+}
+
+#[test]
+fn test_query_timeout() {
+    // a bound socket that never answers
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let port = socket.local_addr().unwrap().port();
+    let output = dns_rs(&[
+        "query",
+        "@127.0.0.1",
+        "-p",
+        &port.to_string(),
+        "example.com",
+        "+timeout=1",
+        "+retry=0",
+    ]);
+    assert_eq!(output.status.code(), Some(9));
+}
+
+#[test]
+fn test_usage_errors() {
+    assert_eq!(dns_rs(&["query"]).status.code(), Some(1));
+    assert_eq!(
+        dns_rs(&["query", "example.com", "+bogus"]).status.code(),
+        Some(1)
+    );
+    assert_eq!(
+        dns_rs(&["query", "-t", "BOGUS", "example.com"])
+            .status
+            .code(),
+        Some(1)
+    );
+    let output = dns_rs(&["query", "+tls-pin=c2hvcnQ=", "example.com"]);
+    assert_eq!(output.status.code(), Some(1));
+    let output = dns_rs(&["query", "+tls-ca=missing.pem", "example.com"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("dns-rs: tls: missing.pem: "));
+}
@@ -0,0 +1,703 @@
+use std::env;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use dns_rs_lib::answer::Answer;
+use dns_rs_lib::base64;
+use dns_rs_lib::class::RRClass;
+use dns_rs_lib::client;
+use dns_rs_lib::dnssec::{self, Algorithm, SigningKey, SECURE_ENTRY_POINT, ZONE_KEY};
+use dns_rs_lib::opcode::OpCode;
+use dns_rs_lib::packet::Packet;
+use dns_rs_lib::r#type::RRType;
+use dns_rs_lib::sign;
+use dns_rs_lib::tcp;
+use dns_rs_lib::tls;
+use dns_rs_lib::tsig::{self, Key};
+use dns_rs_lib::zone::Zone;
+
+fn dns_rs(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_dns-rs"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+// a port nothing is listening on, which is close enough for a test
+fn free_port() -> u16 {
+    UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+fn config_dir(name: &str, config: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("dns-rs-serve-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        dir.join("example.com.zone"),
+        "$TTL 300\n@ IN SOA ns1 hostmaster 1 7200 3600 1209600 300\n@ NS ns1\nns1 A 192.0.2.53\nwww A 192.0.2.1\n",
+    )
+    .unwrap();
+    fs::write(dir.join("dns-rs.toml"), config).unwrap();
+    dir
+}
+
+struct Running(Child);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn serve(dir: &Path) -> Running {
+    let child = Command::new(env!("CARGO_BIN_EXE_dns-rs"))
+        .args(["serve", dir.join("dns-rs.toml").to_str().unwrap()])
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    Running(child)
+}
+
+// retries until the server has started
+fn query(port: u16, args: &[&str]) -> String {
+    let port = port.to_string();
+    let mut full = vec!["query", "@127.0.0.1", "-p", &port, "+timeout=1", "+retry=0"];
+    full.extend_from_slice(args);
+    for _ in 0..50 {
+        let output = dns_rs(&full);
+        if output.status.success() {
+            return String::from_utf8_lossy(&output.stdout).into_owned();
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    panic!("no answer from the server on port {}", port);
+}
+
+#[test]
+fn test_serve() {
+    let port = free_port();
+    let config = format!(
+        r#"
+[[listener]]
+address = "127.0.0.1"
+port = {port}
+
+[[listener]]
+address = "127.0.0.1"
+port = {port}
+transport = "tcp"
+
+[[zone]]
+name = "example.com"
+type = "primary"
+file = "example.com.zone"
+"#
+    );
+    let dir = config_dir("valid", &config);
+    let _server = serve(&dir);
+
+    let stdout = query(port, &["www.example.com"]);
+    assert!(stdout.contains("status: NOERROR"), "{}", stdout);
+    assert!(stdout.contains("flags: qr aa rd;"), "{}", stdout);
+    assert!(
+        stdout.contains("www.example.com.\t300\tIN\tA\t192.0.2.1"),
+        "{}",
+        stdout
+    );
+
+    let stdout = query(port, &["+tcp", "missing.example.com"]);
+    assert!(stdout.contains("status: NXDOMAIN"), "{}", stdout);
+    assert!(stdout.contains("(TCP)"), "{}", stdout);
+
+    // no forwarders, so nothing outside the zone
+    let stdout = query(port, &["example.net"]);
+    assert!(stdout.contains("status: REFUSED"), "{}", stdout);
+    assert!(
+        stdout.contains("; EDE: 20 (Not Authoritative)\n"),
+        "{}",
+        stdout
+    );
+    // trace leaves out the headers, but not why a server said no
+    let port = port.to_string();
+    let output = dns_rs(&["query", "@127.0.0.1", "-p", &port, "+trace", "example.net"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.starts_with("; EDE: 20 (Not Authoritative)\n"),
+        "{}",
+        stdout
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_serve_signed_zone() {
+    let port = free_port();
+    let config = format!(
+        r#"
+[[listener]]
+address = "127.0.0.1"
+port = {port}
+
+[[zone]]
+name = "example.com"
+type = "primary"
+file = "example.com.zone"
+keys = "keys"
+nsec3 = true
+"#
+    );
+    let dir = config_dir("signed", &config);
+    fs::create_dir_all(dir.join("keys")).unwrap();
+    let key = SigningKey::generate(Algorithm::EcdsaP256Sha256, ZONE_KEY | SECURE_ENTRY_POINT);
+    sign::save_key(&dir.join("keys"), "example.com", &key.unwrap(), 3600).unwrap();
+    let _server = serve(&dir);
+
+    let stdout = query(port, &["+dnssec", "www.example.com"]);
+    assert!(
+        stdout.contains("www.example.com.\t300\tIN\tRRSIG\tA 13 3 300 "),
+        "{}",
+        stdout
+    );
+    let stdout = query(port, &["+dnssec", "missing.example.com"]);
+    assert!(stdout.contains("status: NXDOMAIN"), "{}", stdout);
+    assert!(stdout.contains("\tIN\tNSEC3\t1 0 0 - "), "{}", stdout);
+    // without DO the answer is as it would be unsigned
+    let stdout = query(port, &["www.example.com"]);
+    assert!(!stdout.contains("RRSIG"), "{}", stdout);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+// a server with one listener for the transport, with a self-signed certificate
+// for localhost in cert.pem, and the +tls-pin option for its key
+fn tls_listener(transport: &str) -> (u16, PathBuf, String) {
+    let port = free_port();
+    let config = format!(
+        r#"
+[[listener]]
+address = "127.0.0.1"
+port = {port}
+transport = "{transport}"
+tls_certificate = "cert.pem"
+tls_key = "key.pem"
+
+[[zone]]
+name = "example.com"
+type = "primary"
+file = "example.com.zone"
+"#
+    );
+    let dir = config_dir(transport, &config);
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    fs::write(dir.join("cert.pem"), certified.cert.pem()).unwrap();
+    fs::write(dir.join("key.pem"), certified.signing_key.serialize_pem()).unwrap();
+    let pin = base64::encode(&tls::spki_pin(certified.cert.der()).unwrap());
+    (port, dir, format!("+tls-pin={}", pin))
+}
+
+#[test]
+fn test_serve_tls() {
+    let (port, dir, pin) = tls_listener("tls");
+    let _server = serve(&dir);
+
+    let stdout = query(port, &[&pin, "www.example.com"]);
+    assert!(
+        stdout.contains("www.example.com.\t300\tIN\tA\t192.0.2.1"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("(TLS)"), "{}", stdout);
+    // the query is padded, so the response is too
+    assert!(stdout.contains("; PAD: ("), "{}", stdout);
+
+    let ca = format!("+tls-ca={}", dir.join("cert.pem").display());
+    let stdout = query(port, &[&ca, "+tls-hostname=localhost", "www.example.com"]);
+    assert!(stdout.contains("status: NOERROR"), "{}", stdout);
+    // the certificate isn't for 127.0.0.1, which is the name without one
+    let port = port.to_string();
+    let output = dns_rs(&["query", "@127.0.0.1", "-p", &port, &ca, "www.example.com"]);
+    assert_eq!(output.status.code(), Some(9));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_serve_quic() {
+    let (port, dir, pin) = tls_listener("quic");
+    let _server = serve(&dir);
+
+    let stdout = query(port, &["+quic", &pin, "www.example.com"]);
+    assert!(
+        stdout.contains("www.example.com.\t300\tIN\tA\t192.0.2.1"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("(QUIC)"), "{}", stdout);
+
+    let ca = format!("+tls-ca={}", dir.join("cert.pem").display());
+    let stdout = query(
+        port,
+        &[
+            "+quic",
+            &ca,
+            "+tls-hostname=localhost",
+            "missing.example.com",
+        ],
+    );
+    assert!(stdout.contains("status: NXDOMAIN"), "{}", stdout);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_serve_https() {
+    let (port, dir, pin) = tls_listener("https");
+    let _server = serve(&dir);
+
+    let stdout = query(port, &["+https", &pin, "www.example.com"]);
+    assert!(
+        stdout.contains("www.example.com.\t300\tIN\tA\t192.0.2.1"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("(HTTPS)"), "{}", stdout);
+    let stdout = query(port, &["+https-get", &pin, "missing.example.com"]);
+    assert!(stdout.contains("status: NXDOMAIN"), "{}", stdout);
+
+    let port = port.to_string();
+    let output = dns_rs(&[
+        "query",
+        "@127.0.0.1",
+        "-p",
+        &port,
+        "+https=/other",
+        &pin,
+        "www.example.com",
+    ]);
+    assert_eq!(output.status.code(), Some(9));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("404"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+fn scrape(port: u16) -> String {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    stream
+        .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn test_serve_metrics() {
+    let port = free_port();
+    let metrics = free_port();
+    let config = format!(
+        r#"
+[[listener]]
+address = "127.0.0.1"
+port = {port}
+
+[[zone]]
+name = "example.com"
+type = "primary"
+file = "example.com.zone"
+
+[metrics]
+listen = "127.0.0.1:{metrics}"
+"#
+    );
+    let dir = config_dir("metrics", &config);
+    let _server = serve(&dir);
+
+    query(port, &["www.example.com"]);
+    let response = scrape(metrics);
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(
+        response.contains(
+            "dns_queries_total{transport=\"udp\",type=\"A\",class=\"IN\",opcode=\"QUERY\"} 1\n"
+        ),
+        "{}",
+        response
+    );
+    assert!(
+        response.contains(
+            "dns_responses_total{transport=\"udp\",rcode=\"NOERROR\",truncated=\"false\"} 1\n"
+        ),
+        "{}",
+        response
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_serve_query_log() {
+    let port = free_port();
+    let config = format!(
+        r#"
+[[listener]]
+address = "127.0.0.1"
+port = {port}
+
+[[zone]]
+name = "example.com"
+type = "primary"
+file = "example.com.zone"
+
+[logging]
+query_log = "queries.log"
+format = "json"
+"#
+    );
+    let dir = config_dir("query-log", &config);
+    let _server = serve(&dir);
+
+    query(port, &["www.example.com"]);
+    let log = fs::read_to_string(dir.join("queries.log")).unwrap();
+    let line = log.lines().last().unwrap();
+    assert!(line.starts_with("{\"ts\":"), "{}", line);
+    assert!(
+        line.contains(r#""transport":"udp","name":"www.example.com.","type":"A","class":"IN","rcode":"NOERROR","answers":1"#),
+        "{}",
+        line
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_serve_rate_limit() {
+    let port = free_port();
+    let config = format!(
+        r#"
+[[listener]]
+address = "127.0.0.1"
+port = {port}
+
+[[listener]]
+address = "127.0.0.1"
+port = {port}
+transport = "tcp"
+
+[[zone]]
+name = "example.com"
+type = "primary"
+file = "example.com.zone"
+
+[rate_limit]
+responses_per_second = 1
+slip = 0
+"#
+    );
+    let dir = config_dir("rate-limit", &config);
+    let _server = serve(&dir);
+
+    query(port, &["www.example.com"]);
+    let port = port.to_string();
+    let args = ["query", "@127.0.0.1", "-p", &port, "+timeout=1", "+retry=0"];
+    let dropped = (0..3)
+        .filter(|_| {
+            !dns_rs(&[&args[..], &["www.example.com"]].concat())
+                .status
+                .success()
+        })
+        .count();
+    assert!(dropped > 0);
+    // TCP isn't limited
+    let output = dns_rs(&[&args[..], &["+tcp", "www.example.com"]].concat());
+    assert!(output.status.success());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_serve_rpz() {
+    let port = free_port();
+    let config = format!(
+        r#"
+[[listener]]
+address = "127.0.0.1"
+port = {port}
+
+[[zone]]
+name = "example.com"
+type = "primary"
+file = "example.com.zone"
+
+[[rpz]]
+name = "rpz.local"
+file = "rpz.local.zone"
+"#
+    );
+    let dir = config_dir("rpz", &config);
+    let policy = dir.join("rpz.local.zone");
+    fs::write(&policy, "$TTL 60\nwww.example.com CNAME .\n").unwrap();
+    let _server = serve(&dir);
+
+    let stdout = query(port, &["www.example.com"]);
+    assert!(stdout.contains("status: NXDOMAIN"), "{}", stdout);
+    // a changed file is picked up without a restart
+    fs::write(&policy, "$TTL 60\nns1.example.com CNAME .\n").unwrap();
+    let mut stdout = String::new();
+    for _ in 0..30 {
+        stdout = query(port, &["www.example.com"]);
+        if stdout.contains("status: NOERROR") {
+            break;
+        }
+        thread::sleep(Duration::from_millis(500));
+    }
+    assert!(stdout.contains("status: NOERROR"), "{}", stdout);
+    let stdout = query(port, &["ns1.example.com"]);
+    assert!(stdout.contains("status: NXDOMAIN"), "{}", stdout);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_serve_blocklist() {
+    let port = free_port();
+    let config = format!(
+        r#"
+[[listener]]
+address = "127.0.0.1"
+port = {port}
+
+[[zone]]
+name = "example.com"
+type = "primary"
+file = "example.com.zone"
+
+[blocklist]
+mode = "null"
+block = ["hosts"]
+"#
+    );
+    let dir = config_dir("blocklist", &config);
+    let hosts = dir.join("hosts");
+    fs::write(&hosts, "0.0.0.0 www.example.com\n").unwrap();
+    let _server = serve(&dir);
+
+    let stdout = query(port, &["www.example.com"]);
+    assert!(stdout.contains("\tA\t0.0.0.0"), "{}", stdout);
+    // a changed list is picked up without a restart
+    fs::write(&hosts, "0.0.0.0 ns1.example.com\n").unwrap();
+    let mut stdout = String::new();
+    for _ in 0..30 {
+        stdout = query(port, &["www.example.com"]);
+        if stdout.contains("\tA\t192.0.2.1") {
+            break;
+        }
+        thread::sleep(Duration::from_millis(500));
+    }
+    assert!(stdout.contains("\tA\t192.0.2.1"), "{}", stdout);
+    let stdout = query(port, &["ns1.example.com"]);
+    assert!(stdout.contains("\tA\t0.0.0.0"), "{}", stdout);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+// a query for www.example.com signed with the key, and its response
+// once the signature on that checks out
+fn signed_query(port: u16, key: &Key) -> Result<Packet, u16> {
+    let mut query = client::build_query("www.example.com", RRType::A, RRClass::IN).to_bytes();
+    let mac = tsig::sign_query(&mut query, key, dnssec::unix_time());
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    socket.send_to(&query, ("127.0.0.1", port)).unwrap();
+    let mut buf = [0; 1232];
+    let (len, _) = socket.recv_from(&mut buf).unwrap();
+    tsig::verify_response(&buf[..len], key, &mac, dnssec::unix_time())?;
+    Ok(Packet::from_buf(&buf[..len]))
+}
+
+#[test]
+fn test_serve_views() {
+    let port = free_port();
+    let secret = b"the view key's secret";
+    let config = format!(
+        r#"
+[[listener]]
+address = "127.0.0.1"
+port = {port}
+
+[[key]]
+name = "view-key"
+secret = "{}"
+
+[[view]]
+name = "signed"
+match_keys = ["view-key"]
+
+[[view.zone]]
+name = "example.com"
+type = "primary"
+file = "signed.example.com.zone"
+
+[[view]]
+name = "public"
+match_clients = ["127.0.0.0/8"]
+
+[[view.zone]]
+name = "example.com"
+type = "primary"
+file = "example.com.zone"
+"#,
+        base64::encode(secret)
+    );
+    let dir = config_dir("views", &config);
+    fs::write(
+        dir.join("signed.example.com.zone"),
+        "$TTL 300\n@ IN SOA ns1 hostmaster 1 7200 3600 1209600 300\n@ NS ns1\nns1 A 192.0.2.53\nwww A 192.0.2.2\n",
+    )
+    .unwrap();
+    let _server = serve(&dir);
+
+    let stdout = query(port, &["www.example.com"]);
+    assert!(stdout.contains("\tA\t192.0.2.1"), "{}", stdout);
+
+    // the same name from the view the key picks, and signed with it
+    let key = Key::new("view-key", tsig::Algorithm::HmacSha256, secret.to_vec());
+    let response = signed_query(port, &key).unwrap();
+    assert_eq!(response.answers[0].data, vec![192, 0, 2, 2]);
+
+    // a key the server doesn't have doesn't pick a view
+    let wrong = Key::new("view-key", tsig::Algorithm::HmacSha256, b"a guess".to_vec());
+    assert_eq!(signed_query(port, &wrong).unwrap_err(), tsig::BADSIG);
+    let unknown = Key::new("other-key", tsig::Algorithm::HmacSha256, secret.to_vec());
+    assert_eq!(signed_query(port, &unknown).unwrap_err(), tsig::BADKEY);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+// example.org at the serial, with www at 192.0.2.SERIAL and a refresh
+// interval of a second
+fn serial_zone(serial: u32) -> Zone {
+    let text = format!(
+        "$TTL 60\n@ SOA ns1 hostmaster {} 1 1 600 60\n@ NS ns1\nwww A 192.0.2.{}\n",
+        serial, serial
+    );
+    Zone::from_text("example.org", &text).unwrap()
+}
+
+fn reply(query: &Packet, answers: Vec<Answer>) -> Vec<u8> {
+    let mut header = query.header.clone();
+    header.response = true;
+    header.is_authoritative = true;
+    let mut response = Packet::new(header);
+    response.questions = query.questions.clone();
+    response.answers = answers;
+    response.to_bytes()
+}
+
+// a master for example.org answering SOA queries over UDP and transfers
+// over TCP with whatever serial it's at
+fn master(serial: Arc<AtomicU32>) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let socket = UdpSocket::bind(("127.0.0.1", port)).unwrap();
+    let udp_serial = serial.clone();
+    thread::spawn(move || {
+        let mut buf = [0u8; 512];
+        while let Ok((len, from)) = socket.recv_from(&mut buf) {
+            let query = Packet::from_buf(&buf[..len]);
+            let zone = serial_zone(udp_serial.load(Ordering::Relaxed));
+            let soa = zone.lookup("example.org", RRType::SOA)[0].clone();
+            socket.send_to(&reply(&query, vec![soa]), from).unwrap();
+        }
+    });
+    thread::spawn(move || {
+        for mut stream in listener.incoming().map_while(Result::ok) {
+            let query = Packet::from_buf(&tcp::read_message(&mut stream).unwrap());
+            let zone = serial_zone(serial.load(Ordering::Relaxed));
+            let soa = zone.lookup("example.org", RRType::SOA)[0].clone();
+            let mut records = zone.records.clone();
+            records.push(soa);
+            tcp::write_message(&mut stream, &reply(&query, records)).unwrap();
+        }
+    });
+    port
+}
+
+#[test]
+fn test_serve_secondary_and_notify() {
+    let port = free_port();
+    let serial = Arc::new(AtomicU32::new(1));
+    let master = master(serial.clone());
+    let secondary = UdpSocket::bind("127.0.0.1:0").unwrap();
+    secondary
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    let notify = secondary.local_addr().unwrap();
+    let config = format!(
+        r#"
+[[listener]]
+address = "127.0.0.1"
+port = {port}
+
+[[zone]]
+name = "example.com"
+type = "primary"
+file = "example.com.zone"
+notify = ["{notify}"]
+
+[[zone]]
+name = "example.org"
+type = "secondary"
+masters = ["127.0.0.1:{master}"]
+"#
+    );
+    let dir = config_dir("secondary", &config);
+    let _server = serve(&dir);
+
+    // the primary's secondaries hear about it once it's loaded
+    let mut buf = [0u8; 512];
+    let (len, from) = secondary.recv_from(&mut buf).unwrap();
+    let query = Packet::from_buf(&buf[..len]);
+    assert_eq!(OpCode::from_value(query.header.op_code), OpCode::NOTIFY);
+    assert_eq!(query.questions[0].name, "example.com");
+    secondary.send_to(&reply(&query, Vec::new()), from).unwrap();
+
+    let stdout = query_until(port, "\tA\t192.0.2.1");
+    assert!(stdout.contains("\tA\t192.0.2.1"), "{}", stdout);
+    // a newer serial at the master is transferred at the refresh interval
+    serial.store(2, Ordering::Relaxed);
+    let stdout = query_until(port, "\tA\t192.0.2.2");
+    assert!(stdout.contains("\tA\t192.0.2.2"), "{}", stdout);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+// www.example.org until the answer has the text, or a while has passed
+fn query_until(port: u16, text: &str) -> String {
+    let mut stdout = String::new();
+    for _ in 0..30 {
+        stdout = query(port, &["www.example.org"]);
+        if stdout.contains(text) {
+            break;
+        }
+        thread::sleep(Duration::from_millis(500));
+    }
+    stdout
+}
+
+#[test]
+fn test_serve_bad_config() {
+    let dir = config_dir(
+        "invalid",
+        "[[listener]]\naddress = \"127.0.0.1\"\ntransport = \"quic\"\ntls_key = \"key.pem\"\n",
+    );
+    let output = dns_rs(&["serve", dir.join("dns-rs.toml").to_str().unwrap()]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("listener[0].tls_certificate: needed for quic listeners"),
+        "{}",
+        stderr
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,165 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+use dns_rs_lib::class::RRClass;
+use dns_rs_lib::client;
+use dns_rs_lib::edns::Edns;
+use dns_rs_lib::packet::Packet;
+use dns_rs_lib::r#type::RRType;
+use dns_rs_lib::server;
+use dns_rs_lib::validator::{Security, Validator};
+use dns_rs_lib::zone::Zone;
+
+const ZONE: &str = "$TTL 300
+@ SOA ns hostmaster 1 7200 3600 1209600 300
+@ NS ns
+ns A 192.0.2.53
+www A 192.0.2.1
+*.wild TXT wildcard
+";
+
+fn dns_rs(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_dns-rs"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn zone_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("dns-rs-sign-zone-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("example.com.zone"), ZONE).unwrap();
+    dir
+}
+
+fn key_files(dir: &PathBuf) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name.starts_with('K'))
+        .collect();
+    names.sort();
+    names
+}
+
+// the signed zone's own answers, checked from the DS records printed for
+// the parent
+fn validate(zone: &Zone, ds: &str, name: &str, r#type: RRType) -> Security {
+    let lookup = |name: &str, r#type| {
+        let mut query = client::build_query(name, r#type, RRClass::IN);
+        let mut edns = Edns::new(server::UDP_PAYLOAD_SIZE);
+        edns.dnssec_ok = true;
+        query.edns = Some(edns);
+        Ok::<Packet, io::Error>(zone.respond(&query).0)
+    };
+    let response = lookup(name, r#type).unwrap();
+    Validator::from_text(ds)
+        .unwrap()
+        .validate(&response, &lookup)
+}
+
+#[test]
+fn test_sign_zone() {
+    let dir = zone_dir("nsec3");
+    let file = dir.join("example.com.zone");
+    let key_dir = dir.to_str().unwrap();
+    let output = dns_rs(&[
+        "sign-zone",
+        "-K",
+        key_dir,
+        "+nsec3",
+        "example.com",
+        file.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(
+        stderr
+            .matches("dns-rs: generated Kexample.com.+013+")
+            .count(),
+        2
+    );
+    let ds = String::from_utf8(output.stdout).unwrap();
+    assert!(ds.starts_with("example.com.\t3600\tIN\tDS\t"));
+    let keys = key_files(&dir);
+    assert_eq!(keys.len(), 4);
+
+    let text = fs::read_to_string(dir.join("example.com.zone.signed")).unwrap();
+    let signed = Zone::from_text("example.com", &text).unwrap();
+    assert!(!signed.lookup("example.com", RRType::NSEC3PARAM).is_empty());
+    for (name, r#type) in [
+        ("www.example.com", RRType::A),
+        ("a.wild.example.com", RRType::TXT),
+        ("nothing.example.com", RRType::A),
+        ("www.example.com", RRType::AAAA),
+    ] {
+        assert_eq!(
+            validate(&signed, &ds, name, r#type),
+            Security::Secure,
+            "{} {}",
+            name,
+            r#type
+        );
+    }
+
+    // signing again uses the keys already there, and can switch to NSEC
+    let output = dns_rs(&[
+        "sign-zone",
+        "-K",
+        key_dir,
+        "-o",
+        dir.join("out.zone").to_str().unwrap(),
+        "example.com.",
+        file.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+    assert!(!String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("generated"));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), ds);
+    assert_eq!(key_files(&dir), keys);
+    let text = fs::read_to_string(dir.join("out.zone")).unwrap();
+    let signed = Zone::from_text("example.com", &text).unwrap();
+    assert!(!signed.lookup("www.example.com", RRType::NSEC).is_empty());
+    assert_eq!(
+        validate(&signed, &ds, "nothing.example.com", RRType::A),
+        Security::Secure
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_sign_zone_errors() {
+    let dir = zone_dir("errors");
+    let file = dir.join("example.com.zone");
+    let file = file.to_str().unwrap();
+    let key_dir = dir.to_str().unwrap();
+    for (args, error) in [
+        (vec!["example.com"], "expected a zone name and its file"),
+        (
+            vec!["-e", "0", "example.com", file],
+            "invalid validity \"0\"",
+        ),
+        (vec!["-s", "xyz", "example.com", file], "invalid hex digit"),
+        (vec!["-i", "500", "example.com", file], "invalid iterations"),
+        (
+            vec!["-a", "RSAMD5", "example.com", file],
+            "unsupported algorithm",
+        ),
+        (
+            vec!["-K", key_dir, "-a", "RSASHA256", "example.com", file],
+            "RSA keys can't be generated",
+        ),
+        (vec!["example.com", "missing.zone"], "missing.zone: "),
+    ] {
+        let output = dns_rs(&[&["sign-zone"], args.as_slice()].concat());
+        assert_eq!(output.status.code(), Some(1));
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains(error), "{}", stderr);
+    }
+    assert!(key_files(&dir).is_empty());
+    fs::remove_dir_all(&dir).unwrap();
+}